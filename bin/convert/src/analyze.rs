@@ -0,0 +1,177 @@
+use crate::{archive, console, walk};
+use clap::Args;
+use crx::{CrxFile, CrxImageClip};
+use image::GenericImageView;
+use std::{collections::BTreeSet, io, path::PathBuf};
+
+#[derive(Args)]
+pub struct AnalyzeClipsArg {
+    /// CRX files, or directories to search for them
+    paths: Vec<PathBuf>,
+    /// Base image the files are variants of; adds the changed region to the compared features
+    #[arg(long)]
+    base: Option<PathBuf>,
+    /// Smallest share of clips a hypothesis must hold for to be reported, in percent
+    #[arg(long, default_value_t = 50)]
+    min_support: u32,
+}
+
+/// Named per-clip values a clip field may encode.
+type Features = Vec<(&'static str, i64)>;
+
+pub fn run(arg: &AnalyzeClipsArg) -> io::Result<()> {
+    let base = match &arg.base {
+        Some(path) => Some(CrxFile::read(archive::open(path)?)?),
+        None => None,
+    };
+
+    // (clip, features) of every clip in the corpus.
+    let mut samples: Vec<(CrxImageClip, Features)> = Vec::new();
+    let mut files = 0;
+    for path in walk::collect_files(&arg.paths, "crx", &Default::default())? {
+        let crx = match archive::open(&path).and_then(|f| Ok(CrxFile::read(f)?)) {
+            Ok(crx) => crx,
+            Err(e) => {
+                console::file_line(
+                    console::Status::Failed,
+                    &path,
+                    format_args!("decode: {}", e),
+                );
+                continue;
+            }
+        };
+        if crx.clips().is_empty() {
+            continue;
+        }
+        files += 1;
+        let mut features: Features = vec![
+            ("0", 0),
+            ("width", crx.width() as i64),
+            ("height", crx.height() as i64),
+            ("inner_x", crx.inner_x() as i64),
+            ("inner_y", crx.inner_y() as i64),
+            ("clip_count", crx.clips().len() as i64),
+        ];
+        if let Some(base) = &base {
+            if let Some((x, y, w, h)) = changed_region(base, &crx) {
+                features.extend([
+                    ("changed_x", x),
+                    ("changed_y", y),
+                    ("changed_width", w),
+                    ("changed_height", h),
+                    ("changed_right", x + w),
+                    ("changed_bottom", y + h),
+                ]);
+            }
+        }
+        for (index, clip) in crx.clips().iter().enumerate() {
+            let mut features = features.clone();
+            features.push(("clip_index", index as i64));
+            samples.push((*clip, features));
+        }
+    }
+
+    console::line(
+        console::Status::Info("Analyzed"),
+        format_args!("{} clip(s) in {} file(s)", samples.len(), files),
+    );
+    if samples.is_empty() {
+        return Ok(());
+    }
+
+    // every feature name seen, in first-seen order.
+    let mut feature_names: Vec<&str> = Vec::new();
+    for (_, features) in &samples {
+        for (feature, _) in features {
+            if !feature_names.contains(feature) {
+                feature_names.push(feature);
+            }
+        }
+    }
+
+    let field_names = ["unknown_1", "x", "y", "unknown_2", "width", "height"];
+    for (field, name) in field_names.iter().enumerate() {
+        let values: Vec<i64> = samples.iter().map(|(c, _)| field_value(c, field)).collect();
+        let distinct: BTreeSet<i64> = values.iter().copied().collect();
+        console::line(
+            console::Status::Note("Field"),
+            format_args!(
+                "{}: {} distinct value(s) in [{}, {}]",
+                name,
+                distinct.len(),
+                distinct.first().unwrap(),
+                distinct.last().unwrap()
+            ),
+        );
+
+        let mut hypotheses: Vec<(&str, usize, usize)> = feature_names
+            .iter()
+            .map(|feature| {
+                let (mut support, mut total) = (0, 0);
+                for ((_, features), &value) in samples.iter().zip(&values) {
+                    if let Some(&(_, f)) = features.iter().find(|(n, _)| n == feature) {
+                        total += 1;
+                        if f == value {
+                            support += 1;
+                        }
+                    }
+                }
+                (*feature, support, total)
+            })
+            .filter(|&(_, support, total)| {
+                total > 0 && support * 100 >= total * arg.min_support as usize
+            })
+            .collect();
+        hypotheses.sort_by(|a, b| (b.1 * a.2).cmp(&(a.1 * b.2)));
+        for (feature, support, total) in hypotheses {
+            console::line(
+                console::Status::Note(""),
+                format_args!(
+                    "{} == {} in {}/{} clip(s) ({:.0}%)",
+                    name,
+                    feature,
+                    support,
+                    total,
+                    support as f64 * 100.0 / total as f64
+                ),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn field_value(clip: &CrxImageClip, field: usize) -> i64 {
+    match field {
+        0 => clip.unknown_1 as i64,
+        1 => clip.x as i64,
+        2 => clip.y as i64,
+        3 => clip.unknown_2 as i64,
+        4 => clip.width as i64,
+        _ => clip.height as i64,
+    }
+}
+
+/// Bounding box `(x, y, width, height)`, in variant coordinates, of the pixels of `variant` that
+/// differ from the `base` pixels under them, positioning both by their inner offsets.
+fn changed_region(base: &CrxFile, variant: &CrxFile) -> Option<(i64, i64, i64, i64)> {
+    let base_rgba = base.view();
+    let variant_rgba = variant.view();
+    let dx = variant.inner_x() as i64 - base.inner_x() as i64;
+    let dy = variant.inner_y() as i64 - base.inner_y() as i64;
+
+    let (mut x0, mut y0, mut x1, mut y1) = (i64::MAX, i64::MAX, i64::MIN, i64::MIN);
+    for (x, y, pixel) in variant_rgba.pixels() {
+        let (bx, by) = (x as i64 + dx, y as i64 + dy);
+        let same = bx >= 0
+            && by >= 0
+            && base_rgba.in_bounds(bx as u32, by as u32)
+            && base_rgba.get_pixel(bx as u32, by as u32) == pixel;
+        if !same {
+            x0 = x0.min(x as i64);
+            y0 = y0.min(y as i64);
+            x1 = x1.max(x as i64);
+            y1 = y1.max(y as i64);
+        }
+    }
+    (x0 <= x1).then(|| (x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+}
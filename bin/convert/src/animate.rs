@@ -0,0 +1,301 @@
+use crate::{
+    archive, console,
+    sequence::{self, GroupPattern},
+    walk,
+};
+use byteorder::{LittleEndian, WriteBytesExt};
+use clap::{Args, ValueEnum};
+use crx::CrxFile;
+use image::{codecs::gif, Delay, DynamicImage, Frame, ImageEncoder, RgbaImage};
+use rayon::prelude::*;
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct AnimateArg {
+    /// Frames in display order, or directories whose numbered CRX files are grouped into
+    /// sequences as `sequences` does
+    #[arg(required = true)]
+    frames: Vec<PathBuf>,
+    /// Base CG the frames are differential images of; every frame is merged onto it, placed by
+    /// its inner offset. Without it, the frames must all be of the same size
+    #[arg(long, value_name = "CRX")]
+    base: Option<PathBuf>,
+    /// How frame file names are formed in directories, as for `sequences`
+    #[arg(long, value_name = "PATTERN", value_parser = GroupPattern::parse)]
+    group_pattern: Option<GroupPattern>,
+    /// Sequence to animate when the directories hold several
+    #[arg(long, value_name = "NAME")]
+    sequence: Option<String>,
+    /// Time each frame is shown, in milliseconds
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u16).range(1..))]
+    delay: u16,
+    /// Times the animation is played; 0 repeats it forever
+    #[arg(long, default_value_t = 0)]
+    loops: u16,
+    /// Animation format, by default from the extension of the output
+    #[arg(long, value_enum)]
+    format: Option<AnimationFormat>,
+    /// File to write the animation to
+    #[arg(long, short, value_name = "FILE")]
+    output: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AnimationFormat {
+    /// Animated PNG
+    Apng,
+    Gif,
+    /// Lossless animated WebP
+    Webp,
+}
+
+impl AnimationFormat {
+    fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" | "apng" => Some(AnimationFormat::Apng),
+            "gif" => Some(AnimationFormat::Gif),
+            "webp" => Some(AnimationFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+pub fn run(arg: &AnimateArg) -> io::Result<()> {
+    let format = arg
+        .format
+        .or_else(|| AnimationFormat::from_extension(&arg.output))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot tell the animation format from the output name, pass --format",
+            )
+        })?;
+    let paths = frame_paths(arg)?;
+    let base = arg.base.as_deref().map(read).transpose()?;
+    let frames = paths
+        .par_iter()
+        .map(|path| {
+            let frame = read(path)?;
+            let frame = match &base {
+                Some(base) => frame.compose_onto(base)?,
+                None => frame,
+            };
+            let rgba = DynamicImage::try_from(frame)
+                .map_err(io::Error::other)?
+                .into_rgba8();
+            Ok(rgba)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let (width, height) = frames[0].dimensions();
+    if let Some((path, _)) = paths
+        .iter()
+        .zip(&frames)
+        .find(|(_, frame)| frame.dimensions() != (width, height))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is not {}x{} like the first frame; pass the --base the frames belong to",
+                path.display(),
+                width,
+                height
+            ),
+        ));
+    }
+
+    let count = frames.len();
+    let mut writer = BufWriter::new(fs::File::create(&arg.output)?);
+    match format {
+        AnimationFormat::Apng => write_apng(&mut writer, &frames, arg.delay, arg.loops)?,
+        AnimationFormat::Gif => write_gif(&mut writer, frames, arg.delay, arg.loops)?,
+        AnimationFormat::Webp => write_webp(&mut writer, &frames, arg.delay, arg.loops)?,
+    }
+    writer.flush()?;
+    console::file_line(
+        console::Status::Success,
+        &arg.output,
+        format_args!(
+            "{} frame(s) of {}x{}, {} ms each",
+            count, width, height, arg.delay
+        ),
+    );
+    Ok(())
+}
+
+fn read(path: &Path) -> io::Result<CrxFile> {
+    CrxFile::read(archive::open(path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+/// The frames of `arg` in display order: files as given, or the sequence their directories hold.
+fn frame_paths(arg: &AnimateArg) -> io::Result<Vec<PathBuf>> {
+    if arg.frames.iter().all(|path| !path.is_dir()) {
+        return Ok(arg.frames.clone());
+    }
+    let files = walk::collect_files(&arg.frames, "crx", &Default::default())?;
+    let mut sequences = sequence::group(&files, arg.group_pattern.as_ref(), 1);
+    if let Some(name) = &arg.sequence {
+        sequences.retain(|sequence| &sequence.name == name);
+    }
+    match sequences.len() {
+        1 => Ok(sequences
+            .remove(0)
+            .frames
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect()),
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no frame sequence found",
+        )),
+        _ => {
+            let names: Vec<_> = sequences.iter().map(|s| s.name.as_str()).collect();
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "found {} sequences ({}), pick one with --sequence",
+                    names.len(),
+                    names.join(", ")
+                ),
+            ))
+        }
+    }
+}
+
+fn write_apng<W: Write>(writer: W, frames: &[RgbaImage], delay: u16, loops: u16) -> io::Result<()> {
+    let (width, height) = frames[0].dimensions();
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, loops as u32)
+        .map_err(io::Error::other)?;
+    encoder
+        .set_frame_delay(delay, 1000)
+        .map_err(io::Error::other)?;
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    for frame in frames {
+        writer
+            .write_image_data(frame.as_raw())
+            .map_err(io::Error::other)?;
+    }
+    writer.finish().map_err(io::Error::other)
+}
+
+fn write_gif<W: Write>(
+    writer: W,
+    frames: Vec<RgbaImage>,
+    delay: u16,
+    loops: u16,
+) -> io::Result<()> {
+    let mut encoder = gif::GifEncoder::new(writer);
+    encoder
+        .set_repeat(match loops {
+            0 => gif::Repeat::Infinite,
+            // the count is of repetitions after the first play.
+            loops => gif::Repeat::Finite(loops - 1),
+        })
+        .map_err(io::Error::other)?;
+    let delay = Delay::from_numer_denom_ms(delay as u32, 1);
+    encoder
+        .encode_frames(
+            frames
+                .into_iter()
+                .map(|frame| Frame::from_parts(frame, 0, 0, delay)),
+        )
+        .map_err(io::Error::other)
+}
+
+/// An animated WebP: every frame is encoded as a lossless still, whose `VP8L` bitstream is then
+/// placed in an `ANMF` chunk covering the whole canvas.
+fn write_webp<W: Write>(
+    mut writer: W,
+    frames: &[RgbaImage],
+    delay: u16,
+    loops: u16,
+) -> io::Result<()> {
+    let (width, height) = frames[0].dimensions();
+    let mut chunks = Vec::new();
+
+    let mut vp8x = vec![0x02 | 0x10, 0, 0, 0]; // animation, alpha.
+    vp8x.write_u24::<LittleEndian>(width - 1)?;
+    vp8x.write_u24::<LittleEndian>(height - 1)?;
+    write_chunk(&mut chunks, b"VP8X", &vp8x)?;
+
+    let mut anim = Vec::new();
+    anim.write_u32::<LittleEndian>(0)?; // background color.
+    anim.write_u16::<LittleEndian>(loops)?;
+    write_chunk(&mut chunks, b"ANIM", &anim)?;
+
+    let stills = frames
+        .par_iter()
+        .map(|frame| {
+            let mut still = Vec::new();
+            image::codecs::webp::WebPEncoder::new_lossless(&mut still)
+                .write_image(
+                    frame.as_raw(),
+                    width,
+                    height,
+                    image::ExtendedColorType::Rgba8,
+                )
+                .map_err(io::Error::other)?;
+            Ok(still)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    for still in &stills {
+        let bitstream = find_chunk(still, b"VP8L").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "WebP still without a VP8L chunk",
+            )
+        })?;
+        let mut anmf = Vec::new();
+        anmf.write_u24::<LittleEndian>(0)?; // x / 2.
+        anmf.write_u24::<LittleEndian>(0)?; // y / 2.
+        anmf.write_u24::<LittleEndian>(width - 1)?;
+        anmf.write_u24::<LittleEndian>(height - 1)?;
+        anmf.write_u24::<LittleEndian>(delay as u32)?;
+        anmf.write_u8(0x02)?; // replace the canvas rather than blending onto it.
+        write_chunk(&mut anmf, b"VP8L", bitstream)?;
+        write_chunk(&mut chunks, b"ANMF", &anmf)?;
+    }
+
+    writer.write_all(b"RIFF")?;
+    writer.write_u32::<LittleEndian>(4 + chunks.len() as u32)?;
+    writer.write_all(b"WEBP")?;
+    writer.write_all(&chunks)
+}
+
+fn write_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(fourcc)?;
+    out.write_u32::<LittleEndian>(data.len() as u32)?;
+    out.write_all(data)?;
+    // chunks are padded to an even size.
+    if data.len() % 2 == 1 {
+        out.write_u8(0)?;
+    }
+    Ok(())
+}
+
+/// The data of the first `fourcc` chunk of a RIFF WebP file.
+fn find_chunk<'a>(webp: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut rest = webp.get(12..)?;
+    while rest.len() >= 8 {
+        let size = u32::from_le_bytes(rest[4..8].try_into().ok()?) as usize;
+        let data = rest.get(8..8 + size)?;
+        if &rest[..4] == fourcc {
+            return Some(data);
+        }
+        rest = rest.get(8 + size + size % 2..)?;
+    }
+    None
+}
@@ -0,0 +1,102 @@
+use crate::walk;
+use crx::{ArchiveEntry, DatArchive, PckArchive};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+/// The input path that stands for stdin.
+pub const STDIN: &str = "-";
+
+/// Everything read from stdin, read on first use so that the input can be opened repeatedly.
+static STDIN_DATA: OnceLock<Result<Vec<u8>, String>> = OnceLock::new();
+
+/// Archive indices parsed so far, so that each archive is only parsed once per run.
+static INDICES: OnceLock<Mutex<HashMap<PathBuf, Arc<Vec<ArchiveEntry>>>>> = OnceLock::new();
+
+pub fn is_archive(path: &Path) -> bool {
+    (walk::has_extension(path, "pck") || walk::has_extension(path, "dat")) && path.is_file()
+}
+
+/// The archive containing `path`, if `path` is a virtual `archive.pck/entry` or
+/// `archive.dat/entry` path.
+pub fn containing_archive(path: &Path) -> Option<&Path> {
+    path.parent().filter(|parent| is_archive(parent))
+}
+
+fn index(archive: &Path) -> io::Result<Arc<Vec<ArchiveEntry>>> {
+    let indices = INDICES.get_or_init(Default::default);
+    if let Some(entries) = indices.lock().unwrap().get(archive) {
+        return Ok(entries.clone());
+    }
+    let reader = BufReader::new(fs::File::open(archive)?);
+    let entries = Arc::new(if walk::has_extension(archive, "dat") {
+        DatArchive::open(reader)?.entries().to_vec()
+    } else {
+        PckArchive::open(reader)?.entries().to_vec()
+    });
+    indices
+        .lock()
+        .unwrap()
+        .insert(archive.to_path_buf(), entries.clone());
+    Ok(entries)
+}
+
+/// List the CRX entries of `archive` as virtual `archive.pck/entry.crx` paths, or likewise for
+/// `.dat` archives.
+pub fn list_crx(archive: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(index(archive)?
+        .iter()
+        .map(|entry| archive.join(entry.name()))
+        .filter(|path| walk::has_extension(path, "crx"))
+        .collect())
+}
+
+/// Size of `path` in bytes, that of its archive entry if it is a virtual path.
+pub fn size(path: &Path) -> io::Result<u64> {
+    let Some(archive) = containing_archive(path) else {
+        return Ok(fs::metadata(path)?.len());
+    };
+    let name = path.file_name().unwrap_or_default();
+    index(archive)?
+        .iter()
+        .find(|entry| name == entry.name())
+        .map(ArchiveEntry::size)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such archive entry"))
+}
+
+/// Open `path` for reading, looking it up inside its archive if it is a virtual path, or
+/// reading stdin if it is [`STDIN`].
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    if path == Path::new(STDIN) {
+        let data = STDIN_DATA.get_or_init(|| {
+            let mut data = Vec::new();
+            io::stdin()
+                .lock()
+                .read_to_end(&mut data)
+                .map_err(|e| e.to_string())?;
+            Ok(data)
+        });
+        return match data {
+            Ok(data) => Ok(Box::new(Cursor::new(data.clone()))),
+            Err(e) => Err(io::Error::other(format!("stdin: {}", e))),
+        };
+    }
+    let Some(archive) = containing_archive(path) else {
+        return Ok(Box::new(BufReader::new(fs::File::open(path)?)));
+    };
+    let name = path.file_name().unwrap_or_default();
+    let entry = index(archive)?
+        .iter()
+        .find(|entry| name == entry.name())
+        .cloned()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such archive entry"))?;
+    let mut file = fs::File::open(archive)?;
+    file.seek(SeekFrom::Start(entry.offset()))?;
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    file.take(entry.size()).read_to_end(&mut data)?;
+    Ok(Box::new(Cursor::new(data)))
+}
@@ -0,0 +1,246 @@
+use crate::{console, escape, output, walk};
+use clap::Args;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+type Version = (u32, u32, u32);
+
+/// A decode bug that affected every output written by versions in `[introduced, fixed)`.
+struct KnownBug {
+    introduced: Version,
+    fixed: Version,
+    description: &'static str,
+}
+
+/// Decode bugs whose outputs should be re-converted. Add an entry whenever a release changes
+/// decoded pixels. Outputs stamped 0.2.0 come from builds before 0.3.0, the first release to
+/// stamp them; none of its changes alter written pixels, as the fix of zero-width images only
+/// changes files that failed to convert.
+const KNOWN_DECODE_BUGS: &[KnownBug] = &[];
+
+#[derive(Args)]
+pub struct AuditArg {
+    /// Previously converted PNG files, or directories to search for them
+    paths: Vec<PathBuf>,
+    /// Also list PNGs without a version stamp, e.g. written by versions that did not stamp their
+    /// outputs or by other tools; what they were converted from is not known
+    #[arg(long)]
+    include_unstamped: bool,
+}
+
+/// What the text chunks of a PNG say about how it was written.
+#[derive(Debug, Default)]
+struct Stamp {
+    software: Option<String>,
+    source: Option<PathBuf>,
+    options: Option<String>,
+    directory: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Affected(&'static str),
+    Unstamped,
+}
+
+pub fn run(arg: &AuditArg) -> io::Result<()> {
+    let files = walk::collect_files(&arg.paths, "png", &Default::default())?;
+    let mut affected = Vec::new();
+
+    for file in &files {
+        let stamp = match read_stamp(file) {
+            Ok(stamp) => stamp,
+            Err(e) => {
+                console::file_line(console::Status::Failed, file, format_args!("read: {}", e));
+                continue;
+            }
+        };
+        match verdict(&stamp, KNOWN_DECODE_BUGS, arg.include_unstamped) {
+            Some(Verdict::Affected(description)) => {
+                console::file_line(console::Status::Affected, file, description);
+                affected.push(stamp);
+            }
+            Some(Verdict::Unstamped) => {
+                console::file_line(console::Status::Unknown, file, "no version stamp")
+            }
+            None => {}
+        }
+    }
+
+    if affected.is_empty() {
+        console::line(
+            console::Status::Info("Clean"),
+            format_args!("checked {} file(s)", files.len()),
+        );
+    }
+    for command in rerun_commands(&affected) {
+        console::line(console::Status::Note("Re-run"), command);
+    }
+    Ok(())
+}
+
+/// Whether the PNG stamped with `stamp` is affected by one of `bugs`, or unstamped and listed
+/// with `include_unstamped`. Files written by other tools are not.
+fn verdict(stamp: &Stamp, bugs: &[KnownBug], include_unstamped: bool) -> Option<Verdict> {
+    let Some(software) = &stamp.software else {
+        return include_unstamped.then_some(Verdict::Unstamped);
+    };
+    let version = parse_version(software)?;
+    bugs.iter()
+        .find(|bug| bug.introduced <= version && version < bug.fixed)
+        .map(|bug| Verdict::Affected(bug.description))
+}
+
+/// The commands that convert the sources of `stamps` again, one per working directory and set
+/// of options, e.g. `cd /game && crx-convert --output-dir out cg/ev01.crx`. Sources converted
+/// without options recorded, by older versions, are converted with the default ones.
+fn rerun_commands(stamps: &[Stamp]) -> Vec<String> {
+    let mut groups: BTreeMap<(Option<&Path>, &str), Vec<String>> = BTreeMap::new();
+    for stamp in stamps {
+        // outputs of stdin and of content-addressed layouts name no source.
+        let Some(source) = &stamp.source else {
+            continue;
+        };
+        let key = (
+            stamp.directory.as_deref(),
+            stamp.options.as_deref().unwrap_or_default(),
+        );
+        groups
+            .entry(key)
+            .or_default()
+            .push(escape::shell(&source.to_string_lossy()));
+    }
+    groups
+        .into_iter()
+        .map(|((directory, options), sources)| {
+            let mut command = String::new();
+            if let Some(directory) = directory {
+                command += &format!("cd {} && ", escape::shell(&directory.to_string_lossy()));
+            }
+            command += env!("CARGO_PKG_NAME");
+            if !options.is_empty() {
+                command += &format!(" {}", options);
+            }
+            command + " " + &sources.join(" ")
+        })
+        .collect()
+}
+
+/// Read the stamps of a PNG file.
+fn read_stamp(path: &Path) -> io::Result<Stamp> {
+    let decoder = png::Decoder::new(BufReader::new(fs::File::open(path)?));
+    let reader = decoder.read_info()?;
+    let info = reader.info();
+    let latin1 = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.as_str(), Some(chunk.text.clone())));
+    let utf8 = info
+        .utf8_text
+        .iter()
+        .map(|chunk| (chunk.keyword.as_str(), chunk.get_text().ok()));
+    let mut stamp = Stamp::default();
+    for (keyword, text) in latin1.chain(utf8) {
+        match keyword {
+            output::SOFTWARE_KEY => stamp.software = text,
+            output::SOURCE_KEY => stamp.source = text.map(PathBuf::from),
+            output::OPTIONS_KEY => stamp.options = text,
+            output::DIRECTORY_KEY => stamp.directory = text.map(PathBuf::from),
+            _ => {}
+        }
+    }
+    Ok(stamp)
+}
+
+/// Parse `crx-convert X.Y.Z` into a version tuple.
+fn parse_version(software: &str) -> Option<Version> {
+    let version = software.strip_prefix(concat!(env!("CARGO_PKG_NAME"), " "))?;
+    let mut parts = version.splitn(3, '.').map(|p| p.parse::<u32>().ok());
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUGS: &[KnownBug] = &[KnownBug {
+        introduced: (0, 2, 0),
+        fixed: (0, 3, 1),
+        description: "swapped channels",
+    }];
+
+    fn stamped(software: &str) -> Stamp {
+        Stamp {
+            software: Some(software.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn versions_are_parsed_from_our_stamp_only() {
+        assert_eq!(parse_version("crx-convert 0.2.0"), Some((0, 2, 0)));
+        assert_eq!(parse_version("crx-convert 1.10.3"), Some((1, 10, 3)));
+        assert_eq!(parse_version("crx-convert 0.2"), None);
+        assert_eq!(parse_version("crx-convert 0.2.x"), None);
+        assert_eq!(parse_version("GIMP 2.10.34"), None);
+        assert_eq!(parse_version("crx-convert0.2.0"), None);
+    }
+
+    #[test]
+    fn versions_within_a_bug_are_affected() {
+        for (software, affected) in [
+            ("crx-convert 0.1.9", false),
+            ("crx-convert 0.2.0", true),
+            ("crx-convert 0.3.0", true),
+            ("crx-convert 0.3.1", false),
+            ("Adobe Photoshop 25.0", false),
+        ] {
+            let expected = affected.then_some(Verdict::Affected("swapped channels"));
+            assert_eq!(
+                verdict(&stamped(software), BUGS, true),
+                expected,
+                "{}",
+                software
+            );
+        }
+    }
+
+    #[test]
+    fn unstamped_files_are_only_listed_on_request() {
+        let unstamped = Stamp::default();
+        assert_eq!(verdict(&unstamped, BUGS, false), None);
+        assert_eq!(verdict(&unstamped, BUGS, true), Some(Verdict::Unstamped));
+    }
+
+    #[test]
+    fn commands_are_grouped_by_directory_and_options() {
+        let stamp = |source: &str, options: Option<&str>, directory: Option<&str>| Stamp {
+            software: Some("crx-convert 0.2.0".to_string()),
+            source: Some(PathBuf::from(source)),
+            options: options.map(str::to_string),
+            directory: directory.map(PathBuf::from),
+        };
+        let commands = rerun_commands(&[
+            stamp("cg/ev01.crx", Some("--output-dir out"), Some("/game")),
+            stamp("old.crx", None, None),
+            stamp("cg/it's.crx", Some("--output-dir out"), Some("/game")),
+            stamp("sys/a.crx", Some("--format webp"), Some("/game")),
+            Stamp {
+                source: None,
+                ..stamped("crx-convert 0.2.0")
+            },
+        ]);
+        assert_eq!(
+            commands,
+            [
+                "crx-convert old.crx",
+                "cd /game && crx-convert --format webp sys/a.crx",
+                "cd /game && crx-convert --output-dir out cg/ev01.crx 'cg/it'\\''s.crx'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{console, escape, input, walk};
+use clap::Args;
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct AutoPairArg {
+    /// CRX files, or directories to search for them
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Smallest share of the visible pixels of a diff that must match its base, from 0 to 1
+    #[arg(long, default_value_t = 0.5, value_parser = parse_share)]
+    min_similarity: f64,
+    /// Largest per-channel difference that is still considered equal
+    #[arg(long, default_value_t = 8)]
+    tolerance: u8,
+    /// Write the JSON to this file instead of stdout
+    #[arg(long, short, value_name = "JSON")]
+    output: Option<PathBuf>,
+}
+
+struct Image {
+    path: PathBuf,
+    pixels: RgbaImage,
+    offset: (i32, i32),
+}
+
+impl Image {
+    fn area(&self) -> u64 {
+        self.pixels.width() as u64 * self.pixels.height() as u64
+    }
+
+    /// Where `self` lies in `base`, if it lies entirely inside of it.
+    fn position_in(&self, base: &Image) -> Option<(u32, u32)> {
+        let x = u32::try_from(self.offset.0 - base.offset.0).ok()?;
+        let y = u32::try_from(self.offset.1 - base.offset.1).ok()?;
+        let fits = x as u64 + self.pixels.width() as u64 <= base.pixels.width() as u64
+            && y as u64 + self.pixels.height() as u64 <= base.pixels.height() as u64;
+        fits.then_some((x, y))
+    }
+}
+
+/// A diff and the base it was paired with, by index into the decoded images.
+struct Pair {
+    diff: usize,
+    base: usize,
+    similarity: f64,
+}
+
+/// Find the base CG every diff of `paths` was cut from and write them as a manifest of bases
+/// with their diffs, each group being what `diff-rect` or `convert --base` take.
+///
+/// Images are taken largest first: an image is a diff if it lies inside a larger base by their
+/// inner offsets, and enough of its visible pixels match the base under them; it is paired with
+/// the base it matches best. Every other image is a base.
+pub fn run(arg: &AutoPairArg) -> io::Result<()> {
+    if arg.output.is_none() {
+        console::set_stderr(true);
+    }
+    let files = walk::collect_files(&arg.paths, "crx", &Default::default())?;
+    let mut images = files
+        .into_par_iter()
+        .map(|path| {
+            let (image, offset) = input::load_positioned(&path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            Ok(Image {
+                path,
+                pixels: image.into_rgba8(),
+                offset,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    // stable, so that equal sizes keep the order of the walk.
+    images.sort_by_key(|image| std::cmp::Reverse(image.area()));
+
+    let mut bases: Vec<usize> = Vec::new();
+    let mut pairs: Vec<Pair> = Vec::new();
+    for (index, image) in images.iter().enumerate() {
+        let best = bases
+            .par_iter()
+            .filter(|&&base| images[base].area() > image.area())
+            .filter_map(|&base| {
+                let position = image.position_in(&images[base])?;
+                let similarity = similarity(image, &images[base], position, arg.tolerance);
+                Some((base, similarity))
+            })
+            .filter(|&(_, similarity)| similarity >= arg.min_similarity)
+            // the first base wins ties, for stable output.
+            .reduce_with(|a, b| if b.1 > a.1 { b } else { a });
+        match best {
+            Some((base, similarity)) => pairs.push(Pair {
+                diff: index,
+                base,
+                similarity,
+            }),
+            None => bases.push(index),
+        }
+    }
+
+    let paired_bases = bases
+        .iter()
+        .filter(|&&base| pairs.iter().any(|pair| pair.base == base))
+        .count();
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!(
+            "{} diff(s) paired with {} base(s), {} image(s) without diffs",
+            pairs.len(),
+            paired_bases,
+            bases.len() - paired_bases
+        ),
+    );
+    let json = to_json(arg, &images, &bases, &pairs);
+    match &arg.output {
+        Some(path) => fs::write(path, json),
+        None => io::stdout().write_all(json.as_bytes()),
+    }
+}
+
+/// The share of the visible pixels of `diff` that match the `base` pixels under them, with
+/// `diff` placed at `position` in `base`; 0 for a fully transparent diff.
+fn similarity(diff: &Image, base: &Image, position: (u32, u32), tolerance: u8) -> f64 {
+    let (mut visible, mut matching) = (0u64, 0u64);
+    for (x, y, pixel) in diff.pixels.enumerate_pixels() {
+        if pixel.0[3] == 0 {
+            continue;
+        }
+        visible += 1;
+        let under = base.pixels.get_pixel(position.0 + x, position.1 + y);
+        matching += pixel
+            .0
+            .iter()
+            .zip(under.0.iter())
+            .all(|(&a, &b)| a.abs_diff(b) <= tolerance) as u64;
+    }
+    if visible == 0 {
+        return 0.0;
+    }
+    matching as f64 / visible as f64
+}
+
+fn parse_share(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(share) if (0.0..=1.0).contains(&share) => Ok(share),
+        Ok(_) => Err(format!("`{}` is not between 0 and 1", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn path_json(path: &Path) -> String {
+    escape::json(&path.to_string_lossy())
+}
+
+fn to_json(arg: &AutoPairArg, images: &[Image], bases: &[usize], pairs: &[Pair]) -> String {
+    let groups: Vec<String> = bases
+        .iter()
+        .map(|&base| {
+            let diffs: Vec<String> = pairs
+                .iter()
+                .filter(|pair| pair.base == base)
+                .map(|pair| {
+                    let diff = &images[pair.diff];
+                    format!(
+                        "{{\"path\":{},\"width\":{},\"height\":{},\"inner_x\":{},\"inner_y\":{},\"similarity\":{:.4}}}",
+                        path_json(&diff.path),
+                        diff.pixels.width(),
+                        diff.pixels.height(),
+                        diff.offset.0,
+                        diff.offset.1,
+                        pair.similarity
+                    )
+                })
+                .collect();
+            let base = &images[base];
+            format!(
+                "{{\"path\":{},\"width\":{},\"height\":{},\"inner_x\":{},\"inner_y\":{},\"diffs\":[{}]}}",
+                path_json(&base.path),
+                base.pixels.width(),
+                base.pixels.height(),
+                base.offset.0,
+                base.offset.1,
+                diffs.join(",")
+            )
+        })
+        .collect();
+    format!(
+        "{{\"min_similarity\":{},\"tolerance\":{},\"bases\":[{}]}}\n",
+        arg.min_similarity,
+        arg.tolerance,
+        groups.join(",")
+    )
+}
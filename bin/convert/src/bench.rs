@@ -0,0 +1,83 @@
+use crate::{archive, console, walk};
+use clap::Args;
+use crx::CrxFile;
+use rayon::prelude::*;
+use std::{
+    io::{self, Cursor, Read},
+    path::PathBuf,
+    thread,
+    time::Instant,
+};
+
+#[derive(Args)]
+pub struct BenchArg {
+    /// CRX files, or directories to search for them
+    paths: Vec<PathBuf>,
+    /// Thread counts to measure; powers of two up to the number of CPUs by default
+    #[arg(long, value_delimiter = ',')]
+    threads: Vec<usize>,
+    /// How many times every file is decoded per thread count
+    #[arg(long, default_value_t = 1)]
+    rounds: usize,
+}
+
+/// Decode every file with each thread count and report the throughput. Files are read into
+/// memory up front and decoded images are dropped, so neither reading nor writing is measured.
+pub fn run(arg: &BenchArg) -> io::Result<()> {
+    let mut inputs = Vec::new();
+    for path in walk::collect_files(&arg.paths, "crx", &Default::default())? {
+        let mut data = Vec::new();
+        archive::open(&path)?.read_to_end(&mut data)?;
+        inputs.push((path, data));
+    }
+    let bytes: usize = inputs.iter().map(|(_, data)| data.len()).sum();
+    console::line(
+        console::Status::Info("Loaded"),
+        format_args!("{} file(s), {:.1} MB", inputs.len(), bytes as f64 / 1e6),
+    );
+    if inputs.is_empty() {
+        return Ok(());
+    }
+
+    let threads = if arg.threads.is_empty() {
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        let mut threads: Vec<usize> = (0..).map(|i| 1 << i).take_while(|&n| n < cpus).collect();
+        threads.push(cpus);
+        threads
+    } else {
+        arg.threads.clone()
+    };
+
+    for &count in &threads {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(count)
+            .build()
+            .map_err(io::Error::other)?;
+        let start = Instant::now();
+        let failed = pool.install(|| {
+            (0..arg.rounds)
+                .flat_map(|_| &inputs)
+                .par_bridge()
+                .filter(|(_, data)| CrxFile::read_indexed(Cursor::new(data)).is_err())
+                .count()
+        });
+        let seconds = start.elapsed().as_secs_f64();
+        let images = (inputs.len() * arg.rounds) as f64;
+        console::line(
+            console::Status::Note("Bench"),
+            format_args!(
+                "{:>3} thread(s): {:>8.1} MB/s {:>8.1} images/s",
+                count,
+                (bytes * arg.rounds) as f64 / 1e6 / seconds,
+                images / seconds
+            ),
+        );
+        if failed > 0 {
+            console::line(
+                console::Status::Warning,
+                format_args!("{} decode(s) failed", failed),
+            );
+        }
+    }
+    Ok(())
+}
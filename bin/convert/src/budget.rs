@@ -0,0 +1,36 @@
+/// How many failed files a run tolerates before it is aborted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureBudget {
+    /// A number of files.
+    Count(usize),
+    /// A share of the inputs, in percent.
+    Percent(f64),
+}
+
+impl FailureBudget {
+    /// The largest number of failures tolerated in a run of `total` files.
+    pub fn limit(self, total: usize) -> usize {
+        match self {
+            FailureBudget::Count(count) => count,
+            FailureBudget::Percent(percent) => (total as f64 * percent / 100.0).floor() as usize,
+        }
+    }
+}
+
+/// Parse `N` or `N%`.
+pub fn parse_failure_budget(s: &str) -> Result<FailureBudget, String> {
+    match s.trim().strip_suffix('%') {
+        Some(percent) => {
+            let percent = percent.trim().parse::<f64>().map_err(|e| e.to_string())?;
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("`{}` is not between 0% and 100%", s));
+            }
+            Ok(FailureBudget::Percent(percent))
+        }
+        None => s
+            .trim()
+            .parse::<usize>()
+            .map(FailureBudget::Count)
+            .map_err(|e| e.to_string()),
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{archive, console, escape, hash, walk};
+use crx::CrxFile;
+use rayon::prelude::*;
+use std::{
+    io::{self, Read, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// What is kept of a decoded file, rather than its pixels.
+struct Decoded {
+    width: u16,
+    height: u16,
+    version: u16,
+    bpp: usize,
+    pixels_sha256: String,
+}
+
+struct Checked {
+    path: PathBuf,
+    result: io::Result<Decoded>,
+    duration: Duration,
+}
+
+/// Decode every file of `paths` without writing anything, reporting each one as passed or failed.
+/// Fails if any file, or any directory entry, could not be read or decoded, so that the exit
+/// status tells a CI job whether the corpus still decodes.
+pub fn run(
+    paths: &[PathBuf],
+    options: &walk::WalkOptions,
+    sniff: bool,
+    json: bool,
+) -> io::Result<()> {
+    if json {
+        console::set_stderr(true);
+    }
+    let (files, walk_errors) = walk::collect_crx_lenient(paths, options, sniff);
+    for error in &walk_errors {
+        console::line(console::Status::Failed, format_args!("walk: {}", error));
+    }
+    let checked: Vec<_> = files
+        .into_par_iter()
+        .map(|path| {
+            let start = Instant::now();
+            let result = archive::open(&path).and_then(|mut f| {
+                let mut data = Vec::new();
+                f.read_to_end(&mut data)?;
+                let crx = CrxFile::read_indexed(&data[..])?;
+                Ok(Decoded {
+                    width: crx.width(),
+                    height: crx.height(),
+                    version: crx.version().number(),
+                    bpp: crx.bpp(),
+                    pixels_sha256: hash::sha256_hex(crx.raw_buffer()),
+                })
+            });
+            Checked {
+                path,
+                result,
+                duration: start.elapsed(),
+            }
+        })
+        .collect();
+
+    let mut failed = 0;
+    for file in &checked {
+        failed += file.result.is_err() as usize;
+        if json {
+            writeln!(io::stdout().lock(), "{}", to_json(file))?;
+            continue;
+        }
+        match &file.result {
+            Ok(crx) => console::file_line(
+                console::Status::Success,
+                &file.path,
+                format_args!(
+                    "{}x{} v{} {}bpp in {:.1?}",
+                    crx.width, crx.height, crx.version, crx.bpp, file.duration
+                ),
+            ),
+            Err(e) => console::file_line(
+                console::Status::Failed,
+                &file.path,
+                format_args!("decode: {}", e),
+            ),
+        }
+    }
+    if !json {
+        console::line(
+            console::Status::Info("Summary"),
+            format_args!(
+                "{} passed, {} failed, {} unreadable path(s)",
+                checked.len() - failed,
+                failed,
+                walk_errors.len()
+            ),
+        );
+    }
+    if failed > 0 || !walk_errors.is_empty() {
+        return Err(io::Error::other(format!(
+            "check failed: {} file(s) did not decode, {} path(s) were unreadable",
+            failed,
+            walk_errors.len()
+        )));
+    }
+    Ok(())
+}
+
+/// One line of `--check --json`; passed files carry the SHA-256 of their decoded pixels, so that
+/// runs can be diffed for decoder changes.
+fn to_json(file: &Checked) -> String {
+    let path = escape::json(&file.path.to_string_lossy());
+    let duration_ms = file.duration.as_secs_f64() * 1000.0;
+    match &file.result {
+        Ok(crx) => format!(
+            "{{\"path\":{},\"passed\":true,\"width\":{},\"height\":{},\"version\":{},\"bpp\":{},\"pixels_sha256\":\"{}\",\"duration_ms\":{:.3}}}",
+            path,
+            crx.width,
+            crx.height,
+            crx.version,
+            crx.bpp,
+            crx.pixels_sha256,
+            duration_ms
+        ),
+        Err(e) => format!(
+            "{{\"path\":{},\"passed\":false,\"error\":{},\"duration_ms\":{:.3}}}",
+            path,
+            escape::json(&e.to_string()),
+            duration_ms
+        ),
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{console, input};
+use clap::Args;
+use std::{io, path::PathBuf};
+
+#[derive(Args)]
+pub struct CompareArg {
+    /// The original conversion (PNG or CRX)
+    original: PathBuf,
+    /// The re-edited asset (PNG or CRX)
+    edited: PathBuf,
+    /// Largest per-channel difference that is still considered equal
+    #[arg(long, default_value_t = 0)]
+    tolerance: u8,
+}
+
+/// A changed region, as the bounding box of a group of connected changed pixels.
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: usize,
+}
+
+pub fn run(arg: &CompareArg) -> io::Result<()> {
+    let original = input::load_image(&arg.original)?.into_rgba8();
+    let edited = input::load_image(&arg.edited)?.into_rgba8();
+    if original.dimensions() != edited.dimensions() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "dimension mismatch: {}x{} vs {}x{}",
+                original.width(),
+                original.height(),
+                edited.width(),
+                edited.height()
+            ),
+        ));
+    }
+
+    let (width, height) = original.dimensions();
+    let changed: Vec<bool> = original
+        .pixels()
+        .zip(edited.pixels())
+        .map(|(a, b)| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .any(|(&a, &b)| a.abs_diff(b) > arg.tolerance)
+        })
+        .collect();
+
+    let regions = find_regions(&changed, width, height);
+    for region in &regions {
+        console::line(
+            console::Status::Changed,
+            format_args!(
+                "{}x{} at ({}, {}), {} pixel(s)",
+                region.width, region.height, region.x, region.y, region.pixels
+            ),
+        );
+    }
+    let total: usize = regions.iter().map(|r| r.pixels).sum();
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!(
+            "{} region(s), {} of {} pixel(s) differ by more than {}",
+            regions.len(),
+            total,
+            changed.len(),
+            arg.tolerance
+        ),
+    );
+    Ok(())
+}
+
+/// Group changed pixels into 8-connected regions.
+fn find_regions(changed: &[bool], width: u32, height: u32) -> Vec<Region> {
+    let (w, h) = (width as usize, height as usize);
+    let mut visited = vec![false; changed.len()];
+    let mut regions = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..changed.len() {
+        if !changed[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let (mut x0, mut y0, mut x1, mut y1) = (w, h, 0, 0);
+        let mut pixels = 0;
+        while let Some(pix) = stack.pop() {
+            let (x, y) = (pix % w, pix / w);
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+            pixels += 1;
+            for ny in y.saturating_sub(1)..=(y + 1).min(h - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(w - 1) {
+                    let n = ny * w + nx;
+                    if changed[n] && !visited[n] {
+                        visited[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+        regions.push(Region {
+            x: x0 as u32,
+            y: y0 as u32,
+            width: (x1 - x0 + 1) as u32,
+            height: (y1 - y0 + 1) as u32,
+            pixels,
+        });
+    }
+    regions
+}
@@ -0,0 +1,154 @@
+use crate::{archive, console, hash, walk};
+use clap::Args;
+use crx::CrxFile;
+use rayon::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct CompareSetsArg {
+    /// CRX files of the older version of the game
+    old: PathBuf,
+    /// CRX files of the newer version of the game
+    new: PathBuf,
+    /// Also list files that did not change
+    #[arg(long)]
+    all: bool,
+}
+
+/// One CRX file of a set.
+struct Asset {
+    path: PathBuf,
+    sha256: String,
+    /// Width, height and bpp, `None` if the header cannot be read.
+    shape: Option<(u16, u16, usize)>,
+}
+
+pub fn run(arg: &CompareSetsArg) -> io::Result<()> {
+    let old = scan(&arg.old)?;
+    let mut new = scan(&arg.new)?;
+
+    // files missing on one side may have moved; pair them up by content.
+    let mut added_by_hash: HashMap<String, String> = HashMap::new();
+    for (key, asset) in &new {
+        if !old.contains_key(key) {
+            added_by_hash.insert(asset.sha256.clone(), key.clone());
+        }
+    }
+
+    let (mut changed, mut removed, mut moved, mut unchanged) = (0, 0, 0, 0);
+    for (key, before) in &old {
+        let Some(after) = new.remove(key) else {
+            match added_by_hash.remove(&before.sha256) {
+                Some(new_key) => {
+                    let after = new
+                        .remove(&new_key)
+                        .expect("added files are in the new set");
+                    moved += 1;
+                    console::file_line(
+                        console::Status::Note("Moved"),
+                        &before.path,
+                        format_args!("-> {}", after.path.to_string_lossy()),
+                    );
+                }
+                None => {
+                    removed += 1;
+                    console::file_line(
+                        console::Status::Note("Removed"),
+                        &before.path,
+                        shape(before.shape),
+                    );
+                }
+            }
+            continue;
+        };
+        if before.sha256 == after.sha256 {
+            unchanged += 1;
+            if arg.all {
+                console::file_line(console::Status::Note("Same"), &after.path, "");
+            }
+        } else {
+            changed += 1;
+            console::file_line(
+                console::Status::Changed,
+                &after.path,
+                format_args!(
+                    "{} -> {}{}",
+                    shape(before.shape),
+                    shape(after.shape),
+                    delta(before.shape, after.shape)
+                ),
+            );
+        }
+    }
+    for after in new.values() {
+        console::file_line(
+            console::Status::Note("Added"),
+            &after.path,
+            shape(after.shape),
+        );
+    }
+
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!(
+            "{} added, {} removed, {} moved, {} changed, {} unchanged",
+            new.len(),
+            removed,
+            moved,
+            changed,
+            unchanged
+        ),
+    );
+    Ok(())
+}
+
+/// The CRX files below `dir`, by lowercase path relative to it, as the engine looks names up
+/// without regard to case.
+fn scan(dir: &Path) -> io::Result<BTreeMap<String, Asset>> {
+    let files = walk::collect_files(&[dir.to_path_buf()], "crx", &Default::default())?;
+    files
+        .par_iter()
+        .map(|path| {
+            let sha256 = hash::sha256_hex_reader(archive::open(path)?)?;
+            let shape = archive::open(path)
+                .ok()
+                .and_then(|f| CrxFile::read_metadata(f).ok())
+                .map(|meta| (meta.width(), meta.height(), meta.bpp()));
+            let key = path
+                .strip_prefix(dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/")
+                .to_lowercase();
+            Ok((
+                key,
+                Asset {
+                    path: path.clone(),
+                    sha256,
+                    shape,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn shape(shape: Option<(u16, u16, usize)>) -> String {
+    match shape {
+        Some((width, height, bpp)) => format!("{}x{} {}bpp", width, height, bpp),
+        None => "unreadable header".to_string(),
+    }
+}
+
+/// The size difference, e.g. ` (+640x+360)`, if the size changed.
+fn delta(before: Option<(u16, u16, usize)>, after: Option<(u16, u16, usize)>) -> String {
+    match (before, after) {
+        (Some((w0, h0, _)), Some((w1, h1, _))) if (w0, h0) != (w1, h1) => {
+            format!(" ({:+}x{:+})", w1 as i32 - w0 as i32, h1 as i32 - h0 as i32)
+        }
+        _ => String::new(),
+    }
+}
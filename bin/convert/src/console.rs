@@ -0,0 +1,218 @@
+use crate::escape;
+use owo_colors::OwoColorize;
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Width of the right-aligned status column.
+const STATUS_WIDTH: usize = 8;
+/// Width of the path column; longer paths are shortened in the middle.
+const PATH_WIDTH: usize = 56;
+
+/// Whether only failures, warnings and summaries are printed.
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Whether lines go to stderr, leaving stdout to image data.
+static STDERR: AtomicBool = AtomicBool::new(false);
+/// Whether lines are printed as JSON objects, see [`LogFormat::Json`].
+static JSON: AtomicBool = AtomicBool::new(false);
+/// Progress bars drawn below the printed lines, if any.
+static BARS: Mutex<Option<indicatif::MultiProgress>> = Mutex::new(None);
+
+/// Print only failures, warnings and summaries from now on.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Print to stderr from now on, so that stdout only carries what is written to it on purpose.
+pub fn set_stderr(stderr: bool) {
+    STDERR.store(stderr, Ordering::Relaxed);
+}
+
+/// Print lines in `format` from now on.
+pub fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+/// Keep `bars` below the printed lines until it is replaced or `None` is set.
+pub fn set_bars(bars: Option<indicatif::MultiProgress>) {
+    *BARS.lock().unwrap() = bars;
+}
+
+/// How lines are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Colored lines with aligned columns
+    #[default]
+    Human,
+    /// One JSON object per line, e.g. `{"status":"success","path":"a.crx","message":"-> a.png"}`,
+    /// with the fields of the result for converted files
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Failed,
+    Skipped,
+    Conflict,
+    Affected,
+    Unknown,
+    Changed,
+    Warning,
+    /// A summary line.
+    Info(&'static str),
+    /// A detail line.
+    Note(&'static str),
+}
+
+impl Status {
+    /// Whether the line is printed with `--quiet`.
+    fn is_important(self) -> bool {
+        matches!(
+            self,
+            Status::Failed | Status::Conflict | Status::Warning | Status::Info(_)
+        )
+    }
+
+    /// The label right-aligned in the status column, colored.
+    fn label(self) -> String {
+        format!("{:>width$}", self.text(), width = STATUS_WIDTH)
+            .color(self.color())
+            .bold()
+            .to_string()
+    }
+
+    fn text(self) -> &'static str {
+        self.styled().0
+    }
+
+    fn color(self) -> owo_colors::AnsiColors {
+        self.styled().1
+    }
+
+    fn styled(self) -> (&'static str, owo_colors::AnsiColors) {
+        match self {
+            Status::Success => ("Success", owo_colors::AnsiColors::Green),
+            Status::Failed => ("Failed", owo_colors::AnsiColors::Red),
+            Status::Skipped => ("Skipped", owo_colors::AnsiColors::Yellow),
+            Status::Conflict => ("Conflict", owo_colors::AnsiColors::Red),
+            Status::Affected => ("Affected", owo_colors::AnsiColors::Yellow),
+            Status::Unknown => ("Unknown", owo_colors::AnsiColors::Yellow),
+            Status::Changed => ("Changed", owo_colors::AnsiColors::Yellow),
+            Status::Warning => ("Warning", owo_colors::AnsiColors::Yellow),
+            Status::Info(text) => (text, owo_colors::AnsiColors::Green),
+            Status::Note(text) => (text, owo_colors::AnsiColors::Cyan),
+        }
+    }
+}
+
+/// Print a line with `status` and free-form `message`.
+pub fn line(status: Status, message: impl Display) {
+    if JSON.load(Ordering::Relaxed) {
+        return print(status, json_line(status, None, message, &[]));
+    }
+    print(status, format_args!("{} {}", status.label(), message));
+}
+
+/// Print a line about `path`, with the path padded or shortened to a fixed column so that the
+/// `detail` of consecutive lines lines up.
+pub fn file_line(status: Status, path: &Path, detail: impl Display) {
+    file_fields(status, path, detail, &[]);
+}
+
+/// Like [`file_line`], adding `fields`, as names and JSON values, to the line in
+/// [`LogFormat::Json`]; they are left out of human-readable lines.
+pub fn file_fields(status: Status, path: &Path, detail: impl Display, fields: &[(&str, String)]) {
+    if JSON.load(Ordering::Relaxed) {
+        return print(status, json_line(status, Some(path), detail, fields));
+    }
+    print(
+        status,
+        format_args!(
+            "{} {} {}",
+            status.label(),
+            fit(&path.to_string_lossy(), PATH_WIDTH),
+            detail
+        ),
+    );
+}
+
+/// A line as a JSON object, its status in lower case.
+fn json_line(
+    status: Status,
+    path: Option<&Path>,
+    message: impl Display,
+    fields: &[(&str, String)],
+) -> String {
+    let mut line = format!(
+        "{{\"status\":{}",
+        escape::json(&status.text().to_lowercase())
+    );
+    if let Some(path) = path {
+        line += &format!(",\"path\":{}", escape::json(&path.to_string_lossy()));
+    }
+    line += &format!(",\"message\":{}", escape::json(&message.to_string()));
+    for (name, value) in fields {
+        line += &format!(",{}:{}", escape::json(name), value);
+    }
+    line + "}"
+}
+
+fn print(status: Status, text: impl Display) {
+    if QUIET.load(Ordering::Relaxed) && !status.is_important() {
+        return;
+    }
+    let emit = || match STDERR.load(Ordering::Relaxed) {
+        true => eprintln!("{}", text),
+        false => println!("{}", text),
+    };
+    match &*BARS.lock().unwrap() {
+        // the bars are cleared for the line and drawn again below it.
+        Some(bars) => bars.suspend(emit),
+        None => emit(),
+    }
+}
+
+/// Pad `s` with spaces to `width` terminal columns, or shorten it to `width` columns by
+/// replacing its middle with an ellipsis. Wide (e.g. Japanese) characters count as two columns.
+pub fn fit(s: &str, width: usize) -> String {
+    let current = s.width();
+    if current <= width {
+        return format!("{}{}", s, " ".repeat(width - current));
+    }
+
+    // keep more of the end, where the file name is.
+    let budget = width.saturating_sub(1);
+    let tail_budget = budget - budget / 3;
+    let head_budget = budget - tail_budget;
+    let mut head = String::new();
+    let mut head_width = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if head_width + w > head_budget {
+            break;
+        }
+        head.push(c);
+        head_width += w;
+    }
+    let mut tail = Vec::new();
+    let mut tail_width = 0;
+    for c in s.chars().rev() {
+        let w = c.width().unwrap_or(0);
+        if tail_width + w > tail_budget {
+            break;
+        }
+        tail.push(c);
+        tail_width += w;
+    }
+    let tail: String = tail.into_iter().rev().collect();
+    // a wide character that did not fit leaves a column free.
+    let padding = width - (head_width + 1 + tail_width);
+    format!("{}…{}{}", head, tail, " ".repeat(padding))
+}
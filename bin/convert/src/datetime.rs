@@ -0,0 +1,95 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A point in time in UTC, broken down into calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utc {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+}
+
+impl From<SystemTime> for Utc {
+    /// Times before 1970 are taken as 1970-01-01.
+    fn from(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let secs_of_day = (secs % 86400) as u32;
+        Self {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+            millisecond: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// Days since 1970-01-01 to a (year, month, day) date, after Howard Hinnant's
+/// `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn utc(secs: u64, millis: u64) -> Utc {
+        Utc::from(UNIX_EPOCH + Duration::from_millis(secs * 1000 + millis))
+    }
+
+    #[test]
+    fn epoch() {
+        assert_eq!(
+            utc(0, 0),
+            Utc {
+                year: 1970,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 0,
+                millisecond: 0
+            }
+        );
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!((utc(951782400, 0).month, utc(951782400, 0).day), (2, 29)); // 2000-02-29
+        assert_eq!((utc(4107542400, 0).month, utc(4107542400, 0).day), (3, 1)); // 2100-03-01
+    }
+
+    #[test]
+    fn time_of_day() {
+        assert_eq!(
+            utc(1710003931, 42),
+            Utc {
+                year: 2024,
+                month: 3,
+                day: 9,
+                hour: 17,
+                minute: 5,
+                second: 31,
+                millisecond: 42
+            }
+        );
+    }
+}
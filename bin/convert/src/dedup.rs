@@ -0,0 +1,152 @@
+use crate::{console, escape, sink};
+use clap::ValueEnum;
+use crx::CrxFile;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// What `--dedup` does with an image whose pixels match one converted earlier in the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DedupMode {
+    /// Write nothing for it
+    Skip,
+    /// Make its output a hard link to the first output
+    Hardlink,
+    /// Make its output a symbolic link to the first output
+    Symlink,
+}
+
+/// An input whose pixels match those of an earlier one, a copy.
+struct Duplicate {
+    source: PathBuf,
+    output: PathBuf,
+    /// Index into [`Dedup::firsts`].
+    first: usize,
+}
+
+/// Tells apart the images of a run by their decoded pixels.
+pub struct Dedup {
+    mode: DedupMode,
+    /// Index into `firsts` by pixel hash.
+    seen: Mutex<HashMap<[u8; 32], usize>>,
+    /// (source, output) of the first input of every distinct image.
+    firsts: Mutex<Vec<(PathBuf, PathBuf)>>,
+    duplicates: Mutex<Vec<Duplicate>>,
+}
+
+impl Dedup {
+    pub fn new(mode: DedupMode) -> Self {
+        Self {
+            mode,
+            seen: Mutex::new(HashMap::new()),
+            firsts: Mutex::new(Vec::new()),
+            duplicates: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Claim the pixels of `image`, converted from `source` to `output`. Returns the output of
+    /// the input that claimed the same pixels first, in which case `output` must not be written.
+    pub fn claim(&self, source: &Path, output: &Path, image: &CrxFile) -> Option<PathBuf> {
+        let hash = pixel_hash(image);
+        let mut seen = self.seen.lock().unwrap();
+        let mut firsts = self.firsts.lock().unwrap();
+        if let Some(&first) = seen.get(&hash) {
+            self.duplicates.lock().unwrap().push(Duplicate {
+                source: source.to_path_buf(),
+                output: output.to_path_buf(),
+                first,
+            });
+            return Some(firsts[first].1.clone());
+        }
+        seen.insert(hash, firsts.len());
+        firsts.push((source.to_path_buf(), output.to_path_buf()));
+        None
+    }
+
+    /// Once every first output is stored, link the copies to them if asked to, and list every
+    /// group of inputs with identical pixels. Returns the number of links that could not be made.
+    pub fn finish(self, sink: &dyn sink::OutputSink) -> usize {
+        let firsts = self.firsts.into_inner().unwrap();
+        let mut copies: Vec<Vec<Duplicate>> = firsts.iter().map(|_| Vec::new()).collect();
+        for duplicate in self.duplicates.into_inner().unwrap() {
+            copies[duplicate.first].push(duplicate);
+        }
+        let mut failed = 0;
+        let (mut total, mut groups) = (0, 0);
+        for ((first_source, first_output), mut copies) in firsts.iter().zip(copies) {
+            if copies.is_empty() {
+                continue;
+            }
+            copies.sort_by(|a, b| a.source.cmp(&b.source));
+            for copy in &copies {
+                let linked = match self.mode {
+                    DedupMode::Skip => Ok(()),
+                    DedupMode::Hardlink => sink.link(&copy.output, first_output, false),
+                    DedupMode::Symlink => sink.link(&copy.output, first_output, true),
+                };
+                if let Err(e) = linked {
+                    failed += 1;
+                    console::file_line(
+                        console::Status::Failed,
+                        &copy.source,
+                        format_args!("link: {}", e),
+                    );
+                }
+            }
+            total += copies.len();
+            groups += 1;
+            let sources: Vec<_> = copies
+                .iter()
+                .map(|copy| copy.source.to_string_lossy())
+                .collect();
+            console::file_fields(
+                console::Status::Note("Same"),
+                first_source,
+                format_args!("{} cop(ies): {}", copies.len(), sources.join(", ")),
+                &[(
+                    "copies",
+                    format!(
+                        "[{}]",
+                        sources
+                            .iter()
+                            .map(|source| escape::json(source))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    ),
+                )],
+            );
+        }
+        if total > 0 {
+            console::line(
+                console::Status::Info("Dedup"),
+                format_args!(
+                    "{} cop(ies) of {} image(s) {}",
+                    total,
+                    groups,
+                    match self.mode {
+                        DedupMode::Skip => "not written",
+                        DedupMode::Hardlink => "hard-linked",
+                        DedupMode::Symlink => "symlinked",
+                    }
+                ),
+            );
+        }
+        failed
+    }
+}
+
+/// SHA-256 of the size, layout, palette and pixels of `image`.
+fn pixel_hash(image: &CrxFile) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(image.width().to_le_bytes());
+    hasher.update(image.height().to_le_bytes());
+    hasher.update((image.bpp() as u32).to_le_bytes());
+    for color in image.palette().unwrap_or_default() {
+        hasher.update(color);
+    }
+    hasher.update(image.raw_buffer());
+    hasher.finalize().into()
+}
@@ -0,0 +1,81 @@
+use crate::{console, input};
+use clap::Args;
+use image::{Rgba, RgbaImage};
+use std::{io, path::PathBuf};
+
+#[derive(Args)]
+pub struct DiffArg {
+    /// The first image (CRX or PNG)
+    a: PathBuf,
+    /// The second image (CRX or PNG)
+    b: PathBuf,
+    /// Write a PNG of the first image, dimmed to gray, with the differing pixels in red
+    #[arg(short, long, value_name = "PNG")]
+    output: Option<PathBuf>,
+    /// Largest per-channel difference that is still considered equal
+    #[arg(long, default_value_t = 0)]
+    tolerance: u8,
+}
+
+pub fn run(arg: &DiffArg) -> io::Result<()> {
+    let a = input::load_image(&arg.a)?.into_rgba8();
+    let b = input::load_image(&arg.b)?.into_rgba8();
+    if a.dimensions() != b.dimensions() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "dimension mismatch: {}x{} vs {}x{}",
+                a.width(),
+                a.height(),
+                b.width(),
+                b.height()
+            ),
+        ));
+    }
+
+    let (width, height) = a.dimensions();
+    let mut highlight = RgbaImage::new(width, height);
+    let mut changed = 0;
+    let mut max_delta = [0u8; 4];
+    let (mut x0, mut y0, mut x1, mut y1) = (width, height, 0, 0);
+    for ((x, y, pa), pb) in a.enumerate_pixels().zip(b.pixels()) {
+        let delta: [u8; 4] = std::array::from_fn(|c| pa[c].abs_diff(pb[c]));
+        for (max, delta) in max_delta.iter_mut().zip(delta) {
+            *max = (*max).max(delta);
+        }
+        if delta.iter().any(|&d| d > arg.tolerance) {
+            changed += 1;
+            (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x), y1.max(y));
+            highlight.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            // a third of the luma, over black, so that red stands out on any image.
+            let luma = (pa[0] as u32 * 299 + pa[1] as u32 * 587 + pa[2] as u32 * 114) / 1000;
+            let gray = (luma * pa[3] as u32 / 255 / 3) as u8;
+            highlight.put_pixel(x, y, Rgba([gray, gray, gray, 255]));
+        }
+    }
+
+    if let Some(output) = &arg.output {
+        highlight.save(output).map_err(io::Error::other)?;
+    }
+    if changed > 0 {
+        console::line(
+            console::Status::Changed,
+            format_args!("{}x{} at ({}, {})", x1 - x0 + 1, y1 - y0 + 1, x0, y0),
+        );
+    }
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!(
+            "{} of {} pixel(s) differ by more than {}, largest difference R {} G {} B {} A {}",
+            changed,
+            width as usize * height as usize,
+            arg.tolerance,
+            max_delta[0],
+            max_delta[1],
+            max_delta[2],
+            max_delta[3]
+        ),
+    );
+    Ok(())
+}
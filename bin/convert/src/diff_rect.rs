@@ -0,0 +1,190 @@
+use crate::{escape, input};
+use clap::Args;
+use image::{GrayImage, Luma, RgbaImage};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use rayon::prelude::*;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct DiffRectArg {
+    /// The base CG (CRX or PNG)
+    base: PathBuf,
+    /// Variants of the base CG (CRX or PNG)
+    #[arg(required = true)]
+    variants: Vec<PathBuf>,
+    /// Largest per-channel difference that is still considered equal
+    #[arg(long, default_value_t = 0)]
+    tolerance: u8,
+    /// Write the JSON to this file instead of stdout
+    #[arg(long, short, value_name = "JSON")]
+    output: Option<PathBuf>,
+}
+
+/// A bounding box in variant coordinates, with the number of changed pixels inside.
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: usize,
+}
+
+struct VariantDiff {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    offset: (i32, i32),
+    regions: Vec<Rect>,
+}
+
+pub fn run(arg: &DiffRectArg) -> io::Result<()> {
+    let (base, base_offset) = input::load_positioned(&arg.base)?;
+    let base = base.into_rgba8();
+    let diffs = arg
+        .variants
+        .par_iter()
+        .map(|path| {
+            let (variant, offset) = input::load_positioned(path)?;
+            let variant = variant.into_rgba8();
+            let delta = (offset.0 - base_offset.0, offset.1 - base_offset.1);
+            let mask = changed_mask(&base, &variant, delta, arg.tolerance);
+            Ok(VariantDiff {
+                path: path.clone(),
+                width: variant.width(),
+                height: variant.height(),
+                offset,
+                regions: find_regions(&mask),
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let json = to_json(arg, &base, base_offset, &diffs);
+    match &arg.output {
+        Some(path) => fs::write(path, json),
+        None => io::stdout().write_all(json.as_bytes()),
+    }
+}
+
+/// Mark the pixels of `variant` that differ from the `base` pixels under them, with `variant`
+/// placed at `delta` relative to `base`. Pixels outside of `base` always count as changed.
+fn changed_mask(
+    base: &RgbaImage,
+    variant: &RgbaImage,
+    delta: (i32, i32),
+    tolerance: u8,
+) -> GrayImage {
+    GrayImage::from_fn(variant.width(), variant.height(), |x, y| {
+        let (bx, by) = (x as i64 + delta.0 as i64, y as i64 + delta.1 as i64);
+        let same = bx >= 0
+            && by >= 0
+            && base
+                .get_pixel_checked(bx as u32, by as u32)
+                .is_some_and(|b| {
+                    b.0.iter()
+                        .zip(variant.get_pixel(x, y).0.iter())
+                        .all(|(&a, &b)| a.abs_diff(b) <= tolerance)
+                });
+        Luma([if same { 0 } else { 0xFF }])
+    })
+}
+
+/// Bounding boxes of the 8-connected groups of changed pixels, in label order.
+fn find_regions(mask: &GrayImage) -> Vec<Rect> {
+    let labels = connected_components(mask, Connectivity::Eight, Luma([0]));
+    let mut regions: Vec<Option<Rect>> = Vec::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        // labels count up from 1, 0 being the background.
+        let Some(index) = (label.0[0] as usize).checked_sub(1) else {
+            continue;
+        };
+        if index >= regions.len() {
+            regions.resize_with(index + 1, || None);
+        }
+        let rect = regions[index].get_or_insert(Rect {
+            x,
+            y,
+            width: 0,
+            height: 0,
+            pixels: 0,
+        });
+        let (x1, y1) = (
+            (rect.x + rect.width).max(x + 1),
+            (rect.y + rect.height).max(y + 1),
+        );
+        rect.x = rect.x.min(x);
+        rect.y = rect.y.min(y);
+        rect.width = x1 - rect.x;
+        rect.height = y1 - rect.y;
+        rect.pixels += 1;
+    }
+    regions.into_iter().flatten().collect()
+}
+
+/// The smallest box holding every region, `None` if nothing changed.
+fn bounds(regions: &[Rect]) -> Option<Rect> {
+    let first = regions.first()?;
+    let (mut x0, mut y0) = (first.x, first.y);
+    let (mut x1, mut y1) = (first.x + first.width, first.y + first.height);
+    for r in regions {
+        x0 = x0.min(r.x);
+        y0 = y0.min(r.y);
+        x1 = x1.max(r.x + r.width);
+        y1 = y1.max(r.y + r.height);
+    }
+    Some(Rect {
+        x: x0,
+        y: y0,
+        width: x1 - x0,
+        height: y1 - y0,
+        pixels: regions.iter().map(|r| r.pixels).sum(),
+    })
+}
+
+fn rect_json(rect: &Rect) -> String {
+    format!(
+        "{{\"x\":{},\"y\":{},\"width\":{},\"height\":{},\"pixels\":{}}}",
+        rect.x, rect.y, rect.width, rect.height, rect.pixels
+    )
+}
+
+fn path_json(path: &Path) -> String {
+    escape::json(&path.to_string_lossy())
+}
+
+fn to_json(
+    arg: &DiffRectArg,
+    base: &RgbaImage,
+    base_offset: (i32, i32),
+    diffs: &[VariantDiff],
+) -> String {
+    let variants: Vec<String> = diffs
+        .iter()
+        .map(|diff| {
+            let regions: Vec<String> = diff.regions.iter().map(rect_json).collect();
+            format!(
+                "{{\"path\":{},\"width\":{},\"height\":{},\"inner_x\":{},\"inner_y\":{},\"bounds\":{},\"regions\":[{}]}}",
+                path_json(&diff.path),
+                diff.width,
+                diff.height,
+                diff.offset.0,
+                diff.offset.1,
+                bounds(&diff.regions).map_or("null".to_string(), |b| rect_json(&b)),
+                regions.join(",")
+            )
+        })
+        .collect();
+    format!(
+        "{{\"base\":{{\"path\":{},\"width\":{},\"height\":{},\"inner_x\":{},\"inner_y\":{}}},\"tolerance\":{},\"variants\":[{}]}}\n",
+        path_json(&arg.base),
+        base.width(),
+        base.height(),
+        base_offset.0,
+        base_offset.1,
+        arg.tolerance,
+        variants.join(",")
+    )
+}
@@ -0,0 +1,73 @@
+use crate::{archive, console, output};
+use crx::{CrxFile, CrxMetadata};
+use rayon::prelude::*;
+use std::{io, path::PathBuf};
+
+/// What a planned conversion is expected to write.
+struct Planned {
+    metadata: CrxMetadata,
+    estimate: u64,
+}
+
+/// Print what converting `plan` would write, reading only the header of every source, and the
+/// estimated total size of the outputs. Returns the number of sources whose header could not be
+/// read.
+pub fn report(plan: &[(PathBuf, PathBuf)], format: output::Format) -> usize {
+    let planned: Vec<io::Result<Planned>> = plan
+        .par_iter()
+        .map(|(file, _)| {
+            let metadata = CrxFile::read_metadata(archive::open(file)?)?;
+            let estimate = estimate(&metadata, archive::size(file)?, format);
+            Ok(Planned { metadata, estimate })
+        })
+        .collect();
+
+    let mut total = 0;
+    let mut unreadable = 0;
+    for ((file, output), planned) in plan.iter().zip(planned) {
+        match planned {
+            Ok(planned) => {
+                total += planned.estimate;
+                console::file_line(
+                    console::Status::Note("Plan"),
+                    file,
+                    format_args!(
+                        "{} as {}x{} {}-bit, ~{}",
+                        output.display(),
+                        planned.metadata.width(),
+                        planned.metadata.height(),
+                        planned.metadata.bpp(),
+                        indicatif::HumanBytes(planned.estimate)
+                    ),
+                );
+            }
+            Err(e) => {
+                unreadable += 1;
+                console::file_line(console::Status::Failed, file, format_args!("header: {}", e));
+            }
+        }
+    }
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!(
+            "{} file(s) would be converted, to about {}; {} unreadable, nothing was written",
+            plan.len() - unreadable,
+            indicatif::HumanBytes(total),
+            unreadable
+        ),
+    );
+    unreadable
+}
+
+/// Rough size of the output of a source of `source_size` bytes. PNG and WebP outputs are about
+/// as large as the deflated pixels of the source; the others follow from the decoded size.
+fn estimate(metadata: &CrxMetadata, source_size: u64, format: output::Format) -> u64 {
+    let pixels = metadata.width() as u64 * metadata.height() as u64;
+    let channels = if metadata.bpp() == 32 { 4 } else { 3 };
+    match format {
+        output::Format::Png | output::Format::Webp => source_size,
+        output::Format::Bmp | output::Format::Tiff => pixels * channels,
+        // JPEG keeps about a tenth of the decoded size at usual qualities.
+        output::Format::Jpeg => pixels * 3 / 10,
+    }
+}
@@ -0,0 +1,95 @@
+//! Quoting and escaping of strings for the formats the converter writes.
+
+/// Quote and escape a string as a JSON string literal.
+pub fn json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A CSV field, quoted only when it holds a separator, quote or line break.
+pub fn csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Text for HTML element contents and quoted attribute values.
+pub fn html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A field of a tab-separated line, with the characters that would break the line and column
+/// structure escaped.
+pub fn tsv(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encode all but the unreserved characters of RFC 3986, and `/` unless `encode_slash`
+/// is set, e.g. for URL paths.
+pub fn uri(s: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for &b in s.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) || (b == b'/' && !encode_slash) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// A word for POSIX shells, single-quoted unless it only holds characters no shell expands; `'`
+/// is written as `'\''`.
+pub fn shell(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-.,/:=@%+".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_words_are_quoted_literally() {
+        assert_eq!(shell("cg/ev01.crx"), "cg/ev01.crx");
+        assert_eq!(shell("--output-dir=out"), "--output-dir=out");
+        assert_eq!(shell(""), "''");
+        assert_eq!(shell("a b"), "'a b'");
+        assert_eq!(shell("$HOME/`id`\\n\"x\""), "'$HOME/`id`\\n\"x\"'");
+        assert_eq!(shell("it's"), "'it'\\''s'");
+        assert_eq!(shell("#FFFFFF"), "'#FFFFFF'");
+        assert_eq!(shell("イベント.crx"), "'イベント.crx'");
+    }
+}
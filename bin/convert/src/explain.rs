@@ -0,0 +1,84 @@
+use crx::{CrxVersion, PaletteSpec, PixelOrder, RowFilter, FLAG_SIZED_STREAM};
+use std::fmt::Write as _;
+
+/// A primer on the structure of CRX files, for reading decode errors. The values of each table
+/// come from the decoder itself, so that they cannot drift from what it accepts.
+pub fn primer() -> String {
+    let mut out = String::new();
+    // writing to a String cannot fail.
+    let mut line = |text: std::fmt::Arguments| {
+        let _ = writeln!(out, "{}", text);
+    };
+
+    line(format_args!("CRX image format"));
+    line(format_args!(""));
+    line(format_args!(
+        "Header: `CRXG`, or `CRX` and another letter for some engine builds, then little-endian"
+    ));
+    line(format_args!(
+        "inner x and y (i16), width and height (u16), version, flag (u16), depth (i16) and mode"
+    ));
+    line(format_args!(
+        "(u16). A palette, a clip table and the pixel stream follow."
+    ));
+
+    line(format_args!(""));
+    line(format_args!("Versions (offset 0x0C):"));
+    for number in 0..=u16::MAX {
+        if let Ok(version) = CrxVersion::try_from(number) {
+            line(format_args!("  {}  {}", number, version.description()));
+        }
+    }
+    if let Err(e) = CrxVersion::try_from(0) {
+        line(format_args!("  others fail with \"{}\"", e));
+    }
+
+    line(format_args!(""));
+    line(format_args!("Flag (offset 0x0E):"));
+    line(format_args!(
+        "  0x{:02X}  the pixel stream is prefixed with its size (i32)",
+        FLAG_SIZED_STREAM
+    ));
+
+    line(format_args!(""));
+    line(format_args!("Depth (offset 0x10):"));
+    for depth in [0, 1, 2, 0x100, 0x101, 0x102, 0x103, -1] {
+        let meaning = match PaletteSpec::from_depth(depth) {
+            Ok(None) if depth == 0 => "24-bit truecolor".to_string(),
+            Ok(None) => "32-bit truecolor".to_string(),
+            Ok(Some(spec)) => format!(
+                "8-bit, {} palette colors of {} bytes",
+                spec.colors(),
+                spec.entry_size()
+            ),
+            Err(e) => format!("fails with \"{}\"", e),
+        };
+        line(format_args!("  {:<6} {}", depth, meaning));
+    }
+
+    line(format_args!(""));
+    line(format_args!(
+        "Mode (offset 0x12), the channel order of 32-bit pixels:"
+    ));
+    for (mode, label) in [(1, "1"), (2, "2"), (0, "others")] {
+        line(format_args!(
+            "  {:<6} {}",
+            label,
+            PixelOrder::from_mode(mode).description()
+        ));
+    }
+
+    line(format_args!(""));
+    line(format_args!(
+        "Row decode modes, the byte before each row of truecolor images of version 2 and later:"
+    ));
+    for number in 0..=u8::MAX {
+        if let Ok(filter) = RowFilter::try_from(number) {
+            line(format_args!("  {}  {}", number, filter.description()));
+        }
+    }
+    if let Err(e) = RowFilter::try_from(u8::MAX) {
+        line(format_args!("  others fail with \"{}\"", e));
+    }
+    out
+}
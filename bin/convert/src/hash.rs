@@ -0,0 +1,81 @@
+use crate::archive;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+};
+
+/// Lowercase hexadecimal form of `bytes`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 of `data`, in hexadecimal.
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// SHA-256 of everything `reader` yields, in hexadecimal.
+pub fn sha256_hex_reader(mut reader: impl io::Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => return Ok(hex(&hasher.finalize())),
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+}
+
+type Results = (Mutex<HashMap<PathBuf, Option<String>>>, Condvar);
+
+/// Hashes source files on its own thread pool, so that hashing overlaps with decoding instead of
+/// running before or after it.
+pub struct HashPool {
+    pending: HashSet<PathBuf>,
+    results: Arc<Results>,
+    _pool: rayon::ThreadPool,
+}
+
+impl HashPool {
+    /// Start hashing every file of `paths` (archive entries included) on `threads` threads.
+    pub fn spawn(paths: &[PathBuf], threads: usize) -> io::Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("hash-{}", i))
+            .build()
+            .map_err(io::Error::other)?;
+        let results: Arc<Results> = Arc::default();
+        for path in paths {
+            let (path, results) = (path.clone(), results.clone());
+            pool.spawn(move || {
+                let hash = archive::open(&path).and_then(sha256_hex_reader).ok();
+                results.0.lock().unwrap().insert(path, hash);
+                results.1.notify_all();
+            });
+        }
+        Ok(Self {
+            pending: paths.iter().cloned().collect(),
+            results,
+            _pool: pool,
+        })
+    }
+
+    /// The hash of `path`, waiting for it if needed. `None` if the file could not be read or was
+    /// not given to [`HashPool::spawn`].
+    pub fn wait(&self, path: &Path) -> Option<String> {
+        if !self.pending.contains(path) {
+            return None;
+        }
+        let (results, ready) = &*self.results;
+        let mut results = results.lock().unwrap();
+        loop {
+            if let Some(hash) = results.get(path) {
+                return hash.clone();
+            }
+            results = ready.wait(results).unwrap();
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{archive, console, escape, walk};
+use clap::Args;
+use crx::{CrxFile, CrxMetadata};
+use rayon::prelude::*;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct InfoArg {
+    /// CRX files, or directories to search for them
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Print one JSON object per line and file to stdout instead, with the full clip table and
+    /// palette; unreadable files are still reported on the console
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn run(arg: &InfoArg) -> io::Result<()> {
+    let files = walk::collect_files(&arg.paths, "crx", &Default::default())?;
+    let results: Vec<_> = files
+        .par_iter()
+        .map(|file| archive::open(file).and_then(|f| Ok(CrxFile::read_metadata(f)?)))
+        .collect();
+
+    let mut failed = 0;
+    for (file, result) in files.iter().zip(results) {
+        match result {
+            Ok(meta) if arg.json => writeln!(io::stdout().lock(), "{}", to_json(file, &meta))?,
+            Ok(meta) => console::file_line(
+                console::Status::Note("Info"),
+                file,
+                format_args!(
+                    "{}x{} v{} {}bpp mode {} {} clip(s) at ({}, {}){}",
+                    meta.width(),
+                    meta.height(),
+                    meta.version(),
+                    meta.bpp(),
+                    meta.mode(),
+                    meta.clips().len(),
+                    meta.inner_x(),
+                    meta.inner_y(),
+                    match meta.signature() {
+                        signature if signature.is_standard() => String::new(),
+                        signature => format!(
+                            ", signature {}",
+                            String::from_utf8_lossy(&signature.bytes())
+                        ),
+                    }
+                ),
+            ),
+            Err(e) => {
+                failed += 1;
+                console::file_line(console::Status::Failed, file, format_args!("read: {}", e));
+            }
+        }
+    }
+    if !arg.json {
+        console::line(
+            console::Status::Info("Summary"),
+            format_args!("{} file(s), {} unreadable", files.len(), failed),
+        );
+    }
+    Ok(())
+}
+
+fn to_json(path: &Path, meta: &CrxMetadata) -> String {
+    let clips: Vec<String> = meta
+        .clips()
+        .iter()
+        .map(|clip| {
+            format!(
+                "{{\"unknown_1\":{},\"x\":{},\"y\":{},\"unknown_2\":{},\"width\":{},\"height\":{}}}",
+                clip.unknown_1, clip.x, clip.y, clip.unknown_2, clip.width, clip.height
+            )
+        })
+        .collect();
+    let palette = match meta.palette() {
+        Some(palette) => format!(
+            "[{}]",
+            palette
+                .iter()
+                .map(|c| format!("\"#{:02x}{:02x}{:02x}\"", c[0], c[1], c[2]))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"path\":{},\"signature\":{},\"inner_x\":{},\"inner_y\":{},\"width\":{},\"height\":{},\"version\":{},\"flag\":{},\"depth\":{},\"mode\":{},\"bpp\":{},\"clips\":[{}],\"palette\":{}}}",
+        escape::json(&path.to_string_lossy()),
+        escape::json(&String::from_utf8_lossy(&meta.signature().bytes())),
+        meta.inner_x(),
+        meta.inner_y(),
+        meta.width(),
+        meta.height(),
+        meta.version().number(),
+        meta.flag(),
+        meta.depth(),
+        meta.mode(),
+        meta.bpp(),
+        clips.join(","),
+        palette
+    )
+}
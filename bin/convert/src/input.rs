@@ -0,0 +1,33 @@
+use crate::walk;
+use crx::CrxFile;
+use image::DynamicImage;
+use std::{
+    fs,
+    io::{self, BufReader},
+    path::Path,
+};
+
+/// Load a CRX file, or any image format the `image` crate can read, as a `DynamicImage`.
+pub fn load_image(path: &Path) -> io::Result<DynamicImage> {
+    if walk::has_extension(path, "crx") {
+        let crx = CrxFile::read(BufReader::new(fs::File::open(path)?))?;
+        DynamicImage::try_from(crx).map_err(io::Error::other)
+    } else {
+        image::open(path).map_err(io::Error::other)
+    }
+}
+
+/// Like [`load_image`], also returning the inner offsets of CRX files, `(0, 0)` for other
+/// formats.
+pub fn load_positioned(path: &Path) -> io::Result<(DynamicImage, (i32, i32))> {
+    if walk::has_extension(path, "crx") {
+        let crx = CrxFile::read(BufReader::new(fs::File::open(path)?))?;
+        let offset = (crx.inner_x() as i32, crx.inner_y() as i32);
+        Ok((
+            DynamicImage::try_from(crx).map_err(io::Error::other)?,
+            offset,
+        ))
+    } else {
+        Ok((image::open(path).map_err(io::Error::other)?, (0, 0)))
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{datetime::Utc, escape};
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Column names, written as the first line of a new log.
+const HEADER: &str = "timestamp\tlevel\toutcome\tduration_ms\tpath\tdetail";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+/// A tab-separated log of a run, one line per event, appended to across runs.
+///
+/// Timestamps are RFC 3339 in UTC with millisecond precision, so lines sort chronologically as
+/// text whatever the locale. Lines are written unbuffered, so the log is complete up to the last
+/// event even if the run is killed.
+pub struct LogFile {
+    file: Option<Mutex<fs::File>>,
+}
+
+impl LogFile {
+    /// Open the log at `path`, or a log that discards every event if `None`.
+    pub fn open(path: Option<&Path>) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self { file: None });
+        };
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Append an event. `path` and `duration` are written as `-` when absent.
+    pub fn event(
+        &self,
+        level: Level,
+        outcome: &str,
+        duration: Option<Duration>,
+        path: Option<&Path>,
+        detail: impl Display,
+    ) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            rfc3339(SystemTime::now().into()),
+            level.name(),
+            outcome,
+            duration.map_or("-".to_string(), |d| format!("{:.3}", d.as_secs_f64() * 1e3)),
+            path.map_or("-".to_string(), |p| escape::tsv(&p.to_string_lossy())),
+            escape::tsv(&detail.to_string())
+        );
+        // losing a log line must not fail the conversion.
+        let _ = file.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+/// Format `time` as e.g. `2024-03-09T17:05:31.042Z`.
+fn rfc3339(time: Utc) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year, time.month, time.day, time.hour, time.minute, time.second, time.millisecond
+    )
+}
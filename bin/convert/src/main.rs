@@ -1,81 +1,1503 @@
-use clap::Parser;
+mod analyze;
+mod animate;
+mod archive;
+mod audit;
+mod auto_pair;
+mod bench;
+mod budget;
+mod check;
+mod compare;
+mod compare_sets;
+mod console;
+mod datetime;
+mod dedup;
+mod diff;
+mod diff_rect;
+mod dry_run;
+mod escape;
+mod explain;
+mod fast_png;
+mod hash;
+mod info;
+mod input;
+mod logfile;
+mod memory;
+mod output;
+mod pack;
+mod pack_batch;
+mod patch_header;
+mod paths;
+mod preflight;
+#[cfg(feature = "flamegraph")]
+mod profile;
+mod progress;
+mod report;
+#[cfg(feature = "s3")]
+mod s3;
+mod sequence;
+mod sink;
+mod walk;
+mod watch;
+
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
 use crx::CrxFile;
-use image::{DynamicImage, ImageFormat};
-use owo_colors::OwoColorize;
-use rayon::prelude::*;
+use rayon::{iter::Either, prelude::*};
 use std::{
-    fs,
-    io::{self, BufReader, Read},
-    path::PathBuf,
+    cell::Cell,
+    collections::HashMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::{Duration, Instant},
 };
 
+/// Convert CRX images of CIRCUS games to PNG and other formats
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Arg {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Print a primer on the structure of CRX files (versions, flags, modes, row decode modes),
+    /// e.g. to make sense of decode errors, and exit
+    #[arg(long, exclusive = true)]
+    explain_format: bool,
+    #[command(flatten)]
+    convert: ConvertArg,
+}
+
+#[derive(Args)]
+struct ConvertArg {
+    /// CRX files, directories to search for them, or glob patterns such as `cg/ev*.crx`; `-`
+    /// reads a CRX file from stdin, with --stdout. Only versions 1 to 3 of the format are
+    /// decoded: files of other versions fail with the flag and mode of their header
     files: Vec<PathBuf>,
+    #[command(flatten)]
+    walk: walk::WalkOptions,
+    /// Take every file of the searched directories that starts with a CRX signature, whatever its
+    /// extension, and pass over the others silently
+    #[arg(long)]
+    sniff: bool,
+    /// Only decode the inputs, writing nothing, and exit with an error if any of them failed
+    #[arg(long, conflicts_with_all = ["out", "output_dir", "stdout", "watch"])]
+    check: bool,
+    /// Only read the headers of the inputs and print where each would be converted to, its size
+    /// and the estimated total size of the outputs, writing nothing
+    #[arg(long, conflicts_with_all = ["check", "out", "stdout", "watch", "layout"])]
+    dry_run: bool,
+    /// With --check, print one JSON object per line and file to stdout instead, with the SHA-256
+    /// of the decoded pixels
+    #[arg(long, requires = "check")]
+    json: bool,
+    /// Convert at most N files at once; one per CPU by default, or with 0
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+    /// Stop before converting anything if a directory entry cannot be read, instead of skipping it
+    #[arg(long)]
+    fail_on_walk_error: bool,
+    /// Abort the run once more than `N` files, or `N%` of the inputs, failed to convert
+    #[arg(long, value_name = "N[%]", value_parser = budget::parse_failure_budget)]
+    max_failures: Option<budget::FailureBudget>,
+    /// Convert fewer files at once while the memory of the process nears SIZE (e.g. `2G`), or
+    /// the memory limit of its cgroup with `auto`, and more again once it drops
+    #[arg(long, value_name = "SIZE", value_parser = memory::parse_memory_limit)]
+    memory_limit: Option<memory::MemoryLimit>,
+    /// What to do when several inputs map to the same output path
+    #[arg(long, value_enum, default_value_t = preflight::OnCollision::Error)]
+    on_collision: preflight::OnCollision,
+    /// Leave inputs whose output already exists alone
+    #[arg(long, conflicts_with_all = ["force", "newer_only"])]
+    skip_existing: bool,
+    /// Convert every input again, overwriting existing outputs (the default)
+    #[arg(long, conflicts_with = "newer_only")]
+    force: bool,
+    /// Only convert inputs modified after their existing output was written
+    #[arg(long)]
+    newer_only: bool,
+    /// Skip inputs whose content and output options match this ledger of an earlier run, and
+    /// whose output still exists; every converted input is recorded in it
+    #[arg(long, value_name = "FILE")]
+    ledger: Option<PathBuf>,
+    /// Decode files straight from memory maps instead of reading them into a buffer first;
+    /// entries of archives are still read. Files must not change during the run
+    #[arg(long)]
+    mmap: bool,
+    /// Convert truncated or damaged files as far as they decode, zeroing the rows from the first
+    /// unreadable one on, with a warning, instead of failing them
+    #[arg(long, conflicts_with = "mmap")]
+    lenient: bool,
+    /// Write one CSV row of metadata per converted file
+    #[arg(long, value_name = "CSV")]
+    metadata_csv: Option<PathBuf>,
+    /// Write the metadata of every converted file as a versioned JSON document, see `schema`
+    #[arg(long, value_name = "JSON")]
+    metadata_json: Option<PathBuf>,
+    /// Write a report of every input: the converted files with their metadata, output size and
+    /// decode time, the failures and the skipped inputs, as CSV, JSON or HTML
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    /// Format of --report; guessed from its extension by default, falling back to JSON
+    #[arg(long, value_enum)]
+    report_format: Option<report::ReportFormat>,
+    /// Record the SHA-256 of every source file in the metadata
+    #[arg(long)]
+    hash_sources: bool,
+    /// Number of threads hashing sources alongside decoding
+    #[arg(long, default_value_t = 2)]
+    hash_threads: usize,
+    /// What the inner offsets of the game's images are measured from, as reported in the metadata
+    #[arg(long, value_enum, default_value_t = report::Origin::TopLeft)]
+    offset_origin: report::Origin,
+    /// Screen size of the game, e.g. `800x600`, for --normalize-offsets
+    #[arg(long, value_name = "WxH", value_parser = report::parse_screen_size)]
+    screen_size: Option<(u16, u16)>,
+    /// Report inner offsets measured from the top-left corner of the screen
+    #[arg(long, requires = "screen_size")]
+    normalize_offsets: bool,
+    /// Place every image at its inner offset on a transparent canvas of this size, e.g. the
+    /// game's screen, so that sprites keep their alignment; offsets follow --offset-origin
+    #[arg(long, value_name = "WxH", value_parser = report::parse_screen_size)]
+    canvas: Option<(u16, u16)>,
+    /// How result lines are printed; `json` prints one JSON object per line, for scripts
+    #[arg(long, value_enum, default_value_t = console::LogFormat::Human)]
+    log_format: console::LogFormat,
+    /// Emit newline-delimited JSON progress events on stderr, instead of progress bars
+    #[arg(long)]
+    progress_json: bool,
+    /// Only print failures, warnings and the summary, without progress bars
+    #[arg(short, long)]
+    quiet: bool,
+    /// After the summary, name the N files that took longest and the N largest outputs, e.g. to
+    /// spot panoramas that dominate the run; 0 leaves them out
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    top: usize,
+    /// Append a tab-separated log of every event of the run, with UTC timestamps, to this file
+    #[arg(long, value_name = "LOG")]
+    log_file: Option<PathBuf>,
+    /// Write a flame graph of the time spent per stage (open, decode, write, encode, verify) to
+    /// this SVG file; color conversion counts as encoding, as rows are converted while encoded
+    #[cfg(feature = "flamegraph")]
+    #[arg(long, value_name = "SVG")]
+    flamegraph: Option<PathBuf>,
+    /// Also convert the CRX entries of CIRCUS `.pck` archives, into a folder named after the
+    /// archive
+    #[arg(long)]
+    descend_archives: bool,
+    /// CIRCUS `.pck` or `.dat` archive whose CRX entries are converted as well, read in place
+    /// rather than extracted first, into a folder named after the archive
+    #[arg(long, value_name = "ARCHIVE", conflicts_with_all = ["check", "watch"])]
+    archive: Vec<PathBuf>,
+    /// Where to store outputs: `file://DIR`, `zip://ARCHIVE.zip`, `http://HOST[:PORT]/PREFIX` (or
+    /// `https://`), `null://`, or `s3://BUCKET/PREFIX` when built with the `s3` feature; next to
+    /// the inputs by default
+    #[arg(long, value_name = "URI")]
+    out: Option<String>,
+    /// Write outputs under this directory, at their paths relative to the given file or
+    /// directory they were found from; missing directories are created
+    #[arg(short, long, value_name = "DIR", conflicts_with = "out")]
+    output_dir: Option<PathBuf>,
+    /// Place outputs under --output-dir relative to these directories instead of the inputs, e.g.
+    /// to convert a few files of a tree again to where converting the whole tree put them
+    #[arg(long, value_name = "DIR", requires = "output_dir")]
+    relative_to: Vec<PathBuf>,
+    /// Name outputs after this template instead of their source, e.g.
+    /// `{stem}_{width}x{height}.{ext}`: `{stem}` of the source, `{ext}` of --format, `{parent}`,
+    /// the directory or archive the source is in, and `{width}`, `{height}` and `{bpp}` from its
+    /// header. `/` adds directories
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = paths::NameTemplate::parse,
+        conflicts_with = "stdout"
+    )]
+    name_template: Option<paths::NameTemplate>,
+    /// Write the output of the only input to stdout, in --format; messages go to stderr
+    #[arg(long, conflicts_with_all = ["out", "output_dir"])]
+    stdout: bool,
+    /// After converting, keep watching the inputs and convert CRX files as they appear or change
+    #[arg(long, conflicts_with = "stdout")]
+    watch: bool,
+    /// Also write the rectangle of every clip as its own image, `NAME.clipN.EXT` next to the
+    /// output, e.g. to pull the sprite cells out of an atlas
+    #[arg(long, conflicts_with = "stdout")]
+    split_clips: bool,
+    /// Scale every image to WxH before writing it; a side of 0 follows the aspect ratio, e.g.
+    /// `640x0`
+    #[arg(long, value_name = "WxH", value_parser = report::parse_screen_size)]
+    resize: Option<(u16, u16)>,
+    /// Also write a thumbnail that fits in NxN, keeping the aspect ratio, as `NAME.thumb.EXT`
+    /// next to the output
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with = "stdout"
+    )]
+    thumbnail: Option<u16>,
+    /// How images are scaled for --resize and --thumbnail
+    #[arg(long, value_enum, default_value_t = output::ResizeFilter::Lanczos3)]
+    filter: output::ResizeFilter,
+    /// Write images whose decoded pixels match those of an image converted earlier in the run
+    /// only once, and skip the outputs of the copies or link them to the first output; the
+    /// copies are listed after the run
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        conflicts_with_all = ["stdout", "split_clips", "thumbnail", "layout"]
+    )]
+    dedup: Option<dedup::DedupMode>,
+    /// Read every output back after writing it and compare its pixels to the decoded image
+    #[arg(long)]
+    verify_write: bool,
+    /// Write every image as RGBA, adding an opaque alpha channel where the source has none
+    #[arg(long, conflicts_with = "force_rgb")]
+    force_rgba: bool,
+    /// Write every image as RGB, dropping the alpha channel of 32-bit sources
+    #[arg(long, conflicts_with = "truecolor")]
+    force_rgb: bool,
+    /// Write 8-bit images as RGB rather than as palette PNGs
+    #[arg(long, conflicts_with = "force_rgba")]
+    truecolor: bool,
+    /// Palette entry to mark as transparent in palette PNGs, or `auto` for the magenta color key
+    /// of each source, if it has one
+    #[arg(long, value_name = "INDEX", value_parser = output::parse_transparent_index)]
+    transparent_index: Option<output::TransparentIndex>,
+    /// Keep the alpha of 32-bit sources as stored, for titles whose images come out inverted
+    #[arg(long)]
+    no_alpha_flip: bool,
+    /// Write 32-bit images with premultiplied alpha
+    #[arg(long, conflicts_with = "drop_alpha")]
+    premultiply: bool,
+    /// Flatten 32-bit images onto a background color, `#RRGGBB`, white if left out, and write
+    /// them as RGB
+    #[arg(
+        long,
+        value_name = "COLOR",
+        num_args = 0..=1,
+        default_missing_value = "#FFFFFF",
+        value_parser = output::parse_color
+    )]
+    drop_alpha: Option<[u8; 3]>,
+    /// Base CG the inputs are differential images of; every input is merged onto it, placed by
+    /// its inner offset, before being written
+    #[arg(long, value_name = "CRX")]
+    base: Option<PathBuf>,
+    /// Correct the transfer curve of titles mastered for another display gamma: every color
+    /// channel `v` becomes `255 * (v / 255) ^ (1 / G)`, so values above 1 brighten mid-tones
+    #[arg(long, value_name = "G", value_parser = parse_gamma)]
+    gamma: Option<f64>,
+    /// How PNG outputs are compressed: `fast` deflates rows on every thread, `small` trades
+    /// time for size
+    #[arg(long, value_enum, default_value_t = output::PngEncoder::Default)]
+    png_encoder: output::PngEncoder,
+    /// Output file format; only PNG outputs are stamped with their source and can be verified
+    #[arg(long, value_enum, default_value_t = output::Format::Png)]
+    format: output::Format,
+    /// Print resolution to record in PNG outputs, in dots per inch, e.g. 300 for artbook scans
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    dpi: Option<u32>,
+    /// Quality of lossy formats, from 1 to 100
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+    /// When written outputs are flushed to disk, for plain files and zip archives
+    #[arg(long, value_enum, default_value_t = sink::Fsync::None)]
+    fsync: sink::Fsync,
+    /// How outputs are named in the output location
+    #[arg(long, value_enum, default_value_t = sink::Layout::Mirror)]
+    layout: sink::Layout,
+    #[cfg(feature = "s3")]
+    #[command(flatten)]
+    s3: s3::S3Arg,
+    /// The options of [`RERUN_OPTIONS`] given on the command line, as shell words.
+    #[arg(skip)]
+    output_options: Vec<String>,
+    #[arg(skip)]
+    working_dir: Option<PathBuf>,
+}
+
+/// The options outputs depend on, stamped into PNG outputs with the working directory so that
+/// `audit-outputs` can print the command that converts their source again to the same place.
+/// Options that pick or skip inputs, or only report on the run, are left out.
+const RERUN_OPTIONS: &[&str] = &[
+    "out",
+    "output_dir",
+    "name_template",
+    "layout",
+    "format",
+    "png_encoder",
+    "dpi",
+    "quality",
+    "force_rgba",
+    "force_rgb",
+    "truecolor",
+    "transparent_index",
+    "no_alpha_flip",
+    "premultiply",
+    "drop_alpha",
+    "gamma",
+    "base",
+    "canvas",
+    "offset_origin",
+    "resize",
+    "thumbnail",
+    "filter",
+    "split_clips",
+    "lenient",
+];
+
+#[derive(Subcommand)]
+enum Command {
+    /// Correlate clip table fields with image properties across a corpus
+    AnalyzeClips(analyze::AnalyzeClipsArg),
+    /// Compose numbered CRX frames, e.g. eye blinks, into an APNG, GIF or animated WebP
+    Animate(animate::AnimateArg),
+    /// List previously converted PNGs that need to be re-converted
+    ///
+    /// Outputs are checked against a table of releases whose decoding was wrong. No release since
+    /// outputs were first stamped has changed decoded pixels, so the table is empty for now and
+    /// only --include-unstamped lists anything.
+    AuditOutputs(audit::AuditArg),
+    /// Pair the diff images of a directory with the base CGs they were cut from, as JSON
+    AutoPair(auto_pair::AutoPairArg),
+    /// Measure decode throughput for a range of thread counts, writing nothing
+    Bench(bench::BenchArg),
+    /// Report regions that differ between two images beyond a tolerance
+    Compare(compare::CompareArg),
+    /// List the CRX files added, removed, moved or changed between two versions of a game
+    CompareSets(compare_sets::CompareSetsArg),
+    /// Highlight the pixels that differ between two images of the same size, with a summary of
+    /// how many, where and by how much
+    Diff(diff::DiffArg),
+    /// Write the bounding boxes of the regions where variants differ from a base CG as JSON
+    DiffRect(diff_rect::DiffRectArg),
+    /// Print the header fields of CRX files without decoding their pixels
+    Info(info::InfoArg),
+    /// Write an edited PNG back into the CRX file it was converted from
+    Pack(pack::PackArg),
+    /// Pack every edited image listed in a CSV mapping file
+    PackBatch(pack_batch::PackBatchArg),
+    /// Rewrite the inner offset or clip table of CRX files in place, without re-encoding pixels
+    PatchHeader(patch_header::PatchHeaderArg),
+    /// Print the JSON Schema of the --metadata-json report
+    Schema,
+    /// Group numbered CRX files into frame sequences and write ffmpeg concat lists for them
+    Sequences(sequence::SequencesArg),
 }
 
 fn main() -> io::Result<()> {
-    let arg = Arg::parse();
-
-    arg.files.par_iter().for_each(|file| {
-        let f = fs::File::open(file);
-        if let Err(e) = f {
-            println!(
-                "{} \"{}\" read: {}",
-                " Failed".red().bold(),
-                file.to_string_lossy(),
-                e
+    let command = Arg::command();
+    let matches = command.clone().get_matches();
+    let mut arg = Arg::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if arg.command.is_none() {
+        arg.convert.output_options = rerun_options(&command, &matches);
+        arg.convert.working_dir = std::env::current_dir().ok();
+    }
+    if arg.explain_format {
+        print!("{}", explain::primer());
+        return Ok(());
+    }
+
+    match &arg.command {
+        Some(Command::AnalyzeClips(analyze)) => analyze::run(analyze),
+        Some(Command::Animate(animate)) => animate::run(animate),
+        Some(Command::AuditOutputs(audit)) => audit::run(audit),
+        Some(Command::AutoPair(pair)) => auto_pair::run(pair),
+        Some(Command::Bench(bench)) => bench::run(bench),
+        Some(Command::Compare(compare)) => compare::run(compare),
+        Some(Command::CompareSets(sets)) => compare_sets::run(sets),
+        Some(Command::Diff(diff)) => diff::run(diff),
+        Some(Command::DiffRect(diff_rect)) => diff_rect::run(diff_rect),
+        Some(Command::Info(info)) => info::run(info),
+        Some(Command::Pack(pack)) => pack::run(pack),
+        Some(Command::PackBatch(batch)) => pack_batch::run(batch),
+        Some(Command::PatchHeader(patch)) => patch_header::run(patch),
+        Some(Command::Schema) => {
+            print!("{}", report::SCHEMA);
+            Ok(())
+        }
+        Some(Command::Sequences(sequences)) => sequence::run(sequences),
+        None => convert(&arg.convert),
+    }
+}
+
+fn convert(arg: &ConvertArg) -> io::Result<()> {
+    #[cfg(feature = "flamegraph")]
+    let profile = arg
+        .flamegraph
+        .as_deref()
+        .map(profile::Profile::start)
+        .transpose()?;
+    let result = convert_profiled(arg);
+    // the profile is rendered however the run ended, e.g. after --check or a failure.
+    #[cfg(feature = "flamegraph")]
+    if let Some(profile) = profile {
+        return result.and(profile.finish());
+    }
+    result
+}
+
+/// Everything [`convert`] does, while its profile records.
+fn convert_profiled(arg: &ConvertArg) -> io::Result<()> {
+    let log = logfile::LogFile::open(arg.log_file.as_deref())?;
+    let run_start = Instant::now();
+    log.event(
+        logfile::Level::Info,
+        "started",
+        None,
+        None,
+        output::software_stamp(),
+    );
+    console::set_quiet(arg.quiet);
+    console::set_format(arg.log_format);
+    console::set_stderr(arg.stdout);
+    if let Some(jobs) = arg.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .map_err(io::Error::other)?;
+    }
+    if arg.check {
+        return check::run(&arg.files, &arg.walk, arg.sniff, arg.json);
+    }
+    let result = convert_summarized(arg, &arg.files, &log, run_start);
+    if let (true, Ok(_)) = (arg.watch, &result) {
+        watch::run(&arg.files, &arg.walk, arg.sniff, |files| {
+            let _ = convert_summarized(arg, &files, &log, Instant::now());
+        })?;
+    }
+    result.map(|_| ())
+}
+
+/// Convert `files`, then print and log how it went.
+fn convert_summarized(
+    arg: &ConvertArg,
+    files: &[PathBuf],
+    log: &logfile::LogFile,
+    start: Instant,
+) -> io::Result<RunSummary> {
+    let result = convert_logged(arg, files, log);
+    match &result {
+        Ok(_) if arg.dry_run => log.event(
+            logfile::Level::Info,
+            "finished",
+            Some(start.elapsed()),
+            None,
+            "dry run, nothing was written",
+        ),
+        Ok(run) => {
+            let summary = format!(
+                "{} converted, {} failed, {} skipped, {} written in {}",
+                run.converted,
+                run.failed,
+                run.skipped,
+                indicatif::HumanBytes(run.bytes),
+                indicatif::HumanDuration(start.elapsed())
             );
-            return;
-        }
-        let mut reader = BufReader::new(f.unwrap());
-        let crx_img = CrxFile::read(reader.by_ref());
-        if let Err(e) = crx_img {
-            println!(
-                "{} \"{}\" decode: {}",
-                " Failed".red().bold(),
-                file.to_string_lossy(),
-                e
+            console::line(console::Status::Info("Summary"), &summary);
+            for file in &run.slowest {
+                console::file_line(
+                    console::Status::Note("Slowest"),
+                    &file.source,
+                    format_args!("{:.1?} for {}x{}", file.duration, file.width, file.height),
+                );
+            }
+            for file in &run.largest {
+                console::file_line(
+                    console::Status::Note("Largest"),
+                    &file.source,
+                    format_args!(
+                        "{} for {}x{}",
+                        indicatif::HumanBytes(file.output_size),
+                        file.width,
+                        file.height
+                    ),
+                );
+            }
+            log.event(
+                logfile::Level::Info,
+                "finished",
+                Some(start.elapsed()),
+                None,
+                summary,
+            )
+        }
+        Err(e) => log.event(
+            logfile::Level::Error,
+            "aborted",
+            Some(start.elapsed()),
+            None,
+            e,
+        ),
+    }
+    result
+}
+
+/// What a conversion run did.
+struct RunSummary {
+    converted: usize,
+    failed: usize,
+    /// Inputs left alone, e.g. for lacking a signature or having an up-to-date output.
+    skipped: usize,
+    /// Size of the outputs written.
+    bytes: u64,
+    /// The `--top` files that took longest to convert, longest first; empty unless more files
+    /// than that were converted.
+    slowest: Vec<Outlier>,
+    /// Likewise, the files with the largest outputs.
+    largest: Vec<Outlier>,
+}
+
+/// A converted file singled out in the summary.
+struct Outlier {
+    source: PathBuf,
+    width: u16,
+    height: u16,
+    duration: Duration,
+    output_size: u64,
+}
+
+impl Outlier {
+    /// The `n` records of `records` with the largest `key`, largest first, if there are more.
+    fn top<K: Ord>(
+        records: &[report::Record],
+        n: usize,
+        key: impl Fn(&report::Record) -> K,
+    ) -> Vec<Self> {
+        if records.len() <= n {
+            return Vec::new();
+        }
+        let mut sorted: Vec<&report::Record> = records.iter().collect();
+        sorted.sort_by_key(|record| std::cmp::Reverse(key(record)));
+        sorted
+            .into_iter()
+            .take(n)
+            .map(|record| Outlier {
+                source: record.source.clone(),
+                width: record.width,
+                height: record.height,
+                duration: record.duration,
+                output_size: record.output_size,
+            })
+            .collect()
+    }
+}
+
+/// The conversion run proper, over `inputs` (the given files, or those that changed while
+/// watching).
+fn convert_logged(
+    arg: &ConvertArg,
+    inputs: &[PathBuf],
+    log: &logfile::LogFile,
+) -> io::Result<RunSummary> {
+    if inputs.iter().any(|file| file == Path::new(archive::STDIN)) && !arg.stdout {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "`-` reads the input from stdin and needs --stdout",
+        ));
+    }
+    let (mut files, walk_errors) = walk::collect_crx_lenient(inputs, &arg.walk, arg.sniff);
+    for error in &walk_errors {
+        console::line(console::Status::Failed, format_args!("walk: {}", error));
+        log.event(
+            logfile::Level::Warn,
+            "unreadable",
+            None,
+            None,
+            format_args!("walk: {}", error),
+        );
+    }
+    if !walk_errors.is_empty() {
+        if arg.fail_on_walk_error {
+            return Err(io::Error::other(format!(
+                "{} unreadable path(s) while searching for inputs, nothing was converted",
+                walk_errors.len()
+            )));
+        }
+        console::line(
+            console::Status::Skipped,
+            format_args!(
+                "{} unreadable path(s) while searching for inputs",
+                walk_errors.len()
+            ),
+        );
+    }
+    let mut skips = Vec::new();
+    // files found by walking directories must carry the signature, skip the others up front.
+    let unsigned: Vec<PathBuf>;
+    (files, unsigned) = files.into_par_iter().partition(|file| {
+        let keep = inputs.contains(file) || walk::has_crx_signature(file);
+        if !keep {
+            log.event(
+                logfile::Level::Warn,
+                "skipped",
+                None,
+                Some(file),
+                "no CRX signature",
             );
-            return;
-        }
-        let crx_img = crx_img.unwrap();
-        // println!("clip count: {}", crx_img.clips().len());
-        let img = DynamicImage::try_from(crx_img);
-        if let Err(e) = img {
-            println!(
-                "{} \"{}\" convert: {}",
-                " Failed".red().bold(),
-                file.to_string_lossy(),
-                e
+        }
+        keep
+    });
+    if !unsigned.is_empty() {
+        console::line(
+            console::Status::Skipped,
+            format_args!("{} file(s) without a CRX signature", unsigned.len()),
+        );
+        skips.extend(unsigned.into_iter().map(|source| report::Skip {
+            source,
+            reason: "no CRX signature",
+        }));
+    }
+    // an archive whose index cannot be read fails on its own, the other inputs are converted.
+    let mut archive_failures = Vec::new();
+    let mut list_archive = |path: &Path, files: &mut Vec<PathBuf>| match archive::list_crx(path) {
+        Ok(entries) => files.extend(entries),
+        Err(e) => {
+            console::file_line(console::Status::Failed, path, format_args!("list: {}", e));
+            log.event(
+                logfile::Level::Error,
+                "failed",
+                None,
+                Some(path),
+                format_args!("list: {}", e),
             );
-            return;
-        }
-        let img = img.unwrap();
-        // determine output file path
-        let output_path = {
-            let mut tmp = file.clone();
-            tmp.set_extension("png");
-            tmp
-        };
-        // write to file
-        let result = img.save_with_format(&output_path, ImageFormat::Png);
-        if let Err(e) = result {
-            println!(
-                "{} \"{}\" save: {}",
-                " Failed".red().bold(),
-                file.to_string_lossy(),
-                e
+            archive_failures.push(report::Failure {
+                source: path.to_path_buf(),
+                stage: "list",
+                error: e.to_string(),
+                head: Vec::new(),
+            });
+        }
+    };
+    if arg.descend_archives {
+        files.retain(|file| !archive::is_archive(file));
+        for pck in walk::collect_files(inputs, "pck", &arg.walk)? {
+            list_archive(&pck, &mut files);
+        }
+    }
+    for path in &arg.archive {
+        if !archive::is_archive(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a `.pck` or `.dat` file", path.display()),
+            ));
+        }
+        list_archive(path, &mut files);
+    }
+    let roots = output_roots(arg);
+    let plan: Vec<(PathBuf, PathBuf)> = files
+        .into_par_iter()
+        .map(|file| {
+            let mut output = paths::output_path(&file, arg.format);
+            if let Some(template) = &arg.name_template {
+                // sources without a readable header keep their name, and fail to decode later.
+                let header = template
+                    .needs_header()
+                    .then(|| CrxFile::read_metadata(archive::open(&file).ok()?).ok())
+                    .flatten();
+                if header.is_some() || !template.needs_header() {
+                    output = template.apply(&output, &file, arg.format, header.as_ref());
+                }
+            }
+            if let Some(dir) = &arg.output_dir {
+                output = paths::under_dir(dir, &output, &roots);
+            }
+            if arg.stdout {
+                output = PathBuf::from(archive::STDIN);
+            }
+            (file, output)
+        })
+        .collect();
+    let (plan, colliding) = preflight::resolve_collisions(plan, arg.on_collision);
+    for file in &colliding {
+        console::file_line(
+            console::Status::Skipped,
+            file,
+            "output collides with another input",
+        );
+        log.event(
+            logfile::Level::Warn,
+            "skipped",
+            None,
+            Some(file),
+            "output collides with another input",
+        );
+    }
+    skips.extend(colliding.into_iter().map(|source| report::Skip {
+        source,
+        reason: "output collides with another input",
+    }));
+
+    if arg.stdout && plan.len() != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--stdout needs exactly one input, found {}", plan.len()),
+        ));
+    }
+    let sink: Box<dyn sink::OutputSink> = if arg.stdout {
+        Box::new(sink::StdoutSink)
+    } else {
+        sink::from_uri(
+            arg.out.as_deref(),
+            arg.fsync,
+            #[cfg(feature = "s3")]
+            &arg.s3,
+        )?
+    };
+    let sink = sink::with_layout(sink, arg.layout);
+    if arg.verify_write && !sink.can_load() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--verify-write needs an output location that can be read back, e.g. `file://`",
+        ));
+    }
+    if arg.verify_write && arg.format != output::Format::Png {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--verify-write only supports PNG outputs",
+        ));
+    }
+    if arg.dedup.is_some_and(|mode| mode != dedup::DedupMode::Skip) && !sink.can_link() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--dedup hardlink and symlink need outputs written as plain files",
+        ));
+    }
+    let overwrite = if arg.skip_existing {
+        preflight::Overwrite::Never
+    } else if arg.newer_only {
+        preflight::Overwrite::IfNewer
+    } else {
+        preflight::Overwrite::Always
+    };
+    if (overwrite != preflight::Overwrite::Always || arg.ledger.is_some()) && !sink.is_local() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--skip-existing, --newer-only and --ledger need outputs written as plain files, e.g. with --output-dir",
+        ));
+    }
+    let (plan, up_to_date) = preflight::skip_existing(plan, overwrite);
+    if !up_to_date.is_empty() {
+        console::line(
+            console::Status::Skipped,
+            format_args!("{} file(s) with an up-to-date output", up_to_date.len()),
+        );
+        for file in &up_to_date {
+            log.event(
+                logfile::Level::Info,
+                "skipped",
+                None,
+                Some(file),
+                "output is up to date",
             );
-            return;
         }
-        println!(
-            "{} \"{}\" -> \"{}\"",
-            "Success".green().bold(),
-            file.to_string_lossy(),
-            output_path.to_string_lossy()
+    }
+    skips.extend(up_to_date.into_iter().map(|source| report::Skip {
+        source,
+        reason: "output is up to date",
+    }));
+    let settings = ledger_settings(arg);
+    let mut ledger = match &arg.ledger {
+        Some(path) => Some((crx::ConversionLedger::load(path)?, source_hashes(&plan))),
+        None => None,
+    };
+    let plan = match &ledger {
+        Some((ledger, hashes)) => {
+            let (plan, unchanged): (Vec<_>, Vec<_>) =
+                plan.into_iter().partition(|(file, output)| {
+                    !(output.exists()
+                        && hashes
+                            .get(file)
+                            .is_some_and(|hash| ledger.is_current(file, hash, &settings)))
+                });
+            if !unchanged.is_empty() {
+                console::line(
+                    console::Status::Skipped,
+                    format_args!("{} file(s) unchanged since the ledger", unchanged.len()),
+                );
+            }
+            for (file, _) in &unchanged {
+                log.event(
+                    logfile::Level::Info,
+                    "skipped",
+                    None,
+                    Some(file),
+                    "unchanged since the ledger",
+                );
+            }
+            skips.extend(unchanged.into_iter().map(|(source, _)| report::Skip {
+                source,
+                reason: "unchanged since the ledger",
+            }));
+            plan
+        }
+        None => plan,
+    };
+    let problems = preflight::check(&plan, sink.is_local());
+    if !problems.is_empty() {
+        preflight::report(&problems);
+        return Err(io::Error::other(format!(
+            "pre-flight check found {} problem(s), nothing was converted",
+            problems.len()
+        )));
+    }
+    if arg.dry_run {
+        let unreadable = dry_run::report(&plan, arg.format);
+        return Ok(RunSummary {
+            converted: 0,
+            failed: unreadable + archive_failures.len(),
+            skipped: skips.len(),
+            bytes: 0,
+            slowest: Vec::new(),
+            largest: Vec::new(),
+        });
+    }
+
+    let base = match &arg.base {
+        Some(path) => Some(CrxFile::read_with_options(
+            archive::open(path)?,
+            &decode_options(arg),
+        )?),
+        None => None,
+    };
+
+    let hashes = if arg.hash_sources {
+        let sources: Vec<PathBuf> = plan.iter().map(|(file, _)| file.clone()).collect();
+        Some(hash::HashPool::spawn(&sources, arg.hash_threads)?)
+    } else {
+        None
+    };
+
+    let governor = match arg.memory_limit {
+        Some(limit) => {
+            let bytes = limit.resolve().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--memory-limit auto: the process has no cgroup memory limit",
+                )
+            })?;
+            if memory::resident_bytes().is_none() {
+                console::line(
+                    console::Status::Warning,
+                    "the memory of the process cannot be measured here, ignoring --memory-limit",
+                );
+            }
+            Some(memory::MemoryGovernor::new(
+                bytes,
+                rayon::current_num_threads(),
+            ))
+        }
+        None => None,
+    };
+
+    let dedup = arg.dedup.map(dedup::Dedup::new);
+    let progress = progress::Progress::new(arg.progress_json, !arg.quiet, plan.len());
+    let max_failures = arg.max_failures.map(|budget| budget.limit(plan.len()));
+    let failed = AtomicUsize::new(archive_failures.len());
+    let aborted = AtomicBool::new(false);
+    let (records, mut failures): (Vec<_>, Vec<_>) = plan
+        .par_iter()
+        .filter_map(|(file, output_path)| {
+            // files already being converted finish, the others are left alone.
+            if aborted.load(Ordering::Relaxed) {
+                return None;
+            }
+            let _permit = governor.as_ref().map(|governor| governor.acquire());
+            progress.started(file);
+            let start = Instant::now();
+            let result = convert_one(
+                file,
+                output_path,
+                sink.as_ref(),
+                base.as_ref(),
+                dedup.as_ref(),
+                arg,
+            )
+            .map(|record| report::Record {
+                source_sha256: hashes.as_ref().and_then(|h| h.wait(file)),
+                ..record
+            });
+            progress.finished(file, result.is_ok());
+            match &result {
+                Ok(record) => log.event(
+                    logfile::Level::Info,
+                    "converted",
+                    Some(start.elapsed()),
+                    Some(file),
+                    format_args!("-> {}", record.output.to_string_lossy()),
+                ),
+                Err(failure) => log.event(
+                    logfile::Level::Error,
+                    "failed",
+                    Some(start.elapsed()),
+                    Some(file),
+                    format_args!("{}: {}", failure.stage, failure.error),
+                ),
+            }
+            if result.is_err() {
+                let failed = failed.fetch_add(1, Ordering::Relaxed) + 1;
+                if max_failures.is_some_and(|max| failed > max) {
+                    aborted.store(true, Ordering::Relaxed);
+                }
+            }
+            Some(result)
+        })
+        .partition_map(|result| match result {
+            Ok(record) => Either::Left(record),
+            Err(failure) => Either::Right(failure),
+        });
+    failures.splice(0..0, archive_failures);
+    progress.end();
+    sink.finish()?;
+    let link_failures = dedup.map_or(0, |dedup| dedup.finish(sink.as_ref()));
+    if let Some(governor) = governor.filter(|g| g.lowest() < rayon::current_num_threads()) {
+        console::line(
+            console::Status::Note("Memory"),
+            format_args!(
+                "converted as few as {} file(s) at once to stay under --memory-limit",
+                governor.lowest()
+            ),
+        );
+    }
+
+    if let (Some((ledger, hashes)), Some(path)) = (&mut ledger, &arg.ledger) {
+        for record in &records {
+            if let Some(hash) = hashes.get(&record.source) {
+                ledger.update(
+                    record.source.clone(),
+                    crx::LedgerEntry {
+                        source_hash: hash.clone(),
+                        settings: settings.clone(),
+                        output: record.output.clone(),
+                    },
+                );
+            }
+        }
+        for failure in &failures {
+            ledger.remove(&failure.source);
+        }
+        ledger.save(path)?;
+    }
+
+    if let Some(csv) = &arg.metadata_csv {
+        report::write_report(csv, report::ReportFormat::Csv, &records, &[], &[])?;
+    }
+    if let Some(json) = &arg.metadata_json {
+        report::write_report(json, report::ReportFormat::Json, &records, &failures, &[])?;
+    }
+    if let Some(path) = &arg.report {
+        let format = arg
+            .report_format
+            .or_else(|| report::ReportFormat::from_path(path))
+            .unwrap_or(report::ReportFormat::Json);
+        report::write_report(path, format, &records, &failures, &skips)?;
+    }
+
+    if let (true, Some(max)) = (aborted.into_inner(), max_failures) {
+        return Err(io::Error::other(format!(
+            "more than {} file(s) failed, aborted after {} of {} file(s)",
+            max,
+            records.len() + failures.len(),
+            plan.len()
+        )));
+    }
+    Ok(RunSummary {
+        converted: records.len(),
+        failed: failures.len() + link_failures,
+        skipped: skips.len(),
+        bytes: records.iter().map(|record| record.output_size).sum(),
+        slowest: Outlier::top(&records, arg.top, |record| record.duration),
+        largest: Outlier::top(&records, arg.top, |record| record.output_size),
+    })
+}
+
+fn convert_one(
+    file: &Path,
+    output_path: &Path,
+    sink: &dyn sink::OutputSink,
+    base: Option<&CrxFile>,
+    dedup: Option<&dedup::Dedup>,
+    arg: &ConvertArg,
+) -> Result<report::Record, report::Failure> {
+    let start = Instant::now();
+    let fail = |stage: &'static str, e: io::Error| {
+        console::file_fields(
+            console::Status::Failed,
+            file,
+            format_args!("{}: {}", stage, e),
+            &[
+                ("stage", escape::json(stage)),
+                ("error", escape::json(&e.to_string())),
+            ],
+        );
+        let mut head = Vec::new();
+        if let Ok(f) = archive::open(file) {
+            let _ = f
+                .take(report::FAILURE_HEAD_SIZE as u64)
+                .read_to_end(&mut head);
+        }
+        report::Failure {
+            source: file.to_path_buf(),
+            stage,
+            error: e.to_string(),
+            head,
+        }
+    };
+
+    let _span = tracing::info_span!("file").entered();
+    if streams(file, base, dedup, arg) {
+        return convert_streamed(file, output_path, sink, arg, start, &fail);
+    }
+    let is_stdin = file == Path::new(archive::STDIN);
+    let (crx_img, decode_duration) =
+        if arg.mmap && !is_stdin && archive::containing_archive(file).is_none() {
+            // page faults count as decoding here.
+            let decode_start = Instant::now();
+            let crx_img = tracing::info_span!("decode")
+                .in_scope(|| CrxFile::from_mmap(file, &decode_options(arg)))
+                .map_err(|e| fail("decode", e.into()))?;
+            (crx_img, decode_start.elapsed())
+        } else {
+            let (input, read_time) = open_input(file).map_err(|e| fail("read", e))?;
+            let decode_start = Instant::now();
+            let (crx_img, warnings) = tracing::info_span!("decode")
+                .in_scope(|| CrxFile::read_with_warnings(input, &decode_options(arg)))
+                .map_err(|e| fail("decode", e.into()))?;
+            let decode_duration = decode_start.elapsed().saturating_sub(read_time.get());
+            for warning in &warnings {
+                console::file_line(
+                    console::Status::Warning,
+                    file,
+                    format_args!("decoded partially, {}", warning),
+                );
+            }
+            (crx_img, decode_duration)
+        };
+    // the base itself is converted as is.
+    let crx_img = match base.filter(|_| arg.base.as_deref() != Some(file)) {
+        Some(base) => tracing::info_span!("compose")
+            .in_scope(|| crx_img.compose_onto(base))
+            .map_err(|e| fail("compose", e))?,
+        None => crx_img,
+    };
+    let crx_img = match arg.gamma {
+        Some(gamma) => tracing::info_span!("correct")
+            .in_scope(|| crx_img.map_channels(&output::gamma_lut(gamma))),
+        None => crx_img,
+    };
+    // after the gamma correction, which applies to straight colors.
+    let crx_img = match arg.drop_alpha {
+        Some(background) => crx_img.flatten(background),
+        None if arg.premultiply => crx_img.premultiply_alpha(),
+        None => crx_img,
+    };
+    // the report keeps describing the image itself, not the canvas.
+    let positioned = arg.canvas.map(|(width, height)| {
+        tracing::info_span!("position")
+            .in_scope(|| crx_img.to_positioned_image_from(arg.offset_origin.into(), width, height))
+    });
+    let written = positioned.as_ref().unwrap_or(&crx_img);
+    let resized = arg
+        .resize
+        .map(|target| {
+            let (width, height) = output::resized_size(written.width(), written.height(), target);
+            tracing::info_span!("resize")
+                .in_scope(|| written.resize(width, height, arg.filter.into()))
+                .map_err(|e| fail("resize", io::Error::other(e)))
+        })
+        .transpose()?;
+    let written = resized.as_ref().unwrap_or(written);
+    let stamped = stamped_options(file, arg);
+    let options = png_options(file, &stamped, written.color_key(), arg);
+    let write = |output_path: &Path, image: &CrxFile| {
+        let output_size = tracing::info_span!("write")
+            .in_scope(|| {
+                sink.store(output_path, &mut |w| {
+                    tracing::info_span!("encode").in_scope(|| match arg.format {
+                        output::Format::Png => output::write_png(w, image, &options),
+                        format => {
+                            output::write_image(w, image, format, options.force_color, arg.quality)
+                        }
+                    })
+                })
+            })
+            .map_err(|e| fail("save", e))?;
+        if arg.verify_write {
+            tracing::info_span!("verify")
+                .in_scope(|| {
+                    sink.load(output_path)
+                        .and_then(|data| output::verify_png(&data, image, &options))
+                })
+                .map_err(|e| fail("verify", e))?;
+        }
+        Ok(output_size)
+    };
+    // copies of an earlier image are linked to its output once the run is over.
+    let first = dedup.and_then(|dedup| dedup.claim(file, output_path, written));
+    let mut output_size = 0;
+    if first.is_none() {
+        output_size += write(output_path, written)?;
+    }
+    if let Some(size) = arg.thumbnail {
+        let (width, height) = output::thumbnail_size(written.width(), written.height(), size);
+        let thumbnail = tracing::info_span!("resize")
+            .in_scope(|| written.resize(width, height, arg.filter.into()))
+            .map_err(|e| fail("resize", io::Error::other(e)))?;
+        output_size += write(&sibling_path(output_path, "thumb"), &thumbnail)?;
+    }
+    if arg.split_clips {
+        // clips are cut from the image itself, not from the canvas.
+        for index in 0..crx_img.clips().len() {
+            if let Some(clip) = crx_img.extract_clip(index) {
+                output_size += write(&clip_path(output_path, index), &clip)?;
+            }
+        }
+    }
+    let (offset, offset_origin) = record_offset(crx_img.inner_x(), crx_img.inner_y(), arg);
+    let record = report::Record {
+        source: file.to_path_buf(),
+        source_sha256: None,
+        output: match (&first, arg.dedup) {
+            (Some(first), Some(dedup::DedupMode::Skip)) => first.clone(),
+            _ => output_path.to_path_buf(),
+        },
+        width: crx_img.width(),
+        height: crx_img.height(),
+        inner_x: offset.0,
+        inner_y: offset.1,
+        offset_origin,
+        version: crx_img.version().number(),
+        bpp: crx_img.bpp(),
+        palette_spec: crx_img.palette_spec(),
+        clip_count: crx_img.clips().len(),
+        output_size,
+        duration: start.elapsed(),
+        decode_duration,
+    };
+    if let Some(first) = &first {
+        console::file_line(
+            console::Status::Skipped,
+            file,
+            format_args!("same pixels as {}", first.to_string_lossy()),
         );
+        return Ok(record);
+    }
+    print_success(&record);
+    Ok(record)
+}
+
+/// Whether `file` is written as decoded, as a PNG, with nothing that needs the whole image: no
+/// base, color correction, canvas, resizing, clips, deduplication or verification. Those images
+/// are streamed by [`convert_streamed`]. Lenient decoding keeps the whole image too, to write
+/// what could be decoded.
+fn streams(
+    file: &Path,
+    base: Option<&CrxFile>,
+    dedup: Option<&dedup::Dedup>,
+    arg: &ConvertArg,
+) -> bool {
+    arg.format == output::Format::Png
+        && !arg.mmap
+        && !arg.lenient
+        && (base.is_none() || arg.base.as_deref() == Some(file))
+        && arg.gamma.is_none()
+        && arg.drop_alpha.is_none()
+        && !arg.premultiply
+        && arg.canvas.is_none()
+        && arg.resize.is_none()
+        && arg.thumbnail.is_none()
+        && !arg.split_clips
+        && dedup.is_none()
+        && !arg.verify_write
+}
+
+/// Like [`convert_one`], for the images [`streams`] accepts: rows go from the decoder to the PNG
+/// encoder one at a time, so the decoded image is never held whole. Decoding is interleaved
+/// with encoding; its duration adds up the time spent on rows.
+fn convert_streamed(
+    file: &Path,
+    output_path: &Path,
+    sink: &dyn sink::OutputSink,
+    arg: &ConvertArg,
+    start: Instant,
+    fail: &dyn Fn(&'static str, io::Error) -> report::Failure,
+) -> Result<report::Record, report::Failure> {
+    let (input, read_time) = open_input(file).map_err(|e| fail("read", e))?;
+    let decode_start = Instant::now();
+    let mut decoder = tracing::info_span!("decode")
+        .in_scope(|| crx::CrxRowDecoder::new(input, &decode_options(arg)))
+        .map_err(|e| fail("decode", e.into()))?;
+    let mut decode_duration = decode_start.elapsed();
+    let metadata = decoder.metadata().clone();
+    let layout = output::PixelLayout::of_metadata(&metadata);
+    let stamped = stamped_options(file, arg);
+    let options = png_options(file, &stamped, metadata.color_key(), arg);
+
+    // a decode error surfaces as a failed write, but is reported as what it is.
+    let mut decode_error = None;
+    let stored = tracing::info_span!("write").in_scope(|| {
+        sink.store(output_path, &mut |w| {
+            let _span = tracing::info_span!("encode").entered();
+            output::write_png_rows(w, &layout, &options, &mut |emit| loop {
+                let row_start = Instant::now();
+                let row = decoder.next_row();
+                decode_duration += row_start.elapsed();
+                match row {
+                    Ok(Some(row)) => emit(row)?,
+                    Ok(None) => return Ok(()),
+                    Err(e) => {
+                        let e = io::Error::from(e);
+                        let stopped = io::Error::new(e.kind(), e.to_string());
+                        decode_error = Some(e);
+                        return Err(stopped);
+                    }
+                }
+            })
+        })
     });
+    let output_size = match (stored, decode_error) {
+        (_, Some(e)) => return Err(fail("decode", e)),
+        (Err(e), None) => return Err(fail("save", e)),
+        (Ok(size), None) => size,
+    };
+    let decode_duration = decode_duration.saturating_sub(read_time.get());
+
+    let (offset, offset_origin) = record_offset(metadata.inner_x(), metadata.inner_y(), arg);
+    let record = report::Record {
+        source: file.to_path_buf(),
+        source_sha256: None,
+        output: output_path.to_path_buf(),
+        width: metadata.width(),
+        height: metadata.height(),
+        inner_x: offset.0,
+        inner_y: offset.1,
+        offset_origin,
+        version: metadata.version().number(),
+        bpp: metadata.bpp(),
+        palette_spec: metadata.palette_spec(),
+        clip_count: metadata.clips().len(),
+        output_size,
+        duration: start.elapsed(),
+        decode_duration,
+    };
+    print_success(&record);
+    Ok(record)
+}
+
+/// Open `file` for decoding, buffered, with the time spent reading it so far, which decode times
+/// leave out. Inputs are decoded as they are read, so that time cannot be taken up front.
+fn open_input(file: &Path) -> io::Result<(TimedReader, Rc<Cell<Duration>>)> {
+    let input = tracing::info_span!("open").in_scope(|| archive::open(file))?;
+    let read_time = Rc::new(Cell::new(Duration::ZERO));
+    let input = TimedReader {
+        inner: input,
+        elapsed: read_time.clone(),
+    };
+    Ok((input, read_time))
+}
+
+/// A reader that adds the time spent in `inner` to `elapsed`.
+struct TimedReader {
+    inner: Box<dyn Read>,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+impl Read for TimedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        let read = self.inner.read(buf);
+        self.elapsed.set(self.elapsed.get() + start.elapsed());
+        read
+    }
+}
+
+/// The directories outputs are placed relative to under --output-dir.
+fn output_roots(arg: &ConvertArg) -> Vec<PathBuf> {
+    if !arg.relative_to.is_empty() {
+        return arg.relative_to.clone();
+    }
+    // outputs of glob matches are placed relative to the directory the pattern starts from.
+    arg.files
+        .iter()
+        .chain(&arg.archive)
+        .map(|path| walk::root(path))
+        .collect()
+}
+
+/// The options of [`RERUN_OPTIONS`] given on the command line, as shell words, e.g.
+/// `--format webp`.
+fn rerun_options(command: &clap::Command, matches: &ArgMatches) -> Vec<String> {
+    let mut words = Vec::new();
+    for id in RERUN_OPTIONS {
+        if matches.value_source(id) != Some(clap::parser::ValueSource::CommandLine) {
+            continue;
+        }
+        let Some(option) = command.get_arguments().find(|option| option.get_id() == id) else {
+            continue;
+        };
+        let long = format!("--{}", option.get_long().unwrap_or(id));
+        if !option.get_action().takes_values() {
+            words.push(escape::shell(&long));
+            continue;
+        }
+        for value in matches.get_raw(id).into_iter().flatten() {
+            words.push(escape::shell(&long));
+            words.push(escape::shell(&value.to_string_lossy()));
+        }
+    }
+    words
+}
+
+/// The options to stamp into the output of `file`: those given, and the directory the output
+/// was placed relative to under --output-dir.
+fn stamped_options(file: &Path, arg: &ConvertArg) -> String {
+    let mut words = arg.output_options.clone();
+    if arg.output_dir.is_some() {
+        if let Some(base) = paths::input_base(file, &output_roots(arg)) {
+            words.push("--relative-to".to_string());
+            words.push(escape::shell(&base.to_string_lossy()));
+        }
+    }
+    words.join(" ")
+}
+
+/// How `file`, whose color key is `color_key`, is written as PNG. Content-addressed outputs
+/// leave the source out, so that identical images from different files share one output.
+fn png_options<'a>(
+    file: &'a Path,
+    stamped_options: &'a str,
+    color_key: Option<u8>,
+    arg: &'a ConvertArg,
+) -> output::PngOptions<'a> {
+    let is_stdin = file == Path::new(archive::STDIN);
+    output::PngOptions {
+        source: (arg.layout != sink::Layout::Cas && !is_stdin).then_some(file),
+        options: Some(stamped_options),
+        directory: arg.working_dir.as_deref(),
+        force_color: if arg.force_rgba {
+            Some(output::ForceColor::Rgba)
+        } else if arg.force_rgb {
+            Some(output::ForceColor::Rgb)
+        } else if arg.truecolor {
+            Some(output::ForceColor::Truecolor)
+        } else {
+            None
+        },
+        encoder: arg.png_encoder,
+        dpi: arg.dpi,
+        transparent_index: arg
+            .transparent_index
+            .and_then(|index| index.resolve(color_key)),
+    }
+}
+
+/// The inner offset recorded for an image, moved to the top left of the screen with
+/// `--normalize-offsets`.
+fn record_offset(inner_x: i16, inner_y: i16, arg: &ConvertArg) -> ((i32, i32), crx::OffsetOrigin) {
+    let mut offset_origin: crx::OffsetOrigin = arg.offset_origin.into();
+    let mut offset = (inner_x as i32, inner_y as i32);
+    if let (true, Some(screen)) = (arg.normalize_offsets, arg.screen_size) {
+        offset = offset_origin.convert(offset, crx::OffsetOrigin::TopLeft, screen);
+        offset_origin = crx::OffsetOrigin::TopLeft;
+    }
+    (offset, offset_origin)
+}
+
+fn print_success(record: &report::Record) {
+    let (file, output_path) = (&record.source, &record.output);
+    console::file_fields(
+        console::Status::Success,
+        file,
+        format_args!("-> {}", output_path.to_string_lossy()),
+        &[
+            ("output", escape::json(&output_path.to_string_lossy())),
+            ("width", record.width.to_string()),
+            ("height", record.height.to_string()),
+            ("bpp", record.bpp.to_string()),
+            ("version", record.version.to_string()),
+            ("output_size", record.output_size.to_string()),
+            (
+                "duration_ms",
+                format!("{:.3}", record.duration.as_secs_f64() * 1e3),
+            ),
+        ],
+    );
+}
+
+/// The options outputs depend on, so that changing any of them, or the tool version, converts
+/// everything again.
+fn ledger_settings(arg: &ConvertArg) -> String {
+    format!(
+        "{} format={:?} quality={} png-encoder={:?} dpi={:?} rgba={} rgb={} truecolor={} transparent={:?} split-clips={} resize={:?} thumbnail={:?} filter={:?} dedup={:?} keep-alpha={} lenient={} premultiply={} drop-alpha={:?} gamma={:?} base={:?} canvas={:?}",
+        output::software_stamp(),
+        arg.format,
+        arg.quality,
+        arg.png_encoder,
+        arg.dpi,
+        arg.force_rgba,
+        arg.force_rgb,
+        arg.truecolor,
+        arg.transparent_index,
+        arg.split_clips,
+        arg.resize,
+        arg.thumbnail,
+        arg.filter,
+        arg.dedup,
+        arg.no_alpha_flip,
+        arg.lenient,
+        arg.premultiply,
+        arg.drop_alpha,
+        arg.gamma,
+        arg.base,
+        // the origin only moves images placed on a canvas.
+        arg.canvas
+            .map(|canvas| (canvas, crx::OffsetOrigin::from(arg.offset_origin)))
+    )
+}
+
+/// Where the clip at `index` of the image written to `output` goes.
+fn clip_path(output: &Path, index: usize) -> PathBuf {
+    sibling_path(output, &format!("clip{}", index))
+}
+
+/// `NAME.infix.EXT` next to `output`, `NAME.EXT`.
+fn sibling_path(output: &Path, infix: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, infix, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, infix),
+    };
+    output.with_file_name(name)
+}
+
+/// How sources are decoded: 8-bit images keep their palette for palette PNGs.
+fn decode_options(arg: &ConvertArg) -> crx::DecodeOptions {
+    crx::DecodeOptions {
+        keep_indexed: true,
+        keep_alpha: arg.no_alpha_flip,
+        strictness: if arg.lenient {
+            crx::Strictness::Lenient
+        } else {
+            crx::Strictness::Strict
+        },
+        ..Default::default()
+    }
+}
+
+/// SHA-256 of every source of `plan`, leaving out the ones that cannot be read.
+fn source_hashes(plan: &[(PathBuf, PathBuf)]) -> HashMap<PathBuf, String> {
+    plan.par_iter()
+        .filter_map(|(file, _)| {
+            let hash = archive::open(file).and_then(hash::sha256_hex_reader).ok()?;
+            Some((file.clone(), hash))
+        })
+        .collect()
+}
 
-    Ok(())
+fn parse_gamma(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(gamma) if gamma.is_finite() && gamma > 0.0 => Ok(gamma),
+        Ok(_) => Err(format!("`{}` is not a positive number", s)),
+        Err(e) => Err(e.to_string()),
+    }
 }
@@ -0,0 +1,157 @@
+use std::{
+    fs,
+    sync::{Condvar, Mutex},
+};
+
+/// Share of the limit above which fewer files are converted at once.
+const HIGH_WATER: f64 = 0.85;
+/// Share of the limit below which more files are converted at once again.
+const LOW_WATER: f64 = 0.6;
+
+/// Memory the process may use, from `--memory-limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLimit {
+    Bytes(u64),
+    /// The memory limit of the cgroup the process runs in.
+    Auto,
+}
+
+impl MemoryLimit {
+    /// The limit in bytes, `None` if it is `Auto` and no cgroup limit is set.
+    pub fn resolve(self) -> Option<u64> {
+        match self {
+            MemoryLimit::Bytes(bytes) => Some(bytes),
+            MemoryLimit::Auto => cgroup_limit(),
+        }
+    }
+}
+
+/// Parse `auto` or a size such as `512M` or `2G` (powers of 1024).
+pub fn parse_memory_limit(s: &str) -> Result<MemoryLimit, String> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(MemoryLimit::Auto);
+    }
+    let (number, shift) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 30),
+        Some((i, 't' | 'T')) => (&s[..i], 40),
+        _ => (s, 0),
+    };
+    let number = number.trim().parse::<u64>().map_err(|e| e.to_string())?;
+    match number.checked_mul(1 << shift) {
+        Some(0) => Err("the limit must be above zero".to_string()),
+        Some(bytes) => Ok(MemoryLimit::Bytes(bytes)),
+        None => Err(format!("`{}` is too large", s)),
+    }
+}
+
+/// Resident memory of the process, from `/proc/self/status`; `None` where that is not available.
+pub fn resident_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim();
+    kib.parse::<u64>().ok().map(|kib| kib * 1024)
+}
+
+/// The memory limit of the cgroup (v2, then v1) of the process, `None` if there is none.
+fn cgroup_limit() -> Option<u64> {
+    let v2 = fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|cgroups| {
+            let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+            fs::read_to_string(format!(
+                "/sys/fs/cgroup{}/memory.max",
+                path.trim_end_matches('/')
+            ))
+            .or_else(|_| fs::read_to_string("/sys/fs/cgroup/memory.max"))
+            .ok()
+        });
+    let limit =
+        v2.or_else(|| fs::read_to_string("/sys/fs/cgroup/memory/memory.limit_in_bytes").ok())?;
+    // `max` in v2, and a huge page-aligned number in v1, mean no limit.
+    limit
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&limit| limit < 1 << 60)
+}
+
+struct State {
+    in_flight: usize,
+    allowed: usize,
+    /// The smallest `allowed` so far.
+    lowest: usize,
+}
+
+/// Limits how many files are converted at once, shrinking that number while the resident
+/// memory of the process is close to `limit` and growing it back, up to `max`, once memory is
+/// freed. At least one file is always let through, so the run keeps going.
+pub struct MemoryGovernor {
+    limit: u64,
+    max: usize,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+/// Held while a file is converted.
+pub struct Permit<'a>(&'a MemoryGovernor);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.freed.notify_all();
+    }
+}
+
+impl MemoryGovernor {
+    /// Let up to `max` files through while under `limit` bytes.
+    pub fn new(limit: u64, max: usize) -> Self {
+        Self {
+            limit,
+            max: max.max(1),
+            state: Mutex::new(State {
+                in_flight: 0,
+                allowed: max.max(1),
+                lowest: max.max(1),
+            }),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Wait until another file may be converted.
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            self.adjust(&mut state);
+            if state.in_flight < state.allowed {
+                state.in_flight += 1;
+                return Permit(self);
+            }
+            state = self.freed.wait(state).unwrap();
+        }
+    }
+
+    /// The fewest files converted at once so far, below `max` if memory ran short.
+    pub fn lowest(&self) -> usize {
+        self.state.lock().unwrap().lowest
+    }
+
+    fn adjust(&self, state: &mut State) {
+        let Some(resident) = resident_bytes() else {
+            return;
+        };
+        let usage = resident as f64 / self.limit as f64;
+        if usage > HIGH_WATER {
+            state.allowed = (state.in_flight / 2).max(1);
+            state.lowest = state.lowest.min(state.allowed);
+        } else if usage < LOW_WATER && state.allowed < self.max {
+            state.allowed += 1;
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use crate::fast_png;
+use crx::{CrxFile, CrxMetadata};
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+/// tEXt keyword holding the name and version of the tool that wrote the file.
+pub const SOFTWARE_KEY: &str = "Software";
+/// tEXt (or, for non-ASCII paths, iTXt) keyword holding the path of the CRX file the image was
+/// converted from.
+pub const SOURCE_KEY: &str = "Source";
+/// tEXt or iTXt keyword holding the options the source was converted with, as shell words.
+pub const OPTIONS_KEY: &str = "Options";
+/// tEXt or iTXt keyword holding the working directory the source was converted from.
+pub const DIRECTORY_KEY: &str = "Directory";
+
+/// The stamp written into the `Software` chunk, e.g. `crx-convert 0.3.0`.
+pub fn software_stamp() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// Color type every image is converted to, instead of the one matching its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForceColor {
+    /// Drop the alpha channel of 32-bit sources and expand palettes.
+    Rgb,
+    /// Add an opaque alpha channel to 24-bit sources and expand palettes.
+    Rgba,
+    /// Expand palettes, keeping truecolor sources as they are.
+    Truecolor,
+}
+
+/// Palette entry written as transparent, from `--transparent-index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransparentIndex {
+    Index(u8),
+    /// The color key of each source, see [`CrxFile::color_key`].
+    Auto,
+}
+
+impl TransparentIndex {
+    /// The index to write as transparent for an image with `color_key`, if any.
+    pub fn resolve(self, color_key: Option<u8>) -> Option<u8> {
+        match self {
+            TransparentIndex::Index(index) => Some(index),
+            TransparentIndex::Auto => color_key,
+        }
+    }
+}
+
+/// Parse `auto` or a palette index from 0 to 255.
+pub fn parse_transparent_index(s: &str) -> Result<TransparentIndex, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(TransparentIndex::Auto);
+    }
+    s.parse()
+        .map(TransparentIndex::Index)
+        .map_err(|_| format!("`{}` is neither `auto` nor an index from 0 to 255", s))
+}
+
+/// Parse an RGB color written as `RRGGBB`, with or without a leading `#`.
+pub fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("`{}` is not a color like `#RRGGBB`", s)),
+    }
+}
+
+/// How the image data of PNG outputs is compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PngEncoder {
+    /// Deflate chunks of rows in parallel at the fastest level, for somewhat larger files
+    Fast,
+    #[default]
+    Default,
+    /// Highest level of the png crate, for slightly smaller files
+    Small,
+}
+
+/// How images are scaled by `--resize` and `--thumbnail`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ResizeFilter {
+    /// Nearest neighbor, keeping hard pixel edges and the palette of 8-bit images
+    Nearest,
+    /// Bilinear
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for image::imageops::FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => Self::Nearest,
+            ResizeFilter::Triangle => Self::Triangle,
+            ResizeFilter::CatmullRom => Self::CatmullRom,
+            ResizeFilter::Gaussian => Self::Gaussian,
+            ResizeFilter::Lanczos3 => Self::Lanczos3,
+        }
+    }
+}
+
+/// The size `--resize` scales a `width` x `height` image to: `target`, where a side of 0 follows
+/// the aspect ratio of the image, or the image size if both are 0.
+pub fn resized_size(width: u16, height: u16, target: (u16, u16)) -> (u16, u16) {
+    let scale = |side: u16, from: u16, to: u16| {
+        (side as f64 * to as f64 / from.max(1) as f64)
+            .round()
+            .clamp(1.0, u16::MAX as f64) as u16
+    };
+    match target {
+        (0, 0) => (width, height),
+        (0, to_height) => (scale(width, height, to_height), to_height),
+        (to_width, 0) => (to_width, scale(height, width, to_width)),
+        size => size,
+    }
+}
+
+/// The size of a thumbnail of a `width` x `height` image that fits in `size` x `size`, keeping
+/// the aspect ratio; smaller images keep their size.
+pub fn thumbnail_size(width: u16, height: u16, size: u16) -> (u16, u16) {
+    if width <= size && height <= size {
+        return (width, height);
+    }
+    if width >= height {
+        resized_size(width, height, (size, 0))
+    } else {
+        resized_size(width, height, (0, size))
+    }
+}
+
+/// Lookup table of the gamma correction `255 * (v / 255) ^ (1 / gamma)`.
+pub fn gamma_lut(gamma: f64) -> [u8; 256] {
+    std::array::from_fn(|v| ((v as f64 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8)
+}
+
+/// File format of the outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Png,
+    /// Lossless WebP
+    Webp,
+    Bmp,
+    Tiff,
+    /// Lossy, at `--quality`; the alpha channel is dropped
+    Jpeg,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Webp => "webp",
+            Format::Bmp => "bmp",
+            Format::Tiff => "tiff",
+            Format::Jpeg => "jpg",
+        }
+    }
+}
+
+/// How images are written as PNG.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PngOptions<'a> {
+    /// Path stamped into the `Source` chunk.
+    pub source: Option<&'a Path>,
+    /// Stamped into the `Options` chunk with the source, see [`OPTIONS_KEY`].
+    pub options: Option<&'a str>,
+    /// Stamped into the `Directory` chunk with the source, see [`DIRECTORY_KEY`].
+    pub directory: Option<&'a Path>,
+    pub force_color: Option<ForceColor>,
+    pub encoder: PngEncoder,
+    /// Print resolution stamped into the `pHYs` chunk, in dots per inch.
+    pub dpi: Option<u32>,
+    /// Palette entry marked as fully transparent in the `tRNS` chunk of palette PNGs.
+    pub transparent_index: Option<u8>,
+}
+
+/// The size and pixel layout of the rows of an image, as stored in [`CrxFile::raw_buffer`] or
+/// returned by [`crx::CrxRowDecoder::next_row`].
+#[derive(Debug, Clone, Copy)]
+pub struct PixelLayout<'a> {
+    pub width: u16,
+    pub height: u16,
+    pub bpp: usize,
+    pub palette: Option<&'a [[u8; 3]]>,
+}
+
+impl<'a> PixelLayout<'a> {
+    pub fn of(crx: &'a CrxFile) -> Self {
+        Self {
+            width: crx.width(),
+            height: crx.height(),
+            bpp: crx.bpp(),
+            palette: crx.palette(),
+        }
+    }
+
+    /// The layout of rows decoded from `metadata` with `keep_indexed`.
+    pub fn of_metadata(metadata: &'a CrxMetadata) -> Self {
+        Self {
+            width: metadata.width(),
+            height: metadata.height(),
+            bpp: metadata.bpp(),
+            palette: metadata.palette(),
+        }
+    }
+}
+
+/// Feeds every row of an image, in order, to the given function.
+pub type RowFeed<'a> = &'a mut dyn FnMut(&mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()>;
+
+/// Write `crx` as a PNG to `writer`, see [`write_png_rows`].
+pub fn write_png<W: Write>(writer: W, crx: &CrxFile, options: &PngOptions) -> io::Result<()> {
+    write_png_rows(writer, &PixelLayout::of(crx), options, &mut |emit| {
+        crx.rows().try_for_each(emit)
+    })
+}
+
+/// Write the image laid out as `image`, whose rows `rows` feeds, as a PNG to `writer`, stamped
+/// with the tool version and, if given, the source path.
+///
+/// 8-bit images with a palette are written as palette PNGs unless a color type is forced. Rows
+/// are fed to the encoder one at a time, so no intermediate image or filtered copy of the whole
+/// frame is built; with rows from a [`crx::CrxRowDecoder`], neither is the decoded image.
+/// [`PngEncoder::Fast`] only holds back the filtered rows of a batch, 256 KiB per thread.
+pub fn write_png_rows<W: Write>(
+    writer: W,
+    image: &PixelLayout,
+    options: &PngOptions,
+    rows: RowFeed,
+) -> io::Result<()> {
+    let color = color_type(image, options.force_color)?;
+
+    let mut encoder = png::Encoder::new(writer, image.width as u32, image.height as u32);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    if options.encoder == PngEncoder::Small {
+        encoder.set_compression(png::Compression::High);
+    }
+    if let Some(palette) = image.palette.filter(|_| color == png::ColorType::Indexed) {
+        encoder.set_palette(palette.concat());
+        if let Some(index) = options.transparent_index {
+            if index as usize >= palette.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "transparent index `{}` is outside the palette of `{}` colors",
+                        index,
+                        palette.len()
+                    ),
+                ));
+            }
+            // entries after the last one listed stay opaque.
+            let mut alpha = vec![0xFF; index as usize + 1];
+            alpha[index as usize] = 0;
+            encoder.set_trns(alpha);
+        }
+    }
+    if let Some(dpi) = options.dpi {
+        // pHYs counts pixels per meter.
+        let ppm = (dpi as f64 / 0.0254).round() as u32;
+        encoder.set_pixel_dims(Some(png::PixelDimensions {
+            xppu: ppm,
+            yppu: ppm,
+            unit: png::Unit::Meter,
+        }));
+    }
+    encoder.add_text_chunk(SOFTWARE_KEY.to_string(), software_stamp())?;
+    if let Some(source) = options.source {
+        let directory = options.directory.map(|dir| dir.to_string_lossy());
+        let stamps = [
+            (SOURCE_KEY, Some(source.to_string_lossy())),
+            (OPTIONS_KEY, options.options.map(Into::into)),
+            (DIRECTORY_KEY, directory),
+        ];
+        for (key, text) in stamps {
+            let Some(text) = text else { continue };
+            // tEXt only holds Latin-1, so e.g. Japanese paths go into an iTXt chunk.
+            if text.is_ascii() {
+                encoder.add_text_chunk(key.to_string(), text.into_owned())?;
+            } else {
+                encoder.add_itxt_chunk(key.to_string(), text.into_owned())?;
+            }
+        }
+    }
+
+    let mut writer = encoder.write_header()?;
+    let mut converted = Vec::new();
+    if options.encoder == PngEncoder::Fast {
+        let mut idat = fast_png::IdatWriter::new(&mut writer);
+        rows(&mut |row| idat.write_row(convert_row(row, image, color, &mut converted)))?;
+        idat.finish()?;
+    } else {
+        let mut stream = writer.stream_writer()?;
+        rows(&mut |row| stream.write_all(convert_row(row, image, color, &mut converted)))?;
+        stream.finish()?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Write `crx` as `format` through the `image` crate, e.g. for formats other than PNG.
+///
+/// Unlike [`write_png`], the whole image is converted and encoded in memory first, palettes are
+/// expanded to RGB and no metadata is written. `quality` (1 to 100) only applies to JPEG.
+pub fn write_image<W: Write>(
+    mut writer: W,
+    crx: &CrxFile,
+    format: Format,
+    force_color: Option<ForceColor>,
+    quality: u8,
+) -> io::Result<()> {
+    let layout = PixelLayout::of(crx);
+    let color = match color_type(&layout, force_color)? {
+        png::ColorType::Indexed => png::ColorType::Rgb,
+        // JPEG has no alpha channel.
+        _ if format == Format::Jpeg => png::ColorType::Rgb,
+        color => color,
+    };
+    let mut pixels = Vec::with_capacity(crx.width() as usize * crx.height() as usize * 4);
+    let mut converted = Vec::new();
+    for row in crx.rows() {
+        pixels.extend_from_slice(convert_row(row, &layout, color, &mut converted));
+    }
+    let (width, height) = (crx.width() as u32, crx.height() as u32);
+    let too_small = || io::Error::new(io::ErrorKind::InvalidData, "pixel buffer too small");
+    let image = match color {
+        png::ColorType::Rgba => image::DynamicImage::ImageRgba8(
+            image::RgbaImage::from_raw(width, height, pixels).ok_or_else(too_small)?,
+        ),
+        _ => image::DynamicImage::ImageRgb8(
+            image::RgbImage::from_raw(width, height, pixels).ok_or_else(too_small)?,
+        ),
+    };
+
+    // TIFF needs to seek, so encode into memory.
+    let mut encoded = io::Cursor::new(Vec::new());
+    let result = match format {
+        Format::Jpeg => image.write_with_encoder(
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, quality),
+        ),
+        Format::Png => image.write_to(&mut encoded, image::ImageFormat::Png),
+        Format::Webp => image.write_to(&mut encoded, image::ImageFormat::WebP),
+        Format::Bmp => image.write_to(&mut encoded, image::ImageFormat::Bmp),
+        Format::Tiff => image.write_to(&mut encoded, image::ImageFormat::Tiff),
+    };
+    result.map_err(io::Error::other)?;
+    writer.write_all(encoded.get_ref())
+}
+
+/// Decode the PNG in `data` and check that it holds exactly the pixels (and palette) that
+/// [`write_png`] writes for `crx` with `options`.
+pub fn verify_png(data: &[u8], crx: &CrxFile, options: &PngOptions) -> io::Result<()> {
+    let mismatch =
+        |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{} mismatch", what));
+    let layout = PixelLayout::of(crx);
+    let color = color_type(&layout, options.force_color)?;
+
+    let mut decoder = png::Decoder::new(io::Cursor::new(data));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info()?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "image too large"))?;
+    let mut buffer = vec![0; size];
+    let frame = reader.next_frame(&mut buffer)?;
+    if (frame.width, frame.height) != (crx.width() as u32, crx.height() as u32) {
+        return Err(mismatch("dimension"));
+    }
+    if frame.color_type != color {
+        return Err(mismatch("color type"));
+    }
+    if frame.color_type == png::ColorType::Indexed {
+        let palette = reader.info().palette.as_deref().unwrap_or_default();
+        if Some(palette) != crx.palette().map(|p| p.concat()).as_deref() {
+            return Err(mismatch("palette"));
+        }
+    }
+    let mut rows = buffer[..frame.buffer_size()].chunks(frame.line_size);
+    let mut converted = Vec::new();
+    if !crx
+        .rows()
+        .all(|row| rows.next() == Some(convert_row(row, &layout, color, &mut converted)))
+    {
+        return Err(mismatch("pixel"));
+    }
+    Ok(())
+}
+
+fn color_type(image: &PixelLayout, force: Option<ForceColor>) -> io::Result<png::ColorType> {
+    match (image.bpp, force) {
+        (8 | 24 | 32, Some(ForceColor::Rgb)) => Ok(png::ColorType::Rgb),
+        (8 | 24 | 32, Some(ForceColor::Rgba)) => Ok(png::ColorType::Rgba),
+        (8, None) => Ok(png::ColorType::Indexed),
+        (8 | 24, _) => Ok(png::ColorType::Rgb),
+        (32, _) => Ok(png::ColorType::Rgba),
+        (x, _) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid bpp `{}`", x),
+        )),
+    }
+}
+
+/// `row` of `image` in the `color` layout, converted into `buffer` when the layouts differ.
+fn convert_row<'a>(
+    row: &'a [u8],
+    image: &PixelLayout,
+    color: png::ColorType,
+    buffer: &'a mut Vec<u8>,
+) -> &'a [u8] {
+    let source_size = image.bpp / 8;
+    let target_size = color.samples();
+    if source_size == target_size {
+        return row;
+    }
+    let palette = image.palette.unwrap_or_default();
+    buffer.clear();
+    for pixel in row.chunks_exact(source_size) {
+        let rgb = match pixel {
+            [index] => palette[*index as usize],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        };
+        buffer.extend_from_slice(&rgb);
+        if target_size == 4 {
+            buffer.push(pixel.get(3).copied().unwrap_or(0xFF));
+        }
+    }
+    buffer
+}
@@ -0,0 +1,194 @@
+use crate::{archive, console};
+use clap::Args;
+use crx::CrxFile;
+use image::{imageops, Rgba, RgbaImage};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct PackArg {
+    /// The edited PNG, of the same dimensions as the original
+    edited: PathBuf,
+    /// The CRX file the PNG was converted from, whose version, flags, palette and clips are kept
+    #[arg(long, value_name = "CRX")]
+    like: PathBuf,
+    /// Where to write the new CRX file; may be the original
+    #[arg(short, long, value_name = "CRX")]
+    output: PathBuf,
+    /// What to do when the PNG is not the size of the original
+    #[arg(long, value_enum, default_value_t = Fit::Error)]
+    fit: Fit,
+}
+
+/// How a PNG of another size than the original is packed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Fit {
+    /// Refuse to pack it
+    Error,
+    /// Extend the image to cover both sizes, filling the new area on the right and bottom with
+    /// transparent black (or the first palette color)
+    Pad,
+    /// Cut the image down to the area both sizes share
+    Crop,
+    /// Scale the PNG to the size of the original; 8-bit images use nearest-neighbor scaling to
+    /// stay within the palette
+    Resize,
+}
+
+pub fn run(arg: &PackArg) -> io::Result<()> {
+    pack(&arg.edited, &arg.like, &arg.output, arg.fit)
+}
+
+/// Write `edited` into a copy of the CRX file `like` at `output`.
+pub fn pack(edited_path: &Path, like: &Path, output: &Path, fit_policy: Fit) -> io::Result<()> {
+    let original = CrxFile::read_indexed(archive::open(like)?)?;
+    let edited = image::open(edited_path).map_err(io::Error::other)?;
+    let rgba = fit(edited.into_rgba8(), &original, fit_policy)?;
+    let (width, height) = rgba.dimensions();
+    if (width, height) != (original.width() as u32, original.height() as u32) {
+        console::file_line(
+            console::Status::Note("Fit"),
+            edited_path,
+            format_args!(
+                "{}x{} -> {}x{}",
+                original.width(),
+                original.height(),
+                width,
+                height
+            ),
+        );
+    }
+    if original.bpp() != 32 && rgba.pixels().any(|p| p.0[3] != 0xFF) {
+        console::file_line(
+            console::Status::Warning,
+            edited_path,
+            "has transparent pixels, the original has no alpha channel; dropping it",
+        );
+    }
+    let pixels = match (original.bpp(), original.palette()) {
+        (8, Some(palette)) => to_indices(
+            rgba.pixels().map(|p| [p.0[0], p.0[1], p.0[2]]),
+            palette,
+            width,
+        )?,
+        (24, _) => rgba
+            .pixels()
+            .flat_map(|p| [p.0[0], p.0[1], p.0[2]])
+            .collect(),
+        _ => rgba.into_raw(),
+    };
+
+    let packed = original.with_image(width as u16, height as u16, pixels)?;
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(fs::File::create(output)?);
+    packed.write(&mut writer)?;
+    writer.flush()?;
+    console::file_line(
+        console::Status::Success,
+        edited_path,
+        format_args!("-> {}", output.to_string_lossy()),
+    );
+    Ok(())
+}
+
+/// Bring `edited` to the size `policy` gives for `original`.
+fn fit(edited: RgbaImage, original: &CrxFile, policy: Fit) -> io::Result<RgbaImage> {
+    let (width, height) = (original.width() as u32, original.height() as u32);
+    if edited.dimensions() == (width, height) {
+        return Ok(edited);
+    }
+    let fitted = match policy {
+        Fit::Error => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "dimension mismatch: {}x{} vs {}x{}, see --fit",
+                    width,
+                    height,
+                    edited.width(),
+                    edited.height()
+                ),
+            ))
+        }
+        Fit::Pad => {
+            let fill = match (original.bpp(), original.palette()) {
+                (8, Some(palette)) => palette
+                    .first()
+                    .map_or([0, 0, 0, 0xFF], |c| [c[0], c[1], c[2], 0xFF]),
+                (32, _) => [0, 0, 0, 0],
+                _ => [0, 0, 0, 0xFF],
+            };
+            let mut padded = RgbaImage::from_pixel(
+                edited.width().max(width),
+                edited.height().max(height),
+                Rgba(fill),
+            );
+            imageops::replace(&mut padded, &edited, 0, 0);
+            padded
+        }
+        Fit::Crop => imageops::crop_imm(
+            &edited,
+            0,
+            0,
+            edited.width().min(width),
+            edited.height().min(height),
+        )
+        .to_image(),
+        Fit::Resize => {
+            let filter = if original.bpp() == 8 {
+                imageops::FilterType::Nearest
+            } else {
+                imageops::FilterType::Lanczos3
+            };
+            imageops::resize(&edited, width, height, filter)
+        }
+    };
+    if fitted.width() > u16::MAX as u32 || fitted.height() > u16::MAX as u32 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}x{} is too large for CRX",
+                fitted.width(),
+                fitted.height()
+            ),
+        ));
+    }
+    Ok(fitted)
+}
+
+/// Map every color back to its index in `palette`, failing on the first color the palette does
+/// not hold.
+fn to_indices(
+    colors: impl Iterator<Item = [u8; 3]>,
+    palette: &[[u8; 3]],
+    width: u32,
+) -> io::Result<Vec<u8>> {
+    let mut lookup = HashMap::new();
+    for (index, color) in palette.iter().enumerate().rev() {
+        lookup.insert(*color, index as u8);
+    }
+    colors
+        .enumerate()
+        .map(|(i, color)| {
+            lookup.get(&color).copied().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "color #{:02x}{:02x}{:02x} at ({}, {}) is not in the palette of the original",
+                        color[0],
+                        color[1],
+                        color[2],
+                        i as u32 % width,
+                        i as u32 / width
+                    ),
+                )
+            })
+        })
+        .collect()
+}
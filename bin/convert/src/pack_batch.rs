@@ -0,0 +1,136 @@
+use crate::{
+    console,
+    pack::{self, Fit},
+};
+use clap::{Args, ValueEnum};
+use rayon::prelude::*;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Column names of the optional header row of a mapping file.
+const HEADER: [&str; 3] = ["edited", "like", "output"];
+
+#[derive(Args)]
+pub struct PackBatchArg {
+    /// CSV file with one `edited,like,output[,fit]` row per image; relative paths are relative to
+    /// the mapping file, and lines starting with `#` are ignored
+    map: PathBuf,
+    /// What to do when a PNG is not the size of its original, for rows without a fit column
+    #[arg(long, value_enum, default_value_t = Fit::Error)]
+    fit: Fit,
+}
+
+/// One row of a mapping file.
+struct Entry {
+    line: usize,
+    edited: PathBuf,
+    like: PathBuf,
+    output: PathBuf,
+    fit: Fit,
+}
+
+pub fn run(arg: &PackBatchArg) -> io::Result<()> {
+    let entries = read_map(&arg.map, arg.fit)?;
+    let failed = entries
+        .par_iter()
+        .filter(|entry| {
+            pack::pack(&entry.edited, &entry.like, &entry.output, entry.fit)
+                .inspect_err(|e| {
+                    console::file_line(
+                        console::Status::Failed,
+                        &entry.edited,
+                        format_args!("line {}: {}", entry.line, e),
+                    )
+                })
+                .is_err()
+        })
+        .count();
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!("{} packed, {} failed", entries.len() - failed, failed),
+    );
+    if failed > 0 {
+        return Err(io::Error::other(format!(
+            "{} image(s) failed to pack",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+/// Parse the mapping file at `path`, checking every row before anything is packed.
+fn read_map(path: &Path, default_fit: Fit) -> io::Result<Vec<Entry>> {
+    let text = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let invalid = |line: usize, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}: {}", path.to_string_lossy(), line, message),
+        )
+    };
+
+    let mut entries = Vec::new();
+    for (index, row) in text.lines().enumerate() {
+        let line = index + 1;
+        let row = row.trim();
+        if row.is_empty() || row.starts_with('#') {
+            continue;
+        }
+        let fields = split_row(row).map_err(|e| invalid(line, e))?;
+        if entries.is_empty() && fields.iter().take(3).eq(HEADER.iter()) {
+            continue;
+        }
+        let (edited, like, output, fit) = match fields.as_slice() {
+            [edited, like, output] => (edited, like, output, None),
+            [edited, like, output, fit] => (edited, like, output, Some(fit)),
+            _ => {
+                return Err(invalid(
+                    line,
+                    format!("expected 3 or 4 columns, found {}", fields.len()),
+                ))
+            }
+        };
+        let fit = match fit.filter(|fit| !fit.is_empty()) {
+            Some(fit) => Fit::from_str(fit, true).map_err(|e| invalid(line, e))?,
+            None => default_fit,
+        };
+        entries.push(Entry {
+            line,
+            edited: dir.join(edited),
+            like: dir.join(like),
+            output: dir.join(output),
+            fit,
+        });
+    }
+    Ok(entries)
+}
+
+/// Split a CSV row into its fields, unquoting quoted ones.
+fn split_row(row: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = row.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            (',', false) => fields.push(std::mem::take(&mut field).trim().to_string()),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field.trim().to_string());
+    Ok(fields)
+}
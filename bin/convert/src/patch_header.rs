@@ -0,0 +1,132 @@
+use crate::{console, walk};
+use clap::Args;
+use crx::CrxFile;
+use std::{
+    fs,
+    io::{self, BufReader, Seek},
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct PatchHeaderArg {
+    /// CRX files to rewrite in place, or directories to search for them
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// New inner offset
+    #[arg(long, value_name = "X,Y", allow_hyphen_values = true, value_parser = parse_offset)]
+    inner_offset: Option<(i16, i16)>,
+    /// Move and resize clip table entry N, keeping its unknown words; may be repeated
+    #[arg(
+        long,
+        value_name = "N=X,Y,WxH",
+        allow_hyphen_values = true,
+        value_parser = parse_clip_edit
+    )]
+    clip: Vec<ClipEdit>,
+}
+
+/// A `--clip` argument: the new rectangle of one clip table entry.
+#[derive(Debug, Clone, Copy)]
+struct ClipEdit {
+    index: usize,
+    x: i16,
+    y: i16,
+    width: i16,
+    height: i16,
+}
+
+pub fn run(arg: &PatchHeaderArg) -> io::Result<()> {
+    if arg.inner_offset.is_none() && arg.clip.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "nothing to patch, pass --inner-offset or --clip",
+        ));
+    }
+    let files = walk::collect_files(&arg.paths, "crx", &Default::default())?;
+    let mut failed = 0;
+    for file in &files {
+        match patch(file, arg) {
+            Ok(()) => console::file_line(
+                console::Status::Success,
+                file,
+                format_args!(
+                    "{}{} clip(s) rewritten",
+                    match arg.inner_offset {
+                        Some((x, y)) => format!("inner offset ({}, {}), ", x, y),
+                        None => String::new(),
+                    },
+                    arg.clip.len()
+                ),
+            ),
+            Err(e) => {
+                failed += 1;
+                console::file_line(console::Status::Failed, file, format_args!("patch: {}", e));
+            }
+        }
+    }
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!("{} patched, {} failed", files.len() - failed, failed),
+    );
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} file(s) not patched", failed)));
+    }
+    Ok(())
+}
+
+fn patch(path: &Path, arg: &PatchHeaderArg) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let clips = if arg.clip.is_empty() {
+        None
+    } else {
+        let mut clips = CrxFile::read_metadata(BufReader::new(&mut file))?
+            .clips()
+            .to_vec();
+        let count = clips.len();
+        for edit in &arg.clip {
+            let clip = clips.get_mut(edit.index).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no clip {}, the table has {}", edit.index, count),
+                )
+            })?;
+            clip.x = edit.x;
+            clip.y = edit.y;
+            clip.width = edit.width;
+            clip.height = edit.height;
+        }
+        file.rewind()?;
+        Some(clips)
+    };
+    CrxFile::patch_header(&mut file, arg.inner_offset, clips.as_deref())
+}
+
+/// Parse an `X,Y` offset.
+fn parse_offset(s: &str) -> Result<(i16, i16), String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("`{}` is not X,Y", s))?;
+    let parse = |n: &str| n.trim().parse::<i16>().map_err(|e| e.to_string());
+    Ok((parse(x)?, parse(y)?))
+}
+
+/// Parse an `N=X,Y,WxH` clip edit.
+fn parse_clip_edit(s: &str) -> Result<ClipEdit, String> {
+    let invalid = || format!("`{}` is not N=X,Y,WxH", s);
+    let (index, rect) = s.split_once('=').ok_or_else(invalid)?;
+    let mut parts = rect.split(',');
+    let (Some(x), Some(y), Some(size), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let parse = |n: &str| n.trim().parse::<i16>().map_err(|e| e.to_string());
+    Ok(ClipEdit {
+        index: index.trim().parse::<usize>().map_err(|e| e.to_string())?,
+        x: parse(x)?,
+        y: parse(y)?,
+        width: parse(width)?,
+        height: parse(height)?,
+    })
+}
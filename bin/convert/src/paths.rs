@@ -0,0 +1,326 @@
+use crate::{archive, output};
+use crx::CrxMetadata;
+use std::path::{Component, Path, PathBuf};
+
+/// Placeholders of a [`NameTemplate`], and whether they need the header of the source.
+const PLACEHOLDERS: &[(&str, bool)] = &[
+    ("stem", false),
+    ("ext", false),
+    ("parent", false),
+    ("width", true),
+    ("height", true),
+    ("bpp", true),
+];
+
+/// How outputs are named, e.g. `{stem}_{width}x{height}.{ext}`, see `--name-template`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed `{{` in `{}`", s))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.iter().any(|(known, _)| *known == name) {
+                let known: Vec<_> = PLACEHOLDERS
+                    .iter()
+                    .map(|(known, _)| format!("{{{}}}", known))
+                    .collect();
+                return Err(format!(
+                    "unknown placeholder `{{{}}}`, expected one of {}",
+                    name,
+                    known.join(", ")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(Self(s.to_string()))
+    }
+
+    /// Whether rendering needs the header of the source.
+    pub fn needs_header(&self) -> bool {
+        PLACEHOLDERS
+            .iter()
+            .any(|(name, header)| *header && self.0.contains(&format!("{{{}}}", name)))
+    }
+
+    /// `output`, the default output of `file`, renamed after the template. `{parent}` is the
+    /// directory `file` is in, or the archive it is an entry of. Names may hold `/` to add
+    /// directories, which stay below the directory of `output`.
+    pub fn apply(
+        &self,
+        output: &Path,
+        file: &Path,
+        format: output::Format,
+        header: Option<&CrxMetadata>,
+    ) -> PathBuf {
+        let parent = match archive::containing_archive(file) {
+            Some(archive) => archive
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned()),
+            None => file
+                .parent()
+                .and_then(Path::file_name)
+                .map(|s| s.to_string_lossy().into_owned()),
+        };
+        let mut name = self.0.clone();
+        let values = [
+            (
+                "stem",
+                file.file_stem().map(|s| s.to_string_lossy().into_owned()),
+            ),
+            ("ext", Some(format.extension().to_string())),
+            ("parent", parent),
+            ("width", header.map(|h| h.width().to_string())),
+            ("height", header.map(|h| h.height().to_string())),
+            ("bpp", header.map(|h| h.bpp().to_string())),
+        ];
+        for (placeholder, value) in values {
+            name = name.replace(
+                &format!("{{{}}}", placeholder),
+                value.as_deref().unwrap_or_default(),
+            );
+        }
+        output
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(relative_name(Path::new(&name)))
+    }
+}
+
+/// Where `file` is converted to next to itself; archive entries go to a folder named after the
+/// archive.
+pub fn output_path(file: &Path, format: output::Format) -> PathBuf {
+    let mut output = match archive::containing_archive(file) {
+        Some(pck) => pck
+            .with_extension("")
+            .join(file.file_name().unwrap_or_default()),
+        None => file.to_path_buf(),
+    };
+    output.set_extension(format.extension());
+    output
+}
+
+/// `output` moved under `dir`, at its path relative to the input it was found from: below a
+/// given directory, or beside a given file. When several inputs contain it, the closest one
+/// wins; paths outside every input keep their file name only.
+///
+/// Inputs and outputs are compared as absolute paths with `.` and `..` resolved, so e.g. `../cg`
+/// and `/game/cg` name the same root, and the result never leaves `dir`.
+pub fn under_dir(dir: &Path, output: &Path, inputs: &[PathBuf]) -> PathBuf {
+    let output = normalize(&absolute(output));
+    let relative = input_base(&output, inputs)
+        .and_then(|base| output.strip_prefix(base).ok().map(Path::to_path_buf))
+        .or_else(|| output.file_name().map(PathBuf::from))
+        .unwrap_or_default();
+    dir.join(relative_name(&relative))
+}
+
+/// The directory [`under_dir`] places `path` relative to: the closest given directory that
+/// contains it, or that of a given file beside it. Absolute, with `.` and `..` resolved.
+pub fn input_base(path: &Path, inputs: &[PathBuf]) -> Option<PathBuf> {
+    let path = normalize(&absolute(path));
+    inputs
+        .iter()
+        .filter_map(|input| {
+            let input = normalize(&absolute(input));
+            let base = if input.is_dir() {
+                input
+            } else {
+                input.parent()?.to_path_buf()
+            };
+            path.starts_with(&base).then_some(base)
+        })
+        .max_by_key(|base| base.components().count())
+}
+
+/// `path` without prefix, root, `.` and leading `..` components, with the remaining `..`
+/// resolved, as used for names inside a sink, e.g. `C:\cg\..\bg\a.png` gives `bg/a.png`.
+pub fn relative_name(path: &Path) -> String {
+    normalize(path)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `path` relative to the current directory, or as is if that cannot be found. Absolute paths
+/// are returned as they are.
+pub fn absolute(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// How to reach `path` from the directory `dir`, e.g. `../bg/a.png` for `cg/bg/a.png` from
+/// `cg/ev`.
+pub fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    let path = normalize(&absolute(path));
+    let dir = normalize(&absolute(dir));
+    let common = path
+        .components()
+        .zip(dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = dir.components().skip(common).map(|_| "..").collect();
+    relative.extend(path.components().skip(common));
+    relative
+}
+
+/// Resolve `.` and `..` without touching the file system, so that virtual archive paths and
+/// outputs that do not exist yet are handled too. `..` above a root is dropped; leading `..` of a
+/// relative path are kept.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut parts: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match parts.last() {
+                Some(Component::Normal(_)) => {
+                    parts.pop();
+                }
+                Some(Component::Prefix(_) | Component::RootDir) => {}
+                _ => parts.push(component),
+            },
+            _ => parts.push(component),
+        }
+    }
+    parts.iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_resolves_dots() {
+        assert_eq!(
+            normalize(Path::new("cg/./ev/../bg/a.png")),
+            Path::new("cg/bg/a.png")
+        );
+        assert_eq!(normalize(Path::new("./a.png")), Path::new("a.png"));
+        assert_eq!(normalize(Path::new(".")), Path::new(""));
+        // leading `..` of relative paths are kept, `..` above the root are dropped.
+        assert_eq!(
+            normalize(Path::new("../../cg/a.png")),
+            Path::new("../../cg/a.png")
+        );
+        assert_eq!(
+            normalize(Path::new("cg/../../a.png")),
+            Path::new("../a.png")
+        );
+        assert_eq!(
+            normalize(Path::new("/../../cg/a.png")),
+            Path::new("/cg/a.png")
+        );
+        assert_eq!(normalize(Path::new("/cg/..")), Path::new("/"));
+    }
+
+    #[test]
+    fn normalize_drops_trailing_separators() {
+        assert_eq!(normalize(Path::new("cg/ev/")), Path::new("cg/ev"));
+        assert_eq!(normalize(Path::new("cg//ev/./")), Path::new("cg/ev"));
+    }
+
+    #[test]
+    fn relative_name_stays_inside() {
+        assert_eq!(relative_name(Path::new("/cg/../bg/a.png")), "bg/a.png");
+        assert_eq!(relative_name(Path::new("../../a.png")), "a.png");
+        assert_eq!(relative_name(Path::new("cg/../../a.png")), "a.png");
+        assert_eq!(relative_name(Path::new("./cg/./a.png")), "cg/a.png");
+        assert_eq!(relative_name(Path::new("cg/ev/")), "cg/ev");
+        assert_eq!(relative_name(Path::new("/..")), "");
+    }
+
+    #[test]
+    fn under_dir_keeps_path_below_input() {
+        let game = tempfile::tempdir().unwrap();
+        let game = game.path();
+        std::fs::create_dir_all(game.join("cg/ev")).unwrap();
+        let inputs = [game.join("cg"), game.join("cg/ev/"), game.join("bg.pck")];
+        let out = Path::new("out");
+        assert_eq!(
+            under_dir(out, &game.join("cg/bg/a.png"), &inputs),
+            Path::new("out/bg/a.png")
+        );
+        // the closest input wins.
+        assert_eq!(
+            under_dir(out, &game.join("cg/ev/../ev/./b.png"), &inputs),
+            Path::new("out/b.png")
+        );
+        // files are relative to the directory they are in.
+        assert_eq!(
+            under_dir(out, &game.join("bg/c.png"), &inputs),
+            Path::new("out/bg/c.png")
+        );
+        // outside every input, only the file name is kept.
+        assert_eq!(
+            under_dir(out, &game.join("../../../../../../../d.png"), &inputs),
+            Path::new("out/d.png")
+        );
+    }
+
+    #[test]
+    fn relative_to_walks_up() {
+        assert_eq!(
+            relative_to(Path::new("/cg/bg/a.png"), Path::new("/cg/ev")),
+            Path::new("../bg/a.png")
+        );
+        assert_eq!(
+            relative_to(Path::new("/cg/./bg/a.png"), Path::new("/cg/ev/../bg/")),
+            Path::new("a.png")
+        );
+        assert_eq!(
+            relative_to(Path::new("/a.png"), Path::new("/../../cg")),
+            Path::new("../a.png")
+        );
+    }
+
+    #[test]
+    fn absolute_keeps_absolute_paths() {
+        assert_eq!(absolute(Path::new("/cg/a.png")), Path::new("/cg/a.png"));
+        assert_eq!(
+            absolute(Path::new("a.png")),
+            std::env::current_dir().unwrap().join("a.png")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_prefixes() {
+        assert_eq!(
+            normalize(Path::new(r"C:\cg\..\..\bg\")),
+            Path::new(r"C:\bg")
+        );
+        assert_eq!(relative_name(Path::new(r"C:\cg\..\bg\a.png")), "bg/a.png");
+        assert_eq!(relative_name(Path::new(r"C:cg\a.png")), "cg/a.png");
+        assert_eq!(
+            normalize(Path::new(r"\\server\share\..\cg\a.png")),
+            Path::new(r"\\server\share\cg\a.png")
+        );
+        assert_eq!(
+            relative_name(Path::new(r"\\server\share\cg\a.png")),
+            "cg/a.png"
+        );
+        assert_eq!(relative_name(Path::new(r"\\?\C:\cg\.\a.png")), "cg/a.png");
+        assert_eq!(
+            relative_to(Path::new(r"C:\cg\bg\a.png"), Path::new(r"C:\cg\ev\")),
+            Path::new(r"..\bg\a.png")
+        );
+        assert_eq!(
+            under_dir(
+                Path::new("out"),
+                Path::new(r"D:\game\cg\a.png"),
+                &[PathBuf::from(r"C:\game\cg")]
+            ),
+            Path::new("out").join("a.png")
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{archive, console};
+use clap::ValueEnum;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// How to handle several inputs mapping to the same output path.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnCollision {
+    /// Append `_1`, `_2`, ... to the file stem of every colliding output but the first
+    Suffix,
+    /// Convert only the first of the colliding inputs
+    Skip,
+    /// Abort before converting anything
+    Error,
+}
+
+/// Resolve output collisions in `plan` according to `policy`. Colliding inputs are ordered by
+/// path, so the result does not depend on the order of the command line arguments. Returns the
+/// new plan and the inputs that were skipped.
+pub fn resolve_collisions(
+    plan: Vec<(PathBuf, PathBuf)>,
+    policy: OnCollision,
+) -> (Vec<(PathBuf, PathBuf)>, Vec<PathBuf>) {
+    if policy == OnCollision::Error {
+        return (plan, Vec::new());
+    }
+
+    let mut outputs: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for (input, output) in plan {
+        outputs.entry(output).or_default().push(input);
+    }
+    let mut taken: BTreeSet<PathBuf> = outputs.keys().cloned().collect();
+
+    let mut resolved = Vec::new();
+    let mut skipped = Vec::new();
+    for (output, mut inputs) in outputs {
+        inputs.sort();
+        inputs.dedup();
+        let mut inputs = inputs.into_iter();
+        if let Some(first) = inputs.next() {
+            resolved.push((first, output.clone()));
+        }
+        let mut n = 0;
+        for input in inputs {
+            match policy {
+                OnCollision::Suffix => {
+                    let renamed = loop {
+                        n += 1;
+                        let candidate = suffixed(&output, n);
+                        if !taken.contains(&candidate) {
+                            break candidate;
+                        }
+                    };
+                    taken.insert(renamed.clone());
+                    resolved.push((input, renamed));
+                }
+                _ => skipped.push(input),
+            }
+        }
+    }
+    (resolved, skipped)
+}
+
+fn suffixed(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// Which existing outputs are converted again.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    Always,
+    Never,
+    /// Only outputs older than their input.
+    IfNewer,
+}
+
+/// Drop the entries of `plan` whose output exists and, per `policy`, is kept. Inputs inside
+/// archives are dated by the archive. Returns the new plan and the inputs that were skipped.
+pub fn skip_existing(
+    plan: Vec<(PathBuf, PathBuf)>,
+    policy: Overwrite,
+) -> (Vec<(PathBuf, PathBuf)>, Vec<PathBuf>) {
+    if policy == Overwrite::Always {
+        return (plan, Vec::new());
+    }
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut kept = Vec::new();
+    let mut skipped = Vec::new();
+    for (input, output) in plan {
+        let stale = match modified(&output) {
+            None => true,
+            Some(output_time) => {
+                let source = archive::containing_archive(&input).unwrap_or(&input);
+                policy == Overwrite::IfNewer
+                    && modified(source).is_some_and(|input_time| input_time > output_time)
+            }
+        };
+        if stale {
+            kept.push((input, output));
+        } else {
+            skipped.push(input);
+        }
+    }
+    (kept, skipped)
+}
+
+/// A problem found before any file is converted.
+pub enum Problem {
+    /// Several inputs would be written to the same output path.
+    Collision {
+        output: PathBuf,
+        inputs: Vec<PathBuf>,
+    },
+    /// The output path exists but is a directory.
+    OutputIsDir(PathBuf),
+    /// An output directory cannot be created or written to.
+    NotWritable { dir: PathBuf, error: io::Error },
+}
+
+/// Check a conversion plan of `(input, output)` pairs for output collisions, and, when outputs
+/// are `local` files, for unwritable output directories.
+pub fn check(plan: &[(PathBuf, PathBuf)], local: bool) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let mut outputs: BTreeMap<&Path, Vec<PathBuf>> = BTreeMap::new();
+    for (input, output) in plan {
+        outputs.entry(output).or_default().push(input.clone());
+    }
+    for (output, inputs) in outputs {
+        if inputs.len() > 1 {
+            problems.push(Problem::Collision {
+                output: output.to_path_buf(),
+                inputs,
+            });
+        } else if local && output.is_dir() {
+            problems.push(Problem::OutputIsDir(output.to_path_buf()));
+        }
+    }
+
+    if !local {
+        return problems;
+    }
+    let dirs: BTreeSet<&Path> = plan
+        .iter()
+        .map(|(_, output)| output.parent().unwrap_or(Path::new(".")))
+        .collect();
+    for dir in dirs {
+        if let Err(error) = check_writable(dir) {
+            problems.push(Problem::NotWritable {
+                dir: dir.to_path_buf(),
+                error,
+            });
+        }
+    }
+
+    problems
+}
+
+/// Check that `dir` is writable, or if it does not exist, that its nearest existing ancestor is.
+fn check_writable(dir: &Path) -> io::Result<()> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let existing = dir
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."));
+    if !existing.is_dir() {
+        return Err(io::Error::other(format!(
+            "\"{}\" is not a directory",
+            existing.to_string_lossy()
+        )));
+    }
+    let probe = existing.join(format!(".crx-convert-probe-{}", std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+pub fn report(problems: &[Problem]) {
+    for problem in problems {
+        match problem {
+            Problem::Collision { output, inputs } => {
+                console::file_line(
+                    console::Status::Conflict,
+                    output,
+                    format_args!("is the output of {} inputs:", inputs.len()),
+                );
+                for input in inputs {
+                    console::line(console::Status::Note(""), input.to_string_lossy());
+                }
+            }
+            Problem::OutputIsDir(output) => {
+                console::file_line(console::Status::Conflict, output, "is a directory")
+            }
+            Problem::NotWritable { dir, error } => console::file_line(
+                console::Status::Conflict,
+                dir,
+                format_args!("is not writable: {}", error),
+            ),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use tracing_flame::{FlameLayer, FlushGuard};
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Collects the `tracing` spans of a run into a flame graph.
+///
+/// Stacks are recorded in the folded format next to the SVG while the run goes, then rendered
+/// and removed by [`Profile::finish`]. Threads are merged, so the widths add up the time spent
+/// in each stage by all threads.
+pub struct Profile {
+    guard: FlushGuard<BufWriter<fs::File>>,
+    folded: PathBuf,
+    svg: PathBuf,
+}
+
+impl Profile {
+    pub fn start(svg: &Path) -> io::Result<Self> {
+        let folded = svg.with_extension("folded");
+        let (layer, guard) = FlameLayer::with_file(&folded).map_err(io::Error::other)?;
+        let subscriber = tracing_subscriber::registry().with(
+            layer
+                .with_threads_collapsed(true)
+                .with_module_path(false)
+                .with_file_and_line(false)
+                .with_empty_samples(false),
+        );
+        tracing::subscriber::set_global_default(subscriber).map_err(io::Error::other)?;
+        Ok(Self {
+            guard,
+            folded,
+            svg: svg.to_path_buf(),
+        })
+    }
+
+    /// Render the recorded stacks. Spans still open are left out.
+    pub fn finish(self) -> io::Result<()> {
+        self.guard.flush().map_err(io::Error::other)?;
+        let mut options = inferno::flamegraph::Options::default();
+        options.title = format!("{} profile", env!("CARGO_PKG_NAME"));
+        options.count_name = "ns".to_string();
+        let mut writer = BufWriter::new(fs::File::create(&self.svg)?);
+        inferno::flamegraph::from_reader(
+            &mut options,
+            BufReader::new(fs::File::open(&self.folded)?),
+            &mut writer,
+        )
+        .map_err(io::Error::other)?;
+        writer.flush()?;
+        fs::remove_file(&self.folded)
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{console, escape};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// Progress bars on stderr, when it is a terminal: one for the run, and one line per file being
+/// converted.
+struct Bars {
+    bars: MultiProgress,
+    overall: ProgressBar,
+    files: Mutex<HashMap<PathBuf, ProgressBar>>,
+}
+
+/// Progress of a run: newline-delimited JSON events on stderr for GUI wrappers, or progress
+/// bars.
+pub struct Progress {
+    enabled: bool,
+    bars: Option<Bars>,
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+}
+
+impl Progress {
+    /// JSON events if `json` is set, otherwise bars if `bars` is set.
+    pub fn new(json: bool, bars: bool, total: usize) -> Self {
+        let bars = (bars && !json).then(|| {
+            let bars = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+            let overall = bars.add(ProgressBar::new(total as u64));
+            overall.set_style(
+                ProgressStyle::with_template(
+                    "{bar:40.green/white} {pos}/{len} ({percent}%), {elapsed} elapsed, ETA {eta}",
+                )
+                .expect("valid template"),
+            );
+            console::set_bars(Some(bars.clone()));
+            Bars {
+                bars,
+                overall,
+                files: Mutex::default(),
+            }
+        });
+        let progress = Self {
+            enabled: json,
+            bars,
+            total,
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+        };
+        progress.emit(&format!("{{\"event\":\"begin\",\"total\":{}}}", total));
+        progress
+    }
+
+    pub fn started(&self, path: &Path) {
+        if let Some(bars) = &self.bars {
+            let file = bars.bars.insert_before(
+                &bars.overall,
+                ProgressBar::new_spinner()
+                    .with_style(ProgressStyle::with_template("  {msg}").expect("valid template"))
+                    .with_message(path.to_string_lossy().into_owned()),
+            );
+            bars.files.lock().unwrap().insert(path.to_path_buf(), file);
+        }
+        self.emit(&format!(
+            "{{\"event\":\"started\",\"path\":{}}}",
+            escape::json(&path.to_string_lossy())
+        ));
+    }
+
+    pub fn finished(&self, path: &Path, success: bool) {
+        if let Some(bars) = &self.bars {
+            if let Some(file) = bars.files.lock().unwrap().remove(path) {
+                file.finish_and_clear();
+                bars.bars.remove(&file);
+            }
+            bars.overall.inc(1);
+        }
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let eta = elapsed / done as f64 * (self.total - done) as f64;
+        self.emit(&format!(
+            "{{\"event\":\"finished\",\"path\":{},\"success\":{},\"done\":{},\"total\":{},\"percent\":{:.1},\"eta_secs\":{:.1}}}",
+            escape::json(&path.to_string_lossy()),
+            success,
+            done,
+            self.total,
+            done as f64 * 100.0 / self.total as f64,
+            eta
+        ));
+    }
+
+    pub fn end(&self) {
+        if let Some(bars) = &self.bars {
+            bars.overall.finish_and_clear();
+            console::set_bars(None);
+        }
+        self.emit(&format!(
+            "{{\"event\":\"end\",\"done\":{},\"elapsed_secs\":{:.1}}}",
+            self.done.load(Ordering::Relaxed),
+            self.start.elapsed().as_secs_f64()
+        ));
+    }
+
+    fn emit(&self, event: &str) {
+        if self.enabled {
+            eprintln!("{}", event);
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use crate::{escape, hash, output::software_stamp};
+use crx::{OffsetOrigin, PaletteSpec};
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Version of the JSON report format, bumped on incompatible changes.
+pub const FORMAT_VERSION: u32 = 1;
+/// `$id` of the JSON Schema describing the current report format.
+pub const SCHEMA_ID: &str = "urn:crx-convert:report:1";
+
+/// JSON Schema of the JSON report.
+pub const SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "urn:crx-convert:report:1",
+  "title": "crx-convert report",
+  "type": "object",
+  "required": ["$schema", "format_version", "files"],
+  "properties": {
+    "$schema": { "const": "urn:crx-convert:report:1" },
+    "format_version": { "const": 1 },
+    "skipped": {
+      "type": "array",
+      "description": "inputs left alone, only listed by --report",
+      "items": {
+        "type": "object",
+        "required": ["source", "reason"],
+        "properties": {
+          "source": { "type": "string" },
+          "reason": { "type": "string" }
+        }
+      }
+    },
+    "failures": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["source", "stage", "error", "head"],
+        "properties": {
+          "source": { "type": "string" },
+          "stage": { "enum": ["read", "decode", "save", "verify"] },
+          "error": { "type": "string" },
+          "head": { "type": "string", "description": "first bytes of the file, in hexadecimal" }
+        }
+      }
+    },
+    "files": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": [
+          "source", "output", "width", "height", "inner_x", "inner_y", "offset_origin",
+          "version", "bpp", "palette_colors", "palette_entry_size", "clip_count",
+          "output_size", "duration_ms"
+        ],
+        "properties": {
+          "source": { "type": "string", "description": "path of the CRX file" },
+          "source_sha256": {
+            "type": ["string", "null"],
+            "description": "SHA-256 of the CRX file, null unless --hash-sources is given"
+          },
+          "output": { "type": "string", "description": "path the output was stored at" },
+          "width": { "type": "integer", "minimum": 0 },
+          "height": { "type": "integer", "minimum": 0 },
+          "inner_x": { "type": "integer" },
+          "inner_y": { "type": "integer" },
+          "offset_origin": { "enum": ["top-left", "center"] },
+          "version": { "type": "integer", "description": "CRX format version" },
+          "bpp": { "enum": [8, 24, 32] },
+          "palette_colors": { "type": ["integer", "null"] },
+          "palette_entry_size": { "type": ["integer", "null"] },
+          "clip_count": { "type": "integer", "minimum": 0 },
+          "output_size": { "type": "integer", "minimum": 0, "description": "bytes" },
+          "duration_ms": { "type": "number", "minimum": 0 },
+          "decode_ms": {
+            "type": "number",
+            "minimum": 0,
+            "description": "the part of duration_ms spent decoding"
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+/// Command line spelling of [`OffsetOrigin`].
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Origin {
+    /// Offsets are measured from the top-left corner of the screen
+    TopLeft,
+    /// Offsets are measured from the center of the screen
+    Center,
+}
+
+impl From<Origin> for OffsetOrigin {
+    fn from(origin: Origin) -> Self {
+        match origin {
+            Origin::TopLeft => OffsetOrigin::TopLeft,
+            Origin::Center => OffsetOrigin::Center,
+        }
+    }
+}
+
+/// Parse a `WIDTHxHEIGHT` screen size.
+pub fn parse_screen_size(s: &str) -> Result<(u16, u16), String> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("`{}` is not WIDTHxHEIGHT", s))?;
+    let parse = |n: &str| n.trim().parse::<u16>().map_err(|e| e.to_string());
+    Ok((parse(width)?, parse(height)?))
+}
+
+/// Format of the `--report` file.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+    /// A sortable table for reviewing in a browser
+    Html,
+}
+
+impl ReportFormat {
+    /// The format matching the extension of `path`, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(ReportFormat::Csv),
+            "json" => Some(ReportFormat::Json),
+            "html" | "htm" => Some(ReportFormat::Html),
+            _ => None,
+        }
+    }
+
+    fn writer(self) -> Box<dyn ReportWriter> {
+        match self {
+            ReportFormat::Csv => Box::new(CsvReport),
+            ReportFormat::Json => Box::new(JsonReport),
+            ReportFormat::Html => Box::new(HtmlReport),
+        }
+    }
+}
+
+/// Number of leading bytes of a failed file kept for the report.
+pub const FAILURE_HEAD_SIZE: usize = 64;
+
+/// A file that could not be converted.
+pub struct Failure {
+    pub source: PathBuf,
+    /// `list` (for archives), `read`, `decode`, `save` or `verify`.
+    pub stage: &'static str,
+    pub error: String,
+    /// The first [`FAILURE_HEAD_SIZE`] bytes of the file, if it could be read.
+    pub head: Vec<u8>,
+}
+
+/// What is known about one successfully converted file.
+pub struct Record {
+    pub source: PathBuf,
+    /// SHA-256 of the source file, when hashing was requested.
+    pub source_sha256: Option<String>,
+    pub output: PathBuf,
+    pub width: u16,
+    pub height: u16,
+    pub inner_x: i32,
+    pub inner_y: i32,
+    pub offset_origin: OffsetOrigin,
+    pub version: u16,
+    pub bpp: usize,
+    pub palette_spec: Option<PaletteSpec>,
+    pub clip_count: usize,
+    pub output_size: u64,
+    pub duration: Duration,
+    /// The part of `duration` spent decoding.
+    pub decode_duration: Duration,
+}
+
+/// An input left alone, e.g. for having an up-to-date output.
+pub struct Skip {
+    pub source: PathBuf,
+    pub reason: &'static str,
+}
+
+/// Renders the outcome of a run.
+pub trait ReportWriter {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        records: &[Record],
+        failures: &[Failure],
+        skipped: &[Skip],
+    ) -> io::Result<()>;
+}
+
+/// Write the report at `path` in `format`.
+pub fn write_report(
+    path: &Path,
+    format: ReportFormat,
+    records: &[Record],
+    failures: &[Failure],
+    skipped: &[Skip],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    format
+        .writer()
+        .write(&mut writer, records, failures, skipped)?;
+    writer.flush()
+}
+
+/// One CSV row per record, then one per failure and skipped input with only the source, status
+/// and error or reason filled in. The file starts with a UTF-8 byte order mark so that
+/// spreadsheet applications do not mangle non-ASCII (e.g. Japanese) file names.
+struct CsvReport;
+
+impl ReportWriter for CsvReport {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        records: &[Record],
+        failures: &[Failure],
+        skipped: &[Skip],
+    ) -> io::Result<()> {
+        writer.write_all("\u{feff}".as_bytes())?;
+        writeln!(
+            writer,
+            "source,source_sha256,output,width,height,inner_x,inner_y,offset_origin,version,bpp,palette_colors,palette_entry_size,clip_count,output_size,duration_ms,decode_ms,status,error"
+        )?;
+        for record in records {
+            let (colors, entry_size) = match record.palette_spec {
+                Some(spec) => (spec.colors().to_string(), spec.entry_size().to_string()),
+                None => Default::default(),
+            };
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{:.3},{:.3},converted,",
+                escape::csv(&record.source.to_string_lossy()),
+                record.source_sha256.as_deref().unwrap_or_default(),
+                escape::csv(&record.output.to_string_lossy()),
+                record.width,
+                record.height,
+                record.inner_x,
+                record.inner_y,
+                origin_name(record.offset_origin),
+                record.version,
+                record.bpp,
+                colors,
+                entry_size,
+                record.clip_count,
+                record.output_size,
+                record.duration.as_secs_f64() * 1000.0,
+                record.decode_duration.as_secs_f64() * 1000.0
+            )?;
+        }
+        for failure in failures {
+            writeln!(
+                writer,
+                "{},,,,,,,,,,,,,,,,failed,{}",
+                escape::csv(&failure.source.to_string_lossy()),
+                escape::csv(&format!("{}: {}", failure.stage, failure.error))
+            )?;
+        }
+        for skip in skipped {
+            writeln!(
+                writer,
+                "{},,,,,,,,,,,,,,,,skipped,{}",
+                escape::csv(&skip.source.to_string_lossy()),
+                escape::csv(skip.reason)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A JSON document following [`SCHEMA`].
+struct JsonReport;
+
+impl ReportWriter for JsonReport {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        records: &[Record],
+        failures: &[Failure],
+        skipped: &[Skip],
+    ) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{{\"$schema\":{},\"format_version\":{},\"files\":[",
+            escape::json(SCHEMA_ID),
+            FORMAT_VERSION
+        )?;
+        for (i, record) in records.iter().enumerate() {
+            let (colors, entry_size) = match record.palette_spec {
+                Some(spec) => (spec.colors().to_string(), spec.entry_size().to_string()),
+                None => ("null".to_string(), "null".to_string()),
+            };
+            writeln!(
+                writer,
+                "{{\"source\":{},\"source_sha256\":{},\"output\":{},\"width\":{},\"height\":{},\"inner_x\":{},\"inner_y\":{},\"offset_origin\":{},\"version\":{},\"bpp\":{},\"palette_colors\":{},\"palette_entry_size\":{},\"clip_count\":{},\"output_size\":{},\"duration_ms\":{:.3},\"decode_ms\":{:.3}}}{}",
+                escape::json(&record.source.to_string_lossy()),
+                record
+                    .source_sha256
+                    .as_deref()
+                    .map_or("null".to_string(), escape::json),
+                escape::json(&record.output.to_string_lossy()),
+                record.width,
+                record.height,
+                record.inner_x,
+                record.inner_y,
+                escape::json(origin_name(record.offset_origin)),
+                record.version,
+                record.bpp,
+                colors,
+                entry_size,
+                record.clip_count,
+                record.output_size,
+                record.duration.as_secs_f64() * 1000.0,
+                record.decode_duration.as_secs_f64() * 1000.0,
+                if i + 1 < records.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(writer, "],\"failures\":[")?;
+        for (i, failure) in failures.iter().enumerate() {
+            writeln!(
+                writer,
+                "{{\"source\":{},\"stage\":{},\"error\":{},\"head\":{}}}{}",
+                escape::json(&failure.source.to_string_lossy()),
+                escape::json(failure.stage),
+                escape::json(&failure.error),
+                escape::json(&hash::hex(&failure.head)),
+                if i + 1 < failures.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(writer, "],\"skipped\":[")?;
+        for (i, skip) in skipped.iter().enumerate() {
+            writeln!(
+                writer,
+                "{{\"source\":{},\"reason\":{}}}{}",
+                escape::json(&skip.source.to_string_lossy()),
+                escape::json(skip.reason),
+                if i + 1 < skipped.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(writer, "]}}")
+    }
+}
+
+/// A standalone HTML page: a table of converted files that sorts by a column when its header is
+/// clicked, and the failures with a hex dump of the start of each file.
+struct HtmlReport;
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>crx-convert report</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }
+th { background: #eee; cursor: pointer; }
+td.n { text-align: right; }
+pre { background: #f6f6f6; padding: 0.5em; margin: 0.3em 0 1em; }
+.error { color: #b00; }
+</style>
+<script>
+function sortTable(th) {
+  const table = th.closest("table");
+  const index = Array.from(th.parentNode.children).indexOf(th);
+  const ascending = th.dataset.order !== "asc";
+  th.dataset.order = ascending ? "asc" : "desc";
+  const rows = Array.from(table.tBodies[0].rows);
+  rows.sort((a, b) => {
+    const x = a.cells[index].textContent, y = b.cells[index].textContent;
+    const order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+    return ascending ? order : -order;
+  });
+  rows.forEach(row => table.tBodies[0].appendChild(row));
+}
+</script>
+</head>
+<body>
+"#;
+
+impl ReportWriter for HtmlReport {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        records: &[Record],
+        failures: &[Failure],
+        skipped: &[Skip],
+    ) -> io::Result<()> {
+        writer.write_all(HTML_HEAD.as_bytes())?;
+        writeln!(
+            writer,
+            "<h1>crx-convert report</h1>\n<p>{} converted, {} failed, {} skipped, written by {}.</p>",
+            records.len(),
+            failures.len(),
+            skipped.len(),
+            escape::html(&software_stamp())
+        )?;
+
+        writeln!(writer, "<h2>Converted</h2>\n<table>\n<thead><tr>")?;
+        for column in [
+            "Source",
+            "Output",
+            "Width",
+            "Height",
+            "Inner X",
+            "Inner Y",
+            "Version",
+            "Bpp",
+            "Clips",
+            "Size",
+            "Time (ms)",
+            "Decode (ms)",
+        ] {
+            writeln!(writer, "<th onclick=\"sortTable(this)\">{}</th>", column)?;
+        }
+        writeln!(writer, "</tr></thead>\n<tbody>")?;
+        for record in records {
+            writeln!(
+                writer,
+                "<tr><td>{}</td><td>{}</td><td class=n>{}</td><td class=n>{}</td><td class=n>{}</td><td class=n>{}</td><td class=n>{}</td><td class=n>{}</td><td class=n>{}</td><td class=n>{}</td><td class=n>{:.3}</td><td class=n>{:.3}</td></tr>",
+                escape::html(&record.source.to_string_lossy()),
+                escape::html(&record.output.to_string_lossy()),
+                record.width,
+                record.height,
+                record.inner_x,
+                record.inner_y,
+                record.version,
+                record.bpp,
+                record.clip_count,
+                record.output_size,
+                record.duration.as_secs_f64() * 1000.0,
+                record.decode_duration.as_secs_f64() * 1000.0
+            )?;
+        }
+        writeln!(writer, "</tbody>\n</table>")?;
+
+        if !failures.is_empty() {
+            writeln!(writer, "<h2>Failed</h2>")?;
+            for failure in failures {
+                writeln!(
+                    writer,
+                    "<h3>{}</h3>\n<p class=error>{}: {}</p>\n<pre>{}</pre>",
+                    escape::html(&failure.source.to_string_lossy()),
+                    failure.stage,
+                    escape::html(&failure.error),
+                    escape::html(&hexdump(&failure.head))
+                )?;
+            }
+        }
+        if !skipped.is_empty() {
+            writeln!(writer, "<h2>Skipped</h2>\n<ul>")?;
+            for skip in skipped {
+                writeln!(
+                    writer,
+                    "<li>{}: {}</li>",
+                    escape::html(&skip.source.to_string_lossy()),
+                    skip.reason
+                )?;
+            }
+            writeln!(writer, "</ul>")?;
+        }
+        writeln!(writer, "</body>\n</html>")
+    }
+}
+
+/// `xxd`-style dump: offset, 16 bytes in hexadecimal, then the printable ASCII.
+fn hexdump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:08x}  {:<47}  {}\n",
+            line * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    out
+}
+
+fn origin_name(origin: OffsetOrigin) -> &'static str {
+    match origin {
+        OffsetOrigin::TopLeft => "top-left",
+        OffsetOrigin::Center => "center",
+    }
+}
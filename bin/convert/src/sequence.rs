@@ -0,0 +1,235 @@
+use crate::{console, output, paths, walk};
+use clap::Args;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct SequencesArg {
+    /// CRX files, or directories to search for them
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// How frame file names (without extension) are formed: `*` stands for the sequence name and
+    /// a run of `#` for at least that many frame number digits, e.g. `*_##` or `ev###_*`; by
+    /// default a trailing number is taken as the frame number
+    #[arg(long, value_name = "PATTERN", value_parser = GroupPattern::parse)]
+    group_pattern: Option<GroupPattern>,
+    /// Ignore sequences with fewer frames than this
+    #[arg(long, default_value_t = 2)]
+    min_frames: usize,
+    /// Write an ffmpeg concat list per sequence into this directory, naming the converted frames
+    #[arg(long, short, value_name = "DIR")]
+    output: Option<PathBuf>,
+    /// Format the frames were converted to
+    #[arg(long, value_enum, default_value_t)]
+    format: output::Format,
+    /// Frame rate written into the concat lists
+    #[arg(long, default_value_t = 10.0)]
+    fps: f64,
+}
+
+#[derive(Clone)]
+enum Token {
+    Literal(String),
+    /// The sequence name, matching one or more characters.
+    Name,
+    /// The frame number, matching at least this many digits.
+    Frame(usize),
+}
+
+/// A parsed `--group-pattern`.
+#[derive(Clone)]
+pub struct GroupPattern(Vec<Token>);
+
+impl GroupPattern {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut tokens = Vec::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => tokens.push(Token::Name),
+                '#' => {
+                    let mut digits = 1;
+                    while chars.next_if_eq(&'#').is_some() {
+                        digits += 1;
+                    }
+                    tokens.push(Token::Frame(digits));
+                }
+                c => match tokens.last_mut() {
+                    Some(Token::Literal(literal)) => literal.push(c),
+                    _ => tokens.push(Token::Literal(c.to_string())),
+                },
+            }
+        }
+        let count = |f: fn(&Token) -> bool| tokens.iter().filter(|&t| f(t)).count();
+        if count(|t| matches!(t, Token::Name)) != 1 || count(|t| matches!(t, Token::Frame(_))) != 1
+        {
+            return Err("the pattern needs exactly one `*` and one run of `#`".to_string());
+        }
+        Ok(Self(tokens))
+    }
+
+    /// The sequence name and frame number of `stem`, if it matches.
+    fn matches(&self, stem: &str) -> Option<(String, u64)> {
+        fn walk(tokens: &[Token], s: &str, name: &mut String, frame: &mut u64) -> bool {
+            let Some((token, rest)) = tokens.split_first() else {
+                return s.is_empty();
+            };
+            match token {
+                Token::Literal(literal) => s
+                    .strip_prefix(literal.as_str())
+                    .is_some_and(|s| walk(rest, s, name, frame)),
+                Token::Name => s
+                    .char_indices()
+                    .skip(1)
+                    .map(|(i, _)| i)
+                    .chain([s.len()])
+                    .any(|end| {
+                        walk(rest, &s[end..], name, frame) && {
+                            *name = s[..end].to_string();
+                            true
+                        }
+                    }),
+                Token::Frame(digits) => {
+                    let run = s.bytes().take_while(u8::is_ascii_digit).count();
+                    // longest first, so `*##` takes every trailing digit it can.
+                    (*digits..=run).rev().any(|end| {
+                        walk(rest, &s[end..], name, frame)
+                            && s[..end].parse().map(|n| *frame = n).is_ok()
+                    })
+                }
+            }
+        }
+        let (mut name, mut frame) = (String::new(), 0);
+        walk(&self.0, stem, &mut name, &mut frame).then_some((name, frame))
+    }
+}
+
+/// The sequence name and frame number of `stem`, from its trailing number: `ev001_07` is frame
+/// 7 of `ev001`.
+fn infer(stem: &str) -> Option<(String, u64)> {
+    let name = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let frame = stem[name.len()..].parse().ok()?;
+    let name = name.trim_end_matches(['_', '-', '.', ' ']);
+    (!name.is_empty()).then(|| (name.to_string(), frame))
+}
+
+/// Frames of one sequence, in frame number order.
+pub struct Sequence {
+    pub dir: PathBuf,
+    pub name: String,
+    pub frames: Vec<(u64, PathBuf)>,
+}
+
+/// Group `files` into sequences by directory and name, dropping those below `min_frames`.
+pub fn group(
+    files: &[PathBuf],
+    pattern: Option<&GroupPattern>,
+    min_frames: usize,
+) -> Vec<Sequence> {
+    let mut groups: BTreeMap<(PathBuf, String), Vec<(u64, PathBuf)>> = BTreeMap::new();
+    for file in files {
+        let Some(stem) = file.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let found = match pattern {
+            Some(pattern) => pattern.matches(stem),
+            None => infer(stem),
+        };
+        if let Some((name, frame)) = found {
+            let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+            groups
+                .entry((dir, name))
+                .or_default()
+                .push((frame, file.clone()));
+        }
+    }
+    groups
+        .into_iter()
+        .filter(|(_, frames)| frames.len() >= min_frames.max(1))
+        .map(|((dir, name), mut frames)| {
+            frames.sort();
+            Sequence { dir, name, frames }
+        })
+        .collect()
+}
+
+/// An ffmpeg concat demuxer list showing `frames` for `1 / fps` seconds each.
+fn concat_list(frames: &[PathBuf], fps: f64) -> String {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for frame in frames {
+        let path = paths::normalize(&paths::absolute(frame));
+        let path = path.to_string_lossy().replace('\'', r"'\''");
+        let _ = writeln!(list, "file '{}'\nduration {}", path, 1.0 / fps);
+    }
+    // the last duration only applies when the file is repeated.
+    if let Some(last) = list.lines().rev().nth(1).map(str::to_string) {
+        let _ = writeln!(list, "{}", last);
+    }
+    list
+}
+
+pub fn run(arg: &SequencesArg) -> io::Result<()> {
+    if arg.fps.is_nan() || arg.fps <= 0.0 {
+        return Err(io::Error::other("--fps must be above zero"));
+    }
+    let files = walk::collect_files(&arg.paths, "crx", &Default::default())?;
+    let sequences = group(&files, arg.group_pattern.as_ref(), arg.min_frames);
+    if let Some(dir) = &arg.output {
+        fs::create_dir_all(dir)?;
+    }
+    let mut names: BTreeMap<&str, usize> = BTreeMap::new();
+    for sequence in &sequences {
+        *names.entry(&sequence.name).or_default() += 1;
+    }
+    for sequence in &sequences {
+        let frames: Vec<_> = sequence
+            .frames
+            .iter()
+            .map(|(_, file)| paths::output_path(file, arg.format))
+            .collect();
+        let first = sequence.frames.first().map(|(n, _)| *n).unwrap_or_default();
+        let last = sequence.frames.last().map(|(n, _)| *n).unwrap_or_default();
+        let gaps = ((last - first + 1) as usize).saturating_sub(sequence.frames.len());
+        let written = match &arg.output {
+            Some(dir) => {
+                // sequences of the same name in different directories are told apart by path.
+                let name = match names[sequence.name.as_str()] {
+                    1 => sequence.name.clone(),
+                    _ => paths::relative_name(&sequence.dir.join(&sequence.name)).replace('/', "_"),
+                };
+                let list = dir.join(format!("{}.txt", name));
+                fs::write(&list, concat_list(&frames, arg.fps))?;
+                format!(" -> {}", list.display())
+            }
+            None => String::new(),
+        };
+        console::file_line(
+            if gaps == 0 {
+                console::Status::Note("Sequence")
+            } else {
+                console::Status::Warning
+            },
+            &sequence.dir.join(&sequence.name),
+            format_args!(
+                "{} frame(s) {}..={}{}{}",
+                sequence.frames.len(),
+                first,
+                last,
+                match gaps {
+                    0 => String::new(),
+                    gaps => format!(", {} missing", gaps),
+                },
+                written
+            ),
+        );
+    }
+    console::line(
+        console::Status::Info("Summary"),
+        format_args!("{} sequence(s) in {} file(s)", sequences.len(), files.len()),
+    );
+    Ok(())
+}
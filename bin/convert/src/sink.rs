@@ -0,0 +1,491 @@
+use crate::{escape, hash, paths};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Streams the contents of one output into the given writer.
+pub type Producer<'a> = &'a mut dyn FnMut(&mut dyn Write) -> io::Result<()>;
+
+/// Where converted images go.
+pub trait OutputSink: Send + Sync {
+    /// Store the output at `path`, returning the number of bytes stored.
+    fn store(&self, path: &Path, produce: Producer) -> io::Result<u64>;
+
+    /// Called once after every output has been stored.
+    fn finish(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether outputs are plain files at their paths, so they can be checked beforehand.
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    /// Whether stored outputs can be read back with [`OutputSink::load`].
+    fn can_load(&self) -> bool {
+        false
+    }
+
+    /// Read back the output stored at `path`.
+    fn load(&self, _path: &Path) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "output location cannot be read back",
+        ))
+    }
+
+    /// Whether outputs can be links to other outputs, see [`OutputSink::link`].
+    fn can_link(&self) -> bool {
+        false
+    }
+
+    /// Make `path` a hard or, if `symbolic`, relative symbolic link to the output stored at
+    /// `target`, replacing what is at `path`.
+    fn link(&self, _path: &Path, _target: &Path, _symbolic: bool) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "output location cannot hold links",
+        ))
+    }
+}
+
+/// How outputs are named inside a sink.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// At the output path of each input
+    Mirror,
+    /// Content-addressed, as `ab/cdef....png` after the SHA-256 of the output, with a
+    /// `manifest.csv` mapping output paths to hashes; identical outputs are stored once
+    Cas,
+}
+
+/// When written files are flushed to disk, for outputs written as files or zip archives.
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Fsync {
+    /// Leave it to the operating system
+    #[default]
+    None,
+    /// Sync every file once written
+    File,
+    /// Also sync the directory holding it, so that the new directory entry is durable too
+    Dir,
+}
+
+impl Fsync {
+    /// Sync `file`, just written at `path`, as the policy requires.
+    fn apply(self, file: &fs::File, path: &Path) -> io::Result<()> {
+        self.sync_file(file)?;
+        self.sync_dir(path)
+    }
+
+    /// Sync the contents of `file`, unless the policy is [`Fsync::None`].
+    fn sync_file(self, file: &fs::File) -> io::Result<()> {
+        match self {
+            Fsync::None => Ok(()),
+            _ => file.sync_all(),
+        }
+    }
+
+    /// Sync the directory holding `path` with [`Fsync::Dir`].
+    fn sync_dir(self, path: &Path) -> io::Result<()> {
+        // directories cannot be opened for syncing on every platform.
+        #[cfg(unix)]
+        if self == Fsync::Dir {
+            let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+            fs::File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+        }
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+}
+
+/// Apply `layout` on top of `sink`.
+pub fn with_layout(sink: Box<dyn OutputSink>, layout: Layout) -> Box<dyn OutputSink> {
+    match layout {
+        Layout::Mirror => sink,
+        Layout::Cas => Box::new(CasSink {
+            inner: sink,
+            manifest: Mutex::new(Vec::new()),
+            stored: Mutex::new(HashSet::new()),
+        }),
+    }
+}
+
+/// Select a sink by URI scheme: `file://DIR`, `zip://ARCHIVE.zip`, `http://HOST[:PORT]/PREFIX`
+/// (or `https://`), `null://` or, with the `s3` feature, `s3://BUCKET/PREFIX`. Without `--out`,
+/// outputs are written as files at their own paths.
+pub fn from_uri(
+    uri: Option<&str>,
+    fsync: Fsync,
+    #[cfg(feature = "s3")] s3: &crate::s3::S3Arg,
+) -> io::Result<Box<dyn OutputSink>> {
+    let Some(uri) = uri else {
+        return Ok(Box::new(FsSink {
+            root: PathBuf::new(),
+            fsync,
+        }));
+    };
+    let (scheme, rest) = uri.split_once("://").unwrap_or(("file", uri));
+    match scheme {
+        "file" => Ok(Box::new(FsSink {
+            root: PathBuf::from(rest),
+            fsync,
+        })),
+        "zip" => Ok(Box::new(ZipSink::create(Path::new(rest), fsync)?)),
+        "http" | "https" => Ok(Box::new(HttpSink::new(uri))),
+        "null" => Ok(Box::new(NullSink)),
+        #[cfg(feature = "s3")]
+        "s3" => Ok(Box::new(crate::s3::S3Sink::new(rest, s3)?)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported output scheme `{}`", scheme),
+        )),
+    }
+}
+
+/// Counts the bytes written through it.
+struct Counter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Plain files, under `root` when it is set.
+struct FsSink {
+    root: PathBuf,
+    fsync: Fsync,
+}
+
+impl FsSink {
+    fn resolve(&self, path: &Path) -> PathBuf {
+        if self.root.as_os_str().is_empty() {
+            path.to_path_buf()
+        } else {
+            self.root.join(paths::relative_name(path))
+        }
+    }
+}
+
+impl OutputSink for FsSink {
+    fn store(&self, path: &Path, produce: Producer) -> io::Result<u64> {
+        let path = self.resolve(path);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        // outputs are produced while they are written, e.g. from rows still being decoded, so
+        // they are written next to the output and replace it only once complete: one that fails
+        // halfway leaves the output of an earlier run as it was.
+        let temp = temp_path(&path);
+        let written = fs::File::create(&temp).and_then(|file| {
+            let mut writer = Counter {
+                inner: BufWriter::new(file),
+                count: 0,
+            };
+            produce(&mut writer)?;
+            writer.flush()?;
+            self.fsync.sync_file(writer.inner.get_ref())?;
+            Ok(writer.count)
+        });
+        match written.and_then(|count| fs::rename(&temp, &path).map(|_| count)) {
+            Ok(count) => {
+                self.fsync.sync_dir(&path)?;
+                Ok(count)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+
+    fn is_local(&self) -> bool {
+        self.root.as_os_str().is_empty()
+    }
+
+    fn can_load(&self) -> bool {
+        true
+    }
+
+    fn load(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(self.resolve(path))
+    }
+
+    fn can_link(&self) -> bool {
+        true
+    }
+
+    fn link(&self, path: &Path, target: &Path, symbolic: bool) -> io::Result<()> {
+        let (path, target) = (self.resolve(path), self.resolve(target));
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        if !symbolic {
+            return fs::hard_link(target, path);
+        }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let target = paths::relative_to(&target, dir);
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(target, path);
+        #[cfg(windows)]
+        return std::os::windows::fs::symlink_file(target, path);
+        #[cfg(not(any(unix, windows)))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symbolic links are not supported here",
+        ));
+    }
+}
+
+/// Stores outputs under their content hash through another sink.
+struct CasSink {
+    inner: Box<dyn OutputSink>,
+    /// (output name, hash) of every output.
+    manifest: Mutex<Vec<(String, String)>>,
+    stored: Mutex<HashSet<String>>,
+}
+
+impl OutputSink for CasSink {
+    fn store(&self, path: &Path, produce: Producer) -> io::Result<u64> {
+        let mut data = Vec::new();
+        produce(&mut data)?;
+        let hash = hash::sha256_hex(&data);
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        let stored = Path::new(&hash[..2]).join(format!("{}.{}", &hash[2..], extension));
+
+        // identical outputs stored concurrently may both be written, which is harmless; one
+        // whose write failed must not count as stored.
+        let first = !self.stored.lock().unwrap().contains(&hash);
+        if first {
+            self.inner.store(&stored, &mut |w| w.write_all(&data))?;
+            self.stored.lock().unwrap().insert(hash.clone());
+        }
+        self.manifest
+            .lock()
+            .unwrap()
+            .push((paths::relative_name(path), hash));
+        Ok(data.len() as u64)
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut manifest = std::mem::take(&mut *self.manifest.lock().unwrap());
+        manifest.sort();
+        let mut csv = String::from("name,hash\n");
+        for (name, hash) in &manifest {
+            csv.push_str(&format!("{},{}\n", escape::csv(name), hash));
+        }
+        self.inner.store(Path::new("manifest.csv"), &mut |w| {
+            w.write_all(csv.as_bytes())
+        })?;
+        self.inner.finish()
+    }
+}
+
+/// Writes every output to stdout, for `--stdout`.
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn store(&self, _path: &Path, produce: Producer) -> io::Result<u64> {
+        let mut writer = Counter {
+            inner: BufWriter::new(io::stdout().lock()),
+            count: 0,
+        };
+        produce(&mut writer)?;
+        writer.flush()?;
+        Ok(writer.count)
+    }
+}
+
+/// Discards everything, for measuring decode and encode throughput.
+struct NullSink;
+
+impl OutputSink for NullSink {
+    fn store(&self, _path: &Path, produce: Producer) -> io::Result<u64> {
+        let mut writer = Counter {
+            inner: io::sink(),
+            count: 0,
+        };
+        produce(&mut writer)?;
+        Ok(writer.count)
+    }
+}
+
+/// A zip archive of stored (uncompressed) entries. Outputs are already compressed images, so
+/// deflating them again would only cost time. Archives are limited to 4 GiB (no ZIP64).
+struct ZipSink {
+    path: PathBuf,
+    fsync: Fsync,
+    state: Mutex<ZipState>,
+}
+
+struct ZipState {
+    writer: BufWriter<fs::File>,
+    offset: u64,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipSink {
+    fn create(path: &Path, fsync: Fsync) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            fsync,
+            state: Mutex::new(ZipState {
+                writer: BufWriter::new(fs::File::create(path)?),
+                offset: 0,
+                central_directory: Vec::new(),
+                entries: 0,
+            }),
+        })
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::other("zip archive exceeds 4 GiB or 65535 entries")
+}
+
+impl OutputSink for ZipSink {
+    fn store(&self, path: &Path, produce: Producer) -> io::Result<u64> {
+        // encode outside of the lock, so that only the copy into the archive is serialized.
+        let mut data = Vec::new();
+        produce(&mut data)?;
+        let name = paths::relative_name(path);
+        let crc = crc32fast::hash(&data);
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+
+        let mut state = self.state.lock().unwrap();
+        let offset = u32::try_from(state.offset).map_err(|_| too_large())?;
+        let entries = state.entries.checked_add(1).ok_or_else(too_large)?;
+
+        // local file header: version 2.0, UTF-8 names, stored, no timestamp.
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        header.extend_from_slice(&20u16.to_le_bytes());
+        header.extend_from_slice(&0x0800u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&crc.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        // a failed write leaves the archive unusable, but at least not listing the entry.
+        state.writer.write_all(&header)?;
+        state.writer.write_all(&data)?;
+        state.offset += (header.len() + data.len()) as u64;
+        state.entries = entries;
+
+        let directory = &mut state.central_directory;
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&header[4..30]);
+        directory.extend_from_slice(&[0; 6]);
+        directory.extend_from_slice(&0u32.to_le_bytes());
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+        Ok(data.len() as u64)
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let offset = u32::try_from(state.offset).map_err(|_| too_large())?;
+        let directory = std::mem::take(&mut state.central_directory);
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&state.entries.to_le_bytes());
+        end.extend_from_slice(&state.entries.to_le_bytes());
+        end.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        state.writer.write_all(&directory)?;
+        state.writer.write_all(&end)?;
+        state.writer.flush()?;
+        self.fsync.apply(state.writer.get_ref(), &self.path)
+    }
+}
+
+/// A path in the directory of `path` to write it to before it is complete, unique within the
+/// run, so that outputs written at once don't share one.
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(name)
+}
+
+/// An HTTP client that gives up on unresponsive servers instead of hanging the run.
+pub fn http_agent() -> ureq::Agent {
+    let phase = Some(Duration::from_secs(300));
+    ureq::Agent::new_with_config(
+        ureq::Agent::config_builder()
+            .timeout_connect(Some(Duration::from_secs(30)))
+            .timeout_send_request(phase)
+            .timeout_send_body(phase)
+            .timeout_recv_response(phase)
+            .timeout_recv_body(phase)
+            .build(),
+    )
+}
+
+/// HTTP `PUT` of every output to `http://HOST[:PORT]/PREFIX/NAME`, e.g. to WebDAV or an
+/// S3-compatible gateway that accepts unauthenticated requests.
+struct HttpSink {
+    agent: ureq::Agent,
+    /// Scheme, host and prefix, without a trailing slash.
+    base: String,
+}
+
+impl HttpSink {
+    fn new(uri: &str) -> Self {
+        Self {
+            agent: http_agent(),
+            base: uri.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl OutputSink for HttpSink {
+    fn store(&self, path: &Path, produce: Producer) -> io::Result<u64> {
+        let mut data = Vec::new();
+        produce(&mut data)?;
+        let name = paths::relative_name(path);
+        let url = format!("{}/{}", self.base, escape::uri(&name, false));
+        // statuses other than 2xx are errors, without reading the body.
+        self.agent
+            .put(&url)
+            .header("Content-Type", "application/octet-stream")
+            .send(&data[..])
+            .map_err(|e| io::Error::other(format!("PUT {} failed: {}", url, e)))?;
+        Ok(data.len() as u64)
+    }
+}
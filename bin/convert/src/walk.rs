@@ -0,0 +1,201 @@
+use clap::Args;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Limits on directory walks.
+#[derive(Args, Clone, Default)]
+pub struct WalkOptions {
+    /// Only take files at most N levels below each given directory (1: its direct children)
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+    /// Do not walk into paths matching this glob (gitignore syntax, relative to the given
+    /// directory), e.g. `voice/` or `**/movie`; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub prune: Vec<String>,
+    /// Search directories recursively; they always are, the flag is accepted for older scripts
+    #[arg(short, long)]
+    pub recursive: bool,
+}
+
+/// Name of the gitignore-syntax file that excludes paths from directory walks.
+pub const IGNORE_FILE: &str = ".crxignore";
+
+/// Expand `paths` into a list of files. Directories are walked recursively, honoring
+/// `.crxignore` files, and only files with the given extension (case-insensitive) are kept from
+/// them, or every file for an empty extension; paths that do not exist but contain `*`, `?` or
+/// `[` are expanded as glob patterns; explicit file paths are kept as is. Fails on the first
+/// entry that cannot be read.
+pub fn collect_files(
+    paths: &[PathBuf],
+    extension: &str,
+    options: &WalkOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let (files, errors) = collect_files_lenient(paths, extension, options);
+    match errors.into_iter().next() {
+        Some(error) => Err(io::Error::other(error)),
+        None => Ok(files),
+    }
+}
+
+/// Like [`collect_files`], but entries that cannot be read (e.g. permission denied) are skipped
+/// and returned next to the files.
+pub fn collect_files_lenient(
+    paths: &[PathBuf],
+    extension: &str,
+    options: &WalkOptions,
+) -> (Vec<PathBuf>, Vec<ignore::Error>) {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            walk_dir(path, extension, options, &mut files, &mut errors);
+        } else if !path.exists() && is_glob(path) {
+            walk_glob(path, options, &mut files, &mut errors);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    (files, errors)
+}
+
+/// The CRX files of `paths`, as [`collect_files_lenient`] finds them. With `sniff`, every file
+/// of the directories is looked at and kept if it starts with a CRX signature, whatever its
+/// extension; the others are left out without an error.
+pub fn collect_crx_lenient(
+    paths: &[PathBuf],
+    options: &WalkOptions,
+    sniff: bool,
+) -> (Vec<PathBuf>, Vec<ignore::Error>) {
+    if !sniff {
+        return collect_files_lenient(paths, "crx", options);
+    }
+    let (mut files, errors) = collect_files_lenient(paths, "", options);
+    files.retain(|file| paths.contains(file) || has_crx_signature(file));
+    (files, errors)
+}
+
+fn walk_dir(
+    dir: &Path,
+    extension: &str,
+    options: &WalkOptions,
+    files: &mut Vec<PathBuf>,
+    errors: &mut Vec<ignore::Error>,
+) {
+    walk(dir, None, options, files, errors, |path| {
+        extension.is_empty() || has_extension(path, extension)
+    });
+}
+
+/// Walk the directory before the first component of `pattern` holding a wildcard, keeping the
+/// files the rest of it matches.
+fn walk_glob(
+    pattern: &Path,
+    options: &WalkOptions,
+    files: &mut Vec<PathBuf>,
+    errors: &mut Vec<ignore::Error>,
+) {
+    let base = root(pattern);
+    let rest: Vec<_> = pattern
+        .components()
+        .skip(base.components().count())
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    let glob = format!("/{}", rest.join("/"));
+    let before = files.len();
+    walk(&base, Some(&glob), options, files, errors, |_| true);
+    if files.len() == before {
+        errors.push(ignore::Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no file matches {}", pattern.display()),
+        )));
+    }
+}
+
+/// The directory a glob pattern is expanded from: its components before the first wildcard, or
+/// `.` if it starts with one. Other paths are their own root.
+pub fn root(path: &Path) -> PathBuf {
+    if path.exists() || !is_glob(path) {
+        return path.to_path_buf();
+    }
+    let base: PathBuf = path
+        .components()
+        .take_while(|component| !is_glob(component.as_ref()))
+        .collect();
+    if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    }
+}
+
+fn walk(
+    dir: &Path,
+    glob: Option<&str>,
+    options: &WalkOptions,
+    files: &mut Vec<PathBuf>,
+    errors: &mut Vec<ignore::Error>,
+    keep: impl Fn(&Path) -> bool,
+) {
+    let mut overrides = ignore::overrides::OverrideBuilder::new(dir);
+    let globs = glob
+        .map(str::to_string)
+        .into_iter()
+        .chain(options.prune.iter().map(|glob| format!("!{}", glob)));
+    for glob in globs {
+        if let Err(error) = overrides.add(&glob) {
+            errors.push(error);
+            return;
+        }
+    }
+    let overrides = match overrides.build() {
+        Ok(overrides) => overrides,
+        Err(error) => {
+            errors.push(error);
+            return;
+        }
+    };
+
+    let walker = ignore::WalkBuilder::new(dir)
+        .standard_filters(false)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .max_depth(options.max_depth)
+        .overrides(overrides)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    for entry in walker {
+        match entry {
+            Ok(entry) => {
+                let path = entry.path();
+                if entry.file_type().is_some_and(|t| t.is_file()) && keep(path) {
+                    // a base of `.` is only implied by the pattern, leave it out of the paths.
+                    let path = match glob {
+                        Some(_) if dir == Path::new(".") => path.strip_prefix(".").unwrap_or(path),
+                        _ => path,
+                    };
+                    files.push(path.to_path_buf());
+                }
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+}
+
+/// Whether `path` contains glob wildcards.
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Whether the file at `path` starts with a signature the decoder accepts, see
+/// [`crx::CrxSignature::parse`]. Only the signature itself is read.
+pub fn has_crx_signature(path: &Path) -> bool {
+    fs::File::open(path).and_then(crx::is_crx).unwrap_or(false)
+}
+
+pub fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case(extension))
+        .unwrap_or(false)
+}
@@ -0,0 +1,106 @@
+use crate::{archive, console, paths, walk};
+use notify::{
+    event::{AccessKind, AccessMode},
+    EventKind, RecursiveMode, Watcher,
+};
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+/// How long the inputs must stay unchanged before a batch is converted, so that files still
+/// being written are picked up once, when they are complete.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Watch `inputs` (directories recursively, files through their directory, glob patterns from
+/// the directory they start from) and call `convert` with every batch of CRX files that appeared
+/// or changed, as [`walk::collect_crx_lenient`] would have found them. Runs until the process is
+/// stopped.
+pub fn run(
+    inputs: &[PathBuf],
+    options: &walk::WalkOptions,
+    sniff: bool,
+    mut convert: impl FnMut(Vec<PathBuf>),
+) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    let mut watched = 0;
+    for input in inputs
+        .iter()
+        .filter(|input| *input != Path::new(archive::STDIN))
+    {
+        let root = walk::root(input);
+        let (path, mode) = if root.is_dir() {
+            (root, RecursiveMode::Recursive)
+        } else {
+            // editors and extractors often replace files, which only their directory sees.
+            let parent = root.parent().filter(|p| !p.as_os_str().is_empty());
+            (
+                parent.unwrap_or(Path::new(".")).to_path_buf(),
+                RecursiveMode::NonRecursive,
+            )
+        };
+        watcher.watch(&path, mode).map_err(io::Error::other)?;
+        watched += 1;
+    }
+    console::line(
+        console::Status::Note("Watching"),
+        format_args!(
+            "{} location(s) for new or changed CRX files, stop with Ctrl-C",
+            watched
+        ),
+    );
+
+    loop {
+        let mut changed = BTreeSet::new();
+        // block for the first event, then take more until the inputs settle.
+        let mut event = receiver.recv().map_err(io::Error::other)?;
+        loop {
+            match event {
+                Ok(event) if is_write(&event.kind) => {
+                    changed.extend(event.paths.iter().map(|path| key(path)));
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    console::line(console::Status::Failed, format_args!("watch: {}", error))
+                }
+            }
+            match receiver.recv_timeout(SETTLE_TIME) {
+                Ok(next) => event = next,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(error) => return Err(io::Error::other(error)),
+            }
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        // walking again keeps prunes, `.crxignore` files, depth limits and patterns in force.
+        let (files, _) = walk::collect_crx_lenient(inputs, options, sniff);
+        let files: Vec<_> = files
+            .into_iter()
+            .filter(|file| changed.contains(&key(file)) && walk::has_crx_signature(file))
+            .collect();
+        if !files.is_empty() {
+            convert(files);
+        }
+    }
+}
+
+/// Whether `kind` may leave a file with new content.
+fn is_write(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_)
+            | EventKind::Modify(_)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    )
+}
+
+/// `path` in a form comparable between walked and reported paths.
+fn key(path: &Path) -> PathBuf {
+    paths::normalize(&paths::absolute(path))
+}
@@ -0,0 +1,53 @@
+//! Runs `analyze-clips` over version 3 sprites whose clip tables hold their own size, and checks
+//! that it finds which fields match which properties of the images.
+
+mod common;
+
+use crx::{CrxEncoder, CrxImageClip, CrxVersion};
+
+/// A `width` x `height` version 3 sprite with one clip covering it from (1, 2).
+fn sprite(width: u16, height: u16) -> Vec<u8> {
+    let clip = CrxImageClip {
+        unknown_1: 0,
+        x: 1,
+        y: 2,
+        unknown_2: width as i32 * 3,
+        width: width as i16,
+        height: height as i16,
+    };
+    common::encode_rgba(
+        CrxEncoder::new(CrxVersion::V3).with_clips(vec![clip]),
+        width,
+        height,
+        &common::pixels(width, height, 4, 1),
+    )
+}
+
+#[test]
+fn fields_are_matched_with_image_properties() {
+    let dir = tempfile::tempdir().unwrap();
+    common::write(&dir.path().join("a.crx"), &sprite(8, 6));
+    common::write(&dir.path().join("b.crx"), &sprite(10, 4));
+    // files without clips take no part.
+    common::write(
+        &dir.path().join("plain.crx"),
+        &common::encode_rgb(CrxVersion::V2, 4, 4, &common::pixels(4, 4, 3, 1)),
+    );
+
+    let report = common::crx_convert_in(dir.path(), &["analyze-clips", "."]);
+    assert!(report.contains("2 clip(s) in 2 file(s)"), "{}", report);
+    assert!(
+        report.contains("width: 2 distinct value(s) in [8, 10]"),
+        "{}",
+        report
+    );
+    for hypothesis in [
+        "unknown_1 == 0 in 2/2 clip(s) (100%)",
+        "width == width in 2/2 clip(s) (100%)",
+        "height == height in 2/2 clip(s) (100%)",
+    ] {
+        assert!(report.contains(hypothesis), "{}: {}", hypothesis, report);
+    }
+    // no property of the images is three times their width.
+    assert!(!report.contains("unknown_2 =="), "{}", report);
+}
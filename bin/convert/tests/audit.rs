@@ -0,0 +1,117 @@
+//! Converts a file, then checks that the options stamped into its output convert it again to the
+//! same place, and what `audit-outputs` reports on stamped and unstamped outputs.
+
+mod common;
+
+use crx::CrxVersion;
+use std::{fs, path::Path, process::Command};
+
+/// The text of the chunk `keyword` of the PNG at `path`.
+fn text_chunk(path: &Path, keyword: &str) -> Option<String> {
+    let reader = png::Decoder::new(std::io::BufReader::new(fs::File::open(path).unwrap()))
+        .read_info()
+        .unwrap();
+    let info = reader.info();
+    info.uncompressed_latin1_text
+        .iter()
+        .find(|chunk| chunk.keyword == keyword)
+        .map(|chunk| chunk.text.clone())
+        .or_else(|| {
+            info.utf8_text
+                .iter()
+                .find(|chunk| chunk.keyword == keyword)
+                .map(|chunk| chunk.get_text().unwrap())
+        })
+}
+
+#[test]
+fn stamped_options_convert_the_source_again_to_the_same_place() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path().canonicalize().unwrap();
+    fs::create_dir_all(root.join("game/cg")).unwrap();
+    let rgb = common::pixels(8, 6, 3, 1);
+    fs::write(
+        root.join("game/cg/ev 01.crx"),
+        common::encode_rgb(CrxVersion::V2, 8, 6, &rgb),
+    )
+    .unwrap();
+
+    common::crx_convert_in(
+        &root,
+        &[
+            "game",
+            "--output-dir",
+            "out",
+            "--resize",
+            "4x3",
+            "--dpi",
+            "300",
+            "--quiet",
+        ],
+    );
+    let output = root.join("out/cg/ev 01.png");
+    assert_eq!(
+        text_chunk(&output, "Source").as_deref(),
+        Some("game/cg/ev 01.crx")
+    );
+    assert_eq!(
+        text_chunk(&output, "Directory").as_deref(),
+        Some(&*root.to_string_lossy())
+    );
+    let options = text_chunk(&output, "Options").unwrap();
+    assert_eq!(
+        options,
+        format!(
+            "--output-dir out --dpi 300 --resize 4x3 --relative-to {}",
+            root.join("game").display()
+        )
+    );
+
+    // the command `audit-outputs` prints for it, run from elsewhere.
+    fs::remove_dir_all(root.join("out")).unwrap();
+    let command = format!(
+        "cd '{}' && '{}' {} 'game/cg/ev 01.crx' --quiet",
+        root.display(),
+        env!("CARGO_BIN_EXE_crx-convert"),
+        options
+    );
+    let status = Command::new("sh").arg("-c").arg(&command).status().unwrap();
+    assert!(status.success(), "{}", command);
+    let converted = common::image(&output);
+    assert_eq!((converted.width(), converted.height()), (4, 3));
+    assert!(!root.join("out/ev 01.png").exists());
+}
+
+#[test]
+fn unstamped_outputs_are_listed_on_request() {
+    let root = tempfile::tempdir().unwrap();
+    let rgb = common::pixels(8, 6, 3, 2);
+    fs::write(
+        root.path().join("ev02.crx"),
+        common::encode_rgb(CrxVersion::V1, 8, 6, &rgb),
+    )
+    .unwrap();
+    common::crx_convert(&[root.path().join("ev02.crx").as_os_str(), "--quiet".as_ref()]);
+    image::RgbImage::from_raw(8, 6, rgb)
+        .unwrap()
+        .save(root.path().join("edited.png"))
+        .unwrap();
+
+    let report = common::crx_convert(&["audit-outputs".as_ref(), root.path().as_os_str()]);
+    assert!(report.contains("Clean"), "{}", report);
+    assert!(report.contains("checked 2 file(s)"), "{}", report);
+    assert!(!report.contains("edited.png"), "{}", report);
+
+    let report = common::crx_convert(&[
+        "audit-outputs".as_ref(),
+        root.path().as_os_str(),
+        "--include-unstamped".as_ref(),
+    ]);
+    let unstamped: Vec<&str> = report
+        .lines()
+        .filter(|line| line.contains("no version stamp"))
+        .collect();
+    assert_eq!(unstamped.len(), 1, "{}", report);
+    assert!(unstamped[0].contains("edited.png"), "{}", report);
+    assert!(!report.contains("Re-run"), "{}", report);
+}
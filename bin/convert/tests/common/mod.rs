@@ -0,0 +1,71 @@
+//! Helpers shared by the tests that run the converter.
+
+#![allow(dead_code)]
+
+use crx::{CrxEncoder, CrxVersion};
+use std::{
+    ffi::OsStr,
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+/// Gradients with some noise, so that rows take different predictors.
+pub fn pixels(width: u16, height: u16, channels: usize, seed: u8) -> Vec<u8> {
+    (0..width as usize * height as usize * channels)
+        .map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed) ^ (i / 5) as u8)
+        .collect()
+}
+
+pub fn encode_rgb(version: CrxVersion, width: u16, height: u16, rgb: &[u8]) -> Vec<u8> {
+    let mut file = Vec::new();
+    CrxEncoder::new(version)
+        .write_rgb(&mut file, width, height, rgb)
+        .unwrap();
+    file
+}
+
+pub fn encode_rgba(encoder: CrxEncoder, width: u16, height: u16, rgba: &[u8]) -> Vec<u8> {
+    let mut file = Vec::new();
+    encoder.write_rgba(&mut file, width, height, rgba).unwrap();
+    file
+}
+
+/// Write `data` to `path`, creating the directories it is in.
+pub fn write(path: &Path, data: &[u8]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, data).unwrap();
+}
+
+/// Run `crx-convert` with `args` in `dir`, whether it succeeds or not.
+pub fn run_in<S: AsRef<OsStr>>(dir: &Path, args: &[S]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crx-convert"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run `crx-convert` with `args` in `dir`, and return what it printed to stdout; fails the test
+/// if it fails.
+pub fn crx_convert_in<S: AsRef<OsStr>>(dir: &Path, args: &[S]) -> String {
+    let output = run_in(dir, args);
+    let args: Vec<_> = args.iter().map(AsRef::as_ref).collect();
+    assert!(
+        output.status.success(),
+        "crx-convert {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Like [`crx_convert_in`], in the directory of the test.
+pub fn crx_convert<S: AsRef<OsStr>>(args: &[S]) -> String {
+    crx_convert_in(Path::new("."), args)
+}
+
+/// The pixels of the image at `path`, with palettes expanded.
+pub fn image(path: &Path) -> image::DynamicImage {
+    image::open(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,184 @@
+//! Runs the subcommands that compare images over generated CRX files: `compare` on an image and
+//! its edit, `compare-sets` on two versions of a game, and `auto-pair` on base CGs and the diffs
+//! cut from them.
+
+mod common;
+
+use crx::{CrxEncoder, CrxVersion};
+
+const WIDTH: u16 = 8;
+const HEIGHT: u16 = 6;
+
+/// The line of `report` that mentions `what`.
+fn line_of<'a>(report: &'a str, what: &str) -> &'a str {
+    report
+        .lines()
+        .find(|line| line.contains(what))
+        .unwrap_or_else(|| panic!("no line about {}:\n{}", what, report))
+}
+
+#[test]
+fn compare_finds_the_edited_region() {
+    let dir = tempfile::tempdir().unwrap();
+    let original = common::pixels(WIDTH, HEIGHT, 3, 1);
+    let mut edited = original.clone();
+    // a 2x2 block at (3, 1), each pixel off by 128 in red.
+    for (x, y) in [(3, 1), (4, 1), (3, 2), (4, 2)] {
+        edited[(y * WIDTH as usize + x) * 3] ^= 0x80;
+    }
+    let encode = |rgb| common::encode_rgb(CrxVersion::V2, WIDTH, HEIGHT, rgb);
+    common::write(&dir.path().join("a.crx"), &encode(&original));
+    common::write(&dir.path().join("b.crx"), &encode(&edited));
+
+    let report = common::crx_convert_in(dir.path(), &["compare", "a.crx", "b.crx"]);
+    assert!(report.contains("2x2 at (3, 1), 4 pixel(s)"), "{}", report);
+    assert!(
+        report.contains("1 region(s), 4 of 48 pixel(s) differ by more than 0"),
+        "{}",
+        report
+    );
+
+    let report = common::crx_convert_in(
+        dir.path(),
+        &["compare", "a.crx", "b.crx", "--tolerance", "128"],
+    );
+    assert!(
+        report.contains("0 region(s), 0 of 48 pixel(s) differ by more than 128"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn compare_refuses_images_of_different_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let small = common::pixels(WIDTH - 1, HEIGHT, 3, 1);
+    common::write(
+        &dir.path().join("a.crx"),
+        &common::encode_rgb(CrxVersion::V2, WIDTH - 1, HEIGHT, &small),
+    );
+    let rgb = common::pixels(WIDTH, HEIGHT, 3, 1);
+    common::write(
+        &dir.path().join("b.crx"),
+        &common::encode_rgb(CrxVersion::V2, WIDTH, HEIGHT, &rgb),
+    );
+
+    let output = common::run_in(dir.path(), &["compare", "a.crx", "b.crx"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        error.contains("dimension mismatch: 7x6 vs 8x6"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn compare_sets_tells_the_kinds_of_change_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = |seed| {
+        common::encode_rgb(
+            CrxVersion::V2,
+            WIDTH,
+            HEIGHT,
+            &common::pixels(WIDTH, HEIGHT, 3, seed),
+        )
+    };
+    let root = dir.path();
+    common::write(&root.join("old/cg/same.crx"), &image(1));
+    common::write(&root.join("new/cg/same.crx"), &image(1));
+    common::write(&root.join("old/cg/edited.crx"), &image(2));
+    common::write(
+        &root.join("new/cg/edited.crx"),
+        &common::encode_rgb(
+            CrxVersion::V2,
+            WIDTH * 2,
+            HEIGHT,
+            &common::pixels(WIDTH * 2, HEIGHT, 3, 2),
+        ),
+    );
+    common::write(&root.join("old/sys/button.crx"), &image(3));
+    common::write(&root.join("new/sys/ui/button.crx"), &image(3));
+    common::write(&root.join("old/cg/cut.crx"), &image(4));
+    common::write(&root.join("new/cg/extra.crx"), &image(5));
+
+    let report = common::crx_convert_in(root, &["compare-sets", "old", "new"]);
+    assert!(
+        report.contains("1 added, 1 removed, 1 moved, 1 changed, 1 unchanged"),
+        "{}",
+        report
+    );
+    assert!(
+        line_of(&report, "edited.crx").contains("Changed"),
+        "{}",
+        report
+    );
+    assert!(
+        line_of(&report, "cut.crx").contains("Removed"),
+        "{}",
+        report
+    );
+    assert!(
+        line_of(&report, "extra.crx").contains("Added"),
+        "{}",
+        report
+    );
+    let moved = line_of(&report, "old/sys/button.crx");
+    assert!(moved.contains("Moved"), "{}", report);
+    assert!(moved.contains("-> new/sys/ui/button.crx"), "{}", report);
+    assert!(!report.contains("same.crx"), "{}", report);
+
+    let report = common::crx_convert_in(root, &["compare-sets", "old", "new", "--all"]);
+    assert!(line_of(&report, "same.crx").contains("Same"), "{}", report);
+}
+
+#[test]
+fn auto_pair_groups_diffs_under_their_base() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let mut base = common::pixels(WIDTH, HEIGHT, 4, 1);
+    base.chunks_mut(4).for_each(|pixel| pixel[3] = 0xFF);
+    common::write(
+        &root.join("cg/base.crx"),
+        &common::encode_rgba(CrxEncoder::new(CrxVersion::V2), WIDTH, HEIGHT, &base),
+    );
+    // 3x2 images placed at (2, 1) by their inner offset: a cut of the base, and one that is
+    // nothing like it.
+    let cut: Vec<u8> = (1..3)
+        .flat_map(|y| (2..5).map(move |x| (y * WIDTH as usize + x) * 4))
+        .flat_map(|i| base[i..i + 4].to_vec())
+        .collect();
+    let unrelated: Vec<u8> = cut.iter().map(|byte| !byte | 0x0F).collect();
+    let diff = |rgba: &[u8]| {
+        common::encode_rgba(
+            CrxEncoder::new(CrxVersion::V2).with_offset(2, 1),
+            3,
+            2,
+            rgba,
+        )
+    };
+    common::write(&root.join("cg/face.crx"), &diff(&cut));
+    common::write(&root.join("cg/other.crx"), &diff(&unrelated));
+
+    common::crx_convert_in(root, &["auto-pair", "cg", "--output", "pairs.json"]);
+    let json = std::fs::read_to_string(root.join("pairs.json")).unwrap();
+    assert!(
+        json.starts_with("{\"min_similarity\":0.5,\"tolerance\":8,\"bases\":["),
+        "{}",
+        json
+    );
+    assert!(
+        json.contains(
+            "{\"path\":\"cg/base.crx\",\"width\":8,\"height\":6,\"inner_x\":0,\"inner_y\":0,\"diffs\":[{\"path\":\"cg/face.crx\",\"width\":3,\"height\":2,\"inner_x\":2,\"inner_y\":1,\"similarity\":1.0000}]}"
+        ),
+        "{}",
+        json
+    );
+    assert!(
+        json.contains(
+            "{\"path\":\"cg/other.crx\",\"width\":3,\"height\":2,\"inner_x\":2,\"inner_y\":1,\"diffs\":[]}"
+        ),
+        "{}",
+        json
+    );
+}
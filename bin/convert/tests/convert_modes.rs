@@ -0,0 +1,217 @@
+//! Runs the converter in its other modes over generated CRX files: `--check` on its own and with
+//! `--json`, `--dedup`, `--memory-limit`, and `--watch`, and over a file that fails to convert
+//! where an earlier run left an output.
+
+mod common;
+
+use crx::CrxVersion;
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+const WIDTH: u16 = 8;
+const HEIGHT: u16 = 6;
+
+fn image(version: CrxVersion, seed: u8) -> Vec<u8> {
+    common::encode_rgb(
+        version,
+        WIDTH,
+        HEIGHT,
+        &common::pixels(WIDTH, HEIGHT, 3, seed),
+    )
+}
+
+#[test]
+fn check_decodes_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    common::write(&dir.path().join("cg/a.crx"), &image(CrxVersion::V1, 1));
+    common::write(&dir.path().join("cg/b.crx"), &image(CrxVersion::V2, 1));
+    let broken = image(CrxVersion::V2, 2);
+    common::write(&dir.path().join("cg/c.crx"), &broken[..broken.len() / 2]);
+
+    let output = common::run_in(dir.path(), &["cg", "--check"]);
+    assert!(!output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        report.contains("2 passed, 1 failed, 0 unreadable path(s)"),
+        "{}",
+        report
+    );
+    assert!(report.contains("8x6 v1 24bpp"), "{}", report);
+    assert!(report.contains("decode:"), "{}", report);
+    assert!(!dir.path().join("cg/a.png").exists());
+
+    fs::remove_file(dir.path().join("cg/c.crx")).unwrap();
+    let output = common::crx_convert_in(dir.path(), &["cg", "--check", "--json"]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", output);
+    let sha = |line: &str| {
+        let (_, rest) = line.split_once("\"pixels_sha256\":\"").unwrap();
+        rest[..64].to_string()
+    };
+    // the same pixels, stored by two versions of the format.
+    assert_eq!(sha(lines[0]), sha(lines[1]));
+    for (line, (path, version)) in lines.iter().zip([("cg/a.crx", 1), ("cg/b.crx", 2)]) {
+        assert!(
+            line.starts_with(&format!(
+                "{{\"path\":\"{}\",\"passed\":true,\"width\":8,\"height\":6,\"version\":{},\"bpp\":24,",
+                path, version
+            )),
+            "{}",
+            line
+        );
+    }
+}
+
+#[cfg(feature = "flamegraph")]
+#[test]
+fn check_writes_the_flame_graph() {
+    let dir = tempfile::tempdir().unwrap();
+    common::write(&dir.path().join("cg/a.crx"), &image(CrxVersion::V2, 1));
+
+    common::crx_convert_in(
+        dir.path(),
+        &["cg", "--check", "--flamegraph", "profile.svg"],
+    );
+    let svg = fs::read_to_string(dir.path().join("profile.svg")).unwrap();
+    assert!(svg.contains("<svg"), "{}", svg);
+    assert!(!dir.path().join("profile.folded").exists());
+}
+
+#[test]
+fn failed_conversions_keep_earlier_outputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("cg/a.crx");
+    let intact = image(CrxVersion::V2, 1);
+    common::write(&source, &intact);
+    common::crx_convert_in(dir.path(), &["cg", "--quiet"]);
+    let output = dir.path().join("cg/a.png");
+    let converted = fs::read(&output).unwrap();
+
+    common::write(&source, &intact[..intact.len() / 2]);
+    let report = common::crx_convert_in(dir.path(), &["cg"]);
+    assert!(report.contains("0 converted, 1 failed"), "{}", report);
+    assert_eq!(fs::read(&output).unwrap(), converted);
+    // the unfinished output is not left behind either.
+    let mut names: Vec<_> = fs::read_dir(dir.path().join("cg"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a.crx", "a.png"]);
+}
+
+#[test]
+fn dedup_writes_copies_once() {
+    let dir = tempfile::tempdir().unwrap();
+    common::write(&dir.path().join("cg/a.crx"), &image(CrxVersion::V1, 1));
+    common::write(&dir.path().join("cg/b.crx"), &image(CrxVersion::V2, 1));
+    common::write(&dir.path().join("cg/c.crx"), &image(CrxVersion::V2, 2));
+
+    let report = common::crx_convert_in(dir.path(), &["cg", "--dedup", "hardlink"]);
+    assert!(
+        report.contains("1 cop(ies) of 1 image(s) hard-linked"),
+        "{}",
+        report
+    );
+    let png = |name: &str| fs::read(dir.path().join("cg").join(name)).unwrap();
+    assert_eq!(png("a.png"), png("b.png"));
+    assert_ne!(png("a.png"), png("c.png"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let inode = |name: &str| {
+            fs::metadata(dir.path().join("cg").join(name))
+                .unwrap()
+                .ino()
+        };
+        assert_eq!(inode("a.png"), inode("b.png"));
+        assert_ne!(inode("a.png"), inode("c.png"));
+    }
+}
+
+/// The resident memory of the process can only be measured on Linux.
+#[cfg(target_os = "linux")]
+#[test]
+fn memory_limit_converts_fewer_files_at_once() {
+    let dir = tempfile::tempdir().unwrap();
+    for seed in 0..6 {
+        common::write(
+            &dir.path().join(format!("cg/{}.crx", seed)),
+            &image(CrxVersion::V2, seed),
+        );
+    }
+
+    // no process fits in a kilobyte, so the files are converted one at a time.
+    let report = common::crx_convert_in(dir.path(), &["cg", "--memory-limit", "1K", "--jobs", "4"]);
+    assert!(
+        report.contains("converted as few as 1 file(s) at once to stay under --memory-limit"),
+        "{}",
+        report
+    );
+    for seed in 0..6 {
+        assert!(dir.path().join(format!("cg/{}.png", seed)).exists());
+    }
+
+    let output = common::run_in(dir.path(), &["cg", "--memory-limit", "0"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(error.contains("the limit must be above zero"), "{}", error);
+}
+
+#[test]
+fn watch_converts_files_as_they_appear() {
+    let dir = tempfile::tempdir().unwrap();
+    common::write(&dir.path().join("cg/a.crx"), &image(CrxVersion::V2, 1));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_crx-convert"))
+        .current_dir(dir.path())
+        .args(["cg", "--watch"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    thread::spawn(move || {
+        for line in stdout.lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let watching = loop {
+        match receiver.recv_timeout(Duration::from_secs(10)) {
+            Ok(line) if line.contains("Watching") => break true,
+            Ok(_) => {}
+            Err(_) => break false,
+        }
+    };
+
+    let converted = watching && {
+        assert!(dir.path().join("cg/a.png").exists());
+        common::write(&dir.path().join("cg/b.crx"), &image(CrxVersion::V2, 2));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let png = dir.path().join("cg/b.png");
+        // the output is complete once it decodes.
+        while Instant::now() < deadline && image::open(&png).is_err() {
+            thread::sleep(Duration::from_millis(50));
+        }
+        image::open(&png).is_ok()
+    };
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(watching, "the converter did not start watching");
+    assert!(converted, "cg/b.crx was not converted");
+    assert_eq!(
+        common::image(&dir.path().join("cg/b.png"))
+            .to_rgb8()
+            .into_raw(),
+        common::pixels(WIDTH, HEIGHT, 3, 2)
+    );
+}
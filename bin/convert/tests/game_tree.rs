@@ -0,0 +1,268 @@
+//! Runs the converter over a miniature game directory generated in a temporary directory: a
+//! `.pck` archive holding CG of versions 1 and 2, a version 3 sprite with a clip table, a list
+//! file, and a base CG with a differential image. The CRX entries are extracted from the
+//! archive, converted, the difference composed onto its base, and the report checked.
+
+mod common;
+
+use crx::{CrxEncoder, CrxImageClip, CrxVersion};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const WIDTH: u16 = 8;
+const HEIGHT: u16 = 6;
+
+/// A CIRCUS `.pck` archive of `entries`: the entry count, the `(offset, size)` table, the index
+/// of NUL-padded names with offsets and sizes, then the data.
+fn pck(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let index_size = 4 + entries.len() * (8 + 0x38 + 8);
+    let mut offsets = Vec::new();
+    let mut offset = index_size;
+    for (_, data) in entries {
+        offsets.push((offset as u32, data.len() as u32));
+        offset += data.len();
+    }
+    let mut archive = (entries.len() as u32).to_le_bytes().to_vec();
+    for (offset, size) in &offsets {
+        archive.extend_from_slice(&offset.to_le_bytes());
+        archive.extend_from_slice(&size.to_le_bytes());
+    }
+    for ((name, _), (offset, size)) in entries.iter().zip(&offsets) {
+        let mut padded = [0; 0x38];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        archive.extend_from_slice(&padded);
+        archive.extend_from_slice(&offset.to_le_bytes());
+        archive.extend_from_slice(&size.to_le_bytes());
+    }
+    for (_, data) in entries {
+        archive.extend_from_slice(data);
+    }
+    archive
+}
+
+/// What the game tree holds, to compare the outputs against.
+struct Game {
+    data: PathBuf,
+    ev01: Vec<u8>,
+    ev02: Vec<u8>,
+    palette: Vec<[u8; 3]>,
+    button: Vec<u8>,
+    base: Vec<u8>,
+    face: Vec<u8>,
+}
+
+/// ```text
+/// Data/
+///   cg.pck       ev01.crx (v1, RGB), ev02.crx (v2, RGBA), cg.txt
+///   cg.lst       names of the CG, not an image
+///   sys/button.crx (v3, palette, one clip)
+///   face/base.crx  (v2, RGB) and face01.crx (v3, RGBA at (3, 2) of the base)
+/// ```
+fn game_tree(root: &Path) -> Game {
+    let data = root.join("Data");
+    fs::create_dir_all(data.join("sys")).unwrap();
+    fs::create_dir_all(data.join("face")).unwrap();
+
+    let ev01 = common::pixels(WIDTH, HEIGHT, 3, 1);
+    let ev02 = common::pixels(WIDTH, HEIGHT, 4, 2);
+    let archive = pck(&[
+        (
+            "ev01.crx",
+            &common::encode_rgb(CrxVersion::V1, WIDTH, HEIGHT, &ev01),
+        ),
+        (
+            "ev02.crx",
+            &common::encode_rgba(CrxEncoder::new(CrxVersion::V2), WIDTH, HEIGHT, &ev02),
+        ),
+        ("cg.txt", b"not an image"),
+    ]);
+    fs::write(data.join("cg.pck"), archive).unwrap();
+    fs::write(data.join("cg.lst"), "ev01\nev02\n").unwrap();
+
+    let palette: Vec<[u8; 3]> = (0..4).map(|i| [i * 60, 0x80, 0xF0 - i * 30]).collect();
+    let button: Vec<u8> = (0..WIDTH as usize * HEIGHT as usize)
+        .map(|i| (i % 4) as u8)
+        .collect();
+    let clip = CrxImageClip {
+        unknown_1: 0,
+        x: 1,
+        y: 1,
+        unknown_2: 0,
+        width: 4,
+        height: 3,
+    };
+    let mut file = Vec::new();
+    CrxEncoder::new(CrxVersion::V3)
+        .with_clips(vec![clip])
+        .write_indexed(&mut file, WIDTH, HEIGHT, &palette, &button)
+        .unwrap();
+    fs::write(data.join("sys/button.crx"), file).unwrap();
+
+    let base = common::pixels(WIDTH, HEIGHT, 3, 3);
+    fs::write(
+        data.join("face/base.crx"),
+        common::encode_rgb(CrxVersion::V2, WIDTH, HEIGHT, &base),
+    )
+    .unwrap();
+    // opaque, so that composing replaces the pixels of the base.
+    let face: Vec<u8> = common::pixels(2, 2, 4, 4)
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF])
+        .collect();
+    let encoder = CrxEncoder::new(CrxVersion::V3).with_offset(3, 2);
+    fs::write(
+        data.join("face/face01.crx"),
+        common::encode_rgba(encoder, 2, 2, &face),
+    )
+    .unwrap();
+
+    Game {
+        data,
+        ev01,
+        ev02,
+        palette,
+        button,
+        base,
+        face,
+    }
+}
+
+#[test]
+fn converts_a_game_tree() {
+    let root = tempfile::tempdir().unwrap();
+    let game = game_tree(root.path());
+    let out = root.path().join("out");
+    let report = root.path().join("report.csv");
+
+    common::crx_convert(&[
+        &game.data,
+        Path::new("--descend-archives"),
+        Path::new("--output-dir"),
+        &out,
+        Path::new("--report"),
+        &report,
+    ]);
+
+    // extracted from the archive, into a folder named after it.
+    let ev01 = common::image(&out.join("cg/ev01.png"));
+    assert_eq!((ev01.width(), ev01.height()), (WIDTH as u32, HEIGHT as u32));
+    assert_eq!(ev01.to_rgb8().into_raw(), game.ev01);
+    assert_eq!(
+        common::image(&out.join("cg/ev02.png"))
+            .to_rgba8()
+            .into_raw(),
+        game.ev02
+    );
+    assert!(!out.join("cg/cg.png").exists());
+    assert!(!out.join("cg.png").exists());
+
+    let button = common::image(&out.join("sys/button.png"))
+        .to_rgb8()
+        .into_raw();
+    let expected: Vec<u8> = game
+        .button
+        .iter()
+        .flat_map(|&index| game.palette[index as usize])
+        .collect();
+    assert_eq!(button, expected);
+    assert_eq!(
+        common::image(&out.join("face/base.png"))
+            .to_rgb8()
+            .into_raw(),
+        game.base
+    );
+    assert!(out.join("face/face01.png").exists());
+
+    // one row per file, the version in the ninth column and the status in the seventeenth.
+    let report = fs::read_to_string(&report).unwrap();
+    let rows: Vec<Vec<&str>> = report
+        .lines()
+        .skip(1)
+        .map(|row| row.split(',').collect())
+        .collect();
+    assert_eq!(rows.len(), 5, "{}", report);
+    for (source, version) in [
+        ("ev01.crx", "1"),
+        ("ev02.crx", "2"),
+        ("button.crx", "3"),
+        ("base.crx", "2"),
+        ("face01.crx", "3"),
+    ] {
+        let row = rows
+            .iter()
+            .find(|row| row[0].ends_with(source))
+            .unwrap_or_else(|| panic!("{} is not in the report", source));
+        assert_eq!((row[8], row[16]), (version, "converted"), "{}", source);
+    }
+}
+
+#[test]
+fn composes_differences_onto_their_base() {
+    let root = tempfile::tempdir().unwrap();
+    let game = game_tree(root.path());
+    let out = root.path().join("composed");
+
+    common::crx_convert(&[
+        &game.data.join("face/face01.crx"),
+        Path::new("--base"),
+        &game.data.join("face/base.crx"),
+        Path::new("--output-dir"),
+        &out,
+    ]);
+
+    let composed = common::image(&out.join("face01.png")).to_rgba8();
+    assert_eq!(composed.dimensions(), (WIDTH as u32, HEIGHT as u32));
+    for (x, y, pixel) in composed.enumerate_pixels() {
+        let (fx, fy) = (x as i32 - 3, y as i32 - 2);
+        let expected = if (0..2).contains(&fx) && (0..2).contains(&fy) {
+            let i = (fy * 2 + fx) as usize * 4;
+            [game.face[i], game.face[i + 1], game.face[i + 2], 0xFF]
+        } else {
+            let i = (y * WIDTH as u32 + x) as usize * 3;
+            [game.base[i], game.base[i + 1], game.base[i + 2], 0xFF]
+        };
+        assert_eq!(pixel.0, expected, "pixel ({}, {})", x, y);
+    }
+}
+
+#[test]
+fn archives_that_cannot_be_listed_fail_alone() {
+    let root = tempfile::tempdir().unwrap();
+    let game = game_tree(root.path());
+    // no entries, which no archive has.
+    fs::write(game.data.join("broken.pck"), [0; 16]).unwrap();
+    let out = root.path().join("out");
+    let report = root.path().join("report.csv");
+
+    common::crx_convert(&[
+        &game.data,
+        Path::new("--descend-archives"),
+        Path::new("--output-dir"),
+        &out,
+        Path::new("--report"),
+        &report,
+    ]);
+
+    assert!(out.join("cg/ev01.png").exists());
+    assert!(out.join("sys/button.png").exists());
+    let report = fs::read_to_string(&report).unwrap();
+    let broken: Vec<&str> = report
+        .lines()
+        .find(|row| row.starts_with(&*game.data.join("broken.pck").to_string_lossy()))
+        .unwrap_or_else(|| panic!("broken.pck is not in the report:\n{}", report))
+        .split(',')
+        .collect();
+    assert_eq!(broken[16], "failed");
+    assert!(broken[17].starts_with("list: "), "{}", broken[17]);
+    assert_eq!(
+        report
+            .lines()
+            .filter(|row| row.contains(",converted,"))
+            .count(),
+        5,
+        "{}",
+        report
+    );
+}
@@ -0,0 +1,134 @@
+//! Converts generated CRX files to PNG, edits the PNG, and packs it back with `pack` and
+//! `pack-batch`, checking that the edit and the header of the original both survive.
+
+mod common;
+
+use crx::{CrxEncoder, CrxFile, CrxImageClip, CrxVersion};
+use image::{Rgb, RgbImage};
+use std::{fs, path::Path};
+
+const WIDTH: u16 = 8;
+const HEIGHT: u16 = 6;
+
+const CLIP: CrxImageClip = CrxImageClip {
+    unknown_1: 7,
+    x: 1,
+    y: 2,
+    unknown_2: 9,
+    width: 3,
+    height: 4,
+};
+
+/// A version 3 RGB image with an inner offset and a clip, converted to PNG beside it.
+fn original(dir: &Path, name: &str) -> RgbImage {
+    let rgb = common::pixels(WIDTH, HEIGHT, 3, 1);
+    let mut file = Vec::new();
+    CrxEncoder::new(CrxVersion::V3)
+        .with_offset(5, -2)
+        .with_clips(vec![CLIP])
+        .write_rgb(&mut file, WIDTH, HEIGHT, &rgb)
+        .unwrap();
+    common::write(&dir.join(format!("{}.crx", name)), &file);
+    common::crx_convert_in(dir, &[format!("{}.crx", name)]);
+    common::image(&dir.join(format!("{}.png", name))).to_rgb8()
+}
+
+/// The pixels of the CRX file at `path`, converted to `<path>.png`.
+fn unpack(dir: &Path, path: &str) -> RgbImage {
+    common::crx_convert_in(dir, &[path]);
+    common::image(&dir.join(path).with_extension("png")).to_rgb8()
+}
+
+#[test]
+fn an_edited_png_is_packed_like_its_original() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut edited = original(dir.path(), "ev01");
+    edited.put_pixel(2, 3, Rgb([0xFF, 0, 0x80]));
+    edited.save(dir.path().join("ev01.png")).unwrap();
+
+    common::crx_convert_in(
+        dir.path(),
+        &[
+            "pack",
+            "ev01.png",
+            "--like",
+            "ev01.crx",
+            "-o",
+            "packed/ev01.crx",
+        ],
+    );
+    let metadata =
+        CrxFile::read_metadata(&fs::read(dir.path().join("packed/ev01.crx")).unwrap()[..]).unwrap();
+    assert_eq!(metadata.version(), CrxVersion::V3);
+    assert_eq!((metadata.inner_x(), metadata.inner_y()), (5, -2));
+    assert_eq!(metadata.clips(), [CLIP]);
+    assert_eq!(unpack(dir.path(), "packed/ev01.crx"), edited);
+}
+
+#[test]
+fn pngs_of_another_size_are_refused_without_fit() {
+    let dir = tempfile::tempdir().unwrap();
+    let original = original(dir.path(), "ev01");
+    let mut wider = RgbImage::new(WIDTH as u32 + 2, HEIGHT as u32);
+    image::imageops::replace(&mut wider, &original, 0, 0);
+    wider.save(dir.path().join("wider.png")).unwrap();
+
+    let output = common::run_in(
+        dir.path(),
+        &["pack", "wider.png", "--like", "ev01.crx", "-o", "out.crx"],
+    );
+    assert!(!output.status.success());
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        error.contains("dimension mismatch: 8x6 vs 10x6, see --fit"),
+        "{}",
+        error
+    );
+    assert!(!dir.path().join("out.crx").exists());
+
+    common::crx_convert_in(
+        dir.path(),
+        &[
+            "pack",
+            "wider.png",
+            "--like",
+            "ev01.crx",
+            "-o",
+            "out.crx",
+            "--fit",
+            "crop",
+        ],
+    );
+    assert_eq!(unpack(dir.path(), "out.crx"), original);
+}
+
+#[test]
+fn pack_batch_packs_every_row_of_the_map() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut edited = original(dir.path(), "ev01");
+    edited.put_pixel(0, 0, Rgb([1, 2, 3]));
+    fs::create_dir_all(dir.path().join("edits")).unwrap();
+    edited.save(dir.path().join("edits/ev01.png")).unwrap();
+    let mut wider = RgbImage::new(WIDTH as u32 + 2, HEIGHT as u32);
+    image::imageops::replace(&mut wider, &edited, 0, 0);
+    wider.save(dir.path().join("edits/wider.png")).unwrap();
+
+    // paths are relative to the map; the second row has no fit and the third crops.
+    common::write(
+        &dir.path().join("edits/map.csv"),
+        b"edited,like,output\n\
+          # packed into the game\n\
+          ev01.png,../ev01.crx,out/ev01.crx\n\
+          wider.png,../ev01.crx,out/wider.crx\n\
+          \"wider.png\",../ev01.crx,out/cropped.crx,crop\n",
+    );
+    let output = common::run_in(dir.path(), &["pack-batch", "edits/map.csv"]);
+    assert!(!output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("2 packed, 1 failed"), "{}", report);
+    assert!(report.contains("line 4: dimension mismatch"), "{}", report);
+
+    assert_eq!(unpack(dir.path(), "edits/out/ev01.crx"), edited);
+    assert_eq!(unpack(dir.path(), "edits/out/cropped.crx"), edited);
+    assert!(!dir.path().join("edits/out/wider.crx").exists());
+}
@@ -0,0 +1,96 @@
+//! Runs `patch-header` over generated version 3 sprites and checks what it rewrites, and what it
+//! leaves alone.
+
+mod common;
+
+use crx::{CrxEncoder, CrxFile, CrxImageClip, CrxVersion};
+use std::fs;
+
+fn clip(n: i16) -> CrxImageClip {
+    CrxImageClip {
+        unknown_1: n as i32 * 100,
+        x: n,
+        y: n,
+        unknown_2: -(n as i32),
+        width: 4,
+        height: 4,
+    }
+}
+
+fn sprite(seed: u8) -> Vec<u8> {
+    common::encode_rgba(
+        CrxEncoder::new(CrxVersion::V3)
+            .with_offset(10, 20)
+            .with_clips(vec![clip(1), clip(2)]),
+        6,
+        5,
+        &common::pixels(6, 5, 4, seed),
+    )
+}
+
+#[test]
+fn offset_and_clips_are_rewritten_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, seed) in [("chara/a.crx", 1), ("chara/b.crx", 2)] {
+        common::write(&dir.path().join(name), &sprite(seed));
+    }
+
+    let report = common::crx_convert_in(
+        dir.path(),
+        &[
+            "patch-header",
+            "chara",
+            "--inner-offset",
+            "-3,7",
+            "--clip",
+            "1=0,1,5x3",
+        ],
+    );
+    assert!(report.contains("2 patched, 0 failed"), "{}", report);
+
+    for (name, seed) in [("chara/a.crx", 1), ("chara/b.crx", 2)] {
+        let file = fs::read(dir.path().join(name)).unwrap();
+        assert_eq!(file.len(), sprite(seed).len());
+        let patched = CrxFile::read(&file[..]).unwrap();
+        assert_eq!((patched.inner_x(), patched.inner_y()), (-3, 7));
+        assert_eq!(
+            patched.clips(),
+            [
+                clip(1),
+                CrxImageClip {
+                    x: 0,
+                    y: 1,
+                    width: 5,
+                    height: 3,
+                    ..clip(2)
+                }
+            ]
+        );
+        let original = CrxFile::read(&sprite(seed)[..]).unwrap();
+        assert_eq!(patched.raw_buffer(), original.raw_buffer());
+    }
+}
+
+#[test]
+fn missing_clips_fail_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    common::write(&dir.path().join("a.crx"), &sprite(1));
+
+    let output = common::run_in(
+        dir.path(),
+        &["patch-header", "a.crx", "--clip", "2=0,0,1x1"],
+    );
+    assert!(!output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("no clip 2, the table has 2"), "{}", report);
+    assert_eq!(fs::read(dir.path().join("a.crx")).unwrap(), sprite(1));
+
+    let output = common::run_in(dir.path(), &["patch-header", "a.crx"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        error.contains("nothing to patch, pass --inner-offset or --clip"),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,178 @@
+//! Runs the subcommands for numbered frames over a generated character directory: `sequences`
+//! groups the frames and writes ffmpeg concat lists, and `animate` writes a sequence as APNG,
+//! on its own or composed onto a base CG.
+
+mod common;
+
+use crx::{CrxEncoder, CrxVersion};
+use std::{fs, io::BufReader, path::Path};
+
+const WIDTH: u16 = 4;
+const HEIGHT: u16 = 3;
+
+/// Opaque RGBA pixels of a frame, different for each `seed`.
+fn frame_pixels(seed: u8) -> Vec<u8> {
+    let mut rgba = common::pixels(WIDTH, HEIGHT, 4, seed);
+    rgba.chunks_mut(4).for_each(|pixel| pixel[3] = 0xFF);
+    rgba
+}
+
+/// Write a frame of `rgba` to `path`, placed at `offset` of the base it belongs to.
+fn write_frame(path: &Path, rgba: &[u8], offset: (i16, i16)) {
+    let encoder = CrxEncoder::new(CrxVersion::V2).with_offset(offset.0, offset.1);
+    common::write(path, &common::encode_rgba(encoder, WIDTH, HEIGHT, rgba));
+}
+
+/// The frames of the APNG at `path`, as RGBA, with its play count.
+fn apng_frames(path: &Path) -> (Vec<Vec<u8>>, u32) {
+    let decoder = png::Decoder::new(BufReader::new(fs::File::open(path).unwrap()));
+    let mut reader = decoder.read_info().unwrap();
+    let control = reader.info().animation_control.expect("not animated");
+    let mut frames = Vec::new();
+    for _ in 0..control.num_frames {
+        let mut frame = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut frame).unwrap();
+        frames.push(frame);
+    }
+    (frames, control.num_plays)
+}
+
+#[test]
+fn sequences_are_listed_with_their_gaps() {
+    let dir = tempfile::tempdir().unwrap();
+    let chara = dir.path().join("chara");
+    for name in [
+        "blink01", "blink02", "blink03", "mouth_1", "mouth_2", "mouth_4", "talk1",
+    ] {
+        write_frame(
+            &chara.join(format!("{}.crx", name)),
+            &frame_pixels(1),
+            (0, 0),
+        );
+    }
+
+    let report = common::crx_convert_in(
+        dir.path(),
+        &["sequences", "chara", "--output", "lists", "--fps", "4"],
+    );
+    assert!(report.contains("2 sequence(s) in 7 file(s)"), "{}", report);
+    assert!(report.contains("3 frame(s) 1..=3 -> lists"), "{}", report);
+    assert!(
+        report.contains("3 frame(s) 1..=4, 1 missing -> lists"),
+        "{}",
+        report
+    );
+
+    let root = dir.path().canonicalize().unwrap();
+    let frame = |name: &str| root.join("chara").join(name).display().to_string();
+    let list = fs::read_to_string(dir.path().join("lists/blink.txt")).unwrap();
+    assert_eq!(
+        list,
+        format!(
+            "ffconcat version 1.0\nfile '{0}'\nduration 0.25\nfile '{1}'\nduration 0.25\nfile '{2}'\nduration 0.25\nfile '{2}'\n",
+            frame("blink01.png"),
+            frame("blink02.png"),
+            frame("blink03.png")
+        )
+    );
+    assert!(dir.path().join("lists/mouth.txt").exists());
+    assert!(!dir.path().join("lists/talk.txt").exists());
+}
+
+#[test]
+fn animate_writes_the_frames_of_a_sequence_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let eyes = dir.path().join("eyes");
+    // numbered out of order on purpose: frame 10 comes last.
+    for (name, seed) in [("blink_10", 3), ("blink_2", 2), ("blink_1", 1)] {
+        write_frame(
+            &eyes.join(format!("{}.crx", name)),
+            &frame_pixels(seed),
+            (0, 0),
+        );
+    }
+
+    common::crx_convert_in(
+        dir.path(),
+        &["animate", "eyes", "--loops", "2", "-o", "blink.png"],
+    );
+    let (frames, plays) = apng_frames(&dir.path().join("blink.png"));
+    assert_eq!(plays, 2);
+    assert_eq!(frames, [frame_pixels(1), frame_pixels(2), frame_pixels(3)]);
+
+    // a second sequence has to be picked by name.
+    write_frame(&eyes.join("wink_1.crx"), &frame_pixels(4), (0, 0));
+    let output = common::run_in(dir.path(), &["animate", "eyes", "-o", "both.png"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        error.contains("found 2 sequences (blink, wink), pick one with --sequence"),
+        "{}",
+        error
+    );
+    common::crx_convert_in(
+        dir.path(),
+        &["animate", "eyes", "--sequence", "wink", "-o", "wink.png"],
+    );
+    assert_eq!(
+        apng_frames(&dir.path().join("wink.png")).0,
+        [frame_pixels(4)]
+    );
+}
+
+#[test]
+fn animate_composes_frames_onto_their_base() {
+    let dir = tempfile::tempdir().unwrap();
+    let (base_width, base_height) = (8, 6);
+    let mut base = common::pixels(base_width, base_height, 4, 9);
+    base.chunks_mut(4).for_each(|pixel| pixel[3] = 0xFF);
+    common::write(
+        &dir.path().join("base.crx"),
+        &common::encode_rgba(
+            CrxEncoder::new(CrxVersion::V2),
+            base_width,
+            base_height,
+            &base,
+        ),
+    );
+    write_frame(&dir.path().join("face1.crx"), &frame_pixels(1), (3, 2));
+    write_frame(&dir.path().join("face2.crx"), &frame_pixels(2), (3, 2));
+
+    common::crx_convert_in(
+        dir.path(),
+        &[
+            "animate",
+            "face1.crx",
+            "face2.crx",
+            "--base",
+            "base.crx",
+            "-o",
+            "face.apng",
+        ],
+    );
+    let (frames, _) = apng_frames(&dir.path().join("face.apng"));
+    assert_eq!(frames.len(), 2);
+    for (frame, seed) in frames.iter().zip([1, 2]) {
+        let overlay = frame_pixels(seed);
+        for y in 0..base_height as usize {
+            for x in 0..base_width as usize {
+                let at = (y * base_width as usize + x) * 4;
+                let (fx, fy) = (x.wrapping_sub(3), y.wrapping_sub(2));
+                let expected = if fx < WIDTH as usize && fy < HEIGHT as usize {
+                    let from = (fy * WIDTH as usize + fx) * 4;
+                    &overlay[from..from + 4]
+                } else {
+                    &base[at..at + 4]
+                };
+                assert_eq!(
+                    &frame[at..at + 4],
+                    expected,
+                    "frame {} ({}, {})",
+                    seed,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+/* C API of the CRX decoder. Link against the `crx_capi` library built from lib/capi. */
+#ifndef CRX_H
+#define CRX_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CRX_OK 0
+#define CRX_ERROR_NULL 1
+#define CRX_ERROR_DECODE 2
+
+/* A decoded image; release it with crx_free. */
+typedef struct crx_image_t {
+    void *handle;
+} crx_image_t;
+
+/* Decode the CRX file of `len` bytes at `data` into `image`. 8-bit images are expanded to RGB.
+ * Returns CRX_OK, or an error code with the reason in crx_last_error(). */
+int32_t crx_decode(const uint8_t *data, size_t len, crx_image_t *image);
+
+/* Release `image`; it may be freed again. */
+void crx_free(crx_image_t *image);
+
+/* The message of the last failed call on this thread, valid until the next failing call. */
+const char *crx_last_error(void);
+
+uint16_t crx_image_width(const crx_image_t *image);
+uint16_t crx_image_height(const crx_image_t *image);
+/* Bits per pixel of crx_image_pixels: 24 for RGB, 32 for RGBA. */
+uint32_t crx_image_bpp(const crx_image_t *image);
+/* Where the game draws the image. */
+int16_t crx_image_inner_x(const crx_image_t *image);
+int16_t crx_image_inner_y(const crx_image_t *image);
+uint16_t crx_image_version(const crx_image_t *image);
+
+/* The pixels, row by row without padding; their size in bytes is stored in `len` unless it is
+ * NULL. Valid until `image` is freed. */
+const uint8_t *crx_image_pixels(const crx_image_t *image, size_t *len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
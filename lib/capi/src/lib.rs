@@ -0,0 +1,151 @@
+//! C API of the CRX decoder, see `include/crx.h`.
+
+use crx::CrxFile;
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CString},
+    ptr, slice,
+};
+
+/// Status codes of [`crx_decode`].
+pub const CRX_OK: i32 = 0;
+pub const CRX_ERROR_NULL: i32 = 1;
+pub const CRX_ERROR_DECODE: i32 = 2;
+
+/// A decoded image. `handle` is owned by the library and released with [`crx_free`].
+#[repr(C)]
+pub struct crx_image_t {
+    handle: *mut CrxFile,
+}
+
+thread_local! {
+    /// Message of the last failed call on this thread.
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: String) {
+    // a message with a NUL in it is cut there rather than lost.
+    let message = CString::new(message).unwrap_or_else(|e| {
+        let end = e.nul_position();
+        CString::new(&e.into_vec()[..end]).unwrap_or_default()
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Decode the CRX file of `len` bytes at `data` into `image`. 8-bit images are expanded to RGB.
+/// Returns [`CRX_OK`], or an error code with the reason in [`crx_last_error`].
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `image` to a writable `crx_image_t`, which
+/// must later be passed to [`crx_free`] if this returns [`CRX_OK`].
+#[no_mangle]
+pub unsafe extern "C" fn crx_decode(data: *const u8, len: usize, image: *mut crx_image_t) -> i32 {
+    if data.is_null() || image.is_null() {
+        set_last_error("null argument".to_string());
+        return CRX_ERROR_NULL;
+    }
+    let bytes = slice::from_raw_parts(data, len);
+    match CrxFile::read(bytes) {
+        Ok(file) => {
+            (*image).handle = Box::into_raw(Box::new(file));
+            CRX_OK
+        }
+        Err(e) => {
+            (*image).handle = ptr::null_mut();
+            set_last_error(e.to_string());
+            CRX_ERROR_DECODE
+        }
+    }
+}
+
+/// Release the pixels and metadata of `image`; it may be freed again, or be null.
+///
+/// # Safety
+/// `image` must be null or have been filled by [`crx_decode`].
+#[no_mangle]
+pub unsafe extern "C" fn crx_free(image: *mut crx_image_t) {
+    if image.is_null() || (*image).handle.is_null() {
+        return;
+    }
+    drop(Box::from_raw((*image).handle));
+    (*image).handle = ptr::null_mut();
+}
+
+/// The message of the last failed call on this thread, valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn crx_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// The decoded image behind `image`, if any.
+unsafe fn file<'a>(image: *const crx_image_t) -> Option<&'a CrxFile> {
+    image.as_ref().and_then(|image| image.handle.as_ref())
+}
+
+/// Width in pixels, 0 for an image not decoded.
+///
+/// # Safety
+/// `image` must be null or have been filled by [`crx_decode`], and not freed since.
+#[no_mangle]
+pub unsafe extern "C" fn crx_image_width(image: *const crx_image_t) -> u16 {
+    file(image).map_or(0, CrxFile::width)
+}
+
+/// Height in pixels, 0 for an image not decoded.
+///
+/// # Safety
+/// As for [`crx_image_width`].
+#[no_mangle]
+pub unsafe extern "C" fn crx_image_height(image: *const crx_image_t) -> u16 {
+    file(image).map_or(0, CrxFile::height)
+}
+
+/// Bits per pixel of [`crx_image_pixels`]: 24 for RGB, 32 for RGBA.
+///
+/// # Safety
+/// As for [`crx_image_width`].
+#[no_mangle]
+pub unsafe extern "C" fn crx_image_bpp(image: *const crx_image_t) -> u32 {
+    file(image).map_or(0, |file| file.bpp() as u32)
+}
+
+/// Horizontal inner offset, where the game draws the image.
+///
+/// # Safety
+/// As for [`crx_image_width`].
+#[no_mangle]
+pub unsafe extern "C" fn crx_image_inner_x(image: *const crx_image_t) -> i16 {
+    file(image).map_or(0, CrxFile::inner_x)
+}
+
+/// Vertical inner offset, where the game draws the image.
+///
+/// # Safety
+/// As for [`crx_image_width`].
+#[no_mangle]
+pub unsafe extern "C" fn crx_image_inner_y(image: *const crx_image_t) -> i16 {
+    file(image).map_or(0, CrxFile::inner_y)
+}
+
+/// Image version, 1 to 3.
+///
+/// # Safety
+/// As for [`crx_image_width`].
+#[no_mangle]
+pub unsafe extern "C" fn crx_image_version(image: *const crx_image_t) -> u16 {
+    file(image).map_or(0, |file| file.version().number())
+}
+
+/// The pixels, row by row without padding, in the layout [`crx_image_bpp`] gives; their size in
+/// bytes is stored in `len` unless it is null. Valid until `image` is freed.
+///
+/// # Safety
+/// As for [`crx_image_width`]; `len` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn crx_image_pixels(image: *const crx_image_t, len: *mut usize) -> *const u8 {
+    let pixels = file(image).map_or(&[][..], CrxFile::raw_buffer);
+    if !len.is_null() {
+        *len = pixels.len();
+    }
+    pixels.as_ptr()
+}
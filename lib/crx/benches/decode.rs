@@ -0,0 +1,92 @@
+//! Decode timings of generated images, as the best and median of a number of runs:
+//!
+//! ```sh
+//! cargo bench --package crx --bench decode
+//! ```
+//!
+//! Compare the `8-bit` rows to see the cost of expanding palettes to RGB.
+
+use crx::{CrxEncoder, CrxFile, CrxVersion};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 30;
+
+/// Noisy gradients, so that rows use different predictors and zlib has some work to do.
+fn pixels(width: u16, height: u16, channels: usize) -> Vec<u8> {
+    let mut seed = 0x2545_f491u32;
+    let mut data = Vec::with_capacity(width as usize * height as usize * channels);
+    for y in 0..height as u32 {
+        for x in 0..width as u32 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let noise = (seed >> 29) as u8;
+            let pixel = [
+                (x + y) as u8 ^ noise,
+                (x * 2) as u8,
+                (y * 3) as u8,
+                0xFF - noise,
+            ];
+            data.extend_from_slice(&pixel[..channels]);
+        }
+    }
+    data
+}
+
+fn encode(version: CrxVersion, bpp: usize, width: u16, height: u16) -> Vec<u8> {
+    let encoder = CrxEncoder::new(version);
+    let mut out = Vec::new();
+    match bpp {
+        8 => {
+            let palette: Vec<[u8; 3]> = (0..=255u8).map(|i| [i, i / 2, 255 - i]).collect();
+            let indices = pixels(width, height, 1);
+            encoder.write_indexed(&mut out, width, height, &palette, &indices)
+        }
+        24 => encoder.write_rgb(&mut out, width, height, &pixels(width, height, 3)),
+        _ => encoder.write_rgba(&mut out, width, height, &pixels(width, height, 4)),
+    }
+    .expect("generated images encode");
+    out
+}
+
+fn bench(name: &str, pixels: usize, mut decode: impl FnMut()) {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            decode();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    let (best, median) = (times[0], times[RUNS / 2]);
+    println!(
+        "{:<32} best {:>8.3} ms  median {:>8.3} ms  {:>7.1} Mpx/s",
+        name,
+        best.as_secs_f64() * 1e3,
+        median.as_secs_f64() * 1e3,
+        pixels as f64 / best.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    let cases = [
+        ("8-bit 1280x720 v2", CrxVersion::V2, 8, 1280, 720),
+        ("24-bit 1280x720 v2", CrxVersion::V2, 24, 1280, 720),
+        ("32-bit 1280x720 v1", CrxVersion::V1, 32, 1280, 720),
+        ("32-bit 1280x720 v2", CrxVersion::V2, 32, 1280, 720),
+        ("32-bit 2560x1440 v2", CrxVersion::V2, 32, 2560, 1440),
+    ];
+    for (name, version, bpp, width, height) in cases {
+        let data = encode(version, bpp, width, height);
+        let pixels = width as usize * height as usize;
+        bench(name, pixels, || {
+            black_box(CrxFile::read(black_box(&data[..])).unwrap());
+        });
+        if bpp == 8 {
+            bench(&format!("{} indexed", name), pixels, || {
+                black_box(CrxFile::read_indexed(black_box(&data[..])).unwrap());
+            });
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Upper bound of the entry count accepted in an archive header.
+const MAX_ENTRIES: u32 = 0x40000;
+/// Size of an entry name in the PCK index, including NUL padding.
+const PCK_NAME_SIZE: usize = 0x38;
+/// Sizes of an entry name in the DAT index, which differ between titles; tried in turn.
+const DAT_NAME_SIZES: [usize; 3] = [0x24, 0x30, 0x3C];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CrxArchiveError {
+    NotAnArchive,
+    BadEntryPlacement(String),
+}
+
+impl std::fmt::Display for CrxArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrxArchiveError::NotAnArchive => write!(f, "not a CIRCUS archive"),
+            CrxArchiveError::BadEntryPlacement(name) => {
+                write!(f, "entry `{}` lies outside of the archive", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CrxArchiveError {}
+
+macro_rules! archive_error {
+    ($e:expr) => {{
+        std::io::Error::new(std::io::ErrorKind::InvalidData, $e)
+    }};
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    name: String,
+    offset: u64,
+    size: u64,
+}
+
+impl ArchiveEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// A CIRCUS `.pck` archive.
+///
+/// The file starts with an entry count, followed by a table of `(offset, size)` pairs, and then
+/// by the index proper: for each entry, a NUL-padded name of 0x38 bytes, the offset and the size.
+pub struct PckArchive<R> {
+    reader: R,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl<R: Read + Seek> PckArchive<R> {
+    pub fn open(mut reader: R) -> io::Result<Self> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let count = reader.read_u32::<LittleEndian>()?;
+        if count == 0 || count > MAX_ENTRIES {
+            return Err(archive_error!(CrxArchiveError::NotAnArchive));
+        }
+        // skip the (offset, size) table, the index repeats it.
+        reader.seek(SeekFrom::Start(4 + count as u64 * 8))?;
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut name = [0; PCK_NAME_SIZE];
+            reader.read_exact(&mut name)?;
+            let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+            let name = String::from_utf8_lossy(&name[..name_len]).into_owned();
+            let offset = reader.read_u32::<LittleEndian>()? as u64;
+            let size = reader.read_u32::<LittleEndian>()? as u64;
+            if offset + size > file_size {
+                return Err(archive_error!(CrxArchiveError::BadEntryPlacement(name)));
+            }
+            entries.push(ArchiveEntry { name, offset, size });
+        }
+
+        Ok(Self { reader, entries })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// A reader over the contents of `entry`, which must be one of [`PckArchive::entries`].
+    pub fn entry_reader(&mut self, entry: &ArchiveEntry) -> io::Result<io::Take<&mut R>> {
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        Ok(self.reader.by_ref().take(entry.size))
+    }
+
+    /// Every entry in index order, with its contents read into memory, e.g. to convert a whole
+    /// archive in one pass.
+    pub fn entry_readers(
+        &mut self,
+    ) -> impl Iterator<Item = io::Result<(&ArchiveEntry, io::Cursor<Vec<u8>>)>> + '_ {
+        read_entries(&mut self.reader, &self.entries)
+    }
+}
+
+/// A CIRCUS `.dat` archive, as older titles ship.
+///
+/// The file starts with an entry count, followed by the index: for each entry, a NUL-padded name
+/// of 0x24, 0x30 or 0x3C bytes, depending on the title, and the offset. Entries are stored in
+/// index order, each running up to the next one or to the end of the file.
+pub struct DatArchive<R> {
+    reader: R,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl<R: Read + Seek> DatArchive<R> {
+    pub fn open(mut reader: R) -> io::Result<Self> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let count = reader.read_u32::<LittleEndian>()?;
+        if count == 0 || count > MAX_ENTRIES {
+            return Err(archive_error!(CrxArchiveError::NotAnArchive));
+        }
+        for name_size in DAT_NAME_SIZES {
+            let index_size = 4 + count as u64 * (name_size as u64 + 4);
+            if index_size > file_size {
+                continue;
+            }
+            let mut index = vec![0; index_size as usize - 4];
+            reader.seek(SeekFrom::Start(4))?;
+            reader.read_exact(&mut index)?;
+            if let Some(entries) = Self::parse_index(&index, name_size, index_size, file_size) {
+                return Ok(Self { reader, entries });
+            }
+        }
+        Err(archive_error!(CrxArchiveError::NotAnArchive))
+    }
+
+    /// The entries of `index` if it is laid out with names of `name_size` bytes: names are
+    /// non-empty and offsets ascend from the end of the index to the end of the file.
+    fn parse_index(
+        index: &[u8],
+        name_size: usize,
+        index_size: u64,
+        file_size: u64,
+    ) -> Option<Vec<ArchiveEntry>> {
+        let mut entries: Vec<ArchiveEntry> = Vec::new();
+        for record in index.chunks_exact(name_size + 4) {
+            let (name, mut offset) = record.split_at(name_size);
+            let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+            if name_len == 0 || name[..name_len].iter().any(|&c| c < 0x20) {
+                return None;
+            }
+            let offset = offset.read_u32::<LittleEndian>().ok()? as u64;
+            let previous_end = entries.last().map_or(index_size, |entry| entry.offset);
+            if offset < previous_end || offset > file_size {
+                return None;
+            }
+            if let Some(previous) = entries.last_mut() {
+                previous.size = offset - previous.offset;
+            }
+            entries.push(ArchiveEntry {
+                name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                offset,
+                size: file_size - offset,
+            });
+        }
+        Some(entries)
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// A reader over the contents of `entry`, which must be one of [`DatArchive::entries`].
+    pub fn entry_reader(&mut self, entry: &ArchiveEntry) -> io::Result<io::Take<&mut R>> {
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        Ok(self.reader.by_ref().take(entry.size))
+    }
+
+    /// Like [`PckArchive::entry_readers`].
+    pub fn entry_readers(
+        &mut self,
+    ) -> impl Iterator<Item = io::Result<(&ArchiveEntry, io::Cursor<Vec<u8>>)>> + '_ {
+        read_entries(&mut self.reader, &self.entries)
+    }
+}
+
+fn read_entries<'a, R: Read + Seek>(
+    reader: &'a mut R,
+    entries: &'a [ArchiveEntry],
+) -> impl Iterator<Item = io::Result<(&'a ArchiveEntry, io::Cursor<Vec<u8>>)>> + 'a {
+    entries.iter().map(move |entry| {
+        reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = Vec::with_capacity(entry.size as usize);
+        reader.by_ref().take(entry.size).read_to_end(&mut data)?;
+        Ok((entry, io::Cursor::new(data)))
+    })
+}
@@ -0,0 +1,104 @@
+use crate::{CrxFile, Vfs};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Progress of a batch run, mirroring the `--progress-json` events of the converter.
+#[derive(Debug)]
+pub enum BatchEvent {
+    /// The batch is about to start.
+    Begin { total: usize },
+    /// A file is about to be decoded.
+    Started { path: PathBuf },
+    /// A file has been decoded and processed, or failed to.
+    Finished {
+        path: PathBuf,
+        result: io::Result<()>,
+        done: usize,
+        total: usize,
+        eta: Duration,
+    },
+    /// Every file has been handled.
+    End { done: usize, elapsed: Duration },
+}
+
+/// The event stream of a batch running on a background thread. Iterating blocks until the next
+/// event arrives and ends after [`BatchEvent::End`].
+pub struct BatchEvents {
+    receiver: Receiver<BatchEvent>,
+}
+
+impl BatchEvents {
+    /// Return the next event if one is ready, without blocking. Useful from a GUI event loop.
+    pub fn try_next(&self) -> Option<BatchEvent> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Iterator for BatchEvents {
+    type Item = BatchEvent;
+
+    fn next(&mut self) -> Option<BatchEvent> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Decode every file in `paths` on a background thread, handing each decoded image to
+/// `process`, and report progress as [`BatchEvent`]s.
+#[cfg(feature = "fs")]
+pub fn spawn_batch<F>(paths: Vec<PathBuf>, process: F) -> BatchEvents
+where
+    F: Fn(&Path, CrxFile) -> io::Result<()> + Send + 'static,
+{
+    spawn_batch_with(crate::RealFs, paths, process)
+}
+
+/// Like [`spawn_batch`], reading the files from `vfs`.
+pub fn spawn_batch_with<V, F>(vfs: V, paths: Vec<PathBuf>, process: F) -> BatchEvents
+where
+    V: Vfs + 'static,
+    F: Fn(&Path, CrxFile) -> io::Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let start = Instant::now();
+        let total = paths.len();
+        // the receiver may be dropped at any time; stop quietly when it is.
+        if sender.send(BatchEvent::Begin { total }).is_err() {
+            return;
+        }
+        for (index, path) in paths.into_iter().enumerate() {
+            if sender
+                .send(BatchEvent::Started { path: path.clone() })
+                .is_err()
+            {
+                return;
+            }
+            let result = vfs
+                .open(&path)
+                .and_then(|f| Ok(CrxFile::read(f)?))
+                .and_then(|crx| process(&path, crx));
+            let done = index + 1;
+            let eta = start.elapsed().mul_f64((total - done) as f64 / done as f64);
+            let event = BatchEvent::Finished {
+                path,
+                result,
+                done,
+                total,
+                eta,
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(BatchEvent::End {
+            done: total,
+            elapsed: start.elapsed(),
+        });
+    });
+    BatchEvents { receiver }
+}
@@ -0,0 +1,146 @@
+use crate::{CrxFile, CrxImageClip};
+
+impl CrxImageClip {
+    /// The rectangle of the clip as `(x, y, width, height)` in image coordinates, from the fields
+    /// of the same names. That these are what the fields hold is an assumption the game files have
+    /// not confirmed yet; check it against a corpus with `crx-convert analyze-clips`.
+    pub fn rect(&self) -> (i32, i32, i32, i32) {
+        (
+            self.x as i32,
+            self.y as i32,
+            self.width as i32,
+            self.height as i32,
+        )
+    }
+
+    /// The clip in the coordinates of the `width` x `height` region at (`x`, `y`) of its image,
+    /// cut to the region; `None` if nothing of it is left. The other fields are kept.
+    pub(crate) fn cropped(&self, x: u16, y: u16, width: u16, height: u16) -> Option<Self> {
+        let (clip_x, clip_y, clip_width, clip_height) = self.rect();
+        let (clip_x, clip_y) = (clip_x - x as i32, clip_y - y as i32);
+        let (left, top) = (clip_x.max(0), clip_y.max(0));
+        let right = (clip_x + clip_width).min(width as i32);
+        let bottom = (clip_y + clip_height).min(height as i32);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        // within the clip as it was, so every value fits its field.
+        Some(Self {
+            x: left as i16,
+            y: top as i16,
+            width: (right - left) as i16,
+            height: (bottom - top) as i16,
+            ..*self
+        })
+    }
+}
+
+/// The pixels of a clip rectangle, borrowed from the image they are part of.
+///
+/// Rows are not contiguous unless the rectangle spans the full width of the image:
+/// [`ClipBuffer::as_slice`] runs from the first pixel of the first row to the last pixel of the
+/// last row, `stride` bytes apart, and [`ClipBuffer::rows`] yields the rows alone.
+#[derive(Debug, Clone, Copy)]
+pub struct ClipBuffer<'a> {
+    clip: &'a CrxImageClip,
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    pixel_size: usize,
+    stride: usize,
+    data: &'a [u8],
+}
+
+impl<'a> ClipBuffer<'a> {
+    pub fn clip(&self) -> &'a CrxImageClip {
+        self.clip
+    }
+
+    /// Left edge of the rectangle, after it was intersected with the image.
+    pub fn x(&self) -> u16 {
+        self.x
+    }
+
+    /// Top edge of the rectangle, after it was intersected with the image.
+    pub fn y(&self) -> u16 {
+        self.y
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Distance between the starts of two rows in [`ClipBuffer::as_slice`], in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// The pixels of the rectangle, in the layout of [`CrxFile::raw_buffer`], with the pixels of
+    /// the image beside it in between rows.
+    pub fn as_slice(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The rows of the rectangle, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let (data, stride) = (self.data, self.stride);
+        let row_size = self.width as usize * self.pixel_size;
+        (0..self.height as usize).map(move |row| &data[row * stride..row * stride + row_size])
+    }
+}
+
+impl CrxFile {
+    /// The pixels of every clip, see [`CrxImageClip::rect`], borrowed from the image. Rectangles
+    /// are intersected with the image; clips that do not overlap it are left out.
+    pub fn clip_buffers(&self) -> impl Iterator<Item = ClipBuffer<'_>> {
+        let pixel_size = self.bpp() / 8;
+        let stride = self.width() as usize * pixel_size;
+        self.clips().iter().filter_map(move |clip| {
+            let (x, y, width, height) = self.clip_rect(clip)?;
+            let (x, y) = (x as usize, y as usize);
+            let start = y * stride + x * pixel_size;
+            let end = (y + height as usize - 1) * stride + (x + width as usize) * pixel_size;
+            Some(ClipBuffer {
+                clip,
+                x: x as u16,
+                y: y as u16,
+                width,
+                height,
+                pixel_size,
+                stride,
+                data: &self.raw_buffer()[start..end],
+            })
+        })
+    }
+
+    /// Copy the pixels of the clip at `index` into an image of their own, e.g. a sprite cell of
+    /// an atlas, the way [`CrxFile::sub_image`] does. Like [`CrxFile::clip_buffers`], the
+    /// rectangle is intersected with the image first. Returns `None` if there is no such clip or
+    /// it does not overlap the image.
+    pub fn extract_clip(&self, index: usize) -> Option<Self> {
+        let (x, y, width, height) = self.clip_rect(self.clips().get(index)?)?;
+        self.sub_image(x, y, width, height)
+    }
+
+    /// The rectangle of `clip` intersected with the image, `None` if nothing is left.
+    fn clip_rect(&self, clip: &CrxImageClip) -> Option<(u16, u16, u16, u16)> {
+        let (x, y, width, height) = clip.rect();
+        let (left, top) = (x.max(0), y.max(0));
+        let right = x.saturating_add(width).min(self.width() as i32);
+        let bottom = y.saturating_add(height).min(self.height() as i32);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some((
+            left as u16,
+            top as u16,
+            (right - left) as u16,
+            (bottom - top) as u16,
+        ))
+    }
+}
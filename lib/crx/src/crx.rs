@@ -1,245 +1,262 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{self, Read};
+use crate::CrxError;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    io::{self, BufRead, Read, Write},
+    time::Instant,
+};
 
-const CRX_SIGNATURE: &[u8; 4] = b"CRXG";
+/// How the first four bytes of a file are recognized, the first matching prefix deciding:
+/// `CRXG` is what most titles write, and some engine builds write `CRX` with another last byte
+/// for the same layout. `CRXD` differential images name the image they apply to and are laid out
+/// differently.
+const SIGNATURES: &[(&[u8], bool)] = &[(b"CRXG", true), (b"CRXD", false), (b"CRX", true)];
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
-pub enum CrxDecodeError {
-    #[error("invalid file signature")]
-    CrxSignatureInvalid,
-    #[error("unsupported image version `{0}`")]
-    VersionNotSupported(u16),
-    #[error("invalid row decode mode `{0}`")]
-    InvalidRowDecodeMode(u8),
-    #[error("cannot refer to previous row")]
-    NoPreviousRow,
-    #[error("row byte overflow")]
-    RowOverflow,
-    #[error("bad palette index: palette size is `{0}` but trying to access index `{1}`")]
-    BadPaletteIndex(usize, usize),
-}
-
-macro_rules! decode_error {
-    ($e:expr) => {{
-        std::io::Error::new(std::io::ErrorKind::InvalidData, $e)
-    }};
-}
+/// The first four bytes of a CRX file. With the `serde` feature, it is serialized as a string of
+/// four characters, each standing for the byte of the same value, e.g. `"CRXG"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CrxSignature([u8; 4]);
 
-#[cfg(feature = "to_image")]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
-pub enum CrxImageConvertError {
-    #[error("invalid raw pixel color buffer")]
-    InvalidRawBuffer,
-    #[error("invalid bpp `{0}`")]
-    InvalidBPP(usize),
+#[cfg(feature = "serde")]
+impl serde::Serialize for CrxSignature {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let text: String = self.0.iter().map(|&byte| byte as char).collect();
+        serializer.serialize_str(&text)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct CrxDataContext {
-    width: usize,
-    height: usize,
-    bpp: usize,
-    palette: Vec<[u8; 3]>,
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CrxSignature {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let bytes: Vec<u8> = text
+            .chars()
+            .map(|c| u8::try_from(c).ok())
+            .collect::<Option<_>>()
+            .unwrap_or_default();
+        <[u8; 4]>::try_from(bytes)
+            .ok()
+            .and_then(Self::parse)
+            .ok_or_else(|| serde::de::Error::custom(CrxDecodeError::CrxSignatureInvalid))
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct CrxFile {
-    inner_x: i16,
-    inner_y: i16,
-    width: u16,
-    height: u16,
-    bpp: usize,
-    clips: Vec<CrxImageClip>,
-    raw_image_buffer: Vec<u8>,
-}
+impl CrxSignature {
+    /// `CRXG`, written by the encoder unless told otherwise.
+    pub const STANDARD: Self = Self(*b"CRXG");
 
-impl CrxFile {
-    pub fn inner_x(&self) -> i16 {
-        self.inner_x
+    /// The signature `bytes` are, if they are one the decoder accepts.
+    pub fn parse(bytes: [u8; 4]) -> Option<Self> {
+        SIGNATURES
+            .iter()
+            .find(|(prefix, _)| bytes.starts_with(prefix))
+            .filter(|(_, accepted)| *accepted)
+            .map(|_| Self(bytes))
     }
 
-    pub fn inner_y(&self) -> i16 {
-        self.inner_y
+    pub fn bytes(self) -> [u8; 4] {
+        self.0
     }
 
-    pub fn width(&self) -> u16 {
-        self.width
+    pub fn is_standard(self) -> bool {
+        self == Self::STANDARD
     }
+}
 
-    pub fn height(&self) -> u16 {
-        self.height
+/// Whether `bytes`, the start of a file, begin with a signature the decoder accepts, whatever
+/// the file is named.
+pub fn sniff(bytes: &[u8]) -> bool {
+    match bytes.get(..4) {
+        Some(head) => CrxSignature::parse([head[0], head[1], head[2], head[3]]).is_some(),
+        None => false,
     }
+}
 
-    pub fn bpp(&self) -> usize {
-        self.bpp
-    }
+/// Like [`sniff`], reading the first four bytes of `reader`. Readers shorter than that are not
+/// CRX files.
+pub fn is_crx<R: Read>(reader: R) -> io::Result<bool> {
+    let mut head = Vec::with_capacity(4);
+    reader.take(4).read_to_end(&mut head)?;
+    Ok(sniff(&head))
+}
 
-    pub fn clips(&self) -> &[CrxImageClip] {
-        &self.clips
+impl Default for CrxSignature {
+    fn default() -> Self {
+        Self::STANDARD
     }
+}
 
-    pub fn raw_buffer(&self) -> &[u8] {
-        &self.raw_image_buffer
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CrxDecodeError {
+    CrxSignatureInvalid,
+    /// A version other than 1 to 3. `flag` and `mode` are those of the header the version was
+    /// read from, as they tell engine variants apart; they are `None` when there was none.
+    VersionNotSupported {
+        version: u16,
+        flag: Option<u16>,
+        mode: Option<u16>,
+    },
+    InvalidRowDecodeMode(u8),
+    NoPreviousRow,
+    RowOverflow,
+    BadPaletteIndex(usize, usize),
+    InvalidDepth(i16),
+    InvalidClipCount(i32),
+    InvalidStreamSize(i32),
+    LimitExceeded(&'static str, u64, u64),
+    InvalidDimensions(u16, u16),
+    PixelBufferSize(usize, usize),
+    /// The pixel stream holds more than the rows of the header. Only reported as a warning of
+    /// [`Strictness::Lenient`] decoding; strict decoding keeps the rows and ignores the rest.
+    StreamTooLong,
+}
 
-    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
-        // read signature.
-        let sig = {
-            let mut sig: [u8; 4] = [0; 4];
-            reader.read_exact(&mut sig)?;
-            sig
-        };
-        if sig != *CRX_SIGNATURE {
-            return Err(decode_error!(CrxDecodeError::CrxSignatureInvalid));
+impl std::fmt::Display for CrxDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrxDecodeError::CrxSignatureInvalid => write!(f, "invalid file signature"),
+            CrxDecodeError::VersionNotSupported {
+                version,
+                flag: Some(flag),
+                mode: Some(mode),
+            } => write!(
+                f,
+                "unsupported image version `{}` (flag `{:#06x}`, mode `{}`); only versions 1 to 3 are known, please report the file",
+                version, flag, mode
+            ),
+            CrxDecodeError::VersionNotSupported { version, .. } => {
+                write!(f, "unsupported image version `{}`", version)
+            },
+            CrxDecodeError::InvalidRowDecodeMode(mode) => write!(f, "invalid row decode mode `{}`", mode),
+            CrxDecodeError::NoPreviousRow => write!(f, "cannot refer to previous row"),
+            CrxDecodeError::RowOverflow => write!(f, "row byte overflow"),
+            CrxDecodeError::BadPaletteIndex(size, index) => write!(
+                f,
+                "bad palette index: palette size is `{}` but trying to access index `{}`",
+                size, index
+            ),
+            CrxDecodeError::InvalidDepth(depth) => write!(f, "invalid color depth `{}`", depth),
+            CrxDecodeError::InvalidClipCount(count) => write!(f, "invalid clip count `{}`", count),
+            CrxDecodeError::InvalidStreamSize(size) => write!(f, "invalid stream size `{}`", size),
+            CrxDecodeError::LimitExceeded(what, value, limit) => {
+                write!(f, "{} `{}` exceeds the limit of `{}`", what, value, limit)
+            },
+            CrxDecodeError::InvalidDimensions(width, height) => {
+                write!(f, "invalid dimensions `{}`x`{}`", width, height)
+            },
+            CrxDecodeError::PixelBufferSize(expected, actual) => write!(
+                f,
+                "decoded `{}` pixel bytes where the dimensions call for `{}`",
+                actual, expected
+            ),
+            CrxDecodeError::StreamTooLong => write!(f, "pixel stream continues past the last row"),
         }
+    }
+}
 
-        // read header.
-        let header = CrxHeader::read(reader.by_ref())?;
-        let bpp = match header.depth {
-            0 => 24,
-            1 => 32,
-            _ => 8,
-        };
-
-        // read palette, iff bpp is 8.
-        let palette = if bpp == 8 {
-            Some(Self::read_palette(reader.by_ref(), header.depth as i32)?)
-        } else {
-            None
-        };
-
-        // read clipping information
-        let clips = if header.version >= 3 {
-            Some(Self::read_clip(reader.by_ref())?)
-        } else {
-            None
-        };
-
-        // read raw compressed data.
-        let compressed_data = if (header.flag & 0x10) != 0 {
-            // read an int indicating the stream size
-            let data_size = reader.read_i32::<LittleEndian>()?;
-            let mut buf: Vec<u8> = vec![0; data_size as usize];
-            reader.read_exact(&mut buf)?;
-            buf
-        } else {
-            // consume all input
-            let mut buf: Vec<u8> = Vec::new();
-            reader.read_to_end(&mut buf)?;
-            buf
-        };
+impl std::error::Error for CrxDecodeError {}
 
-        // prepare decompress context
-        let context = CrxDataContext {
-            width: header.width as usize,
-            height: header.height as usize,
-            bpp,
-            palette: palette.unwrap_or_default(),
-        };
+macro_rules! decode_error {
+    ($e:expr) => {{
+        std::io::Error::new(std::io::ErrorKind::InvalidData, $e)
+    }};
+}
 
-        // decompress (extract) color data.
-        let mut color_data = if header.version == 1 {
-            Self::unpack_1(&compressed_data, &context)?
-        } else {
-            Self::unpack_2(&compressed_data, &context)?
-        };
+/// Enter a `tracing` span until the end of the enclosing block, with the `tracing` feature.
+macro_rules! trace_span {
+    ($($span:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($span)*).entered();
+    };
+}
 
-        // some final operations I cannot see why.
-        if bpp == 32 && header.mode != 1 {
-            let alpha_flip: u8 = if 2 == header.mode { 0 } else { 0xFF };
-            for h in 0..header.height as usize {
-                for w in 0..header.width as usize {
-                    let offset = (h * header.width as usize + w) * 4; // bpp is 32 as required in `if` condition, byte size is definitely 4
-                    let alpha = color_data[offset];
-                    let b = color_data[offset + 1];
-                    let g = color_data[offset + 2];
-                    let r = color_data[offset + 3];
-                    color_data[offset] = b;
-                    color_data[offset + 1] = g;
-                    color_data[offset + 2] = r;
-                    color_data[offset + 3] = alpha ^ alpha_flip;
-                }
-            }
-        }
+#[cfg(feature = "to_image")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrxImageConvertError {
+    InvalidRawBuffer(usize, usize),
+    BadPaletteIndex,
+    InvalidBPP(usize),
+}
 
-        // from bgr(a) to rgb(a). only applies when not in indexed mode.
-        let pixel_byte = bpp / 8;
-        if bpp != 8 {
-            for pix in 0..(header.height as usize) * (header.width as usize) {
-                color_data.swap(pix * pixel_byte, pix * pixel_byte + 2);
-            }
+#[cfg(feature = "to_image")]
+impl std::fmt::Display for CrxImageConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrxImageConvertError::InvalidRawBuffer(expected, actual) => write!(
+                f,
+                "invalid raw pixel color buffer: expected `{}` bytes, got `{}`",
+                expected, actual
+            ),
+            CrxImageConvertError::BadPaletteIndex => write!(f, "pixel index outside the palette"),
+            CrxImageConvertError::InvalidBPP(bpp) => write!(f, "invalid bpp `{}`", bpp),
         }
-
-        Ok(Self {
-            inner_x: header.inner_x,
-            inner_y: header.inner_y,
-            width: header.width,
-            height: header.height,
-            bpp: if bpp == 8 { 24 } else { bpp },
-            clips: clips.unwrap_or_default(),
-            raw_image_buffer: color_data,
-        })
     }
+}
 
-    fn read_palette<R: Read>(mut reader: R, depth: i32) -> io::Result<Vec<[u8; 3]>> {
-        let color_size = if depth == 0x102 { 4 } else { 3 };
-        let colors = if depth > 0x100 { 0x100 } else { depth };
-        let mut palette: Vec<[u8; 3]> = Vec::new();
+#[cfg(feature = "to_image")]
+impl std::error::Error for CrxImageConvertError {}
 
-        for _ in 0..colors {
-            let r = reader.read_u8()?;
-            let mut g = reader.read_u8()?;
-            let b = reader.read_u8()?;
-            // I don't know why this fourth component exists, even if it is not used.
-            if 4 == color_size {
-                reader.read_u8()?;
-            }
-            // Also I don't know why there is no yellow color in the palette.
-            if 0xFF == b && 0 == g && 0xFF == r {
-                g = 0xFF;
-            }
-            palette.push([r, g, b]);
-        }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CrxDataContext {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) bpp: usize,
+    /// When decoding stops early, checked before every row.
+    pub(crate) deadline: Option<Instant>,
+}
 
-        Ok(palette)
+impl CrxDataContext {
+    pub(crate) fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
     }
+}
 
-    fn read_clip<R: Read>(mut reader: R) -> io::Result<Vec<CrxImageClip>> {
-        let clip_count = reader.read_i32::<LittleEndian>()?;
-        let mut clips = Vec::with_capacity(clip_count as usize);
-        for _ in 0..clip_count {
-            let clip = CrxImageClip::read(reader.by_ref())?;
-            clips.push(clip);
+/// The LZ decompressor of version 1 images. Its state is kept between calls, so that the
+/// output can be produced a row at a time.
+pub(crate) struct LzState {
+    window: [u8; 0x10000],
+    win_pos: usize,
+    flag: i32,
+    /// Window position and remaining length of a back-reference cut off by the end of the last
+    /// output.
+    pending: (usize, usize),
+}
+
+impl LzState {
+    pub(crate) fn new() -> Self {
+        Self {
+            window: [0; 0x10000],
+            win_pos: 0,
+            flag: 0,
+            pending: (0, 0),
         }
-        Ok(clips)
     }
 
-    fn unpack_1(buf: &[u8], context: &CrxDataContext) -> io::Result<Vec<u8>> {
+    /// Decompress exactly `output.len()` bytes.
+    pub(crate) fn fill<R: Read>(&mut self, buf: &mut R, output: &mut [u8]) -> io::Result<()> {
         // The implementation of GARBro seems to be problematic. Tried to fix it.
-        let mut window: [u8; 0x10000] = [0; 0x10000];
-        let mut flag: i32 = 0;
-        let mut win_pos: usize = 0;
-        let mut dst: usize = 0;
-
-        let mut buf = io::Cursor::new(buf);
-        let mut output: Vec<u8> = vec![0; (context.bpp / 8) * context.width * context.height];
-
+        // the state is worked on in locals, which the compiler keeps in registers.
+        let window = &mut self.window;
+        let mut win_pos = self.win_pos;
+        let mut flag = self.flag;
+        let (mut offset, mut count) = self.pending;
+        let mut dst = 0;
         while dst < output.len() {
-            flag >>= 1;
-            if 0 == (flag & 0x100) {
-                flag = buf.read_u8()? as i32 | 0xFF00;
-            }
-            if 0 != (flag & 1) {
-                let dat = buf.read_u8()?;
-                window[win_pos] = dat;
-                win_pos = (win_pos + 1) & 0xFFFF;
-                output[dst] = dat;
-                dst += 1;
-            } else {
+            if count == 0 {
+                flag >>= 1;
+                if 0 == (flag & 0x100) {
+                    flag = buf.read_u8()? as i32 | 0xFF00;
+                }
+                if 0 != (flag & 1) {
+                    let dat = buf.read_u8()?;
+                    window[win_pos] = dat;
+                    win_pos = (win_pos + 1) & 0xFFFF;
+                    output[dst] = dat;
+                    dst += 1;
+                    continue;
+                }
                 let control: usize = buf.read_u8()? as usize;
-                let count: usize;
-                let mut offset: usize;
-
                 if control >= 0xC0 {
                     offset = ((control & 3) << 8) | (buf.read_u8()? as usize);
                     count = 4 + ((control >> 2) & 0xF);
@@ -256,256 +273,2172 @@ impl CrxFile {
                     offset = buf.read_u16::<LittleEndian>()? as usize;
                     count = control + 4;
                 }
-                offset = win_pos - offset;
-                for _ in 0..count {
-                    if dst >= output.len() {
-                        break;
-                    }
-                    offset &= 0xFFFF;
-                    let dat = window[offset];
-                    offset += 1;
-                    window[win_pos] = dat;
-                    win_pos = (win_pos + 1) & 0xFFFF;
-                    output[dst] = dat;
-                    dst += 1;
-                }
+                offset = win_pos.wrapping_sub(offset);
+            }
+            while count > 0 && dst < output.len() {
+                offset &= 0xFFFF;
+                let dat = window[offset];
+                offset += 1;
+                window[win_pos] = dat;
+                win_pos = (win_pos + 1) & 0xFFFF;
+                output[dst] = dat;
+                dst += 1;
+                count -= 1;
             }
         }
-
-        Ok(output)
+        // a failed decode is not resumed, so the state only needs saving on success.
+        self.win_pos = win_pos;
+        self.flag = flag;
+        self.pending = (offset, count);
+        Ok(())
     }
+}
 
-    fn unpack_2(buf: &[u8], context: &CrxDataContext) -> io::Result<Vec<u8>> {
-        use flate2::read::ZlibDecoder;
+/// Header flag announcing that the pixel stream is prefixed with its size.
+pub const FLAG_SIZED_STREAM: u16 = 0x10;
 
-        let pixel_size = context.bpp / 8;
-        let is_palette = pixel_size == 1;
-        let pixel_size = if pixel_size == 1 { 3 } else { pixel_size };
-        // number of bytes in a row's data. applies to both input and output.
-        let stride = pixel_size * context.width;
+/// How the bytes of a row of a zlib-compressed truecolor image are predicted, from the byte
+/// before the row. 8-bit rows are stored as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RowFilter {
+    Left,
+    Up,
+    UpLeft,
+    UpRight,
+    Runs,
+}
 
-        let mut reader = ZlibDecoder::new(buf);
-        let mut output: Vec<u8> = vec![0; stride * context.height];
+impl RowFilter {
+    /// The byte stored before each row.
+    pub fn number(self) -> u8 {
+        match self {
+            RowFilter::Left => 0,
+            RowFilter::Up => 1,
+            RowFilter::UpLeft => 2,
+            RowFilter::UpRight => 3,
+            RowFilter::Runs => 4,
+        }
+    }
 
-        if is_palette {
-            // 8-bit palette color mode.
-            // palette indices of each pixel are stored here.
-            // read palette indices.
-            let mut indices: Vec<u8> = vec![0; context.width * context.height];
-            reader.read_exact(&mut indices)?;
-            // convert palette indices to pixel values.
-            for pix in 0..context.width * context.height {
-                let index = indices[pix] as usize;
-                let color = context.palette.get(index).ok_or_else(|| {
-                    decode_error!(CrxDecodeError::BadPaletteIndex(
-                        context.palette.len(),
-                        index
-                    ))
-                })?;
-                output[pix * pixel_size] = color[0];
-                output[pix * pixel_size + 1] = color[1];
-                output[pix * pixel_size + 2] = color[2];
+    /// How the row is rebuilt, in a few words.
+    pub fn description(self) -> &'static str {
+        match self {
+            RowFilter::Left => "first pixel as is, then differences from the pixel to the left",
+            RowFilter::Up => "differences from the pixel above",
+            RowFilter::UpLeft => {
+                "first pixel as is, then differences from the pixel above and to the left"
             }
-        } else {
-            for y in 0..context.height {
-                let mode = reader.read_u8()?;
-                let row_offset = y * stride;
-                let prev_row_offset = row_offset.checked_sub(stride);
-                match mode {
-                    0 => {
-                        // first pixel is provided as is, remaining pixels are encoded as differences from the previous pixel.
-                        // read the first pixel value as is.
-                        reader.read_exact(&mut output[row_offset..row_offset + pixel_size])?;
-                        // read the remaining pixels (per byte) in the same row.
-                        for xb in pixel_size..stride {
-                            output[row_offset + xb] = reader
-                                .read_u8()?
-                                .wrapping_add(output[row_offset + xb - pixel_size]);
-                        }
-                    }
-                    1 => {
-                        // pixels values are provided as the differences from the corresponding x-position of previous row.
-                        let prev_row_offset = prev_row_offset
-                            .ok_or_else(|| decode_error!(CrxDecodeError::NoPreviousRow))?;
-                        for xb in 0..stride {
-                            output[row_offset + xb] =
-                                reader.read_u8()?.wrapping_add(output[prev_row_offset + xb]);
-                        }
-                    }
-                    2 => {
-                        // first pixel is provided as is, remaining pixels are differences from the the previous row, left-shifting one pixel.
-                        // get previous row offset.
-                        let prev_row_offset = prev_row_offset
-                            .ok_or_else(|| decode_error!(CrxDecodeError::NoPreviousRow))?;
-                        // read the first pixel value as is.
-                        reader.read_exact(&mut output[row_offset..row_offset + pixel_size])?;
-                        // read the remaining pixels (per byte) in the same row.
-                        for xb in pixel_size..stride {
-                            output[row_offset + xb] = reader
-                                .read_u8()?
-                                .wrapping_add(output[prev_row_offset + xb - pixel_size]);
-                        }
-                    }
-                    3 => {
-                        // last pixel is provided as is, pixels before it are differences from the previous row, right-shifting one pixel
-                        // get previous row offset.
-                        let prev_row_offset = prev_row_offset
-                            .ok_or_else(|| decode_error!(CrxDecodeError::NoPreviousRow))?;
-                        // read the pixels
-                        for xb in 0..stride - pixel_size {
-                            output[row_offset + xb] = reader
-                                .read_u8()?
-                                .wrapping_add(output[prev_row_offset + xb + pixel_size]);
-                        }
-                        // read the last pixel as is.
-                        reader.read_exact(
-                            &mut output[row_offset + stride - pixel_size..row_offset + stride],
-                        )?;
-                    }
-                    4 => {
-                        // input is organized by pixel component, for each component, same-value compression is used.
-                        // same-value compression
-                        // 1. read a byte `a`, write to the output.
-                        // 2. read another byte `b`.
-                        // 3.1. if `a == b` then read a third byte `c`, and repeat writing `a` (or `b`) `c` times, go to step 1.
-                        // 3.2. Otherwise set `a` to `b`, go back to step 2.
-                        for pix_offset in 0..pixel_size {
-                            let mut xb = row_offset + pix_offset;
-                            let mut remaining = context.width;
-                            let mut val = reader.read_u8()?; // row init
-                            while remaining > 0 {
-                                output[xb] = val;
-                                xb += pixel_size;
-                                remaining -= 1;
-                                if remaining == 0 {
-                                    break;
-                                }
-                                let next = reader.read_u8()?;
-                                if val == next {
-                                    let count = reader.read_u8()? as usize;
-                                    for _ in 0..count {
-                                        output[xb] = next;
-                                        xb += pixel_size;
-                                    }
-                                    remaining = remaining.checked_sub(count).ok_or_else(|| {
-                                        decode_error!(CrxDecodeError::RowOverflow)
-                                    })?;
-                                    if remaining > 0 {
-                                        val = reader.read_u8()?;
-                                    }
-                                } else {
-                                    val = next;
-                                }
-                            }
-                        }
-                    }
-                    other => {
-                        return Err(decode_error!(CrxDecodeError::InvalidRowDecodeMode(other)))
-                    }
-                }
+            RowFilter::UpRight => {
+                "differences from the pixel above and to the right, then the last pixel as is"
             }
+            RowFilter::Runs => "each channel on its own, repeated values as `a a count` runs",
         }
+    }
+}
+
+impl TryFrom<u8> for RowFilter {
+    type Error = CrxDecodeError;
 
-        Ok(output)
+    fn try_from(filter: u8) -> Result<Self, Self::Error> {
+        match filter {
+            0 => Ok(RowFilter::Left),
+            1 => Ok(RowFilter::Up),
+            2 => Ok(RowFilter::UpLeft),
+            3 => Ok(RowFilter::UpRight),
+            4 => Ok(RowFilter::Runs),
+            x => Err(CrxDecodeError::InvalidRowDecodeMode(x)),
+        }
     }
 }
 
-#[cfg(feature = "to_image")]
-impl TryFrom<CrxFile> for image::DynamicImage {
-    type Error = CrxImageConvertError;
+/// Channel order of 32-bit pixels, from the `mode` header field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelOrder {
+    /// Mode 1.
+    Bgra,
+    /// Mode 2.
+    Abgr,
+    /// Every other mode: the alpha is stored inverted.
+    AbgrInvertedAlpha,
+}
 
-    fn try_from(value: CrxFile) -> Result<Self, CrxImageConvertError> {
-        match value.bpp {
-            24 => {
-                let rgb_image = image::ImageBuffer::from_raw(
-                    value.width as u32,
-                    value.height as u32,
-                    value.raw_image_buffer,
-                )
-                .ok_or(CrxImageConvertError::InvalidRawBuffer)?;
-                Ok(image::DynamicImage::ImageRgb8(rgb_image))
-            }
-            32 => {
-                let rgba_image = image::ImageBuffer::from_raw(
-                    value.width as u32,
-                    value.height as u32,
-                    value.raw_image_buffer,
-                )
-                .ok_or(CrxImageConvertError::InvalidRawBuffer)?;
-                Ok(image::DynamicImage::ImageRgba8(rgba_image))
-            }
-            x => Err(CrxImageConvertError::InvalidBPP(x)),
+impl PixelOrder {
+    pub fn from_mode(mode: u16) -> Self {
+        match mode {
+            1 => PixelOrder::Bgra,
+            2 => PixelOrder::Abgr,
+            _ => PixelOrder::AbgrInvertedAlpha,
+        }
+    }
+
+    /// How the channels are laid out, in a few words.
+    pub fn description(self) -> &'static str {
+        match self {
+            PixelOrder::Bgra => "B, G, R, A",
+            PixelOrder::Abgr => "A, B, G, R",
+            PixelOrder::AbgrInvertedAlpha => "A, B, G, R, with 255 for transparent",
         }
     }
 }
 
+/// The palette layout encoded in the `depth` header field of 8-bit images.
+///
+/// | depth          | colors  | entry        |
+/// |----------------|---------|--------------|
+/// | `0`, `1`       | -       | 24-/32-bit truecolor, no palette |
+/// | `2..=0x100`    | `depth` | 3 bytes, RGB |
+/// | `0x101`        | 256     | 3 bytes, RGB |
+/// | `0x102`        | 256     | 4 bytes, RGB and an unused byte |
+/// | above `0x102`  | 256     | 3 bytes, RGB |
+///
+/// Negative depths are invalid. Serialized as its depth.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct CrxHeader {
-    pub inner_x: i16, // offset 0x04
-    pub inner_y: i16, // offset 0x06
-    pub width: u16,   // offset 0x08
-    pub height: u16,  // offset 0x0A
-    pub version: u16, // offset 0x0C
-    pub flag: u16,    // offset 0x0E
-    pub depth: i16,   // offset 0x10
-    pub mode: u16,    // offset 0x12
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "i16", into = "i16")
+)]
+pub struct PaletteSpec {
+    depth: i16,
+    colors: usize,
+    entry_size: usize,
 }
 
-impl CrxHeader {
-    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
-        let inner_x = reader.read_i16::<LittleEndian>()?;
-        let inner_y = reader.read_i16::<LittleEndian>()?;
-        let width = reader.read_u16::<LittleEndian>()?;
-        let height = reader.read_u16::<LittleEndian>()?;
-        let version = reader.read_u16::<LittleEndian>()?;
-        let flag = reader.read_u16::<LittleEndian>()?;
-        let depth = reader.read_i16::<LittleEndian>()?;
-        let mode = reader.read_u16::<LittleEndian>()?;
+impl From<PaletteSpec> for i16 {
+    fn from(spec: PaletteSpec) -> Self {
+        spec.depth
+    }
+}
 
-        // Verify that the version is supported (1, 2, 3)
-        if !(1..=3).contains(&version) {
-            return Err(decode_error!(CrxDecodeError::VersionNotSupported(version)));
+/// Fails for the depths of truecolor images, which have no palette.
+impl TryFrom<i16> for PaletteSpec {
+    type Error = CrxDecodeError;
+
+    fn try_from(depth: i16) -> Result<Self, Self::Error> {
+        Self::from_depth(depth)?.ok_or(CrxDecodeError::InvalidDepth(depth))
+    }
+}
+
+impl PaletteSpec {
+    /// Parse the `depth` header field. Returns `Ok(None)` for truecolor images.
+    pub fn from_depth(depth: i16) -> Result<Option<Self>, CrxDecodeError> {
+        match depth {
+            ..=-1 => Err(CrxDecodeError::InvalidDepth(depth)),
+            0 | 1 => Ok(None),
+            0x102 => Ok(Some(Self {
+                depth,
+                colors: 0x100,
+                entry_size: 4,
+            })),
+            _ => Ok(Some(Self {
+                depth,
+                colors: (depth as usize).min(0x100),
+                entry_size: 3,
+            })),
         }
+    }
 
-        Ok(CrxHeader {
-            inner_x,
-            inner_y,
-            width,
-            height,
-            version,
-            flag,
-            depth,
-            mode,
-        })
+    /// Number of palette entries.
+    pub fn colors(&self) -> usize {
+        self.colors
+    }
+
+    /// Size of one palette entry in the file, in bytes.
+    pub fn entry_size(&self) -> usize {
+        self.entry_size
+    }
+
+    /// The `depth` header field this was parsed from.
+    pub fn depth(&self) -> i16 {
+        self.depth
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct CrxImageClip {
-    pub field_1: i32,
-    pub field_2: i16,
-    pub field_3: i16,
-    pub field_4: i32,
-    pub field_5: i16,
-    pub field_6: i16,
+/// Version of the CRX format, from the header. Serialized as its number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u16", into = "u16")
+)]
+pub enum CrxVersion {
+    /// LZ-compressed pixels.
+    V1,
+    /// zlib-compressed rows with per-row predictors.
+    V2,
+    /// Like [`CrxVersion::V2`], with a clip table after the palette.
+    V3,
 }
 
-impl CrxImageClip {
-    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
-        let field_1 = reader.read_i32::<LittleEndian>()?;
-        let field_2 = reader.read_i16::<LittleEndian>()?;
-        let field_3 = reader.read_i16::<LittleEndian>()?;
-        let field_4 = reader.read_i32::<LittleEndian>()?;
-        let field_5 = reader.read_i16::<LittleEndian>()?;
-        let field_6 = reader.read_i16::<LittleEndian>()?;
+impl CrxVersion {
+    /// The version number as stored in the header.
+    pub fn number(self) -> u16 {
+        match self {
+            CrxVersion::V1 => 1,
+            CrxVersion::V2 => 2,
+            CrxVersion::V3 => 3,
+        }
+    }
 
-        Ok(Self {
-            field_1,
-            field_2,
-            field_3,
-            field_4,
-            field_5,
-            field_6,
-        })
+    /// Whether the file has a clip table.
+    pub fn has_clip_table(self) -> bool {
+        self >= CrxVersion::V3
+    }
+
+    /// Whether pixel data is zlib-compressed (with row predictors), rather than LZ-compressed.
+    pub fn uses_zlib(self) -> bool {
+        self >= CrxVersion::V2
+    }
+}
+
+impl CrxVersion {
+    /// What sets this version apart, in a few words.
+    pub fn description(self) -> &'static str {
+        match self {
+            CrxVersion::V1 => "LZ-compressed pixels",
+            CrxVersion::V2 => "zlib-compressed rows with per-row predictors",
+            CrxVersion::V3 => "like version 2, with a clip table after the palette",
+        }
+    }
+}
+
+impl From<CrxVersion> for u16 {
+    fn from(version: CrxVersion) -> Self {
+        version.number()
+    }
+}
+
+impl TryFrom<u16> for CrxVersion {
+    type Error = CrxDecodeError;
+
+    fn try_from(version: u16) -> Result<Self, Self::Error> {
+        match version {
+            1 => Ok(CrxVersion::V1),
+            2 => Ok(CrxVersion::V2),
+            3 => Ok(CrxVersion::V3),
+            x => Err(CrxDecodeError::VersionNotSupported {
+                version: x,
+                flag: None,
+                mode: None,
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for CrxVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.number().fmt(f)
+    }
+}
+
+/// What the inner offsets of an image are measured from. The file does not record it; it varies
+/// with the engine generation of the game, so callers have to know (or guess) it per title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OffsetOrigin {
+    /// The top-left corner of the screen, y pointing down.
+    #[default]
+    TopLeft,
+    /// The center of the screen, y pointing down.
+    Center,
+}
+
+impl OffsetOrigin {
+    /// Convert the offset (`x`, `y`), measured from `self`, into one measured from `to` on a
+    /// screen of `screen_width` x `screen_height`.
+    pub fn convert(
+        self,
+        (x, y): (i32, i32),
+        to: OffsetOrigin,
+        (screen_width, screen_height): (u16, u16),
+    ) -> (i32, i32) {
+        let (half_width, half_height) = (screen_width as i32 / 2, screen_height as i32 / 2);
+        match (self, to) {
+            (OffsetOrigin::Center, OffsetOrigin::TopLeft) => (x + half_width, y + half_height),
+            (OffsetOrigin::TopLeft, OffsetOrigin::Center) => (x - half_width, y - half_height),
+            _ => (x, y),
+        }
+    }
+}
+
+/// How [`CrxFile::read_with_options`] decodes, with limits for untrusted input. Limits that
+/// are `None` are not checked, as in [`Default`]. [`CrxReader`](crate::CrxReader) sets them one
+/// by one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Keep 8-bit images as palette indices, like [`CrxFile::read_indexed`].
+    pub keep_indexed: bool,
+    pub max_width: Option<u16>,
+    pub max_height: Option<u16>,
+    /// Bytes allocated for the pixel data, checked before any of it is decoded.
+    pub max_alloc: Option<u64>,
+    pub max_clips: Option<usize>,
+    pub max_palette_colors: Option<usize>,
+    /// Decode images with a width or height of 0 to no pixels, instead of failing with
+    /// [`CrxDecodeError::InvalidDimensions`]. A width or height of 65535, what a header of `-1`
+    /// reads as, is rejected either way.
+    pub allow_empty: bool,
+    /// Keep the alpha of 32-bit pixels as stored, instead of inverting it as images of modes
+    /// other than 1 and 2 require.
+    pub keep_alpha: bool,
+    pub strictness: Strictness,
+}
+
+/// What decoding does with a pixel stream it cannot read to the end, e.g. of a truncated file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Strictness {
+    /// Fail. A stream that holds more than the rows of the header is not a failure: what follows
+    /// the last row is ignored.
+    #[default]
+    Strict,
+    /// Keep the rows above the first unreadable one and zero the others, see
+    /// [`CrxFile::read_with_warnings`]. What follows the last row is ignored with a warning.
+    /// Errors before the pixel stream still fail.
+    Lenient,
+}
+
+/// A problem [`Strictness::Lenient`] decoding recovered from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeWarning {
+    /// The first row that could not be decoded; it and the rows below are zeroed. The height of
+    /// the image, with no row zeroed, when the stream holds more than the image.
+    pub row: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {}: {}", self.row, self.message)
+    }
+}
+
+impl DecodeOptions {
+    fn check(what: &'static str, value: u64, limit: Option<u64>) -> io::Result<()> {
+        match limit {
+            Some(limit) if value > limit => Err(decode_error!(CrxDecodeError::LimitExceeded(
+                what, value, limit
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrxFile {
+    signature: CrxSignature,
+    inner_x: i16,
+    inner_y: i16,
+    width: u16,
+    height: u16,
+    version: CrxVersion,
+    flag: u16,
+    mode: u16,
+    bpp: usize,
+    palette_spec: Option<PaletteSpec>,
+    palette: Vec<[u8; 3]>,
+    color_key: Option<u8>,
+    clips: Vec<CrxImageClip>,
+    raw_image_buffer: Vec<u8>,
+}
+
+impl CrxFile {
+    /// The signature the file started with; other than [`CrxSignature::STANDARD`] for some
+    /// engine builds.
+    pub fn signature(&self) -> CrxSignature {
+        self.signature
+    }
+
+    pub fn inner_x(&self) -> i16 {
+        self.inner_x
+    }
+
+    pub fn inner_y(&self) -> i16 {
+        self.inner_y
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn version(&self) -> CrxVersion {
+        self.version
+    }
+
+    /// The `flag` header field; bit `0x10` announces a size-prefixed pixel stream.
+    pub fn flag(&self) -> u16 {
+        self.flag
+    }
+
+    /// The `mode` header field, selecting the stored channel order of 32-bit images.
+    pub fn mode(&self) -> u16 {
+        self.mode
+    }
+
+    /// Bits per pixel of [`CrxFile::raw_buffer`]: 8 for images read with
+    /// [`CrxFile::read_indexed`] from an 8-bit source, 24 or 32 otherwise.
+    pub fn bpp(&self) -> usize {
+        self.bpp
+    }
+
+    /// Whether [`CrxFile::raw_buffer`] holds indices into [`CrxFile::palette`].
+    pub fn is_indexed(&self) -> bool {
+        self.bpp == 8
+    }
+
+    /// How the palette of an 8-bit source image is stored, `None` for truecolor images.
+    pub fn palette_spec(&self) -> Option<PaletteSpec> {
+        self.palette_spec
+    }
+
+    /// The palette of an 8-bit source image, `None` for truecolor images. It is kept by
+    /// [`CrxFile::read`] too, which expands the pixels to RGB.
+    pub fn palette(&self) -> Option<&[[u8; 3]]> {
+        if self.palette.is_empty() {
+            None
+        } else {
+            Some(&self.palette)
+        }
+    }
+
+    /// Index of the first palette entry stored as magenta `(0xFF, 0x00, 0xFF)`, the color key
+    /// of transparent pixels in 8-bit sprites. [`CrxFile::palette`] holds it as white.
+    pub fn color_key(&self) -> Option<u8> {
+        self.color_key
+    }
+
+    pub fn clips(&self) -> &[CrxImageClip] {
+        &self.clips
+    }
+
+    /// The decoded pixels, row by row: RGB, RGBA or palette indices, see [`CrxFile::bpp`].
+    pub fn raw_buffer(&self) -> &[u8] {
+        &self.raw_image_buffer
+    }
+
+    /// Iterate over the rows of the decoded buffer, top to bottom.
+    pub fn rows(&self) -> std::slice::Chunks<'_, u8> {
+        let stride = (self.bpp / 8) * self.width as usize;
+        self.raw_image_buffer.chunks(stride.max(1))
+    }
+
+    /// Copy the `width` x `height` rectangle at (`x`, `y`) into a new image. The inner offsets are
+    /// moved so that the region keeps its on-screen position, and so are the clips, which are cut
+    /// to the region, those outside of it dropped; the palette is kept. Returns `None` if the
+    /// rectangle is not inside the image.
+    pub fn sub_image(&self, x: u16, y: u16, width: u16, height: u16) -> Option<Self> {
+        let (inner_x, inner_y) = self.cropped_offset(x, y, width, height)?;
+        let pixel_size = self.bpp / 8;
+        let mut buffer = Vec::with_capacity(width as usize * height as usize * pixel_size);
+        for row in self.rows().skip(y as usize).take(height as usize) {
+            buffer.extend_from_slice(
+                &row[x as usize * pixel_size..(x as usize + width as usize) * pixel_size],
+            );
+        }
+        Some(Self {
+            inner_x,
+            inner_y,
+            width,
+            height,
+            clips: self.cropped_clips(x, y, width, height),
+            raw_image_buffer: buffer,
+            ..self.clone_metadata()
+        })
+    }
+
+    /// `self` with its inner offset set to (`inner_x`, `inner_y`), pixels untouched.
+    pub fn with_inner_offset(mut self, inner_x: i16, inner_y: i16) -> Self {
+        self.inner_x = inner_x;
+        self.inner_y = inner_y;
+        self
+    }
+
+    /// `self` with its clip table replaced by `clips`. Only version 3 stores one;
+    /// [`CrxFile::write`] fails for other versions unless `clips` is empty.
+    pub fn with_clips(mut self, clips: Vec<CrxImageClip>) -> Self {
+        self.clips = clips;
+        self
+    }
+
+    /// Like [`CrxFile::sub_image`], but reuses the pixel buffer of `self`.
+    pub fn crop(mut self, x: u16, y: u16, width: u16, height: u16) -> Option<Self> {
+        let (inner_x, inner_y) = self.cropped_offset(x, y, width, height)?;
+        let pixel_size = self.bpp / 8;
+        let stride = self.width as usize * pixel_size;
+        let new_stride = width as usize * pixel_size;
+        // rows only ever move towards the front of the buffer, so compact in place.
+        for row in 0..height as usize {
+            let src = (y as usize + row) * stride + x as usize * pixel_size;
+            self.raw_image_buffer
+                .copy_within(src..src + new_stride, row * new_stride);
+        }
+        self.raw_image_buffer.truncate(new_stride * height as usize);
+        self.clips = self.cropped_clips(x, y, width, height);
+        self.inner_x = inner_x;
+        self.inner_y = inner_y;
+        self.width = width;
+        self.height = height;
+        Some(self)
+    }
+
+    /// A `canvas_width` x `canvas_height` RGBA image, e.g. the size of the game's screen, with
+    /// `self` drawn onto a transparent background at its inner offset, the way the game places
+    /// it. The offset is taken as measured from the top-left corner; see
+    /// [`CrxFile::to_positioned_image_from`] for other origins.
+    pub fn to_positioned_image(&self, canvas_width: u16, canvas_height: u16) -> Self {
+        self.to_positioned_image_from(OffsetOrigin::TopLeft, canvas_width, canvas_height)
+    }
+
+    /// Like [`CrxFile::to_positioned_image`], with the inner offset measured from `origin` of
+    /// the canvas. Parts outside of the canvas are dropped; the result is placed at (0, 0) and
+    /// has no clips.
+    pub fn to_positioned_image_from(
+        &self,
+        origin: OffsetOrigin,
+        canvas_width: u16,
+        canvas_height: u16,
+    ) -> Self {
+        let (left, top) = origin.convert(
+            (self.inner_x as i32, self.inner_y as i32),
+            OffsetOrigin::TopLeft,
+            (canvas_width, canvas_height),
+        );
+        let (width, height) = (canvas_width as i32, canvas_height as i32);
+        let mut buffer = vec![0; width as usize * height as usize * 4];
+        // the columns of `self` that land on the canvas, the same for every row.
+        let columns = (-left).max(0)..(width - left).min(self.width as i32);
+        for y in 0..self.height as i32 {
+            let canvas_y = top + y;
+            if canvas_y < 0 || canvas_y >= height {
+                continue;
+            }
+            for x in columns.clone() {
+                let offset = (canvas_y as usize * width as usize + (left + x) as usize) * 4;
+                buffer[offset..offset + 4].copy_from_slice(&self.rgba_at(x as usize, y as usize));
+            }
+        }
+        Self {
+            inner_x: 0,
+            inner_y: 0,
+            width: canvas_width,
+            height: canvas_height,
+            clips: Vec::new(),
+            ..self.with_rgba(buffer)
+        }
+    }
+
+    /// The clips that overlap the `width` x `height` region at (`x`, `y`), in its coordinates.
+    fn cropped_clips(&self, x: u16, y: u16, width: u16, height: u16) -> Vec<CrxImageClip> {
+        self.clips
+            .iter()
+            .filter_map(|clip| clip.cropped(x, y, width, height))
+            .collect()
+    }
+
+    fn cropped_offset(&self, x: u16, y: u16, width: u16, height: u16) -> Option<(i16, i16)> {
+        if x as u32 + width as u32 > self.width as u32
+            || y as u32 + height as u32 > self.height as u32
+        {
+            return None;
+        }
+        let inner_x = self.inner_x.checked_add(i16::try_from(x).ok()?)?;
+        let inner_y = self.inner_y.checked_add(i16::try_from(y).ok()?)?;
+        Some((inner_x, inner_y))
+    }
+
+    /// The pixels as RGBA, whatever the pixel format.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for y in 0..self.height as usize {
+            for x in 0..self.width as usize {
+                buffer.extend_from_slice(&self.rgba_at(x, y));
+            }
+        }
+        buffer
+    }
+
+    /// The color of the pixel at (`x`, `y`) as RGBA, whatever the pixel format: palette
+    /// entries are looked up and pixels without alpha are opaque. Panics if the pixel is outside
+    /// the image.
+    pub fn get_pixel(&self, x: u16, y: u16) -> [u8; 4] {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) is out of bounds",
+            x,
+            y
+        );
+        self.rgba_at(x as usize, y as usize)
+    }
+
+    /// The color of the pixel at (`x`, `y`) as RGBA, whatever the pixel format.
+    pub(crate) fn rgba_at(&self, x: usize, y: usize) -> [u8; 4] {
+        let pixel_size = self.bpp / 8;
+        let offset = (y * self.width as usize + x) * pixel_size;
+        let pixel = &self.raw_image_buffer[offset..offset + pixel_size];
+        match pixel_size {
+            1 => {
+                let [r, g, b] = self.palette[pixel[0] as usize];
+                [r, g, b, 0xFF]
+            }
+            3 => [pixel[0], pixel[1], pixel[2], 0xFF],
+            _ => [pixel[0], pixel[1], pixel[2], pixel[3]],
+        }
+    }
+
+    /// A 32-bit image with the metadata of `self` and the given RGBA pixels.
+    pub(crate) fn with_rgba(&self, buffer: Vec<u8>) -> Self {
+        Self {
+            bpp: 32,
+            palette_spec: None,
+            palette: Vec::new(),
+            color_key: None,
+            raw_image_buffer: buffer,
+            ..self.clone_metadata()
+        }
+    }
+
+    /// A copy of `self` with every red, green and blue value `v` replaced by `lut[v]`, e.g. for a
+    /// transfer curve correction. Palette images have their palette mapped; alpha is kept.
+    pub fn map_channels(&self, lut: &[u8; 256]) -> Self {
+        let mut mapped = self.clone();
+        if self.bpp == 8 {
+            for color in &mut mapped.palette {
+                color.iter_mut().for_each(|v| *v = lut[*v as usize]);
+            }
+        } else {
+            for pixel in mapped.raw_image_buffer.chunks_exact_mut(self.bpp / 8) {
+                pixel[..3].iter_mut().for_each(|v| *v = lut[*v as usize]);
+            }
+        }
+        mapped
+    }
+
+    /// A copy of `self` with the red, green and blue of every pixel multiplied by its alpha, for
+    /// consumers expecting premultiplied alpha. Images without alpha are copied as they are.
+    pub fn premultiply_alpha(&self) -> Self {
+        let mut premultiplied = self.clone();
+        if self.bpp == 32 {
+            for pixel in premultiplied.raw_image_buffer.chunks_exact_mut(4) {
+                let a = pixel[3] as u32;
+                pixel[..3]
+                    .iter_mut()
+                    .for_each(|v| *v = ((*v as u32 * a + 127) / 255) as u8);
+            }
+        }
+        premultiplied
+    }
+
+    /// A 24-bit copy of `self` with every pixel blended by its alpha onto `background`, an RGB
+    /// color. Images without alpha are copied as they are.
+    pub fn flatten(&self, background: [u8; 3]) -> Self {
+        if self.bpp != 32 {
+            return self.clone();
+        }
+        let mut buffer = Vec::with_capacity(self.raw_image_buffer.len() / 4 * 3);
+        for pixel in self.raw_image_buffer.chunks_exact(4) {
+            let a = pixel[3] as u32;
+            buffer.extend(
+                pixel[..3]
+                    .iter()
+                    .zip(background)
+                    .map(|(&v, b)| ((v as u32 * a + b as u32 * (255 - a) + 127) / 255) as u8),
+            );
+        }
+        Self {
+            bpp: 24,
+            raw_image_buffer: buffer,
+            ..self.clone_metadata()
+        }
+    }
+
+    /// A copy of `self` with its pixels replaced by `buffer`, in the layout of
+    /// [`CrxFile::raw_buffer`]: RGB, RGBA or, for images read with [`CrxFile::read_indexed`],
+    /// indices into the palette.
+    pub fn with_pixels(&self, buffer: Vec<u8>) -> io::Result<Self> {
+        self.with_image(self.width, self.height, buffer)
+    }
+
+    /// Like [`CrxFile::with_pixels`], for an image of a different size. The inner offset and
+    /// clips are kept as they are.
+    pub fn with_image(&self, width: u16, height: u16, buffer: Vec<u8>) -> io::Result<Self> {
+        let expected = (self.bpp / 8) * width as usize * height as usize;
+        if buffer.len() != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "pixel buffer holds `{}` bytes but the image needs `{}`",
+                    buffer.len(),
+                    expected
+                ),
+            ));
+        }
+        if self.bpp == 8 {
+            Self::check_indices(&buffer, &self.palette)?;
+        }
+        Ok(Self {
+            width,
+            height,
+            raw_image_buffer: buffer,
+            ..self.clone_metadata()
+        })
+    }
+
+    /// A copy of `self` without the pixel data.
+    fn clone_metadata(&self) -> Self {
+        Self {
+            palette: self.palette.clone(),
+            clips: self.clips.clone(),
+            raw_image_buffer: Vec::new(),
+            ..*self
+        }
+    }
+
+    /// Decode a CRX image. 8-bit images are expanded to RGB; see [`CrxFile::read_indexed`] to
+    /// keep their indices.
+    pub fn read<R: Read>(reader: R) -> Result<Self, CrxError> {
+        Self::read_with_options(reader, &DecodeOptions::default())
+    }
+
+    /// Like [`CrxFile::read`], but 8-bit images are kept as palette indices (`bpp` is 8) instead
+    /// of being expanded to RGB.
+    pub fn read_indexed<R: Read>(reader: R) -> Result<Self, CrxError> {
+        let options = DecodeOptions {
+            keep_indexed: true,
+            ..Default::default()
+        };
+        Self::read_with_options(reader, &options)
+    }
+
+    /// Like [`CrxFile::read`], failing with [`CrxDecodeError::LimitExceeded`] as soon as the
+    /// header or a table exceeds a limit of `options`.
+    pub fn read_with_options<R: Read>(
+        reader: R,
+        options: &DecodeOptions,
+    ) -> Result<Self, CrxError> {
+        Ok(Self::read_inner(reader, options, None)?.0)
+    }
+
+    /// Like [`CrxFile::read_with_options`], also returning what [`Strictness::Lenient`] decoding
+    /// recovered from; the list is empty for intact files, and always in strict mode.
+    pub fn read_with_warnings<R: Read>(
+        reader: R,
+        options: &DecodeOptions,
+    ) -> Result<(Self, Vec<DecodeWarning>), CrxError> {
+        let (image, _, warnings) = Self::read_inner(reader, options, None)?;
+        Ok((image, warnings))
+    }
+
+    /// Decode the pixels of a CRX image into `buffer`, reusing its allocation, and return the
+    /// rest of the file. For batch pipelines that decode many images of similar size.
+    ///
+    /// `buffer` ends up laid out like [`CrxFile::raw_buffer`]: 8-bit images are expanded to RGB
+    /// unless `options.keep_indexed` is set, even though [`CrxMetadata::bpp`] stays 8.
+    pub fn decode_into<R: Read>(
+        reader: R,
+        buffer: &mut Vec<u8>,
+        options: &DecodeOptions,
+    ) -> Result<CrxMetadata, CrxError> {
+        Ok(Self::decode_pixels(reader, options, None, buffer)?.0)
+    }
+
+    /// Like [`CrxFile::read_with_options`], decoding straight from a memory map of the file at
+    /// `path` instead of reading it through a buffer, leaving caching to the kernel.
+    ///
+    /// The file must not be changed while it is decoded; a truncated file may fail with `SIGBUS`
+    /// on some systems.
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap<P: AsRef<std::path::Path>>(
+        path: P,
+        options: &DecodeOptions,
+    ) -> Result<Self, CrxError> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the map is only read, and callers are told not to change the file meanwhile.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::read_with_options(&map[..], options)
+    }
+
+    /// Read only the palette of an 8-bit image, stopping before clips and pixel data. Returns
+    /// `None` for truecolor images.
+    pub fn read_palette_only<R: Read>(mut reader: R) -> Result<Option<Vec<[u8; 3]>>, CrxError> {
+        let header = Self::read_header(reader.by_ref())?;
+        match PaletteSpec::from_depth(header.depth)? {
+            Some(spec) => Ok(Some(Self::read_palette(reader, spec)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn read_header<R: Read>(mut reader: R) -> io::Result<CrxHeader> {
+        // read signature.
+        let sig = {
+            let mut sig: [u8; 4] = [0; 4];
+            reader.read_exact(&mut sig)?;
+            sig
+        };
+        let signature = CrxSignature::parse(sig)
+            .ok_or_else(|| decode_error!(CrxDecodeError::CrxSignatureInvalid))?;
+
+        CrxHeader::read(signature, reader)
+    }
+
+    /// Read the header, palette and clip table, leaving the pixel data unread.
+    pub fn read_metadata<R: Read>(mut reader: R) -> Result<CrxMetadata, CrxError> {
+        let header = Self::read_header(reader.by_ref())?;
+        let palette_spec = PaletteSpec::from_depth(header.depth)?;
+        let (palette, color_key) = match palette_spec {
+            Some(spec) => Self::read_palette(reader.by_ref(), spec)?,
+            None => (Vec::new(), None),
+        };
+        let clips = if header.version.has_clip_table() {
+            Self::read_clip(reader, None)?
+        } else {
+            Vec::new()
+        };
+        Ok(CrxMetadata {
+            header,
+            bpp: Self::stored_bpp(header.depth, palette_spec),
+            palette_spec,
+            palette,
+            color_key,
+            clips,
+        })
+    }
+
+    /// Bits per pixel of the stored pixel data.
+    fn stored_bpp(depth: i16, palette_spec: Option<PaletteSpec>) -> usize {
+        match (depth, palette_spec) {
+            (_, Some(_)) => 8,
+            (0, None) => 24,
+            _ => 32,
+        }
+    }
+
+    /// Decode, stopping at the first row boundary after `deadline`. Returns the image with the
+    /// rows left undecoded zeroed, the number of decoded rows and the warnings of lenient
+    /// decoding.
+    pub(crate) fn read_inner<R: Read>(
+        reader: R,
+        options: &DecodeOptions,
+        deadline: Option<Instant>,
+    ) -> io::Result<(Self, usize, Vec<DecodeWarning>)> {
+        let mut pixels = Vec::new();
+        let (metadata, rows, warnings) =
+            Self::decode_pixels(reader, options, deadline, &mut pixels)?;
+        let bpp = if metadata.bpp == 8 && !options.keep_indexed {
+            24
+        } else {
+            metadata.bpp
+        };
+        // caught here, a wrong length would otherwise only surface when the pixels are used.
+        let expected = metadata.header.width as usize * metadata.header.height as usize * bpp / 8;
+        if pixels.len() != expected {
+            return Err(decode_error!(CrxDecodeError::PixelBufferSize(
+                expected,
+                pixels.len()
+            )));
+        }
+        let image = Self {
+            signature: metadata.header.signature,
+            inner_x: metadata.header.inner_x,
+            inner_y: metadata.header.inner_y,
+            width: metadata.header.width,
+            height: metadata.header.height,
+            version: metadata.header.version,
+            flag: metadata.header.flag,
+            mode: metadata.header.mode,
+            bpp,
+            palette_spec: metadata.palette_spec,
+            palette: metadata.palette,
+            color_key: metadata.color_key,
+            clips: metadata.clips,
+            raw_image_buffer: pixels,
+        };
+        Ok((image, rows, warnings))
+    }
+
+    /// Decode the pixels into `buffer`, reusing its allocation, and return the rest of the file
+    /// with the number of decoded rows and the warnings of lenient decoding.
+    fn decode_pixels<R: Read>(
+        mut reader: R,
+        options: &DecodeOptions,
+        deadline: Option<Instant>,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<(CrxMetadata, usize, Vec<DecodeWarning>)> {
+        let keep_indexed = options.keep_indexed;
+        let (metadata, stream_size) = Self::read_preamble(reader.by_ref(), options)?;
+        let (header, bpp) = (metadata.header, metadata.bpp);
+        trace_span!(
+            "decode_pixels",
+            width = header.width,
+            height = header.height,
+            bpp,
+            version = header.version.number()
+        );
+
+        // the decompressed pixels and, for expanded palettes, their RGB colors.
+        let pixels = header.width as u64 * header.height as u64;
+        let output_size = pixels
+            * if bpp == 8 && !keep_indexed {
+                3
+            } else {
+                bpp as u64 / 8
+            };
+        let pixel_alloc = pixels * (bpp as u64 / 8) + header.height as u64 + output_size;
+        DecodeOptions::check("allocation", pixel_alloc, options.max_alloc)?;
+
+        // decompressed straight from the input, so the compressed stream is never held whole.
+        let mut stream = io::BufReader::new(reader.take(stream_size));
+
+        // prepare decompress context
+        let context = CrxDataContext {
+            width: header.width as usize,
+            height: header.height as usize,
+            bpp,
+            deadline,
+        };
+        // decompress (extract) color data.
+        let unpacked = if header.version.uses_zlib() {
+            Self::unpack_2(stream, &context, buffer)
+        } else {
+            Self::unpack_1(&mut stream, &context, buffer).and_then(|rows| {
+                // the rest of a sized stream is pixels the image has no room for; a stream that
+                // runs to the end of the input may just be followed by padding.
+                let sized = stream_size != u64::MAX;
+                if rows == context.height && sized && stream.fill_buf().is_ok_and(|b| !b.is_empty())
+                {
+                    Err((rows, decode_error!(CrxDecodeError::StreamTooLong)))
+                } else {
+                    Ok(rows)
+                }
+            })
+        };
+        let mut warnings = Vec::new();
+        let rows = match unpacked {
+            Ok(rows) => rows,
+            Err((row, e)) if options.strictness == Strictness::Lenient => {
+                warnings.push(DecodeWarning {
+                    row,
+                    message: e.to_string(),
+                });
+                row
+            }
+            // only a stream holding more than the image fails past its last row; every row was
+            // decoded, which strict decoding has always accepted.
+            Err((row, _)) if row == context.height => row,
+            Err((_, e)) => return Err(e),
+        };
+
+        // bgr(a) to rgb(a), flipping alpha as the mode requires, in one pass over the decoded
+        // rows. indexed pixels are left for the palette.
+        let decoded_size = rows * header.width as usize * (bpp / 8);
+        Self::to_rgb(
+            &mut buffer[..decoded_size],
+            bpp,
+            header.mode,
+            options.keep_alpha,
+        );
+
+        // palette indices to rgb, unless the caller wants to keep them.
+        if bpp == 8 {
+            if keep_indexed {
+                Self::check_indices(buffer, &metadata.palette)?;
+            } else {
+                Self::expand_palette(buffer, &metadata.palette)?;
+            }
+        }
+
+        // index 0 of undecoded rows was expanded too.
+        let decoded = buffer.len() / (header.height as usize).max(1) * rows;
+        buffer[decoded..].fill(0);
+
+        Ok((metadata, rows, warnings))
+    }
+
+    /// Read everything before the pixel stream, checking the limits of `options`. Returns the
+    /// metadata and the size of the stream, `u64::MAX` when it runs to the end of the input.
+    pub(crate) fn read_preamble<R: Read>(
+        mut reader: R,
+        options: &DecodeOptions,
+    ) -> io::Result<(CrxMetadata, u64)> {
+        let header = Self::read_header(reader.by_ref())?;
+        let invalid = |n: u16| n == u16::MAX || (n == 0 && !options.allow_empty);
+        if invalid(header.width) || invalid(header.height) {
+            return Err(decode_error!(CrxDecodeError::InvalidDimensions(
+                header.width,
+                header.height
+            )));
+        }
+        DecodeOptions::check(
+            "width",
+            header.width as u64,
+            options.max_width.map(u64::from),
+        )?;
+        DecodeOptions::check(
+            "height",
+            header.height as u64,
+            options.max_height.map(u64::from),
+        )?;
+        let palette_spec = PaletteSpec::from_depth(header.depth).map_err(|e| decode_error!(e))?;
+        let bpp = Self::stored_bpp(header.depth, palette_spec);
+
+        // read palette, iff bpp is 8.
+        let (palette, color_key) = match palette_spec {
+            Some(spec) => {
+                DecodeOptions::check(
+                    "palette size",
+                    spec.colors as u64,
+                    options.max_palette_colors.map(|n| n as u64),
+                )?;
+                Self::read_palette(reader.by_ref(), spec)?
+            }
+            None => (Vec::new(), None),
+        };
+
+        // read clipping information
+        let clips = if header.version.has_clip_table() {
+            Self::read_clip(reader.by_ref(), options.max_clips)?
+        } else {
+            Vec::new()
+        };
+
+        // the stream runs to the end of the input unless it is size-prefixed.
+        let stream_size = if (header.flag & FLAG_SIZED_STREAM) != 0 {
+            let data_size = reader.read_i32::<LittleEndian>()?;
+            if data_size < 0 {
+                return Err(decode_error!(CrxDecodeError::InvalidStreamSize(data_size)));
+            }
+            data_size as u64
+        } else {
+            u64::MAX
+        };
+
+        let metadata = CrxMetadata {
+            header,
+            bpp,
+            palette_spec,
+            palette,
+            color_key,
+            clips,
+        };
+        Ok((metadata, stream_size))
+    }
+
+    /// Reorder decoded BGR(A) pixels to RGB(A) in place. 32-bit pixels are stored as ABGR, with
+    /// alpha inverted unless the mode is 2; mode 1 images keep their fourth byte as is.
+    pub(crate) fn to_rgb(data: &mut [u8], bpp: usize, mode: u16, keep_alpha: bool) {
+        trace_span!("to_rgb");
+        #[cfg(feature = "parallel")]
+        if crate::parallel::worthwhile(data.len()) {
+            return crate::parallel::to_rgb(data, bpp, mode, keep_alpha);
+        }
+        match (bpp, PixelOrder::from_mode(mode)) {
+            (32, PixelOrder::Bgra) => {
+                for pixel in data.chunks_exact_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+            (32, order) => {
+                let flip = if order == PixelOrder::Abgr || keep_alpha {
+                    0
+                } else {
+                    0xFF
+                };
+                for pixel in data.chunks_exact_mut(4) {
+                    let (a, b, g, r) = (pixel[0], pixel[1], pixel[2], pixel[3]);
+                    pixel.copy_from_slice(&[r, g, b, a ^ flip]);
+                }
+            }
+            (24, _) => {
+                for pixel in data.chunks_exact_mut(3) {
+                    pixel.swap(0, 2);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Read the palette, along with the index of its color key, see [`CrxFile::color_key`].
+    fn read_palette<R: Read>(
+        mut reader: R,
+        spec: PaletteSpec,
+    ) -> io::Result<(Vec<[u8; 3]>, Option<u8>)> {
+        let mut palette: Vec<[u8; 3]> = Vec::with_capacity(spec.colors);
+        let mut color_key = None;
+
+        for index in 0..spec.colors {
+            let r = reader.read_u8()?;
+            let mut g = reader.read_u8()?;
+            let b = reader.read_u8()?;
+            // I don't know why this fourth component exists, even if it is not used.
+            if 4 == spec.entry_size {
+                reader.read_u8()?;
+            }
+            // Also I don't know why there is no yellow color in the palette.
+            if 0xFF == b && 0 == g && 0xFF == r {
+                g = 0xFF;
+                color_key = color_key.or(Some(index as u8));
+            }
+            palette.push([r, g, b]);
+        }
+
+        Ok((palette, color_key))
+    }
+
+    /// Fail on the first index that is outside of `palette`.
+    pub(crate) fn check_indices(indices: &[u8], palette: &[[u8; 3]]) -> io::Result<()> {
+        match indices.iter().find(|&&i| i as usize >= palette.len()) {
+            Some(&index) => Err(decode_error!(CrxDecodeError::BadPaletteIndex(
+                palette.len(),
+                index as usize
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace the palette indices of `data` with their RGB colors, growing it in place.
+    pub(crate) fn expand_palette(data: &mut Vec<u8>, palette: &[[u8; 3]]) -> io::Result<()> {
+        trace_span!("expand_palette");
+        #[cfg(feature = "parallel")]
+        if crate::parallel::worthwhile(data.len() * 3) {
+            return crate::parallel::expand_palette(data, palette);
+        }
+        Self::check_indices(data, palette)?;
+        // with the indices checked, a full table lets the copy loop run without bounds errors.
+        let mut lut = [[0u8; 3]; 0x100];
+        lut[..palette.len().min(0x100)].copy_from_slice(&palette[..palette.len().min(0x100)]);
+        let count = data.len();
+        data.resize(count * 3, 0);
+        // back to front, so no index is overwritten before it is read.
+        for i in (0..count).rev() {
+            let color = lut[data[i] as usize];
+            data[i * 3..i * 3 + 3].copy_from_slice(&color);
+        }
+        Ok(())
+    }
+
+    fn read_clip<R: Read>(
+        mut reader: R,
+        max_clips: Option<usize>,
+    ) -> io::Result<Vec<CrxImageClip>> {
+        let clip_count = reader.read_i32::<LittleEndian>()?;
+        if clip_count < 0 {
+            return Err(decode_error!(CrxDecodeError::InvalidClipCount(clip_count)));
+        }
+        DecodeOptions::check("clip count", clip_count as u64, max_clips.map(|n| n as u64))?;
+        // grow with the clips actually read, for the same reason as the pixel stream.
+        let mut clips = Vec::with_capacity((clip_count as usize).min(0x100));
+        for _ in 0..clip_count {
+            let clip = CrxImageClip::read(reader.by_ref())?;
+            clips.push(clip);
+        }
+        Ok(clips)
+    }
+
+    /// Decompress a version 1 stream into `output`, returning the number of decoded rows. Fails
+    /// with the row that could not be read, zeroed, or with the height when the stream holds
+    /// more than the image.
+    fn unpack_1<R: Read>(
+        mut buf: R,
+        context: &CrxDataContext,
+        output: &mut Vec<u8>,
+    ) -> Result<usize, (usize, io::Error)> {
+        trace_span!("unpack", zlib = false);
+        let mut lz = LzState::new();
+        output.clear();
+        output.resize((context.bpp / 8) * context.width * context.height, 0);
+        let stride = ((context.bpp / 8) * context.width).max(1);
+
+        for (y, row) in output.chunks_mut(stride).enumerate() {
+            if context.expired() {
+                return Ok(y);
+            }
+            if let Err(e) = lz.fill(&mut buf, row) {
+                row.fill(0);
+                return Err((y, e));
+            }
+        }
+        // a back-reference cut off by the end of the image.
+        if lz.pending.1 > 0 {
+            return Err((context.height, decode_error!(CrxDecodeError::StreamTooLong)));
+        }
+        Ok(context.height)
+    }
+
+    /// Like [`CrxFile::unpack_1`], for the zlib streams of later versions.
+    fn unpack_2<R: BufRead>(
+        buf: R,
+        context: &CrxDataContext,
+        output: &mut Vec<u8>,
+    ) -> Result<usize, (usize, io::Error)> {
+        use flate2::bufread::ZlibDecoder;
+
+        trace_span!("unpack", zlib = true);
+        #[cfg(feature = "parallel")]
+        if crate::parallel::worthwhile(context.bpp / 8 * context.width * context.height) {
+            return crate::parallel::unpack_2(buf, context, output);
+        }
+        let pixel_size = context.bpp / 8;
+        // number of bytes in a row's data. applies to both input and output.
+        let stride = pixel_size * context.width;
+
+        output.clear();
+        // rows of an empty image store nothing, not even their predictor.
+        if stride == 0 {
+            return Ok(context.height);
+        }
+        // rows are read a few bytes at a time, so buffer the inflated data.
+        let mut reader = io::BufReader::new(ZlibDecoder::new(buf));
+        output.resize(stride * context.height, 0);
+
+        for y in 0..context.height {
+            if context.expired() {
+                return Ok(y);
+            }
+            let (done, rest) = output.split_at_mut(y * stride);
+            let previous = y.checked_sub(1).map(|_| &done[done.len() - stride..]);
+            if let Err(e) =
+                Self::unpack_row_2(&mut reader, &mut rest[..stride], previous, pixel_size)
+            {
+                rest[..stride].fill(0);
+                return Err((y, e));
+            }
+        }
+        // an error past the last row, e.g. a bad checksum, is left alone.
+        if reader.fill_buf().is_ok_and(|rest| !rest.is_empty()) {
+            return Err((context.height, decode_error!(CrxDecodeError::StreamTooLong)));
+        }
+
+        Ok(context.height)
+    }
+
+    /// Decompress one row of a zlib stream into `row`, undoing its predictor. `previous` is the
+    /// row above, as stored, if there is one. Rows of zero-width images are empty and read
+    /// nothing.
+    #[inline]
+    pub(crate) fn unpack_row_2<R: Read>(
+        reader: &mut R,
+        row: &mut [u8],
+        previous: Option<&[u8]>,
+        pixel_size: usize,
+    ) -> io::Result<()> {
+        let stride = row.len();
+        if stride == 0 {
+            return Ok(());
+        }
+        if pixel_size == 1 {
+            // 8-bit palette color mode.
+            // palette indices of each pixel are stored as is.
+            return reader.read_exact(row);
+        }
+        let no_previous = || decode_error!(CrxDecodeError::NoPreviousRow);
+        let filter = RowFilter::try_from(reader.read_u8()?).map_err(|e| decode_error!(e))?;
+        match filter {
+            RowFilter::Left => {
+                // first pixel is provided as is, remaining pixels are encoded as differences from the previous pixel.
+                // read the first pixel value as is.
+                reader.read_exact(&mut row[..pixel_size])?;
+                // read the remaining pixels (per byte) in the same row.
+                for xb in pixel_size..stride {
+                    row[xb] = reader.read_u8()?.wrapping_add(row[xb - pixel_size]);
+                }
+            }
+            RowFilter::Up => {
+                // pixels values are provided as the differences from the corresponding x-position of previous row.
+                let previous = previous.ok_or_else(no_previous)?;
+                for xb in 0..stride {
+                    row[xb] = reader.read_u8()?.wrapping_add(previous[xb]);
+                }
+            }
+            RowFilter::UpLeft => {
+                // first pixel is provided as is, remaining pixels are differences from the the previous row, left-shifting one pixel.
+                let previous = previous.ok_or_else(no_previous)?;
+                // read the first pixel value as is.
+                reader.read_exact(&mut row[..pixel_size])?;
+                // read the remaining pixels (per byte) in the same row.
+                for xb in pixel_size..stride {
+                    row[xb] = reader.read_u8()?.wrapping_add(previous[xb - pixel_size]);
+                }
+            }
+            RowFilter::UpRight => {
+                // last pixel is provided as is, pixels before it are differences from the previous row, right-shifting one pixel
+                let previous = previous.ok_or_else(no_previous)?;
+                // read the pixels
+                for xb in 0..stride - pixel_size {
+                    row[xb] = reader.read_u8()?.wrapping_add(previous[xb + pixel_size]);
+                }
+                // read the last pixel as is.
+                reader.read_exact(&mut row[stride - pixel_size..])?;
+            }
+            RowFilter::Runs => {
+                // input is organized by pixel component, for each component, same-value compression is used.
+                // same-value compression
+                // 1. read a byte `a`, write to the output.
+                // 2. read another byte `b`.
+                // 3.1. if `a == b` then read a third byte `c`, and repeat writing `a` (or `b`) `c` times, go to step 1.
+                // 3.2. Otherwise set `a` to `b`, go back to step 2.
+                let width = stride / pixel_size;
+                for pix_offset in 0..pixel_size {
+                    let mut xb = pix_offset;
+                    let mut remaining = width;
+                    let mut val = reader.read_u8()?; // row init
+                    while remaining > 0 {
+                        row[xb] = val;
+                        xb += pixel_size;
+                        remaining -= 1;
+                        if remaining == 0 {
+                            break;
+                        }
+                        let next = reader.read_u8()?;
+                        if val == next {
+                            let count = reader.read_u8()? as usize;
+                            // checked before writing, so a run cannot leave the row.
+                            remaining = remaining
+                                .checked_sub(count)
+                                .ok_or_else(|| decode_error!(CrxDecodeError::RowOverflow))?;
+                            for _ in 0..count {
+                                row[xb] = next;
+                                xb += pixel_size;
+                            }
+                            if remaining > 0 {
+                                val = reader.read_u8()?;
+                            }
+                        } else {
+                            val = next;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "to_image")]
+impl CrxFile {
+    /// Like the conversion of [`CrxFile`] into [`image::DynamicImage`], keeping `self`: only the
+    /// pixels are copied.
+    pub fn to_image(&self) -> Result<image::DynamicImage, CrxImageConvertError> {
+        Self::make_image(
+            self.width,
+            self.height,
+            self.bpp,
+            self.raw_image_buffer.clone(),
+            &self.palette,
+        )
+    }
+
+    /// A copy of `self` scaled to `width` x `height` with `filter`, e.g. for thumbnails. The
+    /// inner offset and clips are kept as they are. Palette images keep their palette with
+    /// [`FilterType::Nearest`](image::imageops::FilterType::Nearest), which only picks existing
+    /// pixels, and are expanded to RGB with the other filters. Fails like [`CrxFile::to_image`]
+    /// on pixels that do not fit the image or its palette.
+    pub fn resize(
+        &self,
+        width: u16,
+        height: u16,
+        filter: image::imageops::FilterType,
+    ) -> Result<Self, CrxImageConvertError> {
+        use image::{imageops, ImageBuffer, Luma, Rgb, Rgba};
+
+        let (from_width, from_height) = (self.width as u32, self.height as u32);
+        let (to_width, to_height) = (width as u32, height as u32);
+        let invalid = |channels: usize, actual: usize| {
+            CrxImageConvertError::InvalidRawBuffer(
+                from_width as usize * from_height as usize * channels,
+                actual,
+            )
+        };
+        let buffer = self.raw_image_buffer.clone();
+        let actual = buffer.len();
+        let resized = match self.bpp {
+            8 if filter == imageops::FilterType::Nearest => {
+                let indices: ImageBuffer<Luma<u8>, _> =
+                    ImageBuffer::from_raw(from_width, from_height, buffer)
+                        .ok_or_else(|| invalid(1, actual))?;
+                return Ok(Self {
+                    width,
+                    height,
+                    raw_image_buffer: imageops::resize(&indices, to_width, to_height, filter)
+                        .into_raw(),
+                    ..self.clone_metadata()
+                });
+            }
+            32 => {
+                let rgba: ImageBuffer<Rgba<u8>, _> =
+                    ImageBuffer::from_raw(from_width, from_height, buffer)
+                        .ok_or_else(|| invalid(4, actual))?;
+                imageops::resize(&rgba, to_width, to_height, filter).into_raw()
+            }
+            bpp @ (8 | 24) => {
+                let mut rgb = buffer;
+                if bpp == 8 {
+                    Self::expand_palette(&mut rgb, &self.palette)
+                        .map_err(|_| CrxImageConvertError::BadPaletteIndex)?;
+                }
+                let actual = rgb.len();
+                let rgb: ImageBuffer<Rgb<u8>, _> =
+                    ImageBuffer::from_raw(from_width, from_height, rgb)
+                        .ok_or_else(|| invalid(3, actual))?;
+                imageops::resize(&rgb, to_width, to_height, filter).into_raw()
+            }
+            bpp => return Err(CrxImageConvertError::InvalidBPP(bpp)),
+        };
+        Ok(Self {
+            width,
+            height,
+            bpp: if self.bpp == 32 { 32 } else { 24 },
+            palette_spec: None,
+            palette: Vec::new(),
+            color_key: None,
+            raw_image_buffer: resized,
+            ..self.clone_metadata()
+        })
+    }
+
+    fn make_image(
+        width: u16,
+        height: u16,
+        bpp: usize,
+        pixels: Vec<u8>,
+        palette: &[[u8; 3]],
+    ) -> Result<image::DynamicImage, CrxImageConvertError> {
+        let (width, height) = (width as u32, height as u32);
+        let invalid = |channels: usize, actual: usize| {
+            CrxImageConvertError::InvalidRawBuffer(
+                width as usize * height as usize * channels,
+                actual,
+            )
+        };
+        match bpp {
+            8 => {
+                let mut rgb = pixels;
+                CrxFile::expand_palette(&mut rgb, palette)
+                    .map_err(|_| CrxImageConvertError::BadPaletteIndex)?;
+                let actual = rgb.len();
+                let rgb_image = image::ImageBuffer::from_raw(width, height, rgb)
+                    .ok_or_else(|| invalid(3, actual))?;
+                Ok(image::DynamicImage::ImageRgb8(rgb_image))
+            }
+            24 => {
+                let actual = pixels.len();
+                let rgb_image = image::ImageBuffer::from_raw(width, height, pixels)
+                    .ok_or_else(|| invalid(3, actual))?;
+                Ok(image::DynamicImage::ImageRgb8(rgb_image))
+            }
+            32 => {
+                let actual = pixels.len();
+                let rgba_image = image::ImageBuffer::from_raw(width, height, pixels)
+                    .ok_or_else(|| invalid(4, actual))?;
+                Ok(image::DynamicImage::ImageRgba8(rgba_image))
+            }
+            x => Err(CrxImageConvertError::InvalidBPP(x)),
+        }
+    }
+}
+
+/// Indexed images are expanded to RGB.
+#[cfg(feature = "to_image")]
+impl TryFrom<CrxFile> for image::DynamicImage {
+    type Error = CrxImageConvertError;
+
+    fn try_from(value: CrxFile) -> Result<Self, CrxImageConvertError> {
+        CrxFile::make_image(
+            value.width,
+            value.height,
+            value.bpp,
+            value.raw_image_buffer,
+            &value.palette,
+        )
+    }
+}
+
+/// What [`CrxFile::read_metadata`] reads: everything but the pixels. With the `serde` feature,
+/// it is serialized as the header fields, followed by `palette` (`null` for truecolor images),
+/// `color_key` and `clips`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "MetadataFields", try_from = "MetadataFields")
+)]
+pub struct CrxMetadata {
+    header: CrxHeader,
+    bpp: usize,
+    palette_spec: Option<PaletteSpec>,
+    palette: Vec<[u8; 3]>,
+    color_key: Option<u8>,
+    clips: Vec<CrxImageClip>,
+}
+
+impl CrxMetadata {
+    pub fn inner_x(&self) -> i16 {
+        self.header.inner_x
+    }
+
+    pub fn inner_y(&self) -> i16 {
+        self.header.inner_y
+    }
+
+    pub fn width(&self) -> u16 {
+        self.header.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.header.height
+    }
+
+    pub fn version(&self) -> CrxVersion {
+        self.header.version
+    }
+
+    /// See [`CrxFile::signature`].
+    pub fn signature(&self) -> CrxSignature {
+        self.header.signature
+    }
+
+    /// See [`CrxFile::flag`].
+    pub fn flag(&self) -> u16 {
+        self.header.flag
+    }
+
+    /// See [`CrxFile::mode`].
+    pub fn mode(&self) -> u16 {
+        self.header.mode
+    }
+
+    /// The raw `depth` header field, which selects the pixel layout.
+    pub fn depth(&self) -> i16 {
+        self.header.depth
+    }
+
+    /// Bits per pixel as stored: 8 for palette images, 24 or 32 otherwise.
+    pub fn bpp(&self) -> usize {
+        self.bpp
+    }
+
+    pub fn palette_spec(&self) -> Option<PaletteSpec> {
+        self.palette_spec
+    }
+
+    pub fn palette(&self) -> Option<&[[u8; 3]]> {
+        if self.palette.is_empty() {
+            None
+        } else {
+            Some(&self.palette)
+        }
+    }
+
+    /// See [`CrxFile::color_key`].
+    pub fn color_key(&self) -> Option<u8> {
+        self.color_key
+    }
+
+    pub fn clips(&self) -> &[CrxImageClip] {
+        &self.clips
+    }
+}
+
+/// The serialized form of [`CrxMetadata`], whose stored bpp and palette layout follow from the
+/// depth.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MetadataFields {
+    signature: CrxSignature,
+    inner_x: i16,
+    inner_y: i16,
+    width: u16,
+    height: u16,
+    version: CrxVersion,
+    flag: u16,
+    depth: i16,
+    mode: u16,
+    palette: Option<Vec<[u8; 3]>>,
+    color_key: Option<u8>,
+    clips: Vec<CrxImageClip>,
+}
+
+#[cfg(feature = "serde")]
+impl From<CrxMetadata> for MetadataFields {
+    fn from(metadata: CrxMetadata) -> Self {
+        let header = metadata.header;
+        Self {
+            signature: header.signature,
+            inner_x: header.inner_x,
+            inner_y: header.inner_y,
+            width: header.width,
+            height: header.height,
+            version: header.version,
+            flag: header.flag,
+            depth: header.depth,
+            mode: header.mode,
+            palette: metadata.palette_spec.map(|_| metadata.palette),
+            color_key: metadata.color_key,
+            clips: metadata.clips,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<MetadataFields> for CrxMetadata {
+    type Error = CrxDecodeError;
+
+    fn try_from(fields: MetadataFields) -> Result<Self, Self::Error> {
+        let palette_spec = PaletteSpec::from_depth(fields.depth)?;
+        let palette = fields.palette.unwrap_or_default();
+        // a palette must match its depth, as the decoder would have read it.
+        if palette.len() != palette_spec.map_or(0, |spec| spec.colors) {
+            return Err(CrxDecodeError::InvalidDepth(fields.depth));
+        }
+        if !fields.version.has_clip_table() && !fields.clips.is_empty() {
+            return Err(CrxDecodeError::InvalidClipCount(fields.clips.len() as i32));
+        }
+        Ok(Self {
+            header: CrxHeader {
+                signature: fields.signature,
+                inner_x: fields.inner_x,
+                inner_y: fields.inner_y,
+                width: fields.width,
+                height: fields.height,
+                version: fields.version,
+                flag: fields.flag,
+                depth: fields.depth,
+                mode: fields.mode,
+            },
+            bpp: CrxFile::stored_bpp(fields.depth, palette_spec),
+            palette_spec,
+            palette,
+            color_key: fields.color_key,
+            clips: fields.clips,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CrxHeader {
+    pub signature: CrxSignature, // offset 0x00
+    pub inner_x: i16,            // offset 0x04
+    pub inner_y: i16,            // offset 0x06
+    pub width: u16,              // offset 0x08
+    pub height: u16,             // offset 0x0A
+    pub version: CrxVersion,     // offset 0x0C
+    pub flag: u16,               // offset 0x0E
+    pub depth: i16,              // offset 0x10
+    pub mode: u16,               // offset 0x12
+}
+
+impl CrxHeader {
+    fn read<R: Read>(signature: CrxSignature, mut reader: R) -> io::Result<Self> {
+        let inner_x = reader.read_i16::<LittleEndian>()?;
+        let inner_y = reader.read_i16::<LittleEndian>()?;
+        let width = reader.read_u16::<LittleEndian>()?;
+        let height = reader.read_u16::<LittleEndian>()?;
+        let version = reader.read_u16::<LittleEndian>()?;
+        let flag = reader.read_u16::<LittleEndian>()?;
+        let depth = reader.read_i16::<LittleEndian>()?;
+        let mode = reader.read_u16::<LittleEndian>()?;
+
+        // Verify that the version is supported (1, 2, 3)
+        let version = CrxVersion::try_from(version).map_err(|_| {
+            decode_error!(CrxDecodeError::VersionNotSupported {
+                version,
+                flag: Some(flag),
+                mode: Some(mode),
+            })
+        })?;
+
+        Ok(CrxHeader {
+            signature,
+            inner_x,
+            inner_y,
+            width,
+            height,
+            version,
+            flag,
+            depth,
+            mode,
+        })
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.signature.bytes())?;
+        writer.write_i16::<LittleEndian>(self.inner_x)?;
+        writer.write_i16::<LittleEndian>(self.inner_y)?;
+        writer.write_u16::<LittleEndian>(self.width)?;
+        writer.write_u16::<LittleEndian>(self.height)?;
+        writer.write_u16::<LittleEndian>(self.version.number())?;
+        writer.write_u16::<LittleEndian>(self.flag)?;
+        writer.write_i16::<LittleEndian>(self.depth)?;
+        writer.write_u16::<LittleEndian>(self.mode)?;
+        Ok(())
+    }
+}
+
+/// An entry of the clip table, its fields in file order. The names of the position and size
+/// follow the layout [`CrxImageClip::rect`] assumes, which no game files have confirmed yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CrxImageClip {
+    /// The first 32-bit word; what it holds is not known.
+    pub unknown_1: i32,
+    /// Left edge of the clip. Unconfirmed: assumed from the layout of the table.
+    pub x: i16,
+    /// Top edge of the clip. Unconfirmed: assumed from the layout of the table.
+    pub y: i16,
+    /// The second 32-bit word; what it holds is not known.
+    pub unknown_2: i32,
+    /// Width of the clip. Unconfirmed: assumed from the layout of the table.
+    pub width: i16,
+    /// Height of the clip. Unconfirmed: assumed from the layout of the table.
+    pub height: i16,
+}
+
+impl CrxImageClip {
+    pub(crate) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let unknown_1 = reader.read_i32::<LittleEndian>()?;
+        let x = reader.read_i16::<LittleEndian>()?;
+        let y = reader.read_i16::<LittleEndian>()?;
+        let unknown_2 = reader.read_i32::<LittleEndian>()?;
+        let width = reader.read_i16::<LittleEndian>()?;
+        let height = reader.read_i16::<LittleEndian>()?;
+
+        Ok(Self {
+            unknown_1,
+            x,
+            y,
+            unknown_2,
+            width,
+            height,
+        })
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_i32::<LittleEndian>(self.unknown_1)?;
+        writer.write_i16::<LittleEndian>(self.x)?;
+        writer.write_i16::<LittleEndian>(self.y)?;
+        writer.write_i32::<LittleEndian>(self.unknown_2)?;
+        writer.write_i16::<LittleEndian>(self.width)?;
+        writer.write_i16::<LittleEndian>(self.height)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::encode_layouts, CrxEncoder, CrxRowDecoder};
+
+    const VERSIONS: [CrxVersion; 3] = [CrxVersion::V1, CrxVersion::V2, CrxVersion::V3];
+
+    /// (colors, entry size) of the palette `depth` describes, `None` for truecolor.
+    fn layout(depth: i16) -> Option<(usize, usize)> {
+        PaletteSpec::from_depth(depth)
+            .unwrap()
+            .map(|spec| (spec.colors(), spec.entry_size()))
+    }
+
+    #[test]
+    fn truecolor_depths_have_no_palette() {
+        assert_eq!(layout(0), None);
+        assert_eq!(layout(1), None);
+        assert_eq!(
+            PaletteSpec::try_from(1),
+            Err(CrxDecodeError::InvalidDepth(1))
+        );
+    }
+
+    #[test]
+    fn small_palettes_have_depth_colors() {
+        assert_eq!(layout(2), Some((2, 3)));
+        assert_eq!(layout(0x10), Some((0x10, 3)));
+        assert_eq!(layout(0xFF), Some((0xFF, 3)));
+        assert_eq!(layout(0x100), Some((0x100, 3)));
+    }
+
+    #[test]
+    fn depth_0x101_is_a_full_rgb_palette() {
+        assert_eq!(layout(0x101), Some((0x100, 3)));
+    }
+
+    #[test]
+    fn depth_0x102_has_four_byte_entries() {
+        assert_eq!(layout(0x102), Some((0x100, 4)));
+    }
+
+    #[test]
+    fn larger_depths_are_full_rgb_palettes() {
+        assert_eq!(layout(0x103), Some((0x100, 3)));
+        assert_eq!(layout(0x1000), Some((0x100, 3)));
+        assert_eq!(layout(i16::MAX), Some((0x100, 3)));
+    }
+
+    #[test]
+    fn negative_depths_are_invalid() {
+        for depth in [-1, -0x102, i16::MIN] {
+            assert_eq!(
+                PaletteSpec::from_depth(depth),
+                Err(CrxDecodeError::InvalidDepth(depth))
+            );
+        }
+    }
+
+    #[test]
+    fn specs_keep_their_depth() {
+        for depth in [2, 0x10, 0x100, 0x101, 0x102, 0x103] {
+            let spec = PaletteSpec::try_from(depth).unwrap();
+            assert_eq!(spec.depth(), depth);
+            assert_eq!(i16::from(spec), depth);
+        }
+    }
+
+    #[test]
+    fn empty_images_decode_when_allowed() {
+        let allow_empty = DecodeOptions {
+            allow_empty: true,
+            ..Default::default()
+        };
+        for version in VERSIONS {
+            for (width, height) in [(0, 3), (3, 0), (0, 0)] {
+                for (bpp, file) in encode_layouts(version, width, height) {
+                    let crx = CrxFile::read_with_options(&file[..], &allow_empty).unwrap();
+                    assert_eq!((crx.width(), crx.height()), (width, height));
+                    assert!(crx.raw_buffer().is_empty(), "v{} {}-bit", version, bpp);
+
+                    let error = CrxFile::read(&file[..]).unwrap_err();
+                    assert!(matches!(
+                        error,
+                        CrxError::Decode(CrxDecodeError::InvalidDimensions(w, h))
+                            if (w, h) == (width, height)
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_images_round_trip() {
+        let allow_empty = DecodeOptions {
+            allow_empty: true,
+            keep_indexed: true,
+            ..Default::default()
+        };
+        for version in VERSIONS {
+            for (_, file) in encode_layouts(version, 0, 2) {
+                let crx = CrxFile::read_with_options(&file[..], &allow_empty).unwrap();
+                let mut written = Vec::new();
+                crx.write(&mut written).unwrap();
+                let again = CrxFile::read_with_options(&written[..], &allow_empty).unwrap();
+                assert_eq!(again, crx);
+            }
+        }
+    }
+
+    fn lenient() -> DecodeOptions {
+        DecodeOptions {
+            strictness: Strictness::Lenient,
+            ..Default::default()
+        }
+    }
+
+    /// The size of everything before the pixel stream of `file`.
+    fn preamble_size(file: &[u8]) -> usize {
+        let mut reader = file;
+        CrxFile::read_preamble(&mut reader, &DecodeOptions::default()).unwrap();
+        file.len() - reader.len()
+    }
+
+    #[test]
+    fn truncated_streams_warn_when_lenient_and_fail_when_strict() {
+        for version in VERSIONS {
+            for (bpp, file) in encode_layouts(version, 16, 12) {
+                let intact = CrxFile::read(&file[..]).unwrap();
+                let preamble = preamble_size(&file);
+                let truncated = &file[..preamble + (file.len() - preamble) / 2];
+                let what = format!("v{} {}-bit", version, bpp);
+
+                assert!(CrxFile::read(truncated).is_err(), "{}", what);
+                let (crx, warnings) = CrxFile::read_with_warnings(truncated, &lenient()).unwrap();
+                assert_eq!(warnings.len(), 1, "{}", what);
+                let row = warnings[0].row;
+                assert!(row < 12, "{}: {:?}", what, warnings);
+                let stride = crx.raw_buffer().len() / 12;
+                let (kept, zeroed) = crx.raw_buffer().split_at(row * stride);
+                assert_eq!(kept, &intact.raw_buffer()[..row * stride], "{}", what);
+                assert!(zeroed.iter().all(|&b| b == 0), "{}", what);
+            }
+        }
+    }
+
+    #[test]
+    fn over_long_streams_warn_when_lenient_and_decode_when_strict() {
+        for version in VERSIONS {
+            for (bpp, mut file) in encode_layouts(version, 16, 12) {
+                let intact = CrxFile::read(&file[..]).unwrap();
+                // the header claims a row less than the stream holds.
+                file[10..12].copy_from_slice(&11u16.to_le_bytes());
+                let what = format!("v{} {}-bit", version, bpp);
+
+                let strict = CrxFile::read(&file[..]).unwrap();
+                let mut rows = CrxRowDecoder::new(&file[..], &Default::default()).unwrap();
+                let mut streamed = Vec::new();
+                while let Some(row) = rows.next_row().unwrap() {
+                    streamed.extend_from_slice(row);
+                }
+                assert_eq!(streamed, strict.raw_buffer(), "{}", what);
+                let (crx, warnings) = CrxFile::read_with_warnings(&file[..], &lenient()).unwrap();
+                assert_eq!(
+                    warnings,
+                    [DecodeWarning {
+                        row: 11,
+                        message: CrxDecodeError::StreamTooLong.to_string(),
+                    }],
+                    "{}",
+                    what
+                );
+                assert_eq!(crx, strict, "{}", what);
+                let kept = crx.raw_buffer().len();
+                assert_eq!(crx.raw_buffer(), &intact.raw_buffer()[..kept], "{}", what);
+                assert_eq!(kept, intact.raw_buffer().len() / 12 * 11, "{}", what);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_width_rows_read_nothing() {
+        for filter in [RowFilter::Left, RowFilter::UpRight] {
+            let stream = [filter.number(), 1, 2, 3];
+            let mut reader = &stream[..];
+            CrxFile::unpack_row_2(&mut reader, &mut [], Some(&[]), 3).unwrap();
+            assert_eq!(reader.len(), stream.len());
+        }
+    }
+
+    fn clip(x: i16, y: i16, width: i16, height: i16) -> CrxImageClip {
+        CrxImageClip {
+            unknown_1: 7,
+            x,
+            y,
+            unknown_2: -1,
+            width,
+            height,
+        }
+    }
+
+    /// A 6x5 version 3 RGB image at (10, 20), each pixel holding its coordinates.
+    fn image_with_clips(clips: Vec<CrxImageClip>) -> CrxFile {
+        let rgb: Vec<u8> = (0..5u8)
+            .flat_map(|y| (0..6u8).flat_map(move |x| [x, y, 0xAA]))
+            .collect();
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V3)
+            .with_offset(10, 20)
+            .with_clips(clips)
+            .write_rgb(&mut file, 6, 5, &rgb)
+            .unwrap();
+        CrxFile::read(&file[..]).unwrap()
+    }
+
+    #[test]
+    fn crops_move_pixels_offsets_and_clips() {
+        let crx = image_with_clips(vec![
+            // inside the region, moved only.
+            clip(2, 2, 2, 1),
+            // across its top-left corner, cut.
+            clip(0, 0, 3, 3),
+            // across its bottom-right corner, cut.
+            clip(4, 3, 2, 2),
+            // outside of it, dropped.
+            clip(0, 4, 6, 1),
+        ]);
+        let expected = [clip(1, 1, 2, 1), clip(0, 0, 2, 2), clip(3, 2, 1, 1)];
+        for cropped in [
+            crx.sub_image(1, 1, 4, 3).unwrap(),
+            crx.clone().crop(1, 1, 4, 3).unwrap(),
+        ] {
+            assert_eq!((cropped.width(), cropped.height()), (4, 3));
+            assert_eq!((cropped.inner_x(), cropped.inner_y()), (11, 21));
+            assert_eq!(cropped.get_pixel(0, 0), [1, 1, 0xAA, 0xFF]);
+            assert_eq!(cropped.get_pixel(3, 2), [4, 3, 0xAA, 0xFF]);
+            assert_eq!(cropped.clips(), &expected[..]);
+        }
+    }
+
+    #[test]
+    fn cropped_clips_survive_a_round_trip() {
+        let crx = image_with_clips(vec![clip(1, 1, 4, 3), clip(5, 0, 1, 1)]);
+        let cropped = crx.crop(2, 0, 3, 5).unwrap();
+        assert_eq!(cropped.clips(), &[clip(0, 1, 3, 3)]);
+        let mut file = Vec::new();
+        cropped.write(&mut file).unwrap();
+        assert_eq!(CrxFile::read(&file[..]).unwrap(), cropped);
+    }
+
+    #[test]
+    fn crops_outside_the_image_fail() {
+        let crx = image_with_clips(Vec::new());
+        assert!(crx.sub_image(3, 0, 4, 1).is_none());
+        assert!(crx.crop(0, 4, 1, 2).is_none());
+    }
+
+    #[test]
+    fn unknown_versions_name_the_flag_and_mode_of_their_header() {
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V2)
+            .with_mode(7)
+            .write_rgb(&mut file, 2, 2, &[0; 12])
+            .unwrap();
+        // the version follows the offset and dimensions.
+        file[12..14].copy_from_slice(&4u16.to_le_bytes());
+        let Err(CrxError::Decode(error)) = CrxFile::read(&file[..]) else {
+            panic!("version 4 decoded");
+        };
+        assert_eq!(
+            error,
+            CrxDecodeError::VersionNotSupported {
+                version: 4,
+                flag: Some(FLAG_SIZED_STREAM),
+                mode: Some(7),
+            }
+        );
+        assert!(error.to_string().contains("mode `7`"), "{}", error);
+        assert_eq!(
+            CrxVersion::try_from(0),
+            Err(CrxDecodeError::VersionNotSupported {
+                version: 0,
+                flag: None,
+                mode: None,
+            })
+        );
+    }
+
+    /// `indices` looked up in `palette` one pixel at a time.
+    fn looked_up(indices: &[u8], palette: &[[u8; 3]]) -> Vec<u8> {
+        indices
+            .iter()
+            .flat_map(|&index| palette[index as usize])
+            .collect()
+    }
+
+    /// `colors` colors, none of them the magenta that reads as the color key.
+    fn test_palette(colors: usize) -> Vec<[u8; 3]> {
+        (0..colors)
+            .map(|i| [i as u8, (i * 7) as u8, (i * 3 % 200) as u8])
+            .collect()
+    }
+
+    #[test]
+    fn expand_palette_matches_lookups() {
+        for colors in [2, 3, 16, 255, 256] {
+            let palette = test_palette(colors);
+            let indices: Vec<u8> = (0..1000).map(|i| (i * 31 % colors) as u8).collect();
+            let mut data = indices.clone();
+            CrxFile::expand_palette(&mut data, &palette).unwrap();
+            assert_eq!(data, looked_up(&indices, &palette), "{} colors", colors);
+        }
+
+        let mut empty = Vec::new();
+        CrxFile::expand_palette(&mut empty, &test_palette(4)).unwrap();
+        assert!(empty.is_empty());
+
+        let mut outside = vec![0, 1, 4, 2];
+        let error = CrxFile::expand_palette(&mut outside, &test_palette(4)).unwrap_err();
+        assert_eq!(
+            error.get_ref().and_then(|e| e.downcast_ref()),
+            Some(&CrxDecodeError::BadPaletteIndex(4, 4))
+        );
+    }
+
+    #[test]
+    fn palettes_with_and_without_a_fourth_byte_expand_alike() {
+        let palette = test_palette(256);
+        let indices: Vec<u8> = (0..16 * 12).map(|i| (i * 97 % 256) as u8).collect();
+        let mut three = Vec::new();
+        CrxEncoder::new(CrxVersion::V2)
+            .write_indexed(&mut three, 16, 12, &palette, &indices)
+            .unwrap();
+
+        // depth 0x102 stores a fourth byte after each color, which decoding ignores.
+        let mut four = three[..20].to_vec();
+        four[16..18].copy_from_slice(&0x102i16.to_le_bytes());
+        for (i, color) in palette.iter().enumerate() {
+            four.extend_from_slice(color);
+            four.push(i as u8 ^ 0x5A);
+        }
+        four.extend_from_slice(&three[20 + 256 * 3..]);
+
+        for (what, file) in [("3-byte", &three), ("4-byte", &four)] {
+            let crx = CrxFile::read(&file[..]).unwrap();
+            assert_eq!(crx.palette(), Some(&palette[..]), "{}", what);
+            assert_eq!(crx.raw_buffer(), looked_up(&indices, &palette), "{}", what);
+            let indexed = CrxFile::read_indexed(&file[..]).unwrap();
+            assert_eq!(indexed.raw_buffer(), indices, "{}", what);
+        }
+    }
+
+    /// A stored `bpp`-bit pixel of an image of `mode` as RGB(A), one channel at a time.
+    fn pixel_to_rgb(pixel: &[u8], mode: u16, keep_alpha: bool) -> Vec<u8> {
+        match (pixel, PixelOrder::from_mode(mode)) {
+            (&[b, g, r], _) => vec![r, g, b],
+            (&[b, g, r, a], PixelOrder::Bgra) => vec![r, g, b, a],
+            (&[a, b, g, r], PixelOrder::Abgr) => vec![r, g, b, a],
+            (&[a, b, g, r], PixelOrder::AbgrInvertedAlpha) if keep_alpha => vec![r, g, b, a],
+            (&[a, b, g, r], PixelOrder::AbgrInvertedAlpha) => vec![r, g, b, 0xFF - a],
+            (pixel, _) => pixel.to_vec(),
+        }
+    }
+
+    #[test]
+    fn to_rgb_matches_per_pixel_conversion() {
+        let stored: Vec<u8> = (0..4 * 300).map(|i| (i * 53 % 256) as u8).collect();
+        // modes 0 and 3 both store inverted alpha.
+        for mode in [0, 1, 2, 3] {
+            for keep_alpha in [false, true] {
+                for bpp in [8, 24, 32] {
+                    let pixel_size = bpp / 8;
+                    let mut data = stored[..stored.len() / 4 * pixel_size].to_vec();
+                    CrxFile::to_rgb(&mut data, bpp, mode, keep_alpha);
+                    let expected: Vec<u8> = stored[..data.len()]
+                        .chunks_exact(pixel_size)
+                        .flat_map(|pixel| pixel_to_rgb(pixel, mode, keep_alpha))
+                        .collect();
+                    assert_eq!(
+                        data, expected,
+                        "mode {} {}-bit keep_alpha {}",
+                        mode, bpp, keep_alpha
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decoded_pixels_follow_the_order_of_the_mode() {
+        let rgba: Vec<u8> = (0..8 * 6 * 4).map(|i| (i * 29 % 256) as u8).collect();
+        for mode in [0, 1, 2, 3] {
+            let mut file = Vec::new();
+            CrxEncoder::new(CrxVersion::V2)
+                .with_mode(mode)
+                .write_rgba(&mut file, 8, 6, &rgba)
+                .unwrap();
+            let crx = CrxFile::read(&file[..]).unwrap();
+            assert_eq!(crx.raw_buffer(), rgba, "mode {}", mode);
+
+            // with the alpha kept as stored, inverted for the modes that invert it.
+            let options = DecodeOptions {
+                keep_alpha: true,
+                ..Default::default()
+            };
+            let kept = CrxFile::read_with_options(&file[..], &options).unwrap();
+            let inverted = PixelOrder::from_mode(mode) == PixelOrder::AbgrInvertedAlpha;
+            for (pixel, original) in kept.raw_buffer().chunks_exact(4).zip(rgba.chunks_exact(4)) {
+                assert_eq!(pixel[..3], original[..3], "mode {}", mode);
+                let alpha = if inverted {
+                    0xFF - original[3]
+                } else {
+                    original[3]
+                };
+                assert_eq!(pixel[3], alpha, "mode {}", mode);
+            }
+        }
     }
 }
@@ -0,0 +1,61 @@
+use crate::{CrxError, CrxFile};
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    ColorType, ImageDecoder, ImageError, ImageResult,
+};
+use std::io::{self, Read};
+
+/// An [`ImageDecoder`] for CRX files, e.g. for [`image::DynamicImage::from_decoder`].
+///
+/// The whole image is decoded up front, so that [`CrxDecoder::crx`] can give the decoded file.
+/// To decode a row at a time instead, e.g. to stream rows into an encoder with little memory,
+/// use [`CrxRowDecoder`](crate::CrxRowDecoder). 8-bit images are expanded to RGB.
+pub struct CrxDecoder<R> {
+    crx: CrxFile,
+    _reader: std::marker::PhantomData<R>,
+}
+
+impl<R: Read> CrxDecoder<R> {
+    pub fn new(reader: R) -> ImageResult<Self> {
+        let crx = CrxFile::read(reader).map_err(|e| match e {
+            CrxError::Io(e) if e.kind() != io::ErrorKind::UnexpectedEof => ImageError::IoError(e),
+            e => ImageError::Decoding(DecodingError::new(
+                ImageFormatHint::Name("CRX".to_string()),
+                e,
+            )),
+        })?;
+        Ok(Self {
+            crx,
+            _reader: std::marker::PhantomData,
+        })
+    }
+
+    /// The decoded file, e.g. for its inner offsets and clips.
+    pub fn crx(&self) -> &CrxFile {
+        &self.crx
+    }
+}
+
+impl<R: Read> ImageDecoder for CrxDecoder<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.crx.width() as u32, self.crx.height() as u32)
+    }
+
+    fn color_type(&self) -> ColorType {
+        if self.crx.bpp() == 32 {
+            ColorType::Rgba8
+        } else {
+            ColorType::Rgb8
+        }
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+        buf.copy_from_slice(self.crx.raw_buffer());
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
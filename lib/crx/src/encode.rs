@@ -0,0 +1,829 @@
+use crate::crx::CrxHeader;
+use crate::{CrxFile, CrxImageClip, CrxSignature, CrxVersion, PaletteSpec, FLAG_SIZED_STREAM};
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Distance limit of a back-reference in the version 1 stream, the size of its window.
+const WINDOW_SIZE: usize = 0x10000;
+/// Longest back-reference the version 1 stream can hold.
+const MAX_MATCH: usize = 0xFFFF + 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CrxEncodeError {
+    BufferSizeMismatch(usize, usize),
+    PaletteTooLarge(usize),
+    BadPaletteIndex(usize, usize),
+    ClipsNotSupported(CrxVersion),
+    InvalidBPP(usize),
+    UnsupportedConversion(usize, usize),
+    InvalidCompressionLevel(u32),
+    ClipCountMismatch(usize, usize),
+}
+
+impl std::fmt::Display for CrxEncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrxEncodeError::BufferSizeMismatch(expected, actual) => write!(
+                f,
+                "pixel buffer holds `{}` bytes but the image needs `{}`",
+                actual, expected
+            ),
+            CrxEncodeError::PaletteTooLarge(size) => {
+                write!(
+                    f,
+                    "palette has `{}` colors, at most 256 are supported",
+                    size
+                )
+            }
+            CrxEncodeError::BadPaletteIndex(size, index) => write!(
+                f,
+                "bad palette index: palette size is `{}` but trying to access index `{}`",
+                size, index
+            ),
+            CrxEncodeError::ClipsNotSupported(version) => {
+                write!(f, "image version `{}` has no clip table", version)
+            }
+            CrxEncodeError::InvalidBPP(bpp) => write!(f, "invalid bpp `{}`", bpp),
+            CrxEncodeError::UnsupportedConversion(from, to) => {
+                write!(f, "cannot store `{}`-bit pixels as `{}`-bit", from, to)
+            }
+            CrxEncodeError::InvalidCompressionLevel(level) => {
+                write!(f, "invalid compression level `{}`, expected 0 to 9", level)
+            }
+            CrxEncodeError::ClipCountMismatch(stored, given) => write!(
+                f,
+                "clip table holds `{}` entries, cannot rewrite it in place with `{}`",
+                stored, given
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CrxEncodeError {}
+
+macro_rules! encode_error {
+    ($e:expr) => {{
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, $e)
+    }};
+}
+
+/// Pixels handed to [`CrxEncoder`], in the layouts [`CrxFile`] decodes to.
+enum Pixels<'a> {
+    /// RGB or RGBA bytes.
+    Truecolor { bpp: usize, data: &'a [u8] },
+    /// Palette indices.
+    Indexed {
+        palette: &'a [[u8; 3]],
+        spec: PaletteSpec,
+        data: &'a [u8],
+    },
+}
+
+/// Settings of a [`CrxEncoder`] shared by every image it writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub version: CrxVersion,
+    /// Bits per pixel to store: 24 or 32 convert truecolor pixels and expand palettes, 8 keeps
+    /// palette images as they are. `None` stores pixels as they are given.
+    pub bpp: Option<usize>,
+    /// The `mode` header field, see [`CrxFile::mode`].
+    pub mode: u16,
+    /// zlib level of versions 2 and 3, 0 to 9; version 1 ignores it.
+    pub compression_level: u32,
+    /// The clip table, only stored by version 3.
+    pub clips: Vec<CrxImageClip>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            version: CrxVersion::V2,
+            bpp: None,
+            mode: 0,
+            compression_level: 9,
+            clips: Vec::new(),
+        }
+    }
+}
+
+/// Writes images in the CRX format.
+///
+/// Versions 2 and 3 store rows zlib-compressed, each behind the predictor that leaves the
+/// smallest differences; version 1 uses the LZ scheme of the original format. Unless set
+/// otherwise, 32-bit images are written in the default alpha layout (mode 0) and the pixel
+/// stream is size-prefixed.
+///
+/// The decoder turns the palette color `(0xFF, 0x00, 0xFF)` into white, so palettes holding it
+/// do not survive a round trip.
+#[derive(Debug, Clone)]
+pub struct CrxEncoder {
+    options: EncodeOptions,
+    signature: CrxSignature,
+    inner_x: i16,
+    inner_y: i16,
+    flag: u16,
+}
+
+impl From<EncodeOptions> for CrxEncoder {
+    fn from(options: EncodeOptions) -> Self {
+        Self {
+            options,
+            signature: CrxSignature::STANDARD,
+            inner_x: 0,
+            inner_y: 0,
+            flag: FLAG_SIZED_STREAM,
+        }
+    }
+}
+
+impl CrxEncoder {
+    pub fn new(version: CrxVersion) -> Self {
+        Self::from(EncodeOptions {
+            version,
+            ..Default::default()
+        })
+    }
+
+    pub fn options(&self) -> &EncodeOptions {
+        &self.options
+    }
+
+    /// Set the signature the file starts with, e.g. to keep the variant of the original.
+    pub fn with_signature(mut self, signature: CrxSignature) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    /// Set the inner offsets stored in the header.
+    pub fn with_offset(mut self, inner_x: i16, inner_y: i16) -> Self {
+        self.inner_x = inner_x;
+        self.inner_y = inner_y;
+        self
+    }
+
+    /// Set the `flag` header field; the pixel stream is size-prefixed iff bit `0x10` is set.
+    pub fn with_flag(mut self, flag: u16) -> Self {
+        self.flag = flag;
+        self
+    }
+
+    /// Set the `mode` header field, see [`CrxFile::mode`].
+    pub fn with_mode(mut self, mode: u16) -> Self {
+        self.options.mode = mode;
+        self
+    }
+
+    /// Set the clip table, only stored by version 3.
+    pub fn with_clips(mut self, clips: Vec<CrxImageClip>) -> Self {
+        self.options.clips = clips;
+        self
+    }
+
+    /// Write a 24-bit image from RGB bytes.
+    pub fn write_rgb<W: Write>(
+        &self,
+        writer: W,
+        width: u16,
+        height: u16,
+        rgb: &[u8],
+    ) -> io::Result<()> {
+        self.write(
+            writer,
+            width,
+            height,
+            Pixels::Truecolor { bpp: 24, data: rgb },
+        )
+    }
+
+    /// Write a 32-bit image from RGBA bytes.
+    pub fn write_rgba<W: Write>(
+        &self,
+        writer: W,
+        width: u16,
+        height: u16,
+        rgba: &[u8],
+    ) -> io::Result<()> {
+        self.write(
+            writer,
+            width,
+            height,
+            Pixels::Truecolor {
+                bpp: 32,
+                data: rgba,
+            },
+        )
+    }
+
+    /// Write an 8-bit image from palette indices.
+    pub fn write_indexed<W: Write>(
+        &self,
+        writer: W,
+        width: u16,
+        height: u16,
+        palette: &[[u8; 3]],
+        indices: &[u8],
+    ) -> io::Result<()> {
+        if palette.len() > 0x100 {
+            return Err(encode_error!(CrxEncodeError::PaletteTooLarge(
+                palette.len()
+            )));
+        }
+        // depths 0 and 1 mean truecolor, so the smallest palette has two entries.
+        let spec = PaletteSpec::from_depth(palette.len().max(2) as i16)
+            .ok()
+            .flatten()
+            .expect("palette sizes 2..=256 are valid depths");
+        self.write(
+            writer,
+            width,
+            height,
+            Pixels::Indexed {
+                palette,
+                spec,
+                data: indices,
+            },
+        )
+    }
+
+    fn write<W: Write>(
+        &self,
+        mut writer: W,
+        width: u16,
+        height: u16,
+        pixels: Pixels,
+    ) -> io::Result<()> {
+        let options = &self.options;
+        if !options.clips.is_empty() && !options.version.has_clip_table() {
+            return Err(encode_error!(CrxEncodeError::ClipsNotSupported(
+                options.version
+            )));
+        }
+        if options.compression_level > 9 {
+            return Err(encode_error!(CrxEncodeError::InvalidCompressionLevel(
+                options.compression_level
+            )));
+        }
+        let (bpp, data) = match &pixels {
+            Pixels::Truecolor { bpp, data } => (*bpp, *data),
+            Pixels::Indexed { data, .. } => (8, *data),
+        };
+        if bpp != 8 && bpp != 24 && bpp != 32 {
+            return Err(encode_error!(CrxEncodeError::InvalidBPP(bpp)));
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("encode", width, height, bpp).entered();
+        let expected = (bpp / 8) * width as usize * height as usize;
+        if data.len() != expected {
+            return Err(encode_error!(CrxEncodeError::BufferSizeMismatch(
+                expected,
+                data.len()
+            )));
+        }
+        if let Pixels::Indexed { palette, data, .. } = &pixels {
+            if let Some(&index) = data.iter().find(|&&i| i as usize >= palette.len()) {
+                return Err(encode_error!(CrxEncodeError::BadPaletteIndex(
+                    palette.len(),
+                    index as usize
+                )));
+            }
+        }
+
+        let converted;
+        let pixels = match (pixels, options.bpp) {
+            (pixels, None) => pixels,
+            (pixels @ Pixels::Indexed { .. }, Some(8)) => pixels,
+            (Pixels::Truecolor { bpp, data }, Some(target)) if bpp == target => {
+                Pixels::Truecolor { bpp, data }
+            }
+            (pixels, Some(target @ (24 | 32))) => {
+                converted = Self::convert(&pixels, target);
+                Pixels::Truecolor {
+                    bpp: target,
+                    data: &converted,
+                }
+            }
+            (_, Some(target)) => {
+                return Err(encode_error!(CrxEncodeError::UnsupportedConversion(
+                    bpp, target
+                )))
+            }
+        };
+        let (bpp, depth) = match &pixels {
+            Pixels::Truecolor { bpp: 24, .. } => (24, 0),
+            Pixels::Truecolor { .. } => (32, 1),
+            Pixels::Indexed { spec, .. } => (8, spec.depth()),
+        };
+
+        CrxHeader {
+            signature: self.signature,
+            inner_x: self.inner_x,
+            inner_y: self.inner_y,
+            width,
+            height,
+            version: options.version,
+            flag: self.flag,
+            depth,
+            mode: options.mode,
+        }
+        .write(writer.by_ref())?;
+
+        let stored = match pixels {
+            Pixels::Indexed {
+                palette,
+                spec,
+                data,
+            } => {
+                for i in 0..spec.colors() {
+                    writer.write_all(&palette.get(i).copied().unwrap_or_default())?;
+                    if spec.entry_size() == 4 {
+                        writer.write_u8(0)?;
+                    }
+                }
+                data.to_vec()
+            }
+            Pixels::Truecolor { bpp, data } => Self::to_stored(data, bpp, options.mode),
+        };
+
+        if options.version.has_clip_table() {
+            writer.write_i32::<LittleEndian>(options.clips.len() as i32)?;
+            for clip in &options.clips {
+                clip.write(writer.by_ref())?;
+            }
+        }
+
+        let stride = (bpp / 8) * width as usize;
+        let stream = if options.version.uses_zlib() {
+            Self::pack_2(&stored, stride, bpp / 8, options.compression_level)?
+        } else {
+            Self::pack_1(&stored)
+        };
+        if self.flag & FLAG_SIZED_STREAM != 0 {
+            writer.write_i32::<LittleEndian>(stream.len() as i32)?;
+        }
+        writer.write_all(&stream)?;
+        Ok(())
+    }
+
+    /// Truecolor or palette `pixels` as `target`-bit RGB(A), with opaque alpha where missing.
+    fn convert(pixels: &Pixels, target: usize) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut push = |rgb: &[u8], alpha: u8| {
+            output.extend_from_slice(&rgb[..3]);
+            if target == 32 {
+                output.push(alpha);
+            }
+        };
+        match pixels {
+            Pixels::Truecolor { bpp, data } => {
+                for pixel in data.chunks_exact(bpp / 8) {
+                    push(pixel, pixel.get(3).copied().unwrap_or(0xFF));
+                }
+            }
+            Pixels::Indexed { palette, data, .. } => {
+                for &index in data.iter() {
+                    push(&palette[index as usize], 0xFF);
+                }
+            }
+        }
+        output
+    }
+
+    /// RGB(A) to the stored layout: BGR, BGRA in mode 1, otherwise ABGR with the alpha inverted
+    /// unless in mode 2.
+    fn to_stored(data: &[u8], bpp: usize, mode: u16) -> Vec<u8> {
+        let mut stored = Vec::with_capacity(data.len());
+        if bpp == 32 {
+            let alpha_flip = if mode == 2 { 0 } else { 0xFF };
+            for pixel in data.chunks_exact(4) {
+                if mode == 1 {
+                    stored.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                } else {
+                    stored.extend_from_slice(&[
+                        pixel[3] ^ alpha_flip,
+                        pixel[2],
+                        pixel[1],
+                        pixel[0],
+                    ]);
+                }
+            }
+        } else {
+            for pixel in data.chunks_exact(3) {
+                stored.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+        }
+        stored
+    }
+
+    /// The inverse of `CrxFile::unpack_1`: flag bytes announce eight literals or back-references
+    /// each, matches are found through the last position of every 4-byte prefix.
+    fn pack_1(data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len() + data.len() / 8 + 1);
+        let mut head = vec![usize::MAX; 0x10000];
+        let hash = |pos: usize| {
+            let key = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+            (key.wrapping_mul(0x9E37_79B1) >> 16) as usize
+        };
+
+        let mut flag_pos = 0;
+        let mut token = 8;
+        let mut pos = 0;
+        while pos < data.len() {
+            if token == 8 {
+                flag_pos = output.len();
+                output.push(0);
+                token = 0;
+            }
+
+            let mut length = 0;
+            let mut distance = 0;
+            if pos + 4 <= data.len() {
+                let candidate = head[hash(pos)];
+                head[hash(pos)] = pos;
+                if candidate != usize::MAX && pos - candidate < WINDOW_SIZE {
+                    let limit = (data.len() - pos).min(MAX_MATCH);
+                    length = (0..limit)
+                        .take_while(|&i| data[candidate + i] == data[pos + i])
+                        .count();
+                    distance = pos - candidate;
+                }
+            }
+
+            if length >= 4 {
+                Self::push_match(&mut output, distance, length);
+                for p in pos + 1..(pos + length).min(data.len().saturating_sub(3)) {
+                    head[hash(p)] = p;
+                }
+                pos += length;
+            } else {
+                output[flag_pos] |= 1 << token;
+                output.push(data[pos]);
+                pos += 1;
+            }
+            token += 1;
+        }
+        output
+    }
+
+    /// Emit the shortest control sequence for a back-reference of `length >= 4`.
+    fn push_match(output: &mut Vec<u8>, distance: usize, length: usize) {
+        // the one-byte form below 0xC0 only holds lengths of 2 and 3, too short to pay off.
+        if length <= 19 && distance < 0x400 {
+            output.push(0xC0 | ((length - 4) << 2) as u8 | (distance >> 8) as u8);
+            output.push(distance as u8);
+        } else if length <= 130 {
+            output.push((length - 4) as u8);
+            output.write_u16::<LittleEndian>(distance as u16).unwrap();
+        } else {
+            output.push(0x7F);
+            output
+                .write_u16::<LittleEndian>((length - 2) as u16)
+                .unwrap();
+            output.write_u16::<LittleEndian>(distance as u16).unwrap();
+        }
+    }
+
+    /// The inverse of `CrxFile::unpack_2`. Truecolor rows get the predictor (modes 0 to 3) with
+    /// the smallest sum of absolute differences, the same heuristic PNG encoders use.
+    fn pack_2(data: &[u8], stride: usize, pixel_size: usize, level: u32) -> io::Result<Vec<u8>> {
+        use flate2::{write::ZlibEncoder, Compression};
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+        // rows of zero-width images are stored as nothing, not even their predictor, which is
+        // what the decoder expects of them.
+        if pixel_size == 1 || stride == 0 {
+            encoder.write_all(data)?;
+            return encoder.finish();
+        }
+
+        let mut candidates: Vec<Vec<u8>> = (0..4).map(|_| Vec::with_capacity(stride)).collect();
+        for (y, row) in data.chunks_exact(stride).enumerate() {
+            let prev = y.checked_sub(1).map(|p| &data[p * stride..y * stride]);
+            for (mode, residual) in candidates.iter_mut().enumerate() {
+                residual.clear();
+                match (mode, prev) {
+                    (0, _) => {
+                        residual.extend_from_slice(&row[..pixel_size]);
+                        for xb in pixel_size..stride {
+                            residual.push(row[xb].wrapping_sub(row[xb - pixel_size]));
+                        }
+                    }
+                    (1, Some(prev)) => {
+                        for xb in 0..stride {
+                            residual.push(row[xb].wrapping_sub(prev[xb]));
+                        }
+                    }
+                    (2, Some(prev)) => {
+                        residual.extend_from_slice(&row[..pixel_size]);
+                        for xb in pixel_size..stride {
+                            residual.push(row[xb].wrapping_sub(prev[xb - pixel_size]));
+                        }
+                    }
+                    (3, Some(prev)) => {
+                        for xb in 0..stride - pixel_size {
+                            residual.push(row[xb].wrapping_sub(prev[xb + pixel_size]));
+                        }
+                        residual.extend_from_slice(&row[stride - pixel_size..]);
+                    }
+                    // the first row has nothing to refer to.
+                    _ => {}
+                }
+            }
+            let (mode, residual) = candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, residual)| residual.len() == stride)
+                .min_by_key(|(_, residual)| {
+                    residual
+                        .iter()
+                        .map(|&b| (b as i8).unsigned_abs() as usize)
+                        .sum::<usize>()
+                })
+                .expect("mode 0 applies to every row");
+            encoder.write_u8(mode as u8)?;
+            encoder.write_all(residual)?;
+        }
+        encoder.finish()
+    }
+}
+
+impl CrxFile {
+    /// Encode the image back to CRX, keeping its version, inner offsets, flag and mode, palette
+    /// layout and clips. 8-bit images keep their palette only if they were read with
+    /// [`CrxFile::read_indexed`].
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        let encoder = CrxEncoder::new(self.version())
+            .with_signature(self.signature())
+            .with_offset(self.inner_x(), self.inner_y())
+            .with_flag(self.flag())
+            .with_mode(self.mode())
+            .with_clips(self.clips().to_vec());
+        let (width, height) = (self.width(), self.height());
+        let pixels = match (self.bpp(), self.palette_spec()) {
+            (8, Some(spec)) => Pixels::Indexed {
+                palette: self.palette().unwrap_or_default(),
+                spec,
+                data: self.raw_buffer(),
+            },
+            (bpp, _) => Pixels::Truecolor {
+                bpp,
+                data: self.raw_buffer(),
+            },
+        };
+        encoder.write(writer, width, height, pixels)
+    }
+
+    /// Rewrite the inner offset and clip table of the encoded CRX `file` in place, leaving the
+    /// palette and the compressed pixels as they are. The clip table has a fixed size in the
+    /// file, so `clips` must hold as many entries as it already does.
+    pub fn patch_header<F: Read + Write + Seek>(
+        mut file: F,
+        inner_offset: Option<(i16, i16)>,
+        clips: Option<&[CrxImageClip]>,
+    ) -> io::Result<()> {
+        file.seek(SeekFrom::Start(0))?;
+        let metadata = CrxFile::read_metadata(&mut file)?;
+        if let Some(clips) = clips {
+            if !metadata.version().has_clip_table() {
+                return Err(encode_error!(CrxEncodeError::ClipsNotSupported(
+                    metadata.version()
+                )));
+            }
+            if clips.len() != metadata.clips().len() {
+                return Err(encode_error!(CrxEncodeError::ClipCountMismatch(
+                    metadata.clips().len(),
+                    clips.len()
+                )));
+            }
+        }
+
+        if let Some((inner_x, inner_y)) = inner_offset {
+            file.seek(SeekFrom::Start(0x04))?;
+            file.write_i16::<LittleEndian>(inner_x)?;
+            file.write_i16::<LittleEndian>(inner_y)?;
+        }
+        if let Some(clips) = clips {
+            // the table follows the header, the palette and its own entry count.
+            let palette_size = metadata
+                .palette_spec()
+                .map_or(0, |spec| spec.colors() * spec.entry_size());
+            file.seek(SeekFrom::Start(0x14 + palette_size as u64 + 4))?;
+            for clip in clips {
+                clip.write(&mut file)?;
+            }
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const VERSIONS: [CrxVersion; 3] = [CrxVersion::V1, CrxVersion::V2, CrxVersion::V3];
+    const WIDTH: u16 = 7;
+    const HEIGHT: u16 = 5;
+
+    /// Gradients with noise and a flat band, so that rows take different predictors and the
+    /// version 1 stream has back-references.
+    fn pixels(channels: usize) -> Vec<u8> {
+        let mut seed = 0x2545_f491u32;
+        let mut data = Vec::new();
+        for y in 0..HEIGHT as u32 {
+            for x in 0..WIDTH as u32 {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let pixel = if y == 2 {
+                    [0x40, 0x50, 0x60, 0x70]
+                } else {
+                    [
+                        (x * 30 + y) as u8,
+                        (seed >> 24) as u8,
+                        (y * 50) as u8,
+                        (x * y) as u8,
+                    ]
+                };
+                data.extend_from_slice(&pixel[..channels]);
+            }
+        }
+        data
+    }
+
+    fn palette() -> Vec<[u8; 3]> {
+        (0..5).map(|i| [i * 40, 0xFF - i * 40, 0x33]).collect()
+    }
+
+    fn indices() -> Vec<u8> {
+        (0..WIDTH as usize * HEIGHT as usize)
+            .map(|i| (i % 5) as u8)
+            .collect()
+    }
+
+    fn decode_indexed(file: &[u8]) -> CrxFile {
+        CrxFile::read_indexed(file).unwrap()
+    }
+
+    #[test]
+    fn truecolor_round_trips() {
+        for version in VERSIONS {
+            for flag in [FLAG_SIZED_STREAM, 0] {
+                let encoder = CrxEncoder::new(version).with_flag(flag);
+                let (rgb, rgba) = (pixels(3), pixels(4));
+
+                let mut file = Vec::new();
+                encoder.write_rgb(&mut file, WIDTH, HEIGHT, &rgb).unwrap();
+                let crx = CrxFile::read(&file[..]).unwrap();
+                assert_eq!((crx.version(), crx.flag(), crx.bpp()), (version, flag, 24));
+                assert_eq!((crx.width(), crx.height()), (WIDTH, HEIGHT));
+                assert_eq!(crx.raw_buffer(), &rgb[..], "v{} flag {:#x}", version, flag);
+
+                let mut file = Vec::new();
+                encoder.write_rgba(&mut file, WIDTH, HEIGHT, &rgba).unwrap();
+                let crx = CrxFile::read(&file[..]).unwrap();
+                assert_eq!(crx.bpp(), 32);
+                assert_eq!(crx.raw_buffer(), &rgba[..], "v{} flag {:#x}", version, flag);
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_round_trips() {
+        for version in VERSIONS {
+            for flag in [FLAG_SIZED_STREAM, 0] {
+                let mut file = Vec::new();
+                CrxEncoder::new(version)
+                    .with_flag(flag)
+                    .write_indexed(&mut file, WIDTH, HEIGHT, &palette(), &indices())
+                    .unwrap();
+                let crx = decode_indexed(&file);
+                assert_eq!((crx.version(), crx.bpp()), (version, 8));
+                assert_eq!(crx.palette(), Some(&palette()[..]));
+                assert_eq!(
+                    crx.raw_buffer(),
+                    &indices()[..],
+                    "v{} flag {:#x}",
+                    version,
+                    flag
+                );
+
+                // expanded by the decoder, to the colors the indices stand for.
+                let expanded: Vec<u8> = indices()
+                    .iter()
+                    .flat_map(|&i| palette()[i as usize])
+                    .collect();
+                assert_eq!(
+                    CrxFile::read(&file[..]).unwrap().raw_buffer(),
+                    &expanded[..]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_layouts_round_trip() {
+        let rgba = pixels(4);
+        for mode in [0, 1, 2, 3] {
+            let mut file = Vec::new();
+            CrxEncoder::new(CrxVersion::V2)
+                .with_mode(mode)
+                .write_rgba(&mut file, WIDTH, HEIGHT, &rgba)
+                .unwrap();
+            let crx = CrxFile::read(&file[..]).unwrap();
+            assert_eq!(crx.mode(), mode);
+            assert_eq!(crx.raw_buffer(), &rgba[..], "mode {}", mode);
+        }
+    }
+
+    #[test]
+    fn files_round_trip_through_write() {
+        let clips = vec![CrxImageClip {
+            unknown_1: 1,
+            x: 2,
+            y: 3,
+            unknown_2: 4,
+            width: 5,
+            height: 6,
+        }];
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V3)
+            .with_offset(-12, 34)
+            .with_clips(clips.clone())
+            .write_indexed(&mut file, WIDTH, HEIGHT, &palette(), &indices())
+            .unwrap();
+        let crx = decode_indexed(&file);
+        assert_eq!(crx.clips(), &clips[..]);
+        assert_eq!((crx.inner_x(), crx.inner_y()), (-12, 34));
+
+        let mut written = Vec::new();
+        crx.write(&mut written).unwrap();
+        assert_eq!(decode_indexed(&written), crx);
+    }
+
+    #[test]
+    fn mismatched_input_is_refused() {
+        let encoder = CrxEncoder::new(CrxVersion::V2);
+        let error = encoder
+            .write_rgb(Vec::new(), WIDTH, HEIGHT, &[0; 3])
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let error = encoder
+            .write_indexed(Vec::new(), 1, 1, &palette(), &[5])
+            .unwrap_err();
+        assert!(matches!(
+            crate::CrxError::from(error),
+            crate::CrxError::Encode(CrxEncodeError::BadPaletteIndex(5, 5))
+        ));
+
+        let error = CrxEncoder::new(CrxVersion::V2)
+            .with_clips(vec![CrxImageClip {
+                unknown_1: 0,
+                x: 0,
+                y: 0,
+                unknown_2: 0,
+                width: 1,
+                height: 1,
+            }])
+            .write_rgb(Vec::new(), 1, 1, &[0; 3])
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn patch_header_rewrites_offset_and_clips_in_place() {
+        let clip = |n: i16| CrxImageClip {
+            unknown_1: n as i32,
+            x: n,
+            y: n + 1,
+            unknown_2: -(n as i32),
+            width: n + 2,
+            height: n + 3,
+        };
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V3)
+            .with_clips(vec![clip(1), clip(2)])
+            .write_indexed(&mut file, WIDTH, HEIGHT, &palette(), &indices())
+            .unwrap();
+        let original = decode_indexed(&file);
+
+        let mut patched = Cursor::new(file.clone());
+        CrxFile::patch_header(&mut patched, Some((5, -6)), Some(&[clip(7), clip(8)])).unwrap();
+        let patched = patched.into_inner();
+        assert_eq!(patched.len(), file.len());
+        let crx = decode_indexed(&patched);
+        assert_eq!((crx.inner_x(), crx.inner_y()), (5, -6));
+        assert_eq!(crx.clips(), &[clip(7), clip(8)]);
+        assert_eq!(crx.raw_buffer(), original.raw_buffer());
+        assert_eq!(crx.palette(), original.palette());
+
+        // the table cannot grow in place.
+        let error =
+            CrxFile::patch_header(Cursor::new(file.clone()), None, Some(&[clip(1)])).unwrap_err();
+        assert!(matches!(
+            crate::CrxError::from(error),
+            crate::CrxError::Encode(CrxEncodeError::ClipCountMismatch(2, 1))
+        ));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{CrxDecodeError, CrxEncodeError};
+use std::{fmt, io};
+
+/// Any error of this crate, so that callers can match on what went wrong.
+///
+/// Converts from and into [`io::Error`]: format errors wrapped in an `io::Error` come back as
+/// their own variant, and go into one of kind [`io::ErrorKind::InvalidData`] (decoding) or
+/// [`io::ErrorKind::InvalidInput`] (encoding).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CrxError {
+    /// The underlying reader or writer failed, or the data ended early.
+    Io(io::Error),
+    Decode(CrxDecodeError),
+    Encode(CrxEncodeError),
+}
+
+impl fmt::Display for CrxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrxError::Io(e) => e.fmt(f),
+            CrxError::Decode(e) => e.fmt(f),
+            CrxError::Encode(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CrxError {
+    /// The source of the wrapped error, which the message already is.
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrxError::Io(e) => e.source(),
+            CrxError::Decode(_) | CrxError::Encode(_) => None,
+        }
+    }
+}
+
+impl From<CrxDecodeError> for CrxError {
+    fn from(error: CrxDecodeError) -> Self {
+        CrxError::Decode(error)
+    }
+}
+
+impl From<CrxEncodeError> for CrxError {
+    fn from(error: CrxEncodeError) -> Self {
+        CrxError::Encode(error)
+    }
+}
+
+impl From<io::Error> for CrxError {
+    fn from(error: io::Error) -> Self {
+        let inner = error.get_ref();
+        if let Some(e) = inner.and_then(|e| e.downcast_ref::<CrxDecodeError>()) {
+            CrxError::Decode(*e)
+        } else if let Some(e) = inner.and_then(|e| e.downcast_ref::<CrxEncodeError>()) {
+            CrxError::Encode(*e)
+        } else {
+            CrxError::Io(error)
+        }
+    }
+}
+
+impl From<CrxError> for io::Error {
+    fn from(error: CrxError) -> Self {
+        match error {
+            CrxError::Io(e) => e,
+            CrxError::Decode(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            CrxError::Encode(e) => io::Error::new(io::ErrorKind::InvalidInput, e),
+        }
+    }
+}
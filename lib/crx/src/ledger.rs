@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "fs")]
+use std::{
+    fs,
+    io::{BufReader, BufWriter},
+};
+
+/// First line of a ledger file.
+const LEDGER_HEADER: &str = "# crx-ledger 1";
+
+/// What an earlier conversion of a source was made from and where it went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry {
+    /// Content hash of the source, in any form the frontend picks, e.g. hex SHA-256.
+    pub source_hash: String,
+    /// Everything else the output depends on, e.g. format and color options; sources converted
+    /// with other settings are not current.
+    pub settings: String,
+    pub output: PathBuf,
+}
+
+/// Record of earlier conversions, keyed by source path, so that frontends skip the sources
+/// whose content and settings have not changed since.
+///
+/// Stored as text: a header line, then one tab-separated `source, hash, settings, output` line
+/// per source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversionLedger {
+    entries: BTreeMap<PathBuf, LedgerEntry>,
+}
+
+impl ConversionLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the ledger at `path`; a missing file gives an empty ledger.
+    #[cfg(feature = "fs")]
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::File::open(path) {
+            Ok(file) => Self::read(BufReader::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the ledger to `path`, replacing it only once it is completely written.
+    #[cfg(feature = "fs")]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut writer = BufWriter::new(fs::File::create(&temp)?);
+        self.write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&temp, path)
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let invalid = |line: usize, message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ledger line {}: {}", line, message),
+            )
+        };
+        let mut lines = reader.lines();
+        match lines.next().transpose()? {
+            Some(header) if header == LEDGER_HEADER => {}
+            None => return Ok(Self::new()),
+            Some(_) => return Err(invalid(1, "not a conversion ledger")),
+        }
+        let mut entries = BTreeMap::new();
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<String> = line.split('\t').map(unescape).collect();
+            let [source, source_hash, settings, output] = <[String; 4]>::try_from(fields)
+                .map_err(|_| invalid(index + 2, "expected 4 fields"))?;
+            entries.insert(
+                PathBuf::from(source),
+                LedgerEntry {
+                    source_hash,
+                    settings,
+                    output: PathBuf::from(output),
+                },
+            );
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", LEDGER_HEADER)?;
+        for (source, entry) in &self.entries {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                escape(&source.to_string_lossy()),
+                escape(&entry.source_hash),
+                escape(&entry.settings),
+                escape(&entry.output.to_string_lossy())
+            )?;
+        }
+        writer.flush()
+    }
+
+    pub fn get(&self, source: &Path) -> Option<&LedgerEntry> {
+        self.entries.get(source)
+    }
+
+    /// Whether `source` was last converted from the same content with the same settings. Whether
+    /// its output still exists is up to the caller to check.
+    pub fn is_current(&self, source: &Path, source_hash: &str, settings: &str) -> bool {
+        self.get(source)
+            .is_some_and(|entry| entry.source_hash == source_hash && entry.settings == settings)
+    }
+
+    /// Record a finished conversion of `source`, replacing any earlier one.
+    pub fn update(&mut self, source: PathBuf, entry: LedgerEntry) {
+        self.entries.insert(source, entry);
+    }
+
+    pub fn remove(&mut self, source: &Path) -> Option<LedgerEntry> {
+        self.entries.remove(source)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &LedgerEntry)> {
+        self.entries
+            .iter()
+            .map(|(source, entry)| (source.as_path(), entry))
+    }
+}
+
+/// Escape the characters that separate fields and lines.
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
@@ -1,7 +1,61 @@
 //! CRX Circus Image Format Parser
 
+#[cfg(feature = "archive")]
+mod archive;
+mod batch;
+mod clip;
 mod crx;
-pub use self::crx::{CrxDecodeError, CrxFile, CrxImageClip};
+#[cfg(feature = "to_image")]
+mod decoder;
+mod encode;
+mod error;
+mod ledger;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "parse")]
+mod parser;
+mod preview;
+mod reader;
+mod rows;
+mod scene;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod vfs;
+#[cfg(feature = "to_image")]
+mod view;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "archive")]
+pub use self::archive::{ArchiveEntry, CrxArchiveError, DatArchive, PckArchive};
+#[cfg(feature = "fs")]
+pub use self::batch::spawn_batch;
+pub use self::batch::{spawn_batch_with, BatchEvent, BatchEvents};
+pub use self::clip::ClipBuffer;
+pub use self::crx::{
+    is_crx, sniff, CrxDecodeError, CrxFile, CrxImageClip, CrxMetadata, CrxSignature, CrxVersion,
+    DecodeOptions, DecodeWarning, OffsetOrigin, PaletteSpec, PixelOrder, RowFilter, Strictness,
+    FLAG_SIZED_STREAM,
+};
+pub use self::encode::{CrxEncodeError, CrxEncoder, EncodeOptions};
+pub use self::error::CrxError;
+pub use self::ledger::{ConversionLedger, LedgerEntry};
+#[cfg(feature = "parse")]
+pub use self::parser::{parse_crx, ParsedCrx};
+pub use self::preview::{decode_preview, PreviewResult};
+pub use self::reader::CrxReader;
+pub use self::rows::CrxRowDecoder;
+pub use self::scene::SceneComposition;
+#[cfg(feature = "fs")]
+pub use self::vfs::RealFs;
+pub use self::vfs::{MemoryFs, Vfs};
+#[cfg(feature = "archive")]
+pub use self::vfs::{PckFs, ZipFs};
 
 #[cfg(feature = "to_image")]
 pub use self::crx::CrxImageConvertError;
+#[cfg(feature = "to_image")]
+pub use self::decoder::CrxDecoder;
+#[cfg(feature = "to_image")]
+pub use self::view::CrxView;
+#[cfg(feature = "wasm")]
+pub use self::wasm::{decode_to_rgba, DecodedImage};
@@ -0,0 +1,508 @@
+//! Decoding of large images on several threads, with the `parallel` feature.
+//!
+//! The zlib stream is inflated a window at a time, and the rows held whole in the window are
+//! split off: rows of most predictors have a fixed size, and the runs of mode 4 rows are scanned
+//! for their end. Rows stored as is, with the left predictor or as runs don't read the row above,
+//! so each of them starts a group of rows that is reconstructed on its own, the groups of a window
+//! in parallel across the `rayon` pool. The components of a row interleave, so they stay on the
+//! thread of their row. The color conversion afterwards has no such dependency and is split
+//! across the pool too.
+
+use std::io::{self, BufRead, Read};
+
+use rayon::prelude::*;
+
+use crate::crx::{CrxDataContext, CrxDecodeError, CrxFile, RowFilter};
+
+/// Decoded size, in bytes, from which images take the parallel path. Below it, spawning and
+/// synchronizing threads costs more than it saves.
+const THRESHOLD: usize = 1 << 20;
+
+/// Bytes of pixels, or palette indices, converted by one task.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// Inflated bytes held before the rows in them are reconstructed, so that no more of the stream
+/// than this, and a row, is held next to the output.
+const WINDOW: usize = 1 << 20;
+
+/// Whether `size` decoded bytes are worth splitting, with more than one thread to split them on.
+/// The pool follows `RAYON_NUM_THREADS`, so setting it to 1 keeps decoding sequential.
+pub(crate) fn worthwhile(size: usize) -> bool {
+    size >= THRESHOLD && rayon::current_num_threads() > 1
+}
+
+/// Where decoding stopped: the first row not decoded, and why, `None` if out of time.
+type Stop = (usize, Option<io::Error>);
+
+/// Like [`CrxFile::unpack_2`], reconstructing independent groups of rows in parallel.
+pub(crate) fn unpack_2<R: BufRead>(
+    buf: R,
+    context: &CrxDataContext,
+    output: &mut Vec<u8>,
+) -> Result<usize, (usize, io::Error)> {
+    use flate2::bufread::ZlibDecoder;
+
+    let pixel_size = context.bpp / 8;
+    let stride = pixel_size * context.width;
+    output.clear();
+    if stride == 0 {
+        return Ok(context.height);
+    }
+    output.resize(stride * context.height, 0);
+
+    // no row takes more than three bytes a component, a run of one pixel, so a full window
+    // always holds a row.
+    let window = WINDOW.max(3 * stride + 1);
+    let mut inflater = ZlibDecoder::new(buf);
+    let mut data = Vec::with_capacity(window + CHUNK_SIZE);
+    let mut inflated = false;
+    // what was inflated before an error is kept, the error reported at the row it cuts.
+    let mut inflate_error = None;
+    let mut stop: Option<Stop> = None;
+    let mut y = 0;
+    'windows: while y < context.height {
+        while data.len() < window && !inflated {
+            if context.expired() {
+                stop = Some((y, None));
+                break 'windows;
+            }
+            let len = data.len();
+            data.resize(len + CHUNK_SIZE, 0);
+            let read = inflater.read(&mut data[len..]);
+            data.truncate(len + read.as_ref().map_or(0, |&n| n));
+            match read {
+                Ok(0) => inflated = true,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    inflate_error = Some(e);
+                    inflated = true;
+                }
+            }
+        }
+
+        let first = y;
+        let mut starts = Vec::new();
+        let mut groups = vec![first];
+        let mut pos = 0;
+        while y < context.height {
+            match row_size(&data[pos..], y == 0, stride, pixel_size) {
+                Ok((size, independent)) => {
+                    if independent && y > first {
+                        groups.push(y);
+                    }
+                    starts.push(pos);
+                    pos += size;
+                    y += 1;
+                }
+                // the rest of the row is in the next window.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !inflated => break,
+                Err(e) => {
+                    let e = match inflate_error.take() {
+                        Some(inflate) if e.kind() == io::ErrorKind::UnexpectedEof => inflate,
+                        _ => e,
+                    };
+                    stop = Some((y, Some(e)));
+                    break;
+                }
+            }
+        }
+
+        // a row reconstructed before the one the window stops at fails first.
+        if let Err(failed) = reconstruct(&data, &starts, &groups, context, output) {
+            stop = Some(failed);
+        }
+        if stop.is_some() {
+            break;
+        }
+        data.drain(..pos);
+    }
+    // an error past the last row, e.g. a bad checksum, is left alone.
+    if stop.is_none() && (!data.is_empty() || inflater.read(&mut [0]).is_ok_and(|n| n > 0)) {
+        let e = io::Error::new(io::ErrorKind::InvalidData, CrxDecodeError::StreamTooLong);
+        stop = Some((context.height, Some(e)));
+    }
+
+    match stop {
+        None => Ok(context.height),
+        Some((y, error)) => {
+            output[y * stride..].fill(0);
+            match error {
+                None => Ok(y),
+                Some(e) => Err((y, e)),
+            }
+        }
+    }
+}
+
+/// Reconstruct the rows of a window into `output`, the rows from `groups[0]` on starting at
+/// `starts` in `data`, each group in parallel. The row above the first group is already in
+/// `output`.
+fn reconstruct(
+    data: &[u8],
+    starts: &[usize],
+    groups: &[usize],
+    context: &CrxDataContext,
+    output: &mut [u8],
+) -> Result<(), Stop> {
+    let pixel_size = context.bpp / 8;
+    let stride = pixel_size * context.width;
+    let first = groups[0];
+    let (above, mut rest) = output[..(first + starts.len()) * stride].split_at_mut(first * stride);
+    let mut parts = Vec::with_capacity(groups.len());
+    for (i, &start) in groups.iter().enumerate() {
+        let end = groups.get(i + 1).copied().unwrap_or(first + starts.len());
+        let (part, after) = rest.split_at_mut((end - start) * stride);
+        parts.push((start, part));
+        rest = after;
+    }
+    let above = first.checked_sub(1).map(|_| &above[above.len() - stride..]);
+    let groups: Vec<Result<(), Stop>> = parts
+        .into_par_iter()
+        .map(|(start, part)| {
+            for y in 0..part.len() / stride {
+                if context.expired() {
+                    return Err((start + y, None));
+                }
+                let (done, rest) = part.split_at_mut(y * stride);
+                // only the first group reads the row of the window above.
+                let previous = match y {
+                    0 => above.filter(|_| start == first),
+                    _ => Some(&done[done.len() - stride..]),
+                };
+                let mut row = &data[starts[start - first + y]..];
+                CrxFile::unpack_row_2(&mut row, &mut rest[..stride], previous, pixel_size)
+                    .map_err(|e| (start + y, Some(e)))?;
+            }
+            Ok(())
+        })
+        .collect();
+
+    // groups are in row order, so the first to stop holds the first row not decoded.
+    groups.into_iter().find_map(Result::err).map_or(Ok(()), Err)
+}
+
+/// The size of the row at the start of `data`, and whether it is reconstructed without the row
+/// above. Fails like [`CrxFile::unpack_row_2`] would on the same row.
+fn row_size(
+    data: &[u8],
+    first: bool,
+    stride: usize,
+    pixel_size: usize,
+) -> io::Result<(usize, bool)> {
+    let eof = || io::Error::from(io::ErrorKind::UnexpectedEof);
+    // palette indices are stored as is.
+    if pixel_size == 1 {
+        return if data.len() < stride {
+            Err(eof())
+        } else {
+            Ok((stride, true))
+        };
+    }
+    let filter = RowFilter::try_from(*data.first().ok_or_else(eof)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let independent = matches!(filter, RowFilter::Left | RowFilter::Runs);
+    if first && !independent {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            CrxDecodeError::NoPreviousRow,
+        ));
+    }
+    let size = match filter {
+        RowFilter::Runs => 1 + runs_size(&data[1..], stride / pixel_size, pixel_size)?,
+        _ => 1 + stride,
+    };
+    if data.len() < size {
+        return Err(eof());
+    }
+    Ok((size, independent))
+}
+
+/// The size of the runs of a mode 4 row of `width` pixels at the start of `data`, read like
+/// [`CrxFile::unpack_row_2`] reads them, without writing the pixels.
+fn runs_size(data: &[u8], width: usize, pixel_size: usize) -> io::Result<usize> {
+    let mut pos = 0;
+    let mut next = || {
+        let byte = data
+            .get(pos)
+            .copied()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        pos += 1;
+        Ok::<_, io::Error>(byte)
+    };
+    for _ in 0..pixel_size {
+        let mut remaining = width;
+        let mut val = next()?;
+        while remaining > 0 {
+            remaining -= 1;
+            if remaining == 0 {
+                break;
+            }
+            let byte = next()?;
+            if val == byte {
+                let count = next()? as usize;
+                remaining = remaining.checked_sub(count).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, CrxDecodeError::RowOverflow)
+                })?;
+                if remaining > 0 {
+                    val = next()?;
+                }
+            } else {
+                val = byte;
+            }
+        }
+    }
+    Ok(pos)
+}
+
+/// Like [`CrxFile::to_rgb`], over pixel-aligned parts of `data` in parallel.
+pub(crate) fn to_rgb(data: &mut [u8], bpp: usize, mode: u16, keep_alpha: bool) {
+    let part = CHUNK_SIZE - CHUNK_SIZE % (bpp / 8).max(1);
+    data.par_chunks_mut(part)
+        .for_each(|part| CrxFile::to_rgb(part, bpp, mode, keep_alpha));
+}
+
+/// Like [`CrxFile::expand_palette`], filling a new buffer in parallel.
+pub(crate) fn expand_palette(data: &mut Vec<u8>, palette: &[[u8; 3]]) -> io::Result<()> {
+    CrxFile::check_indices(data, palette)?;
+    let mut lut = [[0u8; 3]; 0x100];
+    lut[..palette.len().min(0x100)].copy_from_slice(&palette[..palette.len().min(0x100)]);
+    let mut colors = vec![0; data.len() * 3];
+    colors
+        .par_chunks_mut(CHUNK_SIZE * 3)
+        .zip(data.par_chunks(CHUNK_SIZE))
+        .for_each(|(colors, indices)| {
+            for (color, &index) in colors.chunks_exact_mut(3).zip(indices) {
+                color.copy_from_slice(&lut[index as usize]);
+            }
+        });
+    *data = colors;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decode_preview, test_util::runs, CrxEncoder, CrxVersion, DecodeOptions, Strictness,
+    };
+    use std::{
+        io::Write,
+        time::{Duration, Instant},
+    };
+
+    /// Large enough to take the parallel path.
+    const WIDTH: usize = 640;
+    const HEIGHT: usize = 480;
+    const PIXEL_SIZE: usize = 4;
+
+    /// A version 2 RGBA file whose rows take every predictor, with runs of all lengths in the
+    /// mode 4 rows, and its zlib stream cut to `keep` of its length.
+    fn mixed_rows(keep: f64) -> Vec<u8> {
+        let stride = WIDTH * PIXEL_SIZE;
+        let mut stream = Vec::new();
+        for y in 0..HEIGHT {
+            // the first row has no row above to refer to.
+            let filter = if y == 0 { 0 } else { (y % 7 % 5) as u8 };
+            stream.push(filter);
+            if filter == 4 {
+                for component in 0..PIXEL_SIZE {
+                    let values: Vec<u8> = (0..WIDTH)
+                        .map(|x| ((x / (1 + (y + component) % 300)) * 37 + y) as u8)
+                        .collect();
+                    runs(&values, &mut stream);
+                }
+            } else {
+                stream.extend((0..stride).map(|x| ((x * 13) ^ (y * 7)) as u8 % 9));
+            }
+        }
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        zlib.write_all(&stream).unwrap();
+        let mut zlib = zlib.finish().unwrap();
+        zlib.truncate((zlib.len() as f64 * keep) as usize);
+
+        // the header of an encoded file of the same size and layout, then the stream.
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V2)
+            .with_flag(0)
+            .write_rgba(
+                &mut file,
+                WIDTH as u16,
+                HEIGHT as u16,
+                &vec![0; stride * HEIGHT],
+            )
+            .unwrap();
+        file.truncate(20);
+        file.extend(zlib);
+        file
+    }
+
+    /// Decode `file` leniently on a pool of `threads` threads, for the pixels and warnings.
+    fn decode(file: &[u8], threads: usize) -> (Vec<u8>, Vec<(usize, String)>) {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let options = DecodeOptions {
+            strictness: Strictness::Lenient,
+            ..Default::default()
+        };
+        let (image, warnings) = pool
+            .install(|| CrxFile::read_with_warnings(file, &options))
+            .unwrap();
+        let warnings = warnings
+            .into_iter()
+            .map(|warning| (warning.row, warning.message))
+            .collect();
+        (image.raw_buffer().to_vec(), warnings)
+    }
+
+    #[test]
+    fn parallel_decoding_matches_sequential() {
+        let file = mixed_rows(1.0);
+        let (serial, warnings) = decode(&file, 1);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(decode(&file, 4), (serial, warnings));
+    }
+
+    #[test]
+    fn parallel_decoding_stops_where_sequential_does() {
+        let file = mixed_rows(0.6);
+        let (serial, warnings) = decode(&file, 1);
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].0 > 0 && warnings[0].0 < HEIGHT,
+            "{:?}",
+            warnings
+        );
+        assert_eq!(decode(&file, 4), (serial, warnings));
+    }
+
+    #[test]
+    fn parallel_decoding_finds_over_long_streams_like_sequential() {
+        let mut file = mixed_rows(1.0);
+        file[10..12].copy_from_slice(&(HEIGHT as u16 - 1).to_le_bytes());
+        let (serial, warnings) = decode(&file, 1);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, HEIGHT - 1);
+        assert_eq!(decode(&file, 4), (serial, warnings));
+    }
+
+    /// Hands out the first `fast` bytes of `data` at once and the rest a few bytes at a time,
+    /// slowly, like a file on slow media.
+    struct SlowTail<'a> {
+        data: &'a [u8],
+        fast: usize,
+    }
+
+    impl Read for SlowTail<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = if self.fast > 0 {
+                self.fast
+            } else {
+                std::thread::sleep(Duration::from_millis(10));
+                64
+            };
+            let n = n.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.fast = self.fast.saturating_sub(n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn parallel_previews_stop_inflating_at_the_deadline() {
+        let file = mixed_rows(1.0);
+        let (full, _) = decode(&file, 4);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let input = SlowTail {
+            data: &file,
+            fast: file.len() / 2,
+        };
+
+        let started = Instant::now();
+        let preview = pool
+            .install(|| decode_preview(input, Duration::from_millis(100)))
+            .unwrap();
+        // reading the slow half alone takes seconds.
+        assert!(
+            started.elapsed() < Duration::from_secs(2),
+            "{:?}",
+            started.elapsed()
+        );
+        assert!(!preview.is_complete());
+        let (decoded, zeroed) = preview
+            .image
+            .raw_buffer()
+            .split_at(preview.rows * WIDTH * PIXEL_SIZE);
+        assert_eq!(decoded, &full[..decoded.len()]);
+        assert!(zeroed.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn parallel_palette_expansion_matches_lookups() {
+        let palette: Vec<[u8; 3]> = (0..200).map(|i| [i as u8, !(i as u8), 0x40]).collect();
+        let indices: Vec<u8> = (0..3 * CHUNK_SIZE + 5).map(|i| (i % 199) as u8).collect();
+        let mut data = indices.clone();
+        expand_palette(&mut data, &palette).unwrap();
+        assert!(data
+            .chunks_exact(3)
+            .zip(&indices)
+            .all(|(color, &index)| color == palette[index as usize]));
+
+        let mut outside = vec![0; CHUNK_SIZE];
+        outside[CHUNK_SIZE - 1] = 200;
+        assert!(expand_palette(&mut outside, &palette).is_err());
+    }
+
+    #[test]
+    fn parallel_color_conversion_matches_sequential() {
+        let stored: Vec<u8> = (0..12 * CHUNK_SIZE + 12)
+            .map(|i| (i * 53 % 256) as u8)
+            .collect();
+        let sequential = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        for mode in [0, 1, 2] {
+            for keep_alpha in [false, true] {
+                for bpp in [24, 32] {
+                    let mut parallel = stored.clone();
+                    to_rgb(&mut parallel, bpp, mode, keep_alpha);
+                    let mut serial = stored.clone();
+                    sequential.install(|| CrxFile::to_rgb(&mut serial, bpp, mode, keep_alpha));
+                    assert!(
+                        parallel == serial,
+                        "mode {} {}-bit keep_alpha {}",
+                        mode,
+                        bpp,
+                        keep_alpha
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn runs_are_sized_like_they_are_read() {
+        let values = [1, 1, 1, 2, 3, 3, 4, 4, 4, 4, 5];
+        let mut stream = Vec::new();
+        runs(&values, &mut stream);
+        runs(&values, &mut stream);
+        assert_eq!(runs_size(&stream, values.len(), 2).unwrap(), stream.len());
+
+        let mut row = vec![0; values.len() * 2];
+        CrxFile::unpack_row_2(&mut &[&[4], &stream[..]].concat()[..], &mut row, None, 2).unwrap();
+        assert!(row.chunks_exact(2).map(|p| p[0]).eq(values));
+
+        // a run past the end of the row.
+        let overflow = runs_size(&[7, 7, 20], 4, 1).unwrap_err();
+        assert_eq!(overflow.kind(), io::ErrorKind::InvalidData);
+        let eof = runs_size(&stream[..stream.len() - 1], values.len(), 2).unwrap_err();
+        assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,262 @@
+//! Zero-copy parsing of the structure of CRX files with `nom`, with the `parse` feature.
+
+use crate::{
+    crx::CrxHeader, CrxDecodeError, CrxError, CrxImageClip, CrxSignature, CrxVersion, PaletteSpec,
+    FLAG_SIZED_STREAM,
+};
+use nom::{
+    bytes::complete::take,
+    number::complete::{le_i16, le_i32, le_u16},
+    IResult, Parser,
+};
+use std::io;
+
+/// Bytes of an entry of the clip table.
+const CLIP_SIZE: usize = 16;
+
+/// The structure of a CRX file held in memory, see [`parse_crx`]. The header is read; the
+/// palette, clip table and pixel stream are borrowed as stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedCrx<'a> {
+    header: CrxHeader,
+    /// The palette entries, [`PaletteSpec::entry_size`] bytes each; empty for truecolor images.
+    /// Unlike [`CrxMetadata::palette`](crate::CrxMetadata::palette), the colors are as stored,
+    /// with the color key still magenta.
+    pub palette: &'a [u8],
+    /// The entries of the clip table, without the count ahead of them; empty before version 3.
+    /// [`ParsedCrx::clips`] reads them.
+    pub clip_table: &'a [u8],
+    /// The compressed pixel stream, as stored: LZ data for version 1, zlib data otherwise.
+    pub payload: &'a [u8],
+    /// What follows a size-prefixed stream, e.g. the next entry of a container; empty for
+    /// streams that run to the end of the input.
+    pub rest: &'a [u8],
+}
+
+impl<'a> ParsedCrx<'a> {
+    pub fn signature(&self) -> CrxSignature {
+        self.header.signature
+    }
+
+    pub fn inner_x(&self) -> i16 {
+        self.header.inner_x
+    }
+
+    pub fn inner_y(&self) -> i16 {
+        self.header.inner_y
+    }
+
+    pub fn width(&self) -> u16 {
+        self.header.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.header.height
+    }
+
+    pub fn version(&self) -> CrxVersion {
+        self.header.version
+    }
+
+    /// See [`CrxFile::flag`](crate::CrxFile::flag).
+    pub fn flag(&self) -> u16 {
+        self.header.flag
+    }
+
+    /// The raw `depth` header field, which selects the pixel layout.
+    pub fn depth(&self) -> i16 {
+        self.header.depth
+    }
+
+    /// See [`CrxFile::mode`](crate::CrxFile::mode).
+    pub fn mode(&self) -> u16 {
+        self.header.mode
+    }
+
+    /// The layout of [`ParsedCrx::palette`]; `None` for truecolor images.
+    pub fn palette_spec(&self) -> Option<PaletteSpec> {
+        // checked by `parse_crx`.
+        PaletteSpec::from_depth(self.header.depth).ok().flatten()
+    }
+
+    /// The entries of [`ParsedCrx::clip_table`], read as they are iterated.
+    pub fn clips(&self) -> impl Iterator<Item = CrxImageClip> + 'a {
+        self.clip_table.chunks_exact(CLIP_SIZE).map(|entry| {
+            CrxImageClip::read(entry).expect("clip table entries are read from whole entries")
+        })
+    }
+}
+
+/// Parse the structure of the CRX file at the start of `bytes` without decompressing it or
+/// copying any of it: the palette, clip table, payload and rest are borrowed from `bytes`.
+pub fn parse_crx(bytes: &[u8]) -> Result<ParsedCrx<'_>, CrxError> {
+    let (input, signature) = complete(take(4usize).parse(bytes), "signature", bytes)?;
+    let signature = CrxSignature::parse([signature[0], signature[1], signature[2], signature[3]])
+        .ok_or(CrxDecodeError::CrxSignatureInvalid)?;
+    let (input, header) = complete(header(signature, input), "header", input)?;
+    let header = header?;
+    let palette_size =
+        PaletteSpec::from_depth(header.depth)?.map_or(0, |spec| spec.colors() * spec.entry_size());
+    let (input, palette) = complete(take(palette_size).parse(input), "palette", input)?;
+    let (input, clip_table) = if header.version.has_clip_table() {
+        let (input, count) = complete(le_i32(input), "clip count", input)?;
+        let size = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(CLIP_SIZE))
+            .ok_or(CrxDecodeError::InvalidClipCount(count))?;
+        complete(take(size).parse(input), "clip table", input)?
+    } else {
+        (input, &[][..])
+    };
+    let parsed = |payload, rest| ParsedCrx {
+        header,
+        palette,
+        clip_table,
+        payload,
+        rest,
+    };
+    if header.flag & FLAG_SIZED_STREAM == 0 {
+        return Ok(parsed(input, &[]));
+    }
+    let (input, size) = complete(le_i32(input), "stream size", input)?;
+    let size = usize::try_from(size).map_err(|_| CrxDecodeError::InvalidStreamSize(size))?;
+    let (rest, payload) = complete(take(size).parse(input), "stream", input)?;
+    Ok(parsed(payload, rest))
+}
+
+/// The header after `signature` at the start of `input`, failing on a version the decoder does
+/// not accept once it is read whole.
+fn header(
+    signature: CrxSignature,
+    input: &[u8],
+) -> IResult<&[u8], Result<CrxHeader, CrxDecodeError>> {
+    let (input, (inner_x, inner_y, width, height, version, flag, depth, mode)) = (
+        le_i16, le_i16, le_u16, le_u16, le_u16, le_u16, le_i16, le_u16,
+    )
+        .parse(input)?;
+    // Verify that the version is supported (1, 2, 3)
+    let version = CrxVersion::try_from(version).map_err(|_| CrxDecodeError::VersionNotSupported {
+        version,
+        flag: Some(flag),
+        mode: Some(mode),
+    });
+    let header = version.map(|version| CrxHeader {
+        signature,
+        inner_x,
+        inner_y,
+        width,
+        height,
+        version,
+        flag,
+        depth,
+        mode,
+    });
+    Ok((input, header))
+}
+
+/// The result of parsing the `what` from `input`, failing like a reader that reaches the end of
+/// `input` when it is cut short.
+fn complete<'a, O>(
+    result: IResult<&'a [u8], O>,
+    what: &str,
+    input: &[u8],
+) -> io::Result<(&'a [u8], O)> {
+    result.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} cut short, only `{}` bytes remain", what, input.len()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CrxEncoder, CrxFile};
+
+    fn clip(n: i16) -> CrxImageClip {
+        CrxImageClip {
+            unknown_1: n as i32,
+            x: n,
+            y: n + 1,
+            unknown_2: -(n as i32),
+            width: n + 2,
+            height: n + 3,
+        }
+    }
+
+    #[test]
+    fn palette_and_clips_are_borrowed_from_the_input() {
+        let palette: Vec<[u8; 3]> = (0..4).map(|i| [i * 60, 0x10, 0xFF - i * 60]).collect();
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V3)
+            .with_offset(-3, 4)
+            .with_clips(vec![clip(1), clip(2)])
+            .write_indexed(&mut file, 4, 2, &palette, &[0, 1, 2, 3, 3, 2, 1, 0])
+            .unwrap();
+        let parsed = parse_crx(&file).unwrap();
+        let metadata = CrxFile::read_metadata(&file[..]).unwrap();
+
+        assert_eq!((parsed.width(), parsed.height()), (4, 2));
+        assert_eq!((parsed.inner_x(), parsed.inner_y()), (-3, 4));
+        assert_eq!(parsed.palette_spec(), metadata.palette_spec());
+        let entry_size = parsed.palette_spec().unwrap().entry_size();
+        let colors: Vec<[u8; 3]> = parsed
+            .palette
+            .chunks_exact(entry_size)
+            .map(|entry| [entry[0], entry[1], entry[2]])
+            .collect();
+        assert_eq!(Some(&colors[..]), metadata.palette());
+        assert_eq!(parsed.clips().collect::<Vec<_>>(), [clip(1), clip(2)]);
+
+        // everything borrowed lies within the file.
+        let within = |part: &[u8]| part.as_ptr_range().start >= file.as_ptr_range().start;
+        assert!([parsed.palette, parsed.clip_table, parsed.payload]
+            .iter()
+            .all(|part| within(part)));
+        assert_eq!(
+            parsed.payload.as_ptr_range().end,
+            file.as_ptr_range().end,
+            "the stream runs to the end of the file"
+        );
+    }
+
+    #[test]
+    fn truncated_tables_are_refused() {
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V3)
+            .with_clips(vec![clip(1)])
+            .write_rgb(&mut file, 1, 1, &[1, 2, 3])
+            .unwrap();
+        // the clip count follows the header of a truecolor image.
+        file[20..24].copy_from_slice(&1000i32.to_le_bytes());
+        let Err(CrxError::Io(e)) = parse_crx(&file) else {
+            panic!("a clip table past the end was parsed");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        file[20..24].copy_from_slice(&(-1i32).to_le_bytes());
+        assert!(matches!(
+            parse_crx(&file),
+            Err(CrxError::Decode(CrxDecodeError::InvalidClipCount(-1)))
+        ));
+    }
+
+    #[test]
+    fn headers_are_refused_like_the_decoder_refuses_them() {
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V2)
+            .write_rgb(&mut file, 1, 1, &[1, 2, 3])
+            .unwrap();
+        let mut bad_signature = file.clone();
+        bad_signature[..4].copy_from_slice(b"PNG ");
+        let mut bad_version = file.clone();
+        bad_version[12..14].copy_from_slice(&9u16.to_le_bytes());
+
+        for file in [&bad_signature[..], &bad_signature[..4], &bad_version[..]] {
+            let parsed = parse_crx(file).unwrap_err().to_string();
+            let read = CrxFile::read_metadata(file).unwrap_err().to_string();
+            assert_eq!(parsed, read);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{CrxError, CrxFile, DecodeOptions};
+use std::{
+    io::Read,
+    time::{Duration, Instant},
+};
+
+/// What [`decode_preview`] managed to decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewResult {
+    /// The image at full size; rows below [`PreviewResult::rows`] are zeroed.
+    pub image: CrxFile,
+    /// Number of decoded rows, from the top.
+    pub rows: usize,
+}
+
+impl PreviewResult {
+    /// Whether every row was decoded in time.
+    pub fn is_complete(&self) -> bool {
+        self.rows == self.image.height() as usize
+    }
+}
+
+/// Decode as many rows of a CRX image as `budget` allows, e.g. for previews that must stay
+/// responsive on slow media. Reading the input counts against the budget, and the row being
+/// decoded when it runs out is finished first. 8-bit images are expanded to RGB, as by
+/// [`CrxFile::read`].
+pub fn decode_preview<R: Read>(reader: R, budget: Duration) -> Result<PreviewResult, CrxError> {
+    // a budget too large for an `Instant` is no limit.
+    let deadline = Instant::now().checked_add(budget);
+    let (image, rows, _) = CrxFile::read_inner(reader, &DecodeOptions::default(), deadline)?;
+    Ok(PreviewResult { image, rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::encode_layouts, CrxEncoder, CrxVersion};
+
+    const WIDTH: u16 = 12;
+    const HEIGHT: u16 = 9;
+
+    #[test]
+    fn previews_without_a_limit_are_the_whole_image() {
+        for version in [CrxVersion::V1, CrxVersion::V2] {
+            for (bpp, file) in encode_layouts(version, WIDTH, HEIGHT) {
+                let what = format!("v{} {}-bit", version, bpp);
+                let preview = decode_preview(&file[..], Duration::MAX).unwrap();
+                assert!(preview.is_complete(), "{}", what);
+                assert_eq!(preview.rows, HEIGHT as usize, "{}", what);
+                assert_eq!(preview.image, CrxFile::read(&file[..]).unwrap(), "{}", what);
+            }
+        }
+    }
+
+    #[test]
+    fn previews_out_of_time_keep_the_full_size() {
+        for version in [CrxVersion::V1, CrxVersion::V2] {
+            for (bpp, file) in encode_layouts(version, WIDTH, HEIGHT) {
+                let what = format!("v{} {}-bit", version, bpp);
+                let preview = decode_preview(&file[..], Duration::ZERO).unwrap();
+                assert!(!preview.is_complete(), "{}", what);
+                assert_eq!(preview.rows, 0, "{}", what);
+                let image = &preview.image;
+                assert_eq!((image.width(), image.height()), (WIDTH, HEIGHT));
+                // palette images are expanded to RGB.
+                let bpp = bpp.max(24);
+                assert_eq!(image.bpp(), bpp, "{}", what);
+                assert_eq!(
+                    image.raw_buffer().len(),
+                    WIDTH as usize * HEIGHT as usize * bpp / 8,
+                    "{}",
+                    what
+                );
+                assert!(image.raw_buffer().iter().all(|&byte| byte == 0), "{}", what);
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_previews_are_expanded() {
+        let palette: Vec<[u8; 3]> = (0..4).map(|i| [i * 50, 0x40, 0xFF - i * 20]).collect();
+        let indices: Vec<u8> = (0..WIDTH as usize * HEIGHT as usize)
+            .map(|i| (i % 4) as u8)
+            .collect();
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V2)
+            .write_indexed(&mut file, WIDTH, HEIGHT, &palette, &indices)
+            .unwrap();
+
+        let preview = decode_preview(&file[..], Duration::MAX).unwrap();
+        assert_eq!(preview.image.bpp(), 24);
+        let expected: Vec<u8> = indices
+            .iter()
+            .flat_map(|&index| palette[index as usize])
+            .collect();
+        assert_eq!(preview.image.raw_buffer(), expected);
+    }
+}
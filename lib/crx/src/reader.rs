@@ -0,0 +1,109 @@
+use crate::{CrxError, CrxFile, DecodeOptions, DecodeWarning, OffsetOrigin, Strictness};
+use std::io::Read;
+
+/// Reads CRX files, configured step by step instead of through [`DecodeOptions`] and the
+/// `read_*` constructors of [`CrxFile`], e.g.
+/// `CrxReader::new().max_dimensions(8192, 8192).lenient(true).decode(reader)`.
+#[derive(Debug, Clone, Default)]
+pub struct CrxReader {
+    options: DecodeOptions,
+    canvas: Option<(OffsetOrigin, u16, u16)>,
+}
+
+impl From<DecodeOptions> for CrxReader {
+    fn from(options: DecodeOptions) -> Self {
+        Self {
+            options,
+            canvas: None,
+        }
+    }
+}
+
+impl CrxReader {
+    /// A reader without limits, decoding like [`CrxFile::read`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn options(&self) -> &DecodeOptions {
+        &self.options
+    }
+
+    /// Keep 8-bit images as palette indices, like [`CrxFile::read_indexed`].
+    pub fn keep_indexed(mut self, keep_indexed: bool) -> Self {
+        self.options.keep_indexed = keep_indexed;
+        self
+    }
+
+    /// Keep the alpha of 32-bit pixels as stored, see [`DecodeOptions::keep_alpha`].
+    pub fn keep_alpha(mut self, keep_alpha: bool) -> Self {
+        self.options.keep_alpha = keep_alpha;
+        self
+    }
+
+    /// Refuse images wider than `width` or taller than `height`.
+    pub fn max_dimensions(mut self, width: u16, height: u16) -> Self {
+        self.options.max_width = Some(width);
+        self.options.max_height = Some(height);
+        self
+    }
+
+    /// Refuse images whose pixel data would take more than `bytes`.
+    pub fn max_alloc(mut self, bytes: u64) -> Self {
+        self.options.max_alloc = Some(bytes);
+        self
+    }
+
+    /// Refuse clip tables of more than `clips` entries.
+    pub fn max_clips(mut self, clips: usize) -> Self {
+        self.options.max_clips = Some(clips);
+        self
+    }
+
+    /// Refuse palettes of more than `colors` colors.
+    pub fn max_palette_colors(mut self, colors: usize) -> Self {
+        self.options.max_palette_colors = Some(colors);
+        self
+    }
+
+    /// Decode images with a width or height of 0, see [`DecodeOptions::allow_empty`].
+    pub fn allow_empty(mut self, allow_empty: bool) -> Self {
+        self.options.allow_empty = allow_empty;
+        self
+    }
+
+    /// Keep the rows of a damaged pixel stream above the first unreadable one, see
+    /// [`Strictness::Lenient`].
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.options.strictness = if lenient {
+            Strictness::Lenient
+        } else {
+            Strictness::Strict
+        };
+        self
+    }
+
+    /// Place decoded images on a transparent canvas of `width` x `height` by their inner offset,
+    /// measured from `origin`, as [`CrxFile::to_positioned_image_from`] does.
+    pub fn canvas(mut self, origin: OffsetOrigin, width: u16, height: u16) -> Self {
+        self.canvas = Some((origin, width, height));
+        self
+    }
+
+    pub fn decode<R: Read>(&self, reader: R) -> Result<CrxFile, CrxError> {
+        Ok(self.decode_with_warnings(reader)?.0)
+    }
+
+    /// Like [`CrxReader::decode`], also returning what lenient decoding recovered from.
+    pub fn decode_with_warnings<R: Read>(
+        &self,
+        reader: R,
+    ) -> Result<(CrxFile, Vec<DecodeWarning>), CrxError> {
+        let (crx, warnings) = CrxFile::read_with_warnings(reader, &self.options)?;
+        let crx = match self.canvas {
+            Some((origin, width, height)) => crx.to_positioned_image_from(origin, width, height),
+            None => crx,
+        };
+        Ok((crx, warnings))
+    }
+}
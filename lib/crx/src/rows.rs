@@ -0,0 +1,231 @@
+use crate::{crx::LzState, CrxError, CrxFile, CrxMetadata, CrxVersion, DecodeOptions};
+use flate2::bufread::ZlibDecoder;
+use std::io::{BufReader, Read, Take};
+
+/// Where rows come from: version 1 images are LZ-compressed as a whole, later ones are
+/// zlib-compressed row by row.
+enum Stream<R: Read> {
+    // boxed, as the window is 64 KiB.
+    Lz(Box<LzState>, BufReader<Take<R>>),
+    Zlib(BufReader<ZlibDecoder<BufReader<Take<R>>>>),
+}
+
+/// Decodes a CRX image a row at a time, holding no more than a few rows, e.g. to feed a streaming
+/// encoder on machines short of memory.
+pub struct CrxRowDecoder<R: Read> {
+    metadata: CrxMetadata,
+    keep_indexed: bool,
+    keep_alpha: bool,
+    stream: Stream<R>,
+    /// The row being decoded and the one above it, as stored; predictors refer to the latter.
+    current: Vec<u8>,
+    previous: Vec<u8>,
+    /// The last decoded row, as returned.
+    row: Vec<u8>,
+    next_y: usize,
+}
+
+impl<R: Read> CrxRowDecoder<R> {
+    /// Read everything up to the pixels. Of the limits of `options`, `max_alloc` does not apply,
+    /// as only a few rows are allocated.
+    pub fn new(mut reader: R, options: &DecodeOptions) -> Result<Self, CrxError> {
+        let (metadata, stream_size) = CrxFile::read_preamble(&mut reader, options)?;
+        let input = BufReader::new(reader.take(stream_size));
+        let stream = match metadata.version() {
+            CrxVersion::V1 => Stream::Lz(Box::new(LzState::new()), input),
+            _ => Stream::Zlib(BufReader::new(ZlibDecoder::new(input))),
+        };
+        let stride = metadata.width() as usize * (metadata.bpp() / 8);
+        Ok(Self {
+            metadata,
+            keep_indexed: options.keep_indexed,
+            keep_alpha: options.keep_alpha,
+            stream,
+            current: vec![0; stride],
+            previous: vec![0; stride],
+            row: Vec::with_capacity(stride * 3),
+            next_y: 0,
+        })
+    }
+
+    pub fn metadata(&self) -> &CrxMetadata {
+        &self.metadata
+    }
+
+    /// Decode the next row, laid out like a row of [`CrxFile::raw_buffer`] decoded with the same
+    /// options. Returns `None` after the last row. A decoder that failed should not be used
+    /// further.
+    pub fn next_row(&mut self) -> Result<Option<&[u8]>, CrxError> {
+        if self.next_y >= self.metadata.height() as usize {
+            return Ok(None);
+        }
+        let bpp = self.metadata.bpp();
+        match &mut self.stream {
+            Stream::Lz(lz, input) => lz.fill(input, &mut self.current)?,
+            Stream::Zlib(input) => {
+                let previous = (self.next_y > 0).then_some(&self.previous[..]);
+                CrxFile::unpack_row_2(input, &mut self.current, previous, bpp / 8)?;
+            }
+        }
+
+        self.row.clear();
+        self.row.extend_from_slice(&self.current);
+        CrxFile::to_rgb(&mut self.row, bpp, self.metadata.mode(), self.keep_alpha);
+        if bpp == 8 {
+            let palette = self.metadata.palette().unwrap_or_default();
+            if self.keep_indexed {
+                CrxFile::check_indices(&self.row, palette)?;
+            } else {
+                CrxFile::expand_palette(&mut self.row, palette)?;
+            }
+        }
+        std::mem::swap(&mut self.current, &mut self.previous);
+        self.next_y += 1;
+        Ok(Some(&self.row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{encode_layouts, runs},
+        CrxEncoder, RowFilter,
+    };
+    use std::io::Write;
+
+    const WIDTH: u16 = 9;
+    const HEIGHT: u16 = 6;
+
+    /// The rows of `file` from a [`CrxRowDecoder`], concatenated, checking that there are as many
+    /// as the image is high.
+    fn streamed(file: &[u8], options: &DecodeOptions) -> Vec<u8> {
+        let mut decoder = CrxRowDecoder::new(file, options).unwrap();
+        let (mut pixels, mut rows) = (Vec::new(), 0);
+        while let Some(row) = decoder.next_row().unwrap() {
+            pixels.extend_from_slice(row);
+            rows += 1;
+        }
+        assert_eq!(rows, decoder.metadata().height() as usize);
+        pixels
+    }
+
+    /// Check that `file` decodes to the same pixels a row at a time as whole, with and without
+    /// the options that change the layout of rows.
+    fn assert_rows_match(file: &[u8], what: &str) {
+        let options = [
+            DecodeOptions::default(),
+            DecodeOptions {
+                keep_indexed: true,
+                keep_alpha: true,
+                ..Default::default()
+            },
+        ];
+        for options in options.map(|options| DecodeOptions {
+            allow_empty: true,
+            ..options
+        }) {
+            let whole = CrxFile::read_with_options(file, &options).unwrap();
+            assert_eq!(
+                streamed(file, &options),
+                whole.raw_buffer(),
+                "{} {:?}",
+                what,
+                options
+            );
+        }
+    }
+
+    /// A `bpp`-bit truecolor file of `version` whose rows all take `filter`, but for the first
+    /// row, which takes the left predictor unless `filter` needs no row above either.
+    fn with_filter(version: CrxVersion, bpp: usize, filter: RowFilter) -> Vec<u8> {
+        let pixel_size = bpp / 8;
+        let stride = WIDTH as usize * pixel_size;
+        let mut stream = Vec::new();
+        for y in 0..HEIGHT as usize {
+            let filter = match filter {
+                RowFilter::Up | RowFilter::UpLeft | RowFilter::UpRight if y == 0 => RowFilter::Left,
+                filter => filter,
+            };
+            stream.push(filter.number());
+            if filter == RowFilter::Runs {
+                for component in 0..pixel_size {
+                    let values: Vec<u8> = (0..WIDTH as usize)
+                        .map(|x| (x / 3 * 40 + y * 3 + component) as u8)
+                        .collect();
+                    runs(&values, &mut stream);
+                }
+            } else {
+                stream.extend((0..stride).map(|x| (x * 29 + y * 7) as u8));
+            }
+        }
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        zlib.write_all(&stream).unwrap();
+
+        // what an encoded file of the same size and layout holds before its stream.
+        let mut file = Vec::new();
+        let encoder = CrxEncoder::new(version).with_flag(0);
+        let pixels = vec![0; stride * HEIGHT as usize];
+        if bpp == 32 {
+            encoder
+                .write_rgba(&mut file, WIDTH, HEIGHT, &pixels)
+                .unwrap();
+        } else {
+            encoder
+                .write_rgb(&mut file, WIDTH, HEIGHT, &pixels)
+                .unwrap();
+        }
+        let mut rest = &file[..];
+        CrxFile::read_preamble(&mut rest, &DecodeOptions::default()).unwrap();
+        file.truncate(file.len() - rest.len());
+        file.extend(zlib.finish().unwrap());
+        file
+    }
+
+    #[test]
+    fn rows_match_whole_decoding_for_each_version() {
+        for version in [CrxVersion::V1, CrxVersion::V2, CrxVersion::V3] {
+            for (bpp, file) in encode_layouts(version, WIDTH, HEIGHT) {
+                assert_rows_match(&file, &format!("v{} {}-bit", version, bpp));
+            }
+        }
+    }
+
+    #[test]
+    fn rows_match_whole_decoding_for_each_predictor() {
+        let filters = [
+            RowFilter::Left,
+            RowFilter::Up,
+            RowFilter::UpLeft,
+            RowFilter::UpRight,
+            RowFilter::Runs,
+        ];
+        for version in [CrxVersion::V2, CrxVersion::V3] {
+            for bpp in [24, 32] {
+                for filter in filters {
+                    let what = format!("v{} {}-bit {:?}", version, bpp, filter);
+                    assert_rows_match(&with_filter(version, bpp, filter), &what);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zero_width_images_have_empty_rows() {
+        for version in [CrxVersion::V1, CrxVersion::V2, CrxVersion::V3] {
+            for (bpp, file) in encode_layouts(version, 0, HEIGHT) {
+                let what = format!("v{} {}-bit", version, bpp);
+                assert_rows_match(&file, &what);
+                let options = DecodeOptions {
+                    allow_empty: true,
+                    ..Default::default()
+                };
+                let mut decoder = CrxRowDecoder::new(&file[..], &options).unwrap();
+                for _ in 0..HEIGHT {
+                    assert_eq!(decoder.next_row().unwrap(), Some(&[][..]), "{}", what);
+                }
+                assert_eq!(decoder.next_row().unwrap(), None, "{}", what);
+            }
+        }
+    }
+}
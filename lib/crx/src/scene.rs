@@ -0,0 +1,254 @@
+use crate::CrxFile;
+use std::io;
+
+/// Layers CRX images over a base image, the way the game engine draws a scene: each overlay is
+/// alpha-blended onto the base at its position, in the order the overlays were added.
+#[derive(Debug, Clone)]
+pub struct SceneComposition<'a> {
+    base: &'a CrxFile,
+    layers: Vec<(&'a CrxFile, i32, i32)>,
+}
+
+impl<'a> SceneComposition<'a> {
+    pub fn new(base: &'a CrxFile) -> Self {
+        Self {
+            base,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Add an overlay positioned by its inner offset relative to the inner offset of the base.
+    pub fn overlay(self, layer: &'a CrxFile) -> Self {
+        let x = layer.inner_x() as i32 - self.base.inner_x() as i32;
+        let y = layer.inner_y() as i32 - self.base.inner_y() as i32;
+        self.overlay_at(layer, x, y)
+    }
+
+    /// Add an overlay whose top-left corner is at (`x`, `y`) of the base image.
+    pub fn overlay_at(mut self, layer: &'a CrxFile, x: i32, y: i32) -> Self {
+        self.layers.push((layer, x, y));
+        self
+    }
+
+    /// Flatten the scene into a 32-bit image with the size and metadata of the base. Parts of
+    /// overlays outside of the base are dropped.
+    pub fn compose(&self) -> CrxFile {
+        let width = self.base.width() as usize;
+        let height = self.base.height() as usize;
+        let mut buffer = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                buffer.extend_from_slice(&self.base.rgba_at(x, y));
+            }
+        }
+
+        for &(layer, left, top) in &self.layers {
+            for ly in 0..layer.height() as usize {
+                let y = top + ly as i32;
+                if y < 0 || y >= height as i32 {
+                    continue;
+                }
+                for lx in 0..layer.width() as usize {
+                    let x = left + lx as i32;
+                    if x < 0 || x >= width as i32 {
+                        continue;
+                    }
+                    let offset = (y as usize * width + x as usize) * 4;
+                    let dst = &mut buffer[offset..offset + 4];
+                    let blended = blend(layer.rgba_at(lx, ly), [dst[0], dst[1], dst[2], dst[3]]);
+                    dst.copy_from_slice(&blended);
+                }
+            }
+        }
+
+        self.base.with_rgba(buffer)
+    }
+}
+
+impl CrxFile {
+    /// Merge this differential image (e.g. a facial expression) onto the `base` CG it was cut
+    /// from, returning a 32-bit image with the size and metadata of the base.
+    ///
+    /// The difference is placed by its inner offset relative to that of the base, the way the
+    /// game positions it. Clip tables are not used: what their fields mean is not known yet, see
+    /// `crx-convert analyze-clips`. Fails if the difference does not overlap the base.
+    pub fn compose_onto(&self, base: &CrxFile) -> io::Result<CrxFile> {
+        let x = self.inner_x() as i32 - base.inner_x() as i32;
+        let y = self.inner_y() as i32 - base.inner_y() as i32;
+        if x >= base.width() as i32
+            || y >= base.height() as i32
+            || x + self.width() as i32 <= 0
+            || y + self.height() as i32 <= 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "a {}x{} image at ({}, {}) does not overlap the {}x{} base",
+                    self.width(),
+                    self.height(),
+                    x,
+                    y,
+                    base.width(),
+                    base.height()
+                ),
+            ));
+        }
+        Ok(SceneComposition::new(base).overlay_at(self, x, y).compose())
+    }
+}
+
+/// Straight-alpha "source over destination" blending.
+fn blend(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    let src_a = src[3] as u32;
+    if src_a == 0xFF {
+        return src;
+    }
+    if src_a == 0 {
+        return dst;
+    }
+    let dst_a = dst[3] as u32 * (0xFF - src_a) / 0xFF;
+    let out_a = src_a + dst_a;
+    let mut out = [0, 0, 0, out_a as u8];
+    for c in 0..3 {
+        out[c] = ((src[c] as u32 * src_a + dst[c] as u32 * dst_a) / out_a) as u8;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CrxEncoder, CrxVersion};
+
+    /// A decoded RGBA image of one `color`, at the inner offset (`x`, `y`).
+    fn layer(width: u16, height: u16, color: [u8; 4], x: i16, y: i16) -> CrxFile {
+        let rgba = color.repeat(width as usize * height as usize);
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V2)
+            .with_offset(x, y)
+            .write_rgba(&mut file, width, height, &rgba)
+            .unwrap();
+        CrxFile::read(&file[..]).unwrap()
+    }
+
+    /// A decoded RGB gradient, at the inner offset (10, 20).
+    fn base() -> CrxFile {
+        let rgb: Vec<u8> = (0..6 * 4 * 3).map(|i| (i * 11 % 256) as u8).collect();
+        let mut file = Vec::new();
+        CrxEncoder::new(CrxVersion::V2)
+            .with_offset(10, 20)
+            .write_rgb(&mut file, 6, 4, &rgb)
+            .unwrap();
+        CrxFile::read(&file[..]).unwrap()
+    }
+
+    /// The pixels of `composed` where `covered` is true are `color`, the others those of `base`.
+    fn assert_composed(
+        composed: &CrxFile,
+        base: &CrxFile,
+        color: [u8; 4],
+        covered: impl Fn(u16, u16) -> bool,
+    ) {
+        assert_eq!(composed.bpp(), 32);
+        assert_eq!(
+            (composed.width(), composed.height()),
+            (base.width(), base.height())
+        );
+        assert_eq!(
+            (composed.inner_x(), composed.inner_y()),
+            (base.inner_x(), base.inner_y())
+        );
+        for y in 0..base.height() {
+            for x in 0..base.width() {
+                let expected = if covered(x, y) {
+                    color
+                } else {
+                    base.get_pixel(x, y)
+                };
+                assert_eq!(composed.get_pixel(x, y), expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn opaque_overlays_replace_the_base() {
+        let base = base();
+        let face = layer(2, 3, [1, 2, 3, 0xFF], 13, 21);
+        let composed = face.compose_onto(&base).unwrap();
+        assert_composed(&composed, &base, [1, 2, 3, 0xFF], |x, y| {
+            (3..5).contains(&x) && (1..4).contains(&y)
+        });
+        let scene = SceneComposition::new(&base).overlay(&face).compose();
+        assert_eq!(scene, composed);
+    }
+
+    #[test]
+    fn overlays_are_cut_to_the_base() {
+        let base = base();
+        let wide = layer(4, 3, [9, 8, 7, 0xFF], 0, 0);
+        let composed = SceneComposition::new(&base)
+            .overlay_at(&wide, -2, 2)
+            .compose();
+        assert_composed(&composed, &base, [9, 8, 7, 0xFF], |x, y| x < 2 && y >= 2);
+    }
+
+    #[test]
+    fn later_overlays_are_drawn_on_top() {
+        let base = base();
+        let first = layer(3, 3, [0xFF, 0, 0, 0xFF], 0, 0);
+        let second = layer(3, 3, [0, 0xFF, 0, 0xFF], 0, 0);
+        let composed = SceneComposition::new(&base)
+            .overlay_at(&first, 0, 0)
+            .overlay_at(&second, 1, 1)
+            .compose();
+        for (x, y, color) in [(0, 0, [0xFF, 0, 0, 0xFF]), (1, 1, [0, 0xFF, 0, 0xFF])] {
+            assert_eq!(composed.get_pixel(x, y), color, "pixel ({}, {})", x, y);
+        }
+        assert_eq!(composed.get_pixel(3, 0), base.get_pixel(3, 0));
+    }
+
+    #[test]
+    fn translucent_overlays_are_blended() {
+        let base = base();
+        let transparent = layer(6, 4, [0xFF, 0xFF, 0xFF, 0], 10, 20);
+        assert_composed(
+            &transparent.compose_onto(&base).unwrap(),
+            &base,
+            [0; 4],
+            |_, _| false,
+        );
+
+        // half way between the color of the overlay and that of the base.
+        let half = layer(6, 4, [200, 100, 0, 0x80], 10, 20);
+        let composed = half.compose_onto(&base).unwrap();
+        for (x, y) in [(0, 0), (5, 3)] {
+            let [r, g, b, _] = base.get_pixel(x, y);
+            let mix =
+                |over: u8, under: u8| ((over as u32 * 0x80 + under as u32 * 0x7F) / 0xFF) as u8;
+            assert_eq!(
+                composed.get_pixel(x, y),
+                [mix(200, r), mix(100, g), mix(0, b), 0xFF],
+                "pixel ({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn differences_outside_the_base_are_rejected() {
+        let base = base();
+        for (x, y) in [(16, 20), (10, 24), (8, 20), (10, 18)] {
+            let error = layer(2, 2, [0; 4], x, y).compose_onto(&base).unwrap_err();
+            assert_eq!(
+                error.kind(),
+                io::ErrorKind::InvalidInput,
+                "at ({}, {})",
+                x,
+                y
+            );
+        }
+        // touching the base by one pixel is enough.
+        assert!(layer(2, 2, [0; 4], 9, 19).compose_onto(&base).is_ok());
+    }
+}
@@ -0,0 +1,43 @@
+//! Fixtures shared by the tests of the decoding modules.
+
+use crate::{CrxEncoder, CrxVersion};
+
+/// `width` x `height` files of `version` in each pixel layout, as (bpp, file).
+pub(crate) fn encode_layouts(
+    version: CrxVersion,
+    width: u16,
+    height: u16,
+) -> Vec<(usize, Vec<u8>)> {
+    let pixels = width as usize * height as usize;
+    let data: Vec<u8> = (0..pixels * 4).map(|i| (i * 37 % 251) as u8).collect();
+    let palette: Vec<[u8; 3]> = (0..5).map(|i| [i * 50, 0x80, 0xFF - i * 40]).collect();
+    let indices: Vec<u8> = (0..pixels).map(|i| (i * i % 5) as u8).collect();
+    let encoder = CrxEncoder::new(version);
+    let (mut rgb, mut rgba, mut indexed) = (Vec::new(), Vec::new(), Vec::new());
+    encoder
+        .write_rgb(&mut rgb, width, height, &data[..pixels * 3])
+        .unwrap();
+    encoder.write_rgba(&mut rgba, width, height, &data).unwrap();
+    encoder
+        .write_indexed(&mut indexed, width, height, &palette, &indices)
+        .unwrap();
+    vec![(24, rgb), (32, rgba), (8, indexed)]
+}
+
+/// `values` as the runs of one component of a mode 4 row.
+pub(crate) fn runs(values: &[u8], stream: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < values.len() {
+        stream.push(values[i]);
+        i += 1;
+        let run = values[i..]
+            .iter()
+            .take(0xFF)
+            .take_while(|&&value| value == values[i - 1])
+            .count();
+        if run > 0 {
+            stream.extend_from_slice(&[values[i - 1], run as u8]);
+            i += run;
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// Where the batch engine reads its inputs from, see [`crate::spawn_batch_with`].
+pub trait Vfs: Send + Sync {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+}
+
+/// The real filesystem.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+#[cfg(feature = "fs")]
+impl Vfs for RealFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(io::BufReader::new(std::fs::File::open(path)?)))
+    }
+}
+
+/// Files held in memory, e.g. for tests or assets embedded in a program.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace the file at `path`.
+    pub fn insert(&mut self, path: impl Into<PathBuf>, data: Vec<u8>) {
+        self.files.insert(path.into(), data);
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        match self.files.get(path) {
+            Some(data) => Ok(Box::new(&data[..])),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no file at `{}`", path.to_string_lossy()),
+            )),
+        }
+    }
+}
+
+/// The entries of a CIRCUS `.pck` archive, by name.
+#[cfg(feature = "archive")]
+pub struct PckFs<R> {
+    archive: std::sync::Mutex<crate::PckArchive<R>>,
+}
+
+#[cfg(feature = "archive")]
+impl<R> PckFs<R> {
+    pub fn new(archive: crate::PckArchive<R>) -> Self {
+        Self {
+            archive: std::sync::Mutex::new(archive),
+        }
+    }
+}
+
+#[cfg(feature = "archive")]
+impl<R: Read + io::Seek + Send> Vfs for PckFs<R> {
+    /// Entries are read whole, as the archive reader is shared.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        let name = path.to_string_lossy();
+        let mut archive = self.archive.lock().unwrap();
+        let entry = archive
+            .entries()
+            .iter()
+            .find(|entry| entry.name() == name)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no entry `{}` in the archive", name),
+                )
+            })?;
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        archive.entry_reader(&entry)?.read_to_end(&mut data)?;
+        Ok(Box::new(io::Cursor::new(data)))
+    }
+}
+
+/// Where the data of a zip entry lies and how it is stored.
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, Copy)]
+struct ZipEntry {
+    deflated: bool,
+    local_header: u64,
+    compressed_size: u64,
+    size: u64,
+}
+
+/// The entries of a zip archive, by name, e.g. assets repacked for distribution.
+///
+/// Entries are either stored or deflated; encrypted entries and zip64 archives are not
+/// supported.
+#[cfg(feature = "archive")]
+pub struct ZipFs<R> {
+    reader: std::sync::Mutex<R>,
+    entries: HashMap<String, ZipEntry>,
+}
+
+#[cfg(feature = "archive")]
+impl<R: Read + io::Seek> ZipFs<R> {
+    /// Read the central directory of the archive in `reader`.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+        use io::SeekFrom;
+
+        let not_a_zip = || io::Error::new(io::ErrorKind::InvalidData, "not a zip archive");
+
+        // the end of central directory record is 22 bytes, followed by a comment of up to 64 KiB.
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        let tail_size = file_size.min(22 + 0xFFFF);
+        reader.seek(SeekFrom::Start(file_size - tail_size))?;
+        let mut tail = vec![0; tail_size as usize];
+        reader.read_exact(&mut tail)?;
+        let end = (0..tail.len().saturating_sub(21))
+            .rev()
+            .find(|&i| tail[i..i + 4] == [0x50, 0x4B, 0x05, 0x06])
+            .ok_or_else(not_a_zip)?;
+        let mut record = &tail[end + 10..];
+        let count = record.read_u16::<LittleEndian>()?;
+        let directory_size = record.read_u32::<LittleEndian>()? as u64;
+        let directory_offset = record.read_u32::<LittleEndian>()? as u64;
+        if count == 0xFFFF || directory_offset == 0xFFFF_FFFF {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "zip64 archives are not supported",
+            ));
+        }
+        if directory_offset + directory_size > file_size {
+            return Err(not_a_zip());
+        }
+
+        reader.seek(SeekFrom::Start(directory_offset))?;
+        let mut directory = vec![0; directory_size as usize];
+        reader.read_exact(&mut directory)?;
+        let mut directory = &directory[..];
+        let mut entries = HashMap::with_capacity(count as usize);
+        for _ in 0..count {
+            if directory.read_u32::<LittleEndian>()? != 0x0201_4B50 {
+                return Err(not_a_zip());
+            }
+            let mut fixed = [0; 42];
+            directory.read_exact(&mut fixed)?;
+            let mut fixed = &fixed[4..];
+            let flags = fixed.read_u16::<LittleEndian>()?;
+            let method = fixed.read_u16::<LittleEndian>()?;
+            let _modified = fixed.read_u32::<LittleEndian>()?;
+            let _crc = fixed.read_u32::<LittleEndian>()?;
+            let compressed_size = fixed.read_u32::<LittleEndian>()? as u64;
+            let size = fixed.read_u32::<LittleEndian>()? as u64;
+            let name_len = fixed.read_u16::<LittleEndian>()? as usize;
+            let extra_len = fixed.read_u16::<LittleEndian>()? as usize;
+            let comment_len = fixed.read_u16::<LittleEndian>()? as usize;
+            let _disk = fixed.read_u16::<LittleEndian>()?;
+            let _attributes = fixed.read_u16::<LittleEndian>()?;
+            let _external_attributes = fixed.read_u32::<LittleEndian>()?;
+            let local_header = fixed.read_u32::<LittleEndian>()? as u64;
+
+            if directory.len() < name_len + extra_len + comment_len {
+                return Err(not_a_zip());
+            }
+            let name = String::from_utf8_lossy(&directory[..name_len]).into_owned();
+            directory = &directory[name_len + extra_len + comment_len..];
+            // directories carry no data.
+            if name.ends_with('/') {
+                continue;
+            }
+            if flags & 1 != 0 || !matches!(method, 0 | 8) {
+                // kept out of the index, so opening them reports the entry as unreadable.
+                continue;
+            }
+            entries.insert(
+                name,
+                ZipEntry {
+                    deflated: method == 8,
+                    local_header,
+                    compressed_size,
+                    size,
+                },
+            );
+        }
+
+        Ok(Self {
+            reader: std::sync::Mutex::new(reader),
+            entries,
+        })
+    }
+
+    /// Names of the files in the archive, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+}
+
+#[cfg(feature = "archive")]
+impl<R: Read + io::Seek + Send> Vfs for ZipFs<R> {
+    /// Entries are read whole, as the archive reader is shared.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+        use io::SeekFrom;
+
+        // zip names always use forward slashes.
+        let name = path.to_string_lossy().replace('\\', "/");
+        let entry = *self.entries.get(&name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no readable entry `{}` in the archive", name),
+            )
+        })?;
+
+        let mut compressed = Vec::with_capacity(entry.compressed_size as usize);
+        {
+            let mut reader = self.reader.lock().unwrap();
+            reader.seek(SeekFrom::Start(entry.local_header))?;
+            let mut header = [0; 30];
+            reader.read_exact(&mut header)?;
+            let mut fields = &header[..];
+            if fields.read_u32::<LittleEndian>()? != 0x0403_4B50 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("entry `{}` lies outside of the archive", name),
+                ));
+            }
+            // the name and extra field lengths of the local header may differ from the directory.
+            let skip = u16::from_le_bytes([header[26], header[27]]) as i64
+                + u16::from_le_bytes([header[28], header[29]]) as i64;
+            reader.seek(SeekFrom::Current(skip))?;
+            (&mut *reader)
+                .take(entry.compressed_size)
+                .read_to_end(&mut compressed)?;
+        }
+        if (compressed.len() as u64) < entry.compressed_size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if !entry.deflated {
+            return Ok(Box::new(io::Cursor::new(compressed)));
+        }
+
+        let mut data = Vec::with_capacity(entry.size as usize);
+        flate2::read::DeflateDecoder::new(&compressed[..])
+            .take(entry.size)
+            .read_to_end(&mut data)?;
+        Ok(Box::new(io::Cursor::new(data)))
+    }
+}
+
+#[cfg(all(test, feature = "archive"))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A zip archive of `(name, method, data)` entries, without CRCs, which [`ZipFs`] ignores.
+    fn zip(files: &[(&str, u16, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        for &(name, method, data) in files {
+            let stored = match method {
+                8 => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(
+                        Vec::new(),
+                        flate2::Compression::default(),
+                    );
+                    encoder.write_all(data).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => data.to_vec(),
+            };
+            let offset = archive.len() as u32;
+            let fields = |header: &mut Vec<u8>| {
+                header.extend_from_slice(&[0; 2]);
+                header.extend_from_slice(&method.to_le_bytes());
+                header.extend_from_slice(&[0; 8]);
+                header.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                header.extend_from_slice(&[0; 2]);
+            };
+            archive.extend_from_slice(&[0x50, 0x4B, 0x03, 0x04, 20, 0]);
+            fields(&mut archive);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&stored);
+
+            directory.extend_from_slice(&[0x50, 0x4B, 0x01, 0x02, 20, 0, 20, 0]);
+            fields(&mut directory);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let directory_offset = archive.len() as u32;
+        archive.extend_from_slice(&directory);
+        archive.extend_from_slice(&[0x50, 0x4B, 0x05, 0x06, 0, 0, 0, 0]);
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&directory_offset.to_le_bytes());
+        archive.extend_from_slice(&[0; 2]);
+        archive
+    }
+
+    fn read(vfs: &impl Vfs, path: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        vfs.open(Path::new(path))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn zip_fs_reads_stored_and_deflated_entries() {
+        let big = vec![7; 5000];
+        let archive = zip(&[
+            ("image/a.crx", 0, b"stored"),
+            ("image/b.crx", 8, &big),
+            ("image/", 0, b""),
+            ("c.crx", 12, b"bzip2"),
+        ]);
+        let vfs = ZipFs::new(io::Cursor::new(archive)).unwrap();
+
+        let mut names = vfs.names().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["image/a.crx", "image/b.crx"]);
+        assert_eq!(read(&vfs, "image/a.crx").unwrap(), b"stored");
+        assert_eq!(read(&vfs, "image/b.crx").unwrap(), big);
+        assert_eq!(
+            read(&vfs, "c.crx").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn zip_fs_rejects_other_files() {
+        let error = ZipFs::new(io::Cursor::new(vec![0; 100])).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::CrxFile;
+use image::{GenericImageView, Rgba};
+
+/// A borrowed, RGBA view of the pixels of a [`CrxFile`], for the generic algorithms of the
+/// `image` crate. Nothing is copied: palette and 24-bit pixels are converted as they are read.
+#[derive(Debug, Clone, Copy)]
+pub struct CrxView<'a> {
+    crx: &'a CrxFile,
+}
+
+impl CrxFile {
+    pub fn view(&self) -> CrxView<'_> {
+        CrxView { crx: self }
+    }
+}
+
+impl GenericImageView for CrxView<'_> {
+    type Pixel = Rgba<u8>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.crx.width() as u32, self.crx.height() as u32)
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        assert!(
+            self.in_bounds(x, y),
+            "pixel ({}, {}) is out of bounds",
+            x,
+            y
+        );
+        Rgba(self.crx.rgba_at(x as usize, y as usize))
+    }
+}
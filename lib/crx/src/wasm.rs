@@ -0,0 +1,40 @@
+use crate::CrxFile;
+use wasm_bindgen::prelude::*;
+
+/// A decoded image, handed to JavaScript.
+#[wasm_bindgen]
+pub struct DecodedImage {
+    width: u16,
+    height: u16,
+    pixels: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl DecodedImage {
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// RGBA pixels, row by row; a copy, ready for `new ImageData(...)`.
+    #[wasm_bindgen(getter)]
+    pub fn pixels(&self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+/// Decode the CRX file held in `bytes` to RGBA, whatever its pixel format.
+#[wasm_bindgen(js_name = decodeToRgba)]
+pub fn decode_to_rgba(bytes: &[u8]) -> Result<DecodedImage, JsError> {
+    let image = CrxFile::read(bytes)?;
+    Ok(DecodedImage {
+        width: image.width(),
+        height: image.height(),
+        pixels: image.to_rgba(),
+    })
+}
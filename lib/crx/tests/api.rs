@@ -0,0 +1,205 @@
+//! Guards the parts of the public API that downstream crates depend on: these fail to compile
+//! when a signature or an error variant changes, so such changes are made on purpose, with a new
+//! version of `public-api.txt` and of the crate.
+
+use crx::{
+    CrxDecodeError, CrxEncodeError, CrxEncoder, CrxError, CrxFile, CrxMetadata, CrxReader,
+    CrxRowDecoder, CrxVersion, DecodeOptions, Strictness,
+};
+use std::io;
+
+type Decode<T> = Result<T, CrxError>;
+
+/// Decoded input; `'static` keeps the function pointer types below free of elided lifetimes.
+type Input = &'static [u8];
+
+/// `CrxEncoder::write_rgba` and `write_rgb`, writing to a `Vec<u8>`.
+type WritePixels = fn(&CrxEncoder, Vec<u8>, u16, u16, &[u8]) -> io::Result<()>;
+
+#[test]
+fn decode_signatures() {
+    let _: fn(Input) -> Decode<CrxFile> = CrxFile::read::<Input>;
+    let _: fn(Input) -> Decode<CrxFile> = CrxFile::read_indexed::<Input>;
+    let _: fn(Input) -> Decode<CrxMetadata> = CrxFile::read_metadata::<Input>;
+    let _: fn(Input, &DecodeOptions) -> Decode<CrxFile> = CrxFile::read_with_options::<Input>;
+    let _: fn(Input, &mut Vec<u8>, &DecodeOptions) -> Decode<CrxMetadata> =
+        CrxFile::decode_into::<Input>;
+    let _: fn(&CrxReader, Input) -> Decode<CrxFile> = CrxReader::decode::<Input>;
+    let _: fn(Input, &DecodeOptions) -> Decode<CrxRowDecoder<Input>> = CrxRowDecoder::new;
+    let _: for<'a> fn(&'a mut CrxRowDecoder<Input>) -> Decode<Option<&'a [u8]>> =
+        CrxRowDecoder::next_row;
+    let _: fn(&CrxFile, Vec<u8>) -> io::Result<()> = CrxFile::write::<Vec<u8>>;
+}
+
+#[test]
+fn metadata_signatures() {
+    let _: fn(&CrxFile) -> u16 = CrxFile::width;
+    let _: fn(&CrxFile) -> u16 = CrxFile::height;
+    let _: fn(&CrxFile) -> usize = CrxFile::bpp;
+    let _: fn(&CrxFile) -> CrxVersion = CrxFile::version;
+    let _: fn(&CrxFile) -> &[u8] = CrxFile::raw_buffer;
+    let _: fn(&CrxMetadata) -> u16 = CrxMetadata::width;
+    let _: fn(&CrxMetadata) -> u16 = CrxMetadata::height;
+    let _: fn(&CrxMetadata) -> usize = CrxMetadata::bpp;
+    let _: fn(&CrxMetadata) -> CrxVersion = CrxMetadata::version;
+}
+
+#[test]
+fn encode_signatures() {
+    let _: fn(CrxVersion) -> CrxEncoder = CrxEncoder::new;
+    let _: WritePixels = CrxEncoder::write_rgba::<Vec<u8>>;
+    let _: WritePixels = CrxEncoder::write_rgb::<Vec<u8>>;
+}
+
+#[test]
+fn reader_builder() {
+    let reader = CrxReader::new()
+        .max_dimensions(8192, 8192)
+        .keep_indexed(true)
+        .lenient(true);
+    let options = reader.options();
+    assert_eq!(
+        (options.max_width, options.max_height),
+        (Some(8192), Some(8192))
+    );
+    assert!(options.keep_indexed);
+    assert_eq!(options.strictness, Strictness::Lenient);
+    let _: fn(CrxReader, bool) -> CrxReader = CrxReader::keep_alpha;
+    let _: fn(CrxReader, bool) -> CrxReader = CrxReader::allow_empty;
+    let _: fn(CrxReader, u64) -> CrxReader = CrxReader::max_alloc;
+    let _: fn(CrxReader, usize) -> CrxReader = CrxReader::max_clips;
+    let _: fn(CrxReader, usize) -> CrxReader = CrxReader::max_palette_colors;
+}
+
+#[test]
+fn decode_options_fields() {
+    let options = DecodeOptions {
+        max_width: Some(4096),
+        max_height: Some(4096),
+        max_alloc: Some(1 << 28),
+        max_clips: Some(64),
+        max_palette_colors: Some(256),
+        ..DecodeOptions::default()
+    };
+    let _: bool = options.keep_alpha;
+    let _: bool = options.keep_indexed;
+    let _: bool = options.allow_empty;
+}
+
+/// Every variant downstream code matches on, with the types of its fields. A wildcard arm stays
+/// required, as new variants may be added.
+#[allow(unreachable_patterns)]
+fn describe(error: &CrxError) -> String {
+    match error {
+        CrxError::Io(e) => format!("io: {:?}", e.kind()),
+        CrxError::Decode(e) => match *e {
+            CrxDecodeError::CrxSignatureInvalid => "signature".into(),
+            CrxDecodeError::VersionNotSupported {
+                version,
+                flag,
+                mode,
+            } => {
+                let _: (u16, Option<u16>, Option<u16>) = (version, flag, mode);
+                "version".into()
+            }
+            CrxDecodeError::InvalidRowDecodeMode(mode) => {
+                let _: u8 = mode;
+                "row mode".into()
+            }
+            CrxDecodeError::NoPreviousRow | CrxDecodeError::RowOverflow => "rows".into(),
+            CrxDecodeError::BadPaletteIndex(size, index) => {
+                let _: (usize, usize) = (size, index);
+                "palette".into()
+            }
+            CrxDecodeError::InvalidDepth(depth) => {
+                let _: i16 = depth;
+                "depth".into()
+            }
+            CrxDecodeError::InvalidClipCount(count) => {
+                let _: i32 = count;
+                "clips".into()
+            }
+            CrxDecodeError::InvalidStreamSize(size) => {
+                let _: i32 = size;
+                "stream".into()
+            }
+            CrxDecodeError::LimitExceeded(what, value, limit) => {
+                let _: (&str, u64, u64) = (what, value, limit);
+                "limit".into()
+            }
+            CrxDecodeError::InvalidDimensions(width, height) => {
+                let _: (u16, u16) = (width, height);
+                "dimensions".into()
+            }
+            CrxDecodeError::PixelBufferSize(expected, actual) => {
+                let _: (usize, usize) = (expected, actual);
+                "pixels".into()
+            }
+            _ => "other decode error".into(),
+        },
+        CrxError::Encode(e) => match *e {
+            CrxEncodeError::BufferSizeMismatch(expected, actual) => {
+                let _: (usize, usize) = (expected, actual);
+                "buffer".into()
+            }
+            _ => "other encode error".into(),
+        },
+        _ => "other error".into(),
+    }
+}
+
+#[test]
+fn errors_keep_their_variant() {
+    let error = CrxFile::read(&b"NOPE"[..]).unwrap_err();
+    assert_eq!(describe(&error), "signature");
+
+    let error = CrxFile::read(&b"CRXG"[..]).unwrap_err();
+    assert_eq!(describe(&error), "io: UnexpectedEof");
+
+    let mut data = Vec::new();
+    let error = CrxEncoder::new(CrxVersion::V2)
+        .write_rgba(&mut data, 2, 2, &[0; 4])
+        .unwrap_err();
+    assert_eq!(describe(&CrxError::from(error)), "buffer");
+}
+
+#[test]
+fn errors_convert_to_io_errors() {
+    let error: io::Error = CrxError::Decode(CrxDecodeError::InvalidDepth(5)).into();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "invalid color depth `5`");
+    assert!(matches!(
+        CrxError::from(error),
+        CrxError::Decode(CrxDecodeError::InvalidDepth(5))
+    ));
+
+    let error: io::Error = CrxError::Encode(CrxEncodeError::InvalidBPP(12)).into();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    // errors cross thread boundaries and box into `dyn Error`.
+    fn assert_error<E: std::error::Error + Send + Sync + 'static>() {}
+    assert_error::<CrxError>();
+    assert_error::<CrxDecodeError>();
+    assert_error::<CrxEncodeError>();
+}
+
+#[test]
+fn round_trip() {
+    let pixels: Vec<u8> = (0..3 * 2 * 4).map(|i| i as u8 * 10).collect();
+    let mut data = Vec::new();
+    CrxEncoder::new(CrxVersion::V2)
+        .write_rgba(&mut data, 3, 2, &pixels)
+        .unwrap();
+
+    let crx = CrxFile::read(&data[..]).unwrap();
+    assert_eq!((crx.width(), crx.height(), crx.bpp()), (3, 2, 32));
+
+    let mut rows = CrxRowDecoder::new(&data[..], &DecodeOptions::default()).unwrap();
+    assert_eq!(rows.metadata().width(), 3);
+    let mut count = 0;
+    while let Some(row) = rows.next_row().unwrap() {
+        assert_eq!(row.len(), 3 * 4);
+        count += 1;
+    }
+    assert_eq!(count, 2);
+}
@@ -0,0 +1,80 @@
+//! Python bindings of the CRX decoder, imported as `crx`.
+
+use crx_lib::{CrxError, CrxFile};
+use pyo3::{
+    exceptions::{PyOSError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
+use std::{fs, io::BufReader, path::PathBuf};
+
+/// Decode the CRX file at `path`. Returns the pixels and a dict of the header fields, with
+/// `mode` and `size` as `PIL.Image.frombytes` takes them:
+///
+///     data, meta = crx.decode("bg01.crx")
+///     image = Image.frombytes(meta["mode"], meta["size"], data)
+#[pyfunction]
+fn decode(py: Python<'_>, path: PathBuf) -> PyResult<(Py<PyBytes>, Py<PyDict>)> {
+    let file = py
+        .detach(|| {
+            let reader = BufReader::new(fs::File::open(&path)?);
+            CrxFile::read(reader)
+        })
+        .map_err(to_py_error)?;
+    to_python(py, file)
+}
+
+/// Like `decode`, for a CRX file already read into `data`, e.g. an archive entry.
+#[pyfunction]
+fn decode_bytes(py: Python<'_>, data: &[u8]) -> PyResult<(Py<PyBytes>, Py<PyDict>)> {
+    let file = py.detach(|| CrxFile::read(data)).map_err(to_py_error)?;
+    to_python(py, file)
+}
+
+fn to_py_error(error: CrxError) -> PyErr {
+    match error {
+        CrxError::Io(e) => PyOSError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+fn to_python(py: Python<'_>, file: CrxFile) -> PyResult<(Py<PyBytes>, Py<PyDict>)> {
+    let meta = PyDict::new(py);
+    meta.set_item("mode", if file.bpp() == 32 { "RGBA" } else { "RGB" })?;
+    meta.set_item("size", (file.width(), file.height()))?;
+    meta.set_item("width", file.width())?;
+    meta.set_item("height", file.height())?;
+    meta.set_item("inner_x", file.inner_x())?;
+    meta.set_item("inner_y", file.inner_y())?;
+    meta.set_item("version", file.version().number())?;
+    meta.set_item("flag", file.flag())?;
+    meta.set_item("signature", PyBytes::new(py, &file.signature().bytes()))?;
+    let palette: Option<Vec<_>> = file
+        .palette()
+        .map(|palette| palette.iter().map(|&[r, g, b]| (r, g, b)).collect());
+    meta.set_item("palette", palette)?;
+    let clips = file
+        .clips()
+        .iter()
+        .map(|clip| {
+            let entry = PyDict::new(py);
+            entry.set_item("unknown_1", clip.unknown_1)?;
+            entry.set_item("x", clip.x)?;
+            entry.set_item("y", clip.y)?;
+            entry.set_item("unknown_2", clip.unknown_2)?;
+            entry.set_item("width", clip.width)?;
+            entry.set_item("height", clip.height)?;
+            Ok(entry)
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    meta.set_item("clips", clips)?;
+    let data = PyBytes::new(py, file.raw_buffer());
+    Ok((data.unbind(), meta.unbind()))
+}
+
+#[pymodule]
+fn crx(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(decode, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_bytes, m)?)?;
+    Ok(())
+}